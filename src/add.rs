@@ -0,0 +1,491 @@
+/// adding fetched records to a bibtex file, without duplicating the entries
+/// of the other bibtex files of the project, with the prompts left to the
+/// caller
+use crate::dblp::FETCH_DELAY;
+use crate::error::DblpError;
+use crate::similarity::{self, Confidence};
+use crate::sort::{self, SortBy};
+use crate::{bib, config, keygen, tex};
+use anyhow::{bail, Context, Result};
+use biblatex::{Bibliography, Entry};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// How add_records adds the entries
+pub struct Adding {
+    pub keying: Keying,
+    /// open each entry in the editor before adding it
+    pub edit: bool,
+    /// insert the entries in this order, if the file follows it
+    pub sorted: Option<SortBy>,
+    /// the fields to remove from the fetched entries
+    pub strip: Vec<String>,
+}
+
+/// How added entries are keyed
+pub enum Keying {
+    /// with the key of the fetched entry
+    Dblp,
+    Pattern(keygen::Pattern),
+    /// with this key, for a single entry
+    Exact(String),
+}
+
+/// What to do with a fetched entry that is the same paper as an entry of
+/// the bib file
+#[derive(Debug, PartialEq)]
+pub enum Resolution {
+    /// cite the entry of the file, with this key, instead
+    Skip(String),
+    /// add the fetched entry besides it
+    Add,
+    /// replace the entry of the file, with this key, by the fetched one
+    Replace(String),
+}
+
+/// An entry of a bib file that may be the same paper as a fetched one
+pub struct Duplicate<'a> {
+    pub entry: &'a Entry,
+    /// the entry as written in its file
+    pub text: &'a str,
+    pub fetched: &'a Entry,
+    pub confidence: Confidence,
+    /// the other bib file the entry is in, whose entries cannot be replaced
+    pub elsewhere: Option<&'a Path>,
+}
+
+/// What adding the records makes of the bib file
+pub struct Added {
+    /// the keys to cite the records with, in order
+    pub keys: Vec<String>,
+    /// what became of each record, to tell the user
+    pub report: Vec<String>,
+    /// the file with the replaced entries, before the added ones, if it
+    /// exists
+    pub base: Option<String>,
+    /// the entries to add to the base
+    pub missing: Vec<String>,
+    /// the keys of the replaced entries, with the key replacing each
+    pub renames: BTreeMap<String, String>,
+}
+
+impl Added {
+    /// Whether the bib file needs writing
+    pub fn changes_file(&self) -> bool {
+        !self.missing.is_empty() || !self.renames.is_empty()
+    }
+}
+
+/// Fetches the entries with the given keys that are not in the bib file,
+/// the `existing` text if it exists, nor in the `others` files, giving the
+/// keys to cite them and what to write. Fetched entries that may be the
+/// same paper as an entry of a file go through `resolve`, and each entry
+/// through `edit` if adding asks to.
+pub fn add_records(
+    existing: Option<&str>,
+    others: &[(PathBuf, String)],
+    adding: &Adding,
+    records: impl Iterator<Item = String>,
+    mut fetch: impl FnMut(&str) -> Result<String>,
+    mut resolve: impl FnMut(&Duplicate) -> Result<Resolution>,
+    mut edit: impl FnMut(&str) -> Result<String>,
+) -> Result<Added> {
+    let others_src: String = others.iter().map(|(_, src)| format!("\n{}", src)).collect();
+    // the file with the replaced entries, before the added ones
+    let mut base = existing.map(str::to_owned);
+    // the file as it will be once the records are added, so that they are
+    // checked and keyed against each other too
+    let mut src = existing.unwrap_or_default().to_owned();
+    let mut renames = BTreeMap::new();
+    let mut missing = Vec::new();
+    let mut report = Vec::new();
+    let mut keys = Vec::new();
+    for (i, record) in records.enumerate() {
+        let mut key = record;
+        let found = match bib::presence(&src, &key) {
+            bib::Presence::PresentAs(existing) => Some((None, existing)),
+            bib::Presence::NotPresent => {
+                others
+                    .iter()
+                    .find_map(|(path, other)| match bib::presence(other, &key) {
+                        bib::Presence::PresentAs(existing) => Some((Some(path), existing)),
+                        bib::Presence::NotPresent => None,
+                    })
+            }
+        };
+        match found {
+            Some((path, existing)) => {
+                let place = path
+                    .map(|path| format!(" in {}", path.display()))
+                    .unwrap_or_default();
+                if existing == key {
+                    report.push(format!("{} already present{}", key, place));
+                } else {
+                    report.push(format!("{} already present{} as {}", key, place, existing));
+                    key = existing;
+                }
+            }
+            None => {
+                if i > 0 {
+                    std::thread::sleep(FETCH_DELAY);
+                }
+                let bib = bib::strip_fields(&fetch(&key)?, &adding.strip)?;
+                let mut resolution = resolve_duplicate(
+                    base.as_deref().unwrap_or_default(),
+                    &bib,
+                    None,
+                    &mut resolve,
+                )?;
+                for (path, other) in others {
+                    if resolution != Resolution::Add {
+                        break;
+                    }
+                    resolution = resolve_duplicate(other, &bib, Some(path), &mut resolve)?;
+                }
+                // the keys are free in all the files
+                let all = format!("{}{}", src, others_src);
+                if let Resolution::Skip(existing) = resolution {
+                    key = existing;
+                } else {
+                    let bib = match &adding.keying {
+                        Keying::Pattern(pattern) => {
+                            let (new_key, bib) = apply_key_pattern(pattern, &bib, &key, &all)?;
+                            key = new_key;
+                            bib
+                        }
+                        Keying::Exact(new_key) => {
+                            check_free(&all, new_key, &resolution)?;
+                            let bib = bib::rename_entry(&bib, &key, new_key)?;
+                            key = new_key.clone();
+                            bib
+                        }
+                        Keying::Dblp => bib,
+                    };
+                    let bib = if adding.edit {
+                        match edit_entry(&bib, &all, &resolution, &mut edit)? {
+                            Some((edited_key, bib)) => {
+                                key = edited_key;
+                                bib
+                            }
+                            None => {
+                                report.push(format!("not adding {}, left empty", key));
+                                continue;
+                            }
+                        }
+                    } else {
+                        bib
+                    };
+                    let (added, present) = bib::partition_present(&all, &bib)?;
+                    for (k, text) in added {
+                        match &resolution {
+                            Resolution::Replace(old) if k == key => {
+                                // the old key stays in the file, to find it again
+                                let text = format!("% formerly {}\n{}", old, text);
+                                src = bib::replace_entry(&src, old, &text)?;
+                                base = base
+                                    .map(|base| bib::replace_entry(&base, old, &text))
+                                    .transpose()?;
+                                report.push(format!("replaced {} by {}", old, k));
+                                renames.insert(old.clone(), k);
+                            }
+                            _ => {
+                                src.push_str(&format!("{}\n\n", text));
+                                report.push(format!("added {}", k));
+                                missing.push(text.to_owned());
+                            }
+                        }
+                    }
+                    report.extend(present.iter().map(|k| format!("{} already present", k)));
+                }
+            }
+        }
+        keys.push(key);
+    }
+    Ok(Added {
+        keys,
+        report,
+        base,
+        missing,
+        renames,
+    })
+}
+
+/// Looks for an entry of the source that is the same paper as the fetched
+/// one, possibly under a different key, and lets `resolve` decide what to
+/// do with the fetched one. Entries of another file, the one `elsewhere`,
+/// cannot be replaced.
+fn resolve_duplicate(
+    src: &str,
+    fetched: &str,
+    elsewhere: Option<&PathBuf>,
+    resolve: &mut impl FnMut(&Duplicate) -> Result<Resolution>,
+) -> Result<Resolution> {
+    let fetched = match Bibliography::parse(fetched)
+        .ok()
+        .and_then(|b| b.into_iter().next())
+    {
+        Some(entry) => entry,
+        None => return Ok(Resolution::Add),
+    };
+    let bibliography = match Bibliography::parse(src) {
+        Ok(bibliography) => bibliography,
+        Err(_) => return Ok(Resolution::Add),
+    };
+    let spans = bib::entry_spans(src).unwrap_or_default();
+    for entry in bibliography.iter() {
+        let confidence = similarity::match_confidence(entry, &fetched);
+        if confidence == Confidence::Low {
+            continue;
+        }
+        let text = spans
+            .iter()
+            .find(|(k, _)| *k == entry.key)
+            .map_or("", |(_, span)| &src[span.clone()]);
+        let resolution = resolve(&Duplicate {
+            entry,
+            text,
+            fetched: &fetched,
+            confidence,
+            elsewhere: elsewhere.map(PathBuf::as_path),
+        })?;
+        if elsewhere.is_some() && matches!(resolution, Resolution::Replace(_)) {
+            bail!(
+                "{} is in another bib file, it cannot be replaced",
+                entry.key
+            );
+        }
+        return Ok(resolution);
+    }
+    Ok(Resolution::Add)
+}
+
+/// The resolution the answer stands for, the default one if it is empty
+pub fn parse_resolution(answer: &str, key: &str) -> Option<Resolution> {
+    match answer.trim().to_lowercase().as_str() {
+        "" | "s" | "skip" => Some(Resolution::Skip(key.to_owned())),
+        "a" | "add" => Some(Resolution::Add),
+        "r" | "replace" => Some(Resolution::Replace(key.to_owned())),
+        _ => None,
+    }
+}
+
+/// Fails if another entry of the source has the key, in any case, unless it
+/// is the one being replaced
+pub fn check_free(src: &str, key: &str, resolution: &Resolution) -> Result<()> {
+    let taken = bib::entry_spans(src)?.into_iter().any(|(k, _)| {
+        k.eq_ignore_ascii_case(key) && !matches!(resolution, Resolution::Replace(old) if *old == k)
+    });
+    if taken {
+        bail!("{} is already the key of another entry", key);
+    }
+    Ok(())
+}
+
+/// Lines starting with this, at the start of an edited entry, are dropped
+pub const EDIT_COMMENT: &str = "% dblp:";
+
+/// Has `edit` edit the entry until it is saved as a single entry with a
+/// free key, showing what is wrong at the top otherwise. Returns its key and
+/// text, or None if it is saved empty.
+fn edit_entry(
+    bib: &str,
+    src: &str,
+    resolution: &Resolution,
+    edit: &mut impl FnMut(&str) -> Result<String>,
+) -> Result<Option<(String, String)>> {
+    let hint = format!("{} empty the file not to add the entry\n", EDIT_COMMENT);
+    let mut text = format!("{}{}\n", hint, bib.trim_end());
+    loop {
+        let edited = strip_edit_comments(&edit(&text)?);
+        if edited.is_empty() {
+            return Ok(None);
+        }
+        let checked = bib::single_entry(&edited)
+            .map_err(anyhow::Error::from)
+            .and_then(|key| check_free(src, &key, resolution).map(|_| key));
+        match checked {
+            Ok(key) => return Ok(Some((key, edited))),
+            Err(err) => {
+                let err = format!("{:#}", err).replace('\n', " ");
+                text = format!("{} error: {}\n{}{}\n", EDIT_COMMENT, err, hint, edited);
+            }
+        }
+    }
+}
+
+/// The edited text, without the comments added at its start and trimmed
+pub fn strip_edit_comments(edited: &str) -> String {
+    edited
+        .lines()
+        .skip_while(|line| line.starts_with(EDIT_COMMENT))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+/// Rekeys the entry of the fetched bibtex following the pattern, returning
+/// the new key and the bibtex
+pub fn apply_key_pattern(
+    pattern: &keygen::Pattern,
+    fetched: &str,
+    key: &str,
+    existing: &str,
+) -> Result<(String, String)> {
+    let bibliography = Bibliography::parse(fetched).map_err(|e| DblpError::from_bib(e, fetched))?;
+    let entry = bibliography
+        .get(key)
+        .with_context(|| format!("{} is not in the fetched bibtex", key))?;
+    let mut keys: Vec<String> = bib::entry_spans(existing)?
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    keys.extend(
+        bibliography
+            .iter()
+            .filter(|e| e.key != key)
+            .map(|e| e.key.clone()),
+    );
+    let new_key = keygen::generate(pattern, entry, &keys);
+    let renamed = bib::rename_entry(fetched, key, &new_key)?;
+    Ok((new_key, renamed))
+}
+
+/// The text of the bib file, the `existing` one if it exists, with the
+/// entries inserted where they go in the given order, or else appended with
+/// one blank line before each. The order is given back too if the file
+/// does not follow it. A file that does not exist yet starts with the
+/// template of new bib files.
+pub fn insert_entries(
+    bib_path: &Path,
+    existing: Option<&str>,
+    entries: &[String],
+    sorted: Option<SortBy>,
+) -> Result<(String, Option<SortBy>)> {
+    if let (Some(src), Some(by)) = (existing, sorted) {
+        let first = match by {
+            SortBy::Cited => tex::first_citations(tex::citations(tex::dir_of(bib_path))?),
+            _ => Vec::new(),
+        };
+        if let Some(inserted) = sort::insert_sorted(src, entries, by, &first)? {
+            return Ok((inserted, None));
+        }
+    }
+    let header = match existing {
+        Some(src) => src.to_owned(),
+        None => config::load()?.new_bib_template()?.unwrap_or_default(),
+    };
+    let unsorted = sorted.filter(|_| existing.is_some());
+    Ok((bib::append(&header, entries), unsorted))
+}
+
+#[test]
+fn test_add_records() {
+    let existing =
+        "@misc{a, title = {Sorting in Linear Time}, author = {Jane Doe}, year = {2019}}\n";
+    let others = [(
+        PathBuf::from("other.bib"),
+        "@misc{o, title = {Other}}\n".to_owned(),
+    )];
+    let adding = Adding {
+        keying: Keying::Exact("new".into()),
+        edit: true,
+        sorted: None,
+        strip: vec!["note".into()],
+    };
+    let records = ["o".to_owned(), "DBLP:b".to_owned()];
+    let mut asked = Vec::new();
+    let added = add_records(
+        Some(existing),
+        &others,
+        &adding,
+        records.into_iter(),
+        |key| {
+            Ok(format!(
+                "@misc{{{}, title = {{B}}, note = {{dropped}}}}",
+                key
+            ))
+        },
+        |duplicate| {
+            asked.push(duplicate.entry.key.clone());
+            Ok(Resolution::Add)
+        },
+        |text| {
+            assert!(text.starts_with(EDIT_COMMENT), "{}", text);
+            assert!(!text.contains("dropped"), "{}", text);
+            Ok(text.replace("{B}", "{Edited}"))
+        },
+    )
+    .unwrap();
+    assert!(asked.is_empty());
+    assert_eq!(added.keys, ["o", "new"]);
+    assert_eq!(
+        added.report,
+        ["o already present in other.bib", "added new"]
+    );
+    assert_eq!(added.base.as_deref(), Some(existing));
+    assert_eq!(added.missing, ["@misc{new, title = {Edited}, }"]);
+    assert!(added.renames.is_empty() && added.changes_file());
+
+    // the same paper as an entry of the file, replaced
+    let adding = Adding {
+        keying: Keying::Dblp,
+        edit: false,
+        sorted: None,
+        strip: Vec::new(),
+    };
+    let added = add_records(
+        Some(existing),
+        &[],
+        &adding,
+        std::iter::once("DBLP:a".to_owned()),
+        |_| {
+            Ok("@misc{DBLP:a, title = {Sorting in Linear Time}, author = {Jane Doe}, year = {2019}, doi = {10/x}}".to_owned())
+        },
+        |duplicate| {
+            assert_eq!(duplicate.confidence, Confidence::High);
+            Ok(Resolution::Replace(duplicate.entry.key.clone()))
+        },
+        |_| unreachable!(),
+    )
+    .unwrap();
+    assert_eq!(added.keys, ["DBLP:a"]);
+    assert_eq!(added.report, ["replaced a by DBLP:a"]);
+    assert!(added.missing.is_empty());
+    assert!(added
+        .base
+        .unwrap()
+        .starts_with("% formerly a\n@misc{DBLP:a,"));
+    assert_eq!(
+        added.renames,
+        BTreeMap::from([("a".to_owned(), "DBLP:a".to_owned())])
+    );
+}
+
+#[test]
+fn test_strip_edit_comments() {
+    let edited = "% dblp: error: expected a single entry, found 0\n% dblp: empty the file not to add the entry\n\n@misc{a,\n  title = {A}}\n% a note\n\n";
+    assert_eq!(
+        strip_edit_comments(edited),
+        "@misc{a,\n  title = {A}}\n% a note"
+    );
+    assert_eq!(
+        strip_edit_comments("% dblp: empty the file not to add the entry\n\n"),
+        ""
+    );
+}
+
+#[test]
+fn test_parse_resolution() {
+    let skip = Some(Resolution::Skip("chan16".into()));
+    assert_eq!(parse_resolution("\n", "chan16"), skip);
+    assert_eq!(parse_resolution(" S\n", "chan16"), skip);
+    assert_eq!(parse_resolution("add", "chan16"), Some(Resolution::Add));
+    assert_eq!(
+        parse_resolution("r", "chan16"),
+        Some(Resolution::Replace("chan16".into()))
+    );
+    assert_eq!(parse_resolution("y", "chan16"), None);
+}
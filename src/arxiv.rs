@@ -0,0 +1,223 @@
+/// utilities to make bibtex entries of arXiv preprints, for the ones DBLP
+/// does not have yet
+use crate::error::DblpError;
+use crate::stats::STATS;
+use anyhow::{anyhow, bail, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::LazyLock;
+
+pub const URL: &str = "http://export.arxiv.org";
+
+/// Environment variable replacing the arXiv API, to point the tool at a
+/// test server
+pub const BASE_VAR: &str = "DBLP_RS_ARXIV_BASE";
+
+fn base() -> String {
+    std::env::var(BASE_VAR)
+        .map(|base| base.trim_end_matches('/').to_owned())
+        .unwrap_or_else(|_| URL.to_owned())
+}
+
+/// Identifiers like `2301.01234`, since 2007, and `cs/0101012` before,
+/// optionally with a version
+static ID: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d{4}\.\d{4,5}|[a-z-]+(\.[A-Z]{2})?/\d{7})(v\d+)?$").unwrap());
+
+/// The identifier in `2301.01234`, `arXiv:2301.01234`, or the address of its
+/// abstract or pdf
+pub fn parse_id(id: &str) -> Result<String> {
+    let id = id.trim();
+    let id = ["/abs/", "/pdf/"]
+        .iter()
+        .find_map(|dir| id.find(dir).map(|i| &id[i + dir.len()..]))
+        .unwrap_or(id);
+    let id = id.strip_suffix(".pdf").unwrap_or(id);
+    let id = id
+        .strip_prefix("arXiv:")
+        .or(id.strip_prefix("arxiv:"))
+        .unwrap_or(id);
+    if !ID.is_match(id) {
+        bail!(
+            "{:?} is not an arXiv identifier, which look like 2301.01234 or cs/0101012",
+            id
+        );
+    }
+    Ok(id.to_owned())
+}
+
+/// The key of the entry of the preprint, the same for all its versions
+pub fn key(id: &str) -> String {
+    format!("arXiv:{}", without_version(id))
+}
+
+fn without_version(id: &str) -> &str {
+    match id.rfind('v') {
+        Some(i) if i > 0 && id[i + 1..].chars().all(|c| c.is_ascii_digit()) => &id[..i],
+        _ => id,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Preprint {
+    pub id: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub year: String,
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Feed {
+    #[serde(rename = "entry", default)]
+    entries: Vec<FeedEntry>,
+}
+
+#[derive(Deserialize)]
+struct FeedEntry {
+    id: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    published: String,
+    #[serde(rename = "author", default)]
+    authors: Vec<FeedAuthor>,
+    #[serde(rename = "primary_category")]
+    category: Option<Category>,
+}
+
+#[derive(Deserialize)]
+struct FeedAuthor {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Category {
+    #[serde(rename = "@term")]
+    term: String,
+}
+
+/// Fetches the metadata of the preprint from the arXiv API
+pub fn fetch(id: &str) -> Result<Preprint> {
+    let url = format!("{}/api/query?id_list={}", base(), id);
+    let body = STATS.phase("network", || {
+        crate::dblp::AGENT
+            .get(&url)
+            .call()
+            .map_err(|err| anyhow!(err).context(format!("fetching arXiv {}", id)))?
+            .into_string()
+            .context("reading the answer of arXiv")
+    })?;
+    STATS.request(body.len());
+    parse_feed(&body, id)
+}
+
+fn parse_feed(xml: &str, id: &str) -> Result<Preprint> {
+    let feed: Feed = quick_xml::de::from_str(xml).context("parsing the answer of arXiv")?;
+    // unknown identifiers get no entry, or an entry describing the error
+    let entry = feed
+        .entries
+        .into_iter()
+        .find(|e| !e.id.contains("/api/errors"))
+        .ok_or(DblpError::UnknownArxiv { id: id.to_owned() })?;
+    let collapse = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok(Preprint {
+        id: without_version(entry.id.rsplit("/abs/").next().unwrap_or(id)).to_owned(),
+        title: collapse(&entry.title),
+        authors: entry.authors.iter().map(|a| collapse(&a.name)).collect(),
+        year: entry.published.chars().take(4).collect(),
+        category: entry.category.map(|c| c.term),
+    })
+}
+
+impl Preprint {
+    /// A `@misc` entry, with the `eprint` fields biblatex and natbib use to
+    /// link the preprint
+    pub fn to_bibtex(&self) -> String {
+        let mut fields = vec![
+            ("author", self.authors.join(" and ")),
+            ("title", self.title.clone()),
+            ("year", self.year.clone()),
+            ("eprint", self.id.clone()),
+            ("archivePrefix", "arXiv".to_owned()),
+        ];
+        fields.extend(self.category.clone().map(|c| ("primaryClass", c)));
+        fields.push(("url", format!("https://arxiv.org/abs/{}", self.id)));
+        let mut bibtex = format!("@misc{{{},\n", key(&self.id));
+        for (name, value) in fields {
+            bibtex.push_str(&format!("  {:<13} = {{{}}},\n", name, value));
+        }
+        bibtex.push('}');
+        bibtex
+    }
+}
+
+#[cfg(test)]
+const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title type="html">ArXiv Query: id_list=2301.01234</title>
+  <entry>
+    <id>http://arxiv.org/abs/2301.01234v2</id>
+    <published>2023-01-03T17:00:00Z</published>
+    <title>Sketching Shortest
+      Paths</title>
+    <author><name>Jane Doe</name></author>
+    <author><name>John Roe</name></author>
+    <arxiv:primary_category xmlns:arxiv="http://arxiv.org/schemas/atom" term="cs.DS" scheme="http://arxiv.org/schemas/atom"/>
+  </entry>
+</feed>"#;
+
+#[test]
+fn test_parse_id() {
+    for (given, expected) in [
+        ("2301.01234", "2301.01234"),
+        ("arXiv:2301.01234v3", "2301.01234v3"),
+        ("https://arxiv.org/abs/2301.01234", "2301.01234"),
+        ("https://arxiv.org/pdf/2301.01234v1.pdf", "2301.01234v1"),
+        ("cs/0101012", "cs/0101012"),
+        ("arxiv:math.CO/0101012v2", "math.CO/0101012v2"),
+    ] {
+        assert_eq!(parse_id(given).unwrap(), expected, "{}", given);
+    }
+    for wrong in ["conf/soda/Chan16", "2301", "10.1145/3313276.3316350"] {
+        assert!(parse_id(wrong).is_err(), "{}", wrong);
+    }
+    assert_eq!(key("2301.01234v3"), "arXiv:2301.01234");
+    assert_eq!(key("cs/0101012"), "arXiv:cs/0101012");
+}
+
+#[test]
+fn test_parse_feed() {
+    let preprint = parse_feed(FEED, "2301.01234").unwrap();
+    assert_eq!(
+        preprint,
+        Preprint {
+            id: "2301.01234".into(),
+            title: "Sketching Shortest Paths".into(),
+            authors: vec!["Jane Doe".into(), "John Roe".into()],
+            year: "2023".into(),
+            category: Some("cs.DS".into()),
+        }
+    );
+    let bibtex = preprint.to_bibtex();
+    assert!(
+        bibtex.starts_with("@misc{arXiv:2301.01234,\n"),
+        "{}",
+        bibtex
+    );
+    assert!(bibtex.contains("  author        = {Jane Doe and John Roe},\n"));
+    assert!(bibtex.contains("  primaryClass  = {cs.DS},\n"));
+    assert!(biblatex::Bibliography::parse(&bibtex).is_ok());
+
+    let error = r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry>
+        <id>http://arxiv.org/api/errors#incorrect_id_format_for_2301</id>
+        <title>Error</title></entry></feed>"#;
+    let empty = r#"<feed xmlns="http://www.w3.org/2005/Atom"><title>x</title></feed>"#;
+    for feed in [error, empty] {
+        let err = parse_feed(feed, "2301.99999").unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DblpError>().map(|e| e.error_code()),
+            Some("unknown_arxiv")
+        );
+    }
+}
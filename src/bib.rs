@@ -0,0 +1,1148 @@
+/// utilities to work with the entries of a bibtex file
+use crate::error::{DblpError, Result};
+use biblatex::{Bibliography, ChunksExt, Entry, EntryType, RawBibliography};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ops::Range,
+    sync::LazyLock,
+};
+
+/// Fields that change without the entry meaningfully changing, and are thus
+/// ignored when fingerprinting.
+pub const VOLATILE_FIELDS: [&str; 1] = ["timestamp"];
+
+/// Computes a fingerprint of the entry that is insensitive to field order,
+/// whitespace, and braces, so that two entries differing only in formatting
+/// get the same fingerprint. The citation key is not part of the fingerprint.
+pub fn entry_fingerprint(entry: &Entry) -> String {
+    let mut canonical = entry_type_name(&entry.entry_type);
+    let mut fields: Vec<(String, String)> = entry
+        .fields
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_lowercase(),
+                normalize_value(&value.format_verbatim()),
+            )
+        })
+        .filter(|(name, _)| !VOLATILE_FIELDS.contains(&name.as_str()))
+        .collect();
+    fields.sort();
+    for (name, value) in fields {
+        canonical.push('\n');
+        canonical.push_str(&name);
+        canonical.push('=');
+        canonical.push_str(&value);
+    }
+    format!("{:016x}", fnv1a(canonical.as_bytes()))
+}
+
+/// The lowercase name of the type, which for unknown types is the one
+/// they were written with
+pub fn entry_type_name(entry_type: &EntryType) -> String {
+    match entry_type {
+        EntryType::Unknown(name) => name.to_lowercase(),
+        t => t.to_string(),
+    }
+}
+
+/// Aliases of entry types that biblatex does not know, besides the ones of
+/// the user, who can override them
+pub const DEFAULT_TYPE_ALIASES: [(&str, &str); 2] = [("webpage", "online"), ("website", "online")];
+
+/// The type with the given name, in any case, going through the aliases
+/// of the user if biblatex does not know it
+pub fn resolve_type(name: &str, aliases: &BTreeMap<String, String>) -> EntryType {
+    let name = name.to_lowercase();
+    let alias = aliases
+        .iter()
+        .find(|(alias, _)| alias.to_lowercase() == name)
+        .map(|(_, target)| target.as_str())
+        .or_else(|| {
+            DEFAULT_TYPE_ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, target)| *target)
+        });
+    match EntryType::new(&name) {
+        EntryType::Unknown(_) if alias.is_some() => EntryType::new(alias.unwrap_or_default()),
+        known => known,
+    }
+}
+
+pub fn normalize_value(value: &str) -> String {
+    value
+        .replace(['{', '}'], "")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// A short hash of the bytes, as hex
+pub fn hash_hex(bytes: &[u8]) -> String {
+    format!("{:016x}", fnv1a(bytes))
+}
+
+/// 64 bit FNV-1a, used instead of `DefaultHasher` because its output must
+/// be stable across runs and compiler versions.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Parses the first entry of the bibtex string into a hayagriva entry
+pub fn hayagriva_entry(bibtex: &str) -> Result<hayagriva::Entry> {
+    let invalid = |reason: String| DblpError::InvalidEntry { reason };
+    hayagriva::io::from_biblatex_str(bibtex)
+        .map_err(|e| invalid(format!("parsing bibtex: {}", e.first().unwrap())))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("expected a single entry, found 0".to_owned()))
+}
+
+/// A field of an entry, with the location of its value in the source
+#[derive(Debug, Clone)]
+pub struct FieldSpan {
+    pub key: String,
+    pub field: String,
+    /// the value as written in the source, including delimiters
+    pub value: Range<usize>,
+}
+
+/// Locates the value of every field of every entry in the source
+pub fn field_spans(src: &str) -> Result<Vec<FieldSpan>> {
+    let raw = RawBibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    Ok(raw
+        .entries
+        .iter()
+        .flat_map(|entry| {
+            entry.v.fields.iter().map(|pair| {
+                // the parser includes the whitespace following the value
+                let span = pair.value.span.clone();
+                let end = span.start + src[span.clone()].trim_end().len();
+                FieldSpan {
+                    key: entry.v.key.v.to_owned(),
+                    field: pair.key.v.to_lowercase(),
+                    value: span.start..end,
+                }
+            })
+        })
+        .collect())
+}
+
+/// Locates every entry in the source, from the `@` to the closing brace
+pub fn entry_spans(src: &str) -> Result<Vec<(String, Range<usize>)>> {
+    let raw = RawBibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    Ok(raw
+        .entries
+        .iter()
+        .map(|entry| {
+            // the parser stops right before the closing brace
+            let end = src[entry.span.end..]
+                .find('}')
+                .map_or(src.len(), |i| entry.span.end + i + 1);
+            (entry.v.key.v.to_owned(), entry.span.start..end)
+        })
+        .collect())
+}
+
+/// Default size, in bytes, beyond which an entry is considered oversized
+pub const MAX_ENTRY_SIZE: usize = 100_000;
+
+/// The entries larger than `limit` bytes, with their size
+pub fn oversized_entries(src: &str, limit: usize) -> Result<Vec<(String, usize)>> {
+    Ok(entry_spans(src)?
+        .into_iter()
+        .filter(|(_, range)| range.len() > limit)
+        .map(|(key, range)| (key, range.len()))
+        .collect())
+}
+
+/// Formats a size in bytes for humans, like `2.1 MB`
+pub fn human_size(bytes: usize) -> String {
+    match bytes {
+        b if b >= 1_000_000 => format!("{:.1} MB", b as f64 / 1e6),
+        b if b >= 1_000 => format!("{:.1} KB", b as f64 / 1e3),
+        b => format!("{} B", b),
+    }
+}
+
+/// The key of an entry, with its text in the source
+pub type SourceEntry<'a> = (String, &'a str);
+
+/// Splits the source into its entries, for sources like DBLP records that
+/// may contain more than one entry
+pub fn split_entries(src: &str) -> Result<Vec<SourceEntry<'_>>> {
+    Ok(entry_spans(src)?
+        .into_iter()
+        .map(|(key, range)| (key, &src[range]))
+        .collect())
+}
+
+/// Whether a bib file has an entry
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum Presence {
+    NotPresent,
+    /// under this key, which is another one if the entry is the DBLP record
+    /// of the key looked for, told by its `biburl`
+    PresentAs(String),
+}
+
+/// The headers of entries, like `@inproceedings{key,`, with their key
+static HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\s*@\s*\w+\s*[{(]\s*([^,\s]+)\s*,").unwrap());
+
+static BIBURL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bbiburl\s*=\s*[{"]\s*([^}"\s]+)"#).unwrap());
+
+/// Whether the source has the entry with the key or, for a DBLP key, the
+/// entry of the same DBLP record. Keys are compared exactly, reading the
+/// headers of the entries if the source does not parse.
+pub fn presence(src: &str, key: &str) -> Presence {
+    // the biburl of DBLP entries is like https://dblp.org/rec/conf/soda/Chan16.bib
+    let record = key
+        .strip_prefix("DBLP:")
+        .map(|record| format!("/rec/{}.bib", record));
+    let is_record = |biburl: &str| {
+        record
+            .as_ref()
+            .is_some_and(|r| biburl.ends_with(r.as_str()))
+    };
+    let entries: Vec<(String, Option<String>)> = match Bibliography::parse(src) {
+        Ok(bibliography) => bibliography
+            .iter()
+            .map(|entry| {
+                let biburl = entry
+                    .fields
+                    .get("biburl")
+                    .map(|v| normalize_value(&v.format_verbatim()));
+                (entry.key.clone(), biburl)
+            })
+            .collect(),
+        Err(_) => {
+            let headers: Vec<_> = HEADER.captures_iter(src).collect();
+            headers
+                .iter()
+                .enumerate()
+                .map(|(i, header)| {
+                    let start = header.get(0).map_or(0, |m| m.end());
+                    let end = headers
+                        .get(i + 1)
+                        .and_then(|next| next.get(0))
+                        .map_or(src.len(), |m| m.start());
+                    let biburl = BIBURL.captures(&src[start..end]).map(|c| c[1].to_owned());
+                    (header[1].to_owned(), biburl)
+                })
+                .collect()
+        }
+    };
+    if entries.iter().any(|(k, _)| k == key) {
+        return Presence::PresentAs(key.to_owned());
+    }
+    entries
+        .into_iter()
+        .find(|(_, biburl)| biburl.as_deref().is_some_and(is_record))
+        .map_or(Presence::NotPresent, |(k, _)| Presence::PresentAs(k))
+}
+
+/// Whether the source has the entry with the key, or the one of the same
+/// DBLP record, as told by `presence`
+pub fn is_present(src: &str, key: &str) -> bool {
+    presence(src, key) != Presence::NotPresent
+}
+
+/// Splits the entries of `fetched` into the ones missing from `existing`,
+/// and the keys of the ones already there
+pub fn partition_present<'a>(
+    existing: &str,
+    fetched: &'a str,
+) -> Result<(Vec<SourceEntry<'a>>, Vec<String>)> {
+    let keys: HashSet<String> = entry_spans(existing)
+        .map(|spans| spans.into_iter().map(|(key, _)| key).collect())
+        .unwrap_or_default();
+    let (present, missing): (Vec<_>, Vec<_>) = split_entries(fetched)?
+        .into_iter()
+        .partition(|(key, _)| keys.contains(key));
+    Ok((missing, present.into_iter().map(|(key, _)| key).collect()))
+}
+
+/// The edit adding the given fields at the end of the entry spanning the range
+pub fn add_fields(
+    src: &str,
+    entry: &Range<usize>,
+    fields: &[(&str, &str)],
+) -> (Range<usize>, String) {
+    let body = src[entry.start..entry.end - 1].trim_end();
+    let insert_at = entry.start + body.len();
+    let mut text = String::new();
+    if !body.ends_with(',') {
+        text.push(',');
+    }
+    for (name, value) in fields {
+        text.push_str(&format!("\n  {} = {{{}}},", name, value));
+    }
+    text.push('\n');
+    (insert_at..entry.end - 1, text)
+}
+
+/// A range of the source, with the text replacing it
+pub type Edit = (Range<usize>, String);
+
+/// Replaces the given ranges of the source, which must not overlap
+pub fn splice(src: &str, mut edits: Vec<Edit>) -> String {
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut out = String::with_capacity(src.len());
+    let mut last = 0;
+    for (range, text) in edits {
+        out.push_str(&src[last..range.start]);
+        out.push_str(&text);
+        last = range.end;
+    }
+    out.push_str(&src[last..]);
+    out
+}
+
+/// The edit removing the entry spanning the range, along with the blank
+/// lines after it
+pub fn removal(src: &str, entry: &Range<usize>) -> (Range<usize>, String) {
+    let rest = &src[entry.end..];
+    let blank = rest.len() - rest.trim_start_matches(['\r', '\n']).len();
+    (entry.start..entry.end + blank, String::new())
+}
+
+/// The source without the entries with the given keys, and with the rest
+/// of it as it was
+pub fn remove_entries(src: &str, keys: &[String]) -> Result<String> {
+    let edits = entry_spans(src)?
+        .into_iter()
+        .filter(|(key, _)| keys.contains(key))
+        .map(|(_, range)| removal(src, &range))
+        .collect();
+    let removed = splice(src, edits);
+    // the last entries leave the blank lines before them, unlike the others
+    let ending = &src[src.trim_end().len()..];
+    Ok(format!("{}{}", removed.trim_end(), ending))
+}
+
+/// Rewrites the source with the entries sorted by the given rank, keeping
+/// entries of equal rank in their current order. Text between two entries,
+/// like comments, moves along with the entry that follows it, except for
+/// `@string` and `@preamble` commands, which move to the top since the
+/// entries after them may use them.
+pub fn sort_entries<K: Ord>(src: &str, mut rank: impl FnMut(&str) -> K) -> Result<String> {
+    let spans = entry_spans(src)?;
+    let (first, last) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => (first.1.start, last.1.end),
+        _ => return Ok(src.to_owned()),
+    };
+    let mut commands: Vec<&str> = Vec::new();
+    let mut chunks: Vec<(&str, &str)> = Vec::with_capacity(spans.len());
+    let mut prev = first;
+    for (key, range) in spans.iter() {
+        let start = prev + commands_end(&src[prev..range.start]);
+        commands.extend(Some(src[prev..start].trim()).filter(|text| !text.is_empty()));
+        chunks.push((key, src[start..range.end].trim()));
+        prev = range.end;
+    }
+    chunks.sort_by_cached_key(|(key, _)| rank(key));
+    let mut out = src[..first].to_owned();
+    for text in commands.into_iter().chain(chunks.into_iter().map(|c| c.1)) {
+        out.push_str(text);
+        out.push_str("\n\n");
+    }
+    out.truncate(out.len() - 1);
+    out.push_str(src[last..].trim_start());
+    Ok(out)
+}
+
+/// The source with the text inserted before the entry with the given key,
+/// and before the text between it and the previous entry, which goes with
+/// it like when sorting
+pub fn insert_before(src: &str, key: &str, text: &str) -> Result<String> {
+    let spans = entry_spans(src)?;
+    let i = spans
+        .iter()
+        .position(|(k, _)| k == key)
+        .ok_or_else(|| no_entry(key))?;
+    Ok(match i.checked_sub(1) {
+        Some(previous) => {
+            let at = spans[previous].1.end;
+            format!("{}\n\n{}{}", &src[..at], text.trim(), &src[at..])
+        }
+        None => {
+            let at = spans[i].1.start;
+            format!("{}{}\n\n{}", &src[..at], text.trim(), &src[at..])
+        }
+    })
+}
+
+/// Fields DBLP adds about its own records, which change without the entry
+/// changing
+pub const DBLP_FIELDS: [&str; 3] = ["timestamp", "biburl", "bibsource"];
+
+/// The source without the given fields in any of its entries. A field on a
+/// line of its own goes with its line.
+pub fn strip_fields(src: &str, fields: &[String]) -> Result<String> {
+    if fields.is_empty() {
+        return Ok(src.to_owned());
+    }
+    let raw = RawBibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    let edits = raw
+        .entries
+        .iter()
+        .flat_map(|entry| entry.v.fields.iter())
+        .filter(|pair| fields.iter().any(|f| f.eq_ignore_ascii_case(pair.key.v)))
+        .map(|pair| {
+            let mut start = pair.key.span.start;
+            // the parser includes the whitespace following the value
+            let value = pair.value.span.clone();
+            let mut end = value.start + src[value].trim_end().len();
+            end += src[end..].starts_with(',') as usize;
+            end = src.len() - src[end..].trim_start_matches([' ', '\t']).len();
+            let indent = src[..start].trim_end_matches([' ', '\t']);
+            let eol = ["\r\n", "\n"]
+                .into_iter()
+                .find(|eol| src[end..].starts_with(eol));
+            if let (true, Some(eol)) = (indent.ends_with('\n'), eol) {
+                start = indent.len();
+                end += eol.len();
+            }
+            (start..end, String::new())
+        })
+        .collect();
+    Ok(splice(src, edits))
+}
+
+/// Where the last `@string` or `@preamble` command of the text between two
+/// entries ends, or 0 if there is none
+fn commands_end(text: &str) -> usize {
+    let lower = text.to_ascii_lowercase();
+    let Some(start) = ["@string", "@preamble"]
+        .iter()
+        .filter_map(|command| lower.rfind(command))
+        .max()
+    else {
+        return 0;
+    };
+    closing(&text[start..]).map_or(text.len(), |end| start + end)
+}
+
+/// Where the first bracket of the text is closed, right after the closing
+/// bracket, if it is
+pub fn closing(text: &str) -> Option<usize> {
+    let open = text.find(['{', '('])?;
+    // only the kind of bracket that opens the command closes it
+    let (opening, closing) = match &text[open..open + 1] {
+        "{" => ('{', '}'),
+        _ => ('(', ')'),
+    };
+    let mut depth = 0;
+    for (i, c) in text[open..].char_indices() {
+        if c == opening {
+            depth += 1;
+        } else if c == closing {
+            depth -= 1;
+            if depth == 0 {
+                return Some(open + i + 1);
+            }
+        }
+    }
+    None
+}
+
+/// The title of the entry as written, empty if it has none
+pub fn title(entry: &Entry) -> String {
+    entry
+        .title()
+        .map(|t| t.format_verbatim())
+        .unwrap_or_default()
+}
+
+/// The year of the entry, from its `year` field or else from its `date`
+pub fn year(entry: &Entry) -> Option<u32> {
+    let value = entry.fields.get("year").or(entry.fields.get("date"))?;
+    let year: String = normalize_value(&value.format_verbatim())
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    year.parse().ok()
+}
+
+/// The surname and given names of the first author of the entry, without
+/// markup and in lowercase, to sort entries by
+pub fn first_author(entry: &Entry) -> Option<String> {
+    let author = entry.author().ok()?.into_iter().next()?;
+    let name = format!(
+        "{} {}",
+        crate::keygen::strip(&author.name),
+        crate::keygen::strip(&author.given_name)
+    );
+    Some(name.trim().to_lowercase())
+}
+
+/// Fields added by hand that refreshing an entry must never lose
+pub const PRESERVE_FIELDS: [&str; 5] = ["keywords", "options", "note", "file", "annotation"];
+
+/// Carries the preserved fields of the entry `local_key` of the local source
+/// into the entry `fetched_key` of the fetched source, where they replace
+/// any fetched values
+pub fn carry_preserved(
+    local: &str,
+    local_key: &str,
+    fetched: &str,
+    fetched_key: &str,
+) -> Result<String> {
+    let preserved: Vec<FieldSpan> = field_spans(local)?
+        .into_iter()
+        .filter(|s| s.key == local_key && PRESERVE_FIELDS.contains(&s.field.as_str()))
+        .collect();
+    let entry = match entry_spans(fetched)?
+        .into_iter()
+        .find(|(key, _)| key == fetched_key)
+    {
+        Some((_, range)) if !preserved.is_empty() => range,
+        _ => return Ok(fetched.to_owned()),
+    };
+    let fetched_spans = field_spans(fetched)?;
+    let mut edits = Vec::new();
+    let mut added = Vec::new();
+    for span in preserved.iter() {
+        let value = &local[span.value.clone()];
+        match fetched_spans
+            .iter()
+            .find(|f| f.key == fetched_key && f.field == span.field)
+        {
+            Some(f) => edits.push((f.value.clone(), value.to_owned())),
+            None => added.push((span.field.as_str(), strip_delimiters(value))),
+        }
+    }
+    if !added.is_empty() {
+        edits.push(add_fields(fetched, &entry, &added));
+    }
+    Ok(splice(fetched, edits))
+}
+
+/// Like `carry_preserved`, also adding the other fields of the local entry
+/// that the fetched one lacks, like an abstract. The fetched values win for
+/// the fields both have.
+pub fn merge_local(
+    local: &str,
+    local_key: &str,
+    fetched: &str,
+    fetched_key: &str,
+) -> Result<String> {
+    let carried = carry_preserved(local, local_key, fetched, fetched_key)?;
+    let Some((_, entry)) = entry_spans(&carried)?
+        .into_iter()
+        .find(|(key, _)| key == fetched_key)
+    else {
+        return Ok(carried);
+    };
+    let present: HashSet<String> = field_spans(&carried)?
+        .into_iter()
+        .filter(|s| s.key == fetched_key)
+        .map(|s| s.field)
+        .collect();
+    let local_spans = field_spans(local)?;
+    let missing: Vec<(&str, &str)> = local_spans
+        .iter()
+        .filter(|s| s.key == local_key && !present.contains(&s.field))
+        .map(|s| (s.field.as_str(), strip_delimiters(&local[s.value.clone()])))
+        .collect();
+    if missing.is_empty() {
+        return Ok(carried);
+    }
+    let edit = add_fields(&carried, &entry, &missing);
+    Ok(splice(&carried, vec![edit]))
+}
+
+/// The value of a field without the braces or quotes around it
+fn strip_delimiters(value: &str) -> &str {
+    value
+        .strip_prefix('{')
+        .and_then(|v| v.strip_suffix('}'))
+        .or_else(|| value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+fn parse_single(src: &str) -> Entry {
+    biblatex::Bibliography::parse(src)
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap()
+}
+
+/// The key of the entry of the text, failing unless the text is a single
+/// entry with a valid key
+pub fn single_entry(text: &str) -> Result<String> {
+    let bibliography = Bibliography::parse(text).map_err(|e| DblpError::from_bib(e, text))?;
+    let entry = match bibliography.iter().collect::<Vec<_>>().as_slice() {
+        [entry] => *entry,
+        entries => {
+            return Err(DblpError::InvalidEntry {
+                reason: format!("expected a single entry, found {}", entries.len()),
+            })
+        }
+    };
+    check_key(&entry.key)?;
+    Ok(entry.key.clone())
+}
+
+/// Renames the entry with the given key, leaving the rest as it is
+pub fn rename_entry(src: &str, key: &str, new_key: &str) -> Result<String> {
+    let (_, range) = entry_spans(src)?
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .ok_or_else(|| no_entry(key))?;
+    let malformed = || DblpError::InvalidEntry {
+        reason: format!("malformed entry {}", key),
+    };
+    let open = src[range.clone()].find(['{', '(']).ok_or_else(malformed)?;
+    let start = range.start + open + 1;
+    let start = start + src[start..].find(key).ok_or_else(malformed)?;
+    Ok(splice(
+        src,
+        vec![(start..start + key.len(), new_key.to_owned())],
+    ))
+}
+
+/// Fails unless the key can be the key of a bibtex entry
+pub fn check_key(key: &str) -> Result<()> {
+    let wrong = key
+        .chars()
+        .find(|c| c.is_whitespace() || ",{}()\"#%'=\\~".contains(*c));
+    let reason = match wrong {
+        _ if key.is_empty() => "the key cannot be empty".to_owned(),
+        Some(c) => format!("the key {:?} cannot contain {:?}", key, c),
+        None => return Ok(()),
+    };
+    Err(DblpError::InvalidEntry { reason })
+}
+
+/// Replaces the entry with the given key by the text, leaving the rest as
+/// it is
+pub fn replace_entry(src: &str, key: &str, text: &str) -> Result<String> {
+    let (_, range) = entry_spans(src)?
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .ok_or_else(|| no_entry(key))?;
+    Ok(splice(src, vec![(range, text.to_owned())]))
+}
+
+fn no_entry(key: &str) -> DblpError {
+    DblpError::NoEntry {
+        key: key.to_owned(),
+    }
+}
+
+/// The content of a bib file with the entries appended, each after exactly
+/// one blank line, whatever the file ends with and whatever whitespace the
+/// entries come with, and a blank line at the end
+pub fn append(src: &str, entries: &[String]) -> String {
+    let mut out = src.trim_end().to_owned();
+    for text in entries.iter().map(|text| text.trim()) {
+        if !out.is_empty() {
+            out.push_str("\n\n");
+        }
+        out.push_str(text);
+    }
+    if !out.is_empty() {
+        out.push_str("\n\n");
+    }
+    out
+}
+
+#[test]
+fn test_entry_fingerprint() {
+    let orig = parse_single(
+        "@inproceedings{DBLP:conf/soda/Foo21,
+          author    = {Foo Bar and Baz Qux},
+          title     = {{A Title}},
+          booktitle = {{SODA}},
+          pages     = {101--110},
+          year      = {2021},
+          timestamp = {Mon, 01 Jan 2021 10:00:00 +0100}
+        }",
+    );
+    let fp = entry_fingerprint(&orig);
+
+    let reserialized = parse_single(&orig.to_bibtex_string().unwrap());
+    assert_eq!(fp, entry_fingerprint(&reserialized));
+
+    let reformatted = parse_single(
+        "@InProceedings{DBLP:conf/soda/Foo21,
+        year = 2021,
+        pages = {101--110},
+        title = {A   Title},
+        author = {Foo Bar and
+                  Baz Qux},
+        booktitle = {SODA},
+        timestamp = {Tue, 02 Feb 2021 10:00:00 +0100},
+        }",
+    );
+    assert_eq!(fp, entry_fingerprint(&reformatted));
+
+    let changed = parse_single(
+        "@inproceedings{DBLP:conf/soda/Foo21,
+          author    = {Foo Bar and Baz Qux},
+          title     = {{A Title}},
+          booktitle = {{SODA}},
+          pages     = {101--112},
+          year      = {2021}
+        }",
+    );
+    assert_ne!(fp, entry_fingerprint(&changed));
+}
+
+#[test]
+fn test_splice_field() {
+    let src = "@inproceedings{a,\n  booktitle = {STOC} ,\n  year = 2019\n}\n";
+    let span = field_spans(src)
+        .unwrap()
+        .into_iter()
+        .find(|f| f.field == "booktitle")
+        .unwrap();
+    assert_eq!(&src[span.value.clone()], "{STOC}");
+    assert_eq!(
+        splice(src, vec![(span.value, "{FOCS}".to_owned())]),
+        "@inproceedings{a,\n  booktitle = {FOCS} ,\n  year = 2019\n}\n"
+    );
+}
+
+#[test]
+fn test_add_fields() {
+    let src = "@misc{a,\n  title = {A}\n}\n\n@misc{b, title = {B},}";
+    let spans = entry_spans(src).unwrap();
+    assert_eq!(&src[spans[1].1.clone()], "@misc{b, title = {B},}");
+    let edits = spans
+        .iter()
+        .map(|(_, range)| add_fields(src, range, &[("related", "c")]))
+        .collect();
+    assert_eq!(
+        splice(src, edits),
+        "@misc{a,\n  title = {A},\n  related = {c},\n}\n\n@misc{b, title = {B},\n  related = {c},\n}"
+    );
+}
+
+#[test]
+fn test_partition_present() {
+    let paper = "@inproceedings{DBLP:conf/soda/Foo21,\n  crossref = {DBLP:conf/soda/2021},\n  title = {Foo}\n}";
+    let proceedings = "@proceedings{DBLP:conf/soda/2021,\n  title = {SODA 2021}\n}";
+    let single = format!("{}\n", paper);
+    let double = format!("{}\n\n{}\n", paper, proceedings);
+
+    let (missing, present) = partition_present("", &single).unwrap();
+    assert_eq!(missing, vec![("DBLP:conf/soda/Foo21".to_owned(), paper)]);
+    assert!(present.is_empty());
+
+    let (missing, present) = partition_present("", &double).unwrap();
+    assert_eq!(missing.len(), 2);
+    assert_eq!(missing[1], ("DBLP:conf/soda/2021".to_owned(), proceedings));
+    assert!(present.is_empty());
+
+    let existing = "@inproceedings{DBLP:conf/soda/Bar21,\n  crossref = {DBLP:conf/soda/2021}\n}\n\n@proceedings{DBLP:conf/soda/2021,\n  title = {SODA 2021}\n}\n";
+    let (missing, present) = partition_present(existing, &double).unwrap();
+    assert_eq!(missing, vec![("DBLP:conf/soda/Foo21".to_owned(), paper)]);
+    assert_eq!(present, vec!["DBLP:conf/soda/2021"]);
+}
+
+#[test]
+fn test_presence() {
+    let key = "DBLP:conf/soda/Chan16";
+    let present = |src: &str| presence(src, key);
+    assert_eq!(present(""), Presence::NotPresent);
+    assert_eq!(
+        present("@inproceedings{\n  DBLP:conf/soda/Chan16 ,\n  title = {A}\n}\n"),
+        Presence::PresentAs(key.into())
+    );
+    // neither in comments, nor as a part of another key
+    assert_eq!(
+        present("% see DBLP:conf/soda/Chan16\n@misc{DBLP:conf/soda/Chan16a, title = {A}}\n"),
+        Presence::NotPresent
+    );
+    // the same record, under another key
+    let mine = "@inproceedings{chan16,\n  title = {A},\n  biburl = {https://dblp.org/rec/conf/soda/Chan16.bib}\n}\n";
+    assert_eq!(present(mine), Presence::PresentAs("chan16".into()));
+    assert_eq!(
+        presence(mine, "chan16"),
+        Presence::PresentAs("chan16".into())
+    );
+    assert_eq!(presence(mine, "chan"), Presence::NotPresent);
+    assert!(is_present(mine, key));
+    assert!(!is_present(mine, "chan"));
+    // also when the file does not parse
+    let broken = format!("{}\n@misc{{broken, title = {{Open}}\n", mine);
+    assert!(Bibliography::parse(&broken).is_err());
+    assert_eq!(present(&broken), Presence::PresentAs("chan16".into()));
+    assert_eq!(
+        presence(&broken, "broken"),
+        Presence::PresentAs("broken".into())
+    );
+    assert_eq!(presence(&broken, "Open"), Presence::NotPresent);
+}
+
+#[test]
+fn test_carry_preserved() {
+    let local = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {Old},\n  keywords = {mine},\n  options = \"skipbib\",\n  note = {{Best} paper},\n  timestamp = {1}\n}\n";
+    let fetched = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {New},\n  note = {fetched},\n  timestamp = {2}\n}\n";
+    let carried = carry_preserved(
+        local,
+        "DBLP:conf/soda/Chan16",
+        fetched,
+        "DBLP:conf/soda/Chan16",
+    )
+    .unwrap();
+    let entry = parse_single(&carried);
+    for (field, value) in [
+        ("title", "New"),
+        ("keywords", "mine"),
+        ("options", "skipbib"),
+        ("note", "Best paper"),
+        ("timestamp", "2"),
+    ] {
+        assert_eq!(
+            entry.get(field).map(|v| v.format_verbatim()).as_deref(),
+            Some(value),
+            "{}",
+            field
+        );
+    }
+    assert!(carried.contains("note = {{Best} paper}"));
+    // replacing with another entry, like a journal version
+    let journal = fetched.replace("conf/soda", "journals/talg");
+    let carried = carry_preserved(
+        local,
+        "DBLP:conf/soda/Chan16",
+        &journal,
+        "DBLP:journals/talg/Chan16",
+    )
+    .unwrap();
+    assert!(carried.contains("keywords = {mine}"));
+    let untouched = carry_preserved(
+        "@misc{a, title = {A}}",
+        "a",
+        fetched,
+        "DBLP:conf/soda/Chan16",
+    )
+    .unwrap();
+    assert_eq!(untouched, fetched);
+}
+
+#[test]
+fn test_merge_local() {
+    let local = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {My Title},\n  abstract = {We show {APSP} faster.},\n  keywords = {mine},\n  annote = \"read\"\n}\n";
+    let fetched = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {All-Pairs Shortest Paths},\n  year = {2016},\n}\n";
+    let merged = merge_local(
+        local,
+        "DBLP:conf/soda/Chan16",
+        fetched,
+        "DBLP:conf/soda/Chan16",
+    )
+    .unwrap();
+    let entry = parse_single(&merged);
+    for (field, value) in [
+        ("title", "All-Pairs Shortest Paths"),
+        ("year", "2016"),
+        ("abstract", "We show APSP faster."),
+        ("keywords", "mine"),
+        ("annote", "read"),
+    ] {
+        assert_eq!(
+            entry.get(field).map(|v| v.format_verbatim()).as_deref(),
+            Some(value),
+            "{}",
+            field
+        );
+    }
+    assert_eq!(merged.matches("title =").count(), 1, "{}", merged);
+    assert!(
+        merged.contains("abstract = {We show {APSP} faster.}"),
+        "{}",
+        merged
+    );
+    // nothing to merge
+    let same = merge_local(
+        fetched,
+        "DBLP:conf/soda/Chan16",
+        fetched,
+        "DBLP:conf/soda/Chan16",
+    );
+    assert_eq!(same.unwrap(), fetched);
+}
+
+#[test]
+fn test_remove_entries() {
+    let src = "% refs\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n% about c\n@misc{c, title = {C}}\n";
+    let remove = |keys: &[&str]| {
+        let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+        remove_entries(src, &keys).unwrap()
+    };
+    assert_eq!(
+        remove(&["a"]),
+        "% refs\n@misc{b, title = {B}}\n% about c\n@misc{c, title = {C}}\n"
+    );
+    assert_eq!(
+        remove(&["b", "c"]),
+        "% refs\n@misc{a, title = {A}}\n\n% about c\n"
+    );
+    assert_eq!(remove(&["missing"]), src);
+    assert_eq!(
+        remove(&["c"]),
+        "% refs\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n% about c\n"
+    );
+    let crlf = "@misc{a, title = {A}}\r\n\r\n@misc{b, title = {B}}\r\n";
+    assert_eq!(
+        remove_entries(crlf, &["b".to_owned()]).unwrap(),
+        "@misc{a, title = {A}}\r\n"
+    );
+    assert_eq!(
+        remove_entries("@misc{a, title = {A}}", &["a".to_owned()]).unwrap(),
+        ""
+    );
+}
+
+#[test]
+fn test_strip_fields() {
+    let strip = |src: &str| {
+        let fields: Vec<String> = DBLP_FIELDS.iter().map(|f| f.to_string()).collect();
+        strip_fields(src, &fields).unwrap()
+    };
+    let src = "@book{DBLP:books/aw/Knuth97,\n  title        = {The Art of Computer Programming},\n  timestamp    = {Wed, 07 Jun 2017 14:42:54 +0200},\n  year         = {1997},\n  biburl       = {https://dblp.org/rec/books/aw/Knuth97.bib},\n  bibsource    = {dblp computer science bibliography, https://dblp.org}\n}\n";
+    assert_eq!(
+        strip(src),
+        "@book{DBLP:books/aw/Knuth97,\n  title        = {The Art of Computer Programming},\n  year         = {1997},\n}\n"
+    );
+    // values spanning lines, and fields sharing a line
+    assert_eq!(
+        strip("@misc{a,\r\n  BibSource = {dblp computer\r\n     science},\r\n  title = {A}\r\n}"),
+        "@misc{a,\r\n  title = {A}\r\n}"
+    );
+    assert_eq!(
+        strip("@misc{a, timestamp = {x}, title = {A}, biburl = \"y\"}"),
+        "@misc{a, title = {A}, }"
+    );
+    assert_eq!(strip("@misc{a, title = {A}}"), "@misc{a, title = {A}}");
+}
+
+#[test]
+fn test_replace_entry() {
+    let src = "% refs\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n";
+    assert_eq!(
+        replace_entry(src, "a", "@misc{c, title = {C}}").unwrap(),
+        "% refs\n@misc{c, title = {C}}\n\n@misc{b, title = {B}}\n"
+    );
+    assert!(replace_entry(src, "c", "").is_err());
+}
+
+#[test]
+fn test_sort_entries() {
+    let src = "% refs\n@misc{a, title = {A}}\n% about b\n@misc{b, title = {B}}\n@misc{c, title = {C}}\n% end\n";
+    let rank = |key: &str| match key {
+        "c" => 0,
+        "a" => 1,
+        _ => 2,
+    };
+    assert_eq!(
+        sort_entries(src, rank).unwrap(),
+        "% refs\n@misc{c, title = {C}}\n\n@misc{a, title = {A}}\n\n% about b\n@misc{b, title = {B}}\n% end\n"
+    );
+    let src = "@misc{b, title = {B}}\n\n@String{s = {Some (Journal}}\n\n% about a\n@misc{a, title = {A}}\n\n@preamble{\"x\"}\n@misc{c, title = s}\n";
+    assert_eq!(
+        sort_entries(src, |key: &str| key.to_owned()).unwrap(),
+        "@String{s = {Some (Journal}}\n\n@preamble{\"x\"}\n\n% about a\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n\n@misc{c, title = s}\n"
+    );
+}
+
+#[test]
+fn test_year_and_first_author() {
+    let src = "@misc{a, author = {Jos{\\'e} de la Cruz and B. Other}, year = {2016}}\n\
+        @misc{b, author = {Doe, Jane}, date = {2019-05-01}}\n@misc{c, title = {C}}";
+    let bibliography = biblatex::Bibliography::parse(src).unwrap();
+    let entries: Vec<&Entry> = bibliography.iter().collect();
+    assert_eq!(
+        entries.iter().map(|e| year(e)).collect::<Vec<_>>(),
+        [Some(2016), Some(2019), None]
+    );
+    assert_eq!(
+        entries.iter().map(|e| first_author(e)).collect::<Vec<_>>(),
+        [
+            Some("cruz jose".to_owned()),
+            Some("doe jane".to_owned()),
+            None
+        ]
+    );
+}
+
+#[cfg(test)]
+mod alloc_count {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    thread_local! {
+        static LARGEST: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Keeps track of the largest allocation made by each thread
+    struct Counting;
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = LARGEST.try_with(|l| l.set(l.get().max(layout.size())));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    /// The largest allocation made by `f`
+    pub fn largest_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        LARGEST.with(|l| l.set(0));
+        let res = f();
+        (res, LARGEST.with(|l| l.get()))
+    }
+}
+
+#[test]
+fn test_huge_entry() {
+    let abstract_text = "lorem ipsum ".repeat(200_000);
+    let src = format!(
+        "@misc{{small, title = {{Small}}}}\n\n@misc{{huge,\n  title = {{Huge}},\n  abstract = {{{}}}\n}}\n",
+        abstract_text
+    );
+    let (oversized, largest) =
+        alloc_count::largest_allocation(|| oversized_entries(&src, MAX_ENTRY_SIZE).unwrap());
+    assert_eq!(oversized.len(), 1);
+    assert_eq!(oversized[0].0, "huge");
+    assert_eq!(human_size(oversized[0].1), "2.4 MB");
+    // the text of the entries is never copied
+    assert!(largest < abstract_text.len(), "allocated {} bytes", largest);
+}
+
+#[test]
+fn test_append() {
+    let entries = ["@misc{b}".to_owned(), "\n@misc{c}\n\n\n".to_owned()];
+    let appended = "@misc{a}\n\n@misc{b}\n\n@misc{c}\n\n";
+    for src in [
+        "@misc{a}",
+        "@misc{a}\n",
+        "@misc{a}\n\n",
+        "@misc{a}\n\n\n\n",
+        "@misc{a} \n\t\n",
+    ] {
+        assert_eq!(append(src, &entries), appended, "{:?}", src);
+    }
+    assert_eq!(append("", &entries), "@misc{b}\n\n@misc{c}\n\n");
+    assert_eq!(append("\n\n", &entries[..1]), "@misc{b}\n\n");
+    assert_eq!(
+        append("% header\n", &entries[..1]),
+        "% header\n\n@misc{b}\n\n"
+    );
+    assert_eq!(append("", &[]), "");
+    // the result parses back to all the entries
+    let entries = ["@misc{b, title = {B}}\n".to_owned()];
+    let parsed = Bibliography::parse(&append("@misc{a, title = {A}}", &entries)).unwrap();
+    assert_eq!(parsed.len(), 2);
+}
+
+#[test]
+fn test_rename_entry() {
+    let src = "@misc{DBLP:a, crossref = {DBLP:b}}\n\n@proceedings{ DBLP:b,\n  title = {DBLP:b}}\n";
+    assert_eq!(
+        rename_entry(src, "DBLP:b", "proc").unwrap(),
+        "@misc{DBLP:a, crossref = {DBLP:b}}\n\n@proceedings{ proc,\n  title = {DBLP:b}}\n"
+    );
+    assert_eq!(
+        rename_entry(src, "DBLP:a", "a").unwrap(),
+        "@misc{a, crossref = {DBLP:b}}\n\n@proceedings{ DBLP:b,\n  title = {DBLP:b}}\n"
+    );
+    let err = rename_entry(src, "DBLP:c", "c").unwrap_err();
+    assert_eq!(err.error_code(), "no_entry");
+    assert_eq!(err.to_string(), "there is no entry DBLP:c");
+}
+
+#[test]
+fn test_single_entry() {
+    let chan = include_str!("../tests/fixtures/Chan16.bib");
+    assert_eq!(single_entry(chan).unwrap(), "DBLP:conf/soda/Chan16");
+    assert_eq!(single_entry("@misc{a, title = {A}}").unwrap(), "a");
+    let err = |text| single_entry(text).unwrap_err().to_string();
+    assert_eq!(err(""), "expected a single entry, found 0");
+    assert_eq!(
+        err("@misc{a, title = {A}}\n@misc{b, title = {B}}"),
+        "expected a single entry, found 2"
+    );
+    assert!(single_entry("@misc{a, title = {A}").is_err());
+}
+
+#[test]
+fn test_insert_before() {
+    let src = "% refs\n@misc{b, title = {B}}\n\n% about d\n@misc{d, title = {D}}\n";
+    assert_eq!(
+        insert_before(src, "b", "@misc{a, title = {A}}").unwrap(),
+        "% refs\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n\n% about d\n@misc{d, title = {D}}\n"
+    );
+    assert_eq!(
+        insert_before(src, "d", "@misc{c, title = {C}}").unwrap(),
+        "% refs\n@misc{b, title = {B}}\n\n@misc{c, title = {C}}\n\n% about d\n@misc{d, title = {D}}\n"
+    );
+    assert!(insert_before(src, "e", "@misc{e}").is_err());
+}
+
+#[test]
+fn test_check_key() {
+    for key in [
+        "chan2016apsp",
+        "DBLP:conf/soda/Chan16",
+        "doi:10.1145/3313276.3316350",
+    ] {
+        assert!(check_key(key).is_ok(), "{}", key);
+    }
+    for key in ["", "chan 2016", "chan,2016", "chan{16}", "50%"] {
+        let err = check_key(key).unwrap_err();
+        assert_eq!(err.error_code(), "invalid_entry", "{}", key);
+    }
+}
+
+#[test]
+fn test_resolve_type() {
+    let aliases = BTreeMap::from([
+        ("BlogPost".to_owned(), "online".to_owned()),
+        ("webpage".to_owned(), "misc".to_owned()),
+    ]);
+    for (name, expected) in [
+        ("inproceedings", EntryType::InProceedings),
+        ("InProceedings", EntryType::InProceedings),
+        ("INPROCEEDINGS", EntryType::InProceedings),
+        ("Electronic", EntryType::Online),
+        ("blogpost", EntryType::Online),
+        // the aliases of the user win over the default ones
+        ("WebPage", EntryType::Misc),
+        ("website", EntryType::Online),
+        ("onlne", EntryType::Unknown("onlne".to_owned())),
+    ] {
+        assert_eq!(resolve_type(name, &aliases), expected, "{}", name);
+    }
+    assert_eq!(resolve_type("webpage", &BTreeMap::new()), EntryType::Online);
+}
+
+#[test]
+fn test_fingerprint_ignores_type_case() {
+    assert_eq!(
+        entry_fingerprint(&parse_single("@InProceedings{a, title = {T}}")),
+        entry_fingerprint(&parse_single("@inproceedings{a, title = {T}}"))
+    );
+    assert_eq!(
+        entry_fingerprint(&parse_single("@BlogPost{a, title = {T}}")),
+        entry_fingerprint(&parse_single("@blogpost{a, title = {T}}"))
+    );
+}
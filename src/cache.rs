@@ -0,0 +1,187 @@
+/// a cache of the responses of DBLP on disk, so that running the same
+/// search again, or fetching the same record, does not hit the network
+use crate::config::Config;
+use crate::scratch;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// How long the hits of a search are reused, by default
+pub const SEARCH_TTL: Duration = Duration::from_secs(60 * 60);
+/// How long records are reused, by default, since they rarely change
+pub const RECORD_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Neither reads nor writes the cache for the rest of the command
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ttls {
+    pub search: Duration,
+    pub record: Duration,
+}
+
+impl Ttls {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            search: config
+                .search_cache_ttl_s
+                .map_or(SEARCH_TTL, Duration::from_secs),
+            record: config
+                .record_cache_ttl_s
+                .map_or(RECORD_TTL, Duration::from_secs),
+        }
+    }
+
+    /// How long the response to the url is reused, if at all
+    pub fn of(&self, url: &str) -> Option<Duration> {
+        let path = url.split('?').next().unwrap_or(url);
+        if path.ends_with("/search/publ/api") {
+            Some(self.search)
+        } else if path.contains("/rec/") && path.ends_with(".bib") {
+            Some(self.record)
+        } else {
+            None
+        }
+    }
+}
+
+/// The lifetimes of the config, or the default ones if it cannot be read
+pub static TTLS: LazyLock<Ttls> = LazyLock::new(|| {
+    let config = crate::config::load().unwrap_or_default();
+    Ttls::from_config(&config)
+});
+
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    url: String,
+    /// seconds since the epoch
+    fetched_at: u64,
+    body: String,
+}
+
+pub fn dir() -> PathBuf {
+    scratch::cache_dir().join("responses")
+}
+
+fn path_in(dir: &Path, url: &str) -> PathBuf {
+    dir.join(format!("{}.json", crate::bib::hash_hex(url.as_bytes())))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The body of the response to the url, if fetched less than `ttl` ago
+pub fn get(url: &str, ttl: Duration) -> Option<String> {
+    if DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    get_in(&dir(), url, ttl, now())
+}
+
+fn get_in(dir: &Path, url: &str, ttl: Duration, now: u64) -> Option<String> {
+    let text = std::fs::read_to_string(path_in(dir, url)).ok()?;
+    let cached: Cached = serde_json::from_str(&text).ok()?;
+    let fresh = now.saturating_sub(cached.fetched_at) < ttl.as_secs();
+    (cached.url == url && fresh).then_some(cached.body)
+}
+
+/// Keeps the body of the response. The cache is only an optimization,
+/// failing to write it is fine.
+pub fn put(url: &str, body: &str) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let _ = put_in(&dir(), url, body, now());
+}
+
+fn put_in(dir: &Path, url: &str, body: &str, now: u64) -> Result<()> {
+    let cached = Cached {
+        url: url.to_owned(),
+        fetched_at: now,
+        body: body.to_owned(),
+    };
+    std::fs::create_dir_all(dir)?;
+    // written aside and renamed, so that concurrent runs read either the
+    // old response or the new one
+    scratch::write_atomically(path_in(dir, url), |f| {
+        Ok(serde_json::to_writer(f, &cached)?)
+    })
+}
+
+/// Removes every cached response, returning how many there were
+pub fn clear() -> Result<usize> {
+    clear_in(&dir())
+}
+
+fn clear_in(dir: &Path) -> Result<usize> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let mut removed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[test]
+fn test_ttls() {
+    let ttls = Ttls::from_config(&toml::from_str("search_cache_ttl_s = 10").unwrap());
+    assert_eq!(ttls.search, Duration::from_secs(10));
+    assert_eq!(ttls.record, RECORD_TTL);
+    let cases = [
+        (
+            "https://dblp.org/search/publ/api?q=x&format=json&h=100&f=0&param=0",
+            Some(ttls.search),
+        ),
+        (
+            "https://dblp.org/rec/conf/soda/Chan16.bib?param=1",
+            Some(ttls.record),
+        ),
+        ("https://dblp.org/rec/conf/soda/Chan16.html", None),
+        ("https://dblp.org/", None),
+    ];
+    for (url, ttl) in cases {
+        assert_eq!(ttls.of(url), ttl, "{}", url);
+    }
+}
+
+#[test]
+fn test_get_put() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-cache-{}", std::process::id()));
+    let url = "https://dblp.org/rec/conf/soda/Chan16.bib?param=1";
+    let hour = Duration::from_secs(60 * 60);
+    assert_eq!(get_in(&dir, url, hour, 1000), None);
+    put_in(&dir, url, "@inproceedings{...}", 1000).unwrap();
+    assert_eq!(
+        get_in(&dir, url, hour, 1000 + 60).as_deref(),
+        Some("@inproceedings{...}")
+    );
+    // expired, or about another url
+    assert_eq!(get_in(&dir, url, hour, 1000 + 60 * 60), None);
+    assert_eq!(get_in(&dir, "https://dblp.org/", hour, 1000), None);
+    put_in(&dir, url, "newer", 2000).unwrap();
+    assert_eq!(get_in(&dir, url, hour, 2000).as_deref(), Some("newer"));
+    assert_eq!(clear_in(&dir).unwrap(), 1);
+    assert_eq!(get_in(&dir, url, hour, 2000), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
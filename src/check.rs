@@ -0,0 +1,972 @@
+/// checks on the consistency of a bibliography
+use crate::bib::{self, FieldSpan};
+use crate::dblp::{self, DblpHitInfo, Format, FETCH_DELAY};
+use crate::diff::FieldChange;
+use crate::error::DblpError;
+use crate::output::{EntryRef, Finding, FindingKind, Location};
+use crate::similarity::{self, edit_distance, normalize_text, Confidence, TYPO_DISTANCE};
+use crate::{progress, scratch, stats, suggestions};
+use anyhow::Result;
+use biblatex::{ChunksExt, Entry};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The names of the entry types, to suggest one for unknown types
+pub const ENTRY_TYPES: [&str; 21] = [
+    "article",
+    "book",
+    "booklet",
+    "collection",
+    "conference",
+    "dataset",
+    "inbook",
+    "incollection",
+    "inproceedings",
+    "manual",
+    "mastersthesis",
+    "misc",
+    "online",
+    "patent",
+    "phdthesis",
+    "proceedings",
+    "report",
+    "software",
+    "techreport",
+    "thesis",
+    "unpublished",
+];
+
+/// An entry of a type unknown to biblatex and to the aliases
+#[derive(Debug, PartialEq)]
+pub struct UnknownType {
+    pub key: String,
+    /// as written
+    pub name: String,
+    pub offset: usize,
+    /// A known type or alias with a similar name
+    pub suggestion: Option<String>,
+}
+
+/// The entries whose type is unknown, and the number of entries of each
+/// type, unknown ones included, by the lowercase name of the type
+pub fn entry_types(
+    src: &str,
+    aliases: &BTreeMap<String, String>,
+) -> Result<(Vec<UnknownType>, BTreeMap<String, usize>)> {
+    let raw = biblatex::RawBibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    let mut unknown = Vec::new();
+    let mut counts = BTreeMap::new();
+    for entry in raw.entries.iter() {
+        let name = entry.v.kind.v;
+        let resolved = bib::resolve_type(name, aliases);
+        *counts.entry(bib::entry_type_name(&resolved)).or_insert(0) += 1;
+        if !matches!(resolved, biblatex::EntryType::Unknown(_)) {
+            continue;
+        }
+        let lower = name.to_lowercase();
+        let suggestion = ENTRY_TYPES
+            .iter()
+            .copied()
+            .chain(aliases.keys().map(String::as_str))
+            .chain(bib::DEFAULT_TYPE_ALIASES.iter().map(|(alias, _)| *alias))
+            .map(|known| (edit_distance(&lower, known), known))
+            .filter(|(d, _)| *d <= TYPO_DISTANCE)
+            .min()
+            .map(|(_, known)| known.to_owned());
+        unknown.push(UnknownType {
+            key: entry.v.key.v.to_owned(),
+            name: name.to_owned(),
+            offset: entry.span.start,
+            suggestion,
+        });
+    }
+    Ok((unknown, counts))
+}
+
+/// Fields naming the venue of an entry
+pub const VENUE_FIELDS: [&str; 2] = ["booktitle", "journal"];
+
+/// The entries of the same venue stream in the same year
+#[derive(Debug)]
+pub struct VenueGroup {
+    pub stream: String,
+    pub year: String,
+    pub field: String,
+    /// the venue field of each entry, with its value as written in the source
+    pub members: Vec<(FieldSpan, String)>,
+}
+
+impl VenueGroup {
+    /// The distinct venue strings of the group, with the keys using each,
+    /// most used first
+    pub fn variants(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut variants: Vec<(String, &str, Vec<&str>)> = Vec::new();
+        for (span, raw) in self.members.iter() {
+            let normalized = bib::normalize_value(raw);
+            match variants.iter_mut().find(|(n, _, _)| *n == normalized) {
+                Some((_, _, keys)) => keys.push(&span.key),
+                None => variants.push((normalized, raw, vec![&span.key])),
+            }
+        }
+        // stable, so ties go to the first variant in the file
+        variants.sort_by_key(|(_, _, keys)| std::cmp::Reverse(keys.len()));
+        variants
+            .into_iter()
+            .map(|(_, raw, keys)| (raw, keys))
+            .collect()
+    }
+
+    pub fn majority(&self) -> &str {
+        self.variants()[0].0
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.variants().len() <= 1
+    }
+
+    /// The edits setting the venue of every entry of the group to the given value
+    pub fn fixes(&self, value: &str) -> Vec<(Range<usize>, String)> {
+        let target = bib::normalize_value(value);
+        self.members
+            .iter()
+            .filter(|(_, raw)| bib::normalize_value(raw) != target)
+            .map(|(span, _)| (span.value.clone(), value.to_owned()))
+            .collect()
+    }
+}
+
+/// Groups the entries by venue stream and year, using the structure of DBLP
+/// keys when available, and the venue string otherwise
+pub fn venue_groups(src: &str) -> Result<Vec<VenueGroup>> {
+    let spans = bib::field_spans(src)?;
+    let years: BTreeMap<&str, String> = spans
+        .iter()
+        .filter(|s| s.field == "year")
+        .map(|s| (s.key.as_str(), bib::normalize_value(&src[s.value.clone()])))
+        .collect();
+
+    let mut groups: BTreeMap<(String, String, String), VenueGroup> = BTreeMap::new();
+    for span in spans.iter() {
+        if !VENUE_FIELDS.contains(&span.field.as_str()) {
+            continue;
+        }
+        let year = match years.get(span.key.as_str()) {
+            Some(year) => year.clone(),
+            None => continue,
+        };
+        let raw = src[span.value.clone()].to_owned();
+        let stream = venue_stream(&span.key, &bib::normalize_value(&raw), &year);
+        groups
+            .entry((stream.clone(), year.clone(), span.field.clone()))
+            .or_insert_with(|| VenueGroup {
+                stream,
+                year,
+                field: span.field.clone(),
+                members: Vec::new(),
+            })
+            .members
+            .push((span.clone(), raw));
+    }
+    Ok(groups.into_values().collect())
+}
+
+/// The short name of the venue stream, like `stoc`
+pub fn venue_stream(key: &str, venue: &str, year: &str) -> String {
+    if let Some(dblp_key) = key.strip_prefix("DBLP:") {
+        let parts: Vec<&str> = dblp_key.split('/').collect();
+        if parts.len() == 3 {
+            return parts[1].to_lowercase();
+        }
+    }
+    // look for the acronym right before the year, as in "STOC 2019"
+    let words: Vec<&str> = venue
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    words
+        .windows(2)
+        .find(|w| w[1] == year && w[0].len() > 1 && w[0].chars().all(|c| c.is_uppercase()))
+        .map(|w| w[0].to_lowercase())
+        .unwrap_or_else(|| {
+            normalize_text(venue)
+                .split(' ')
+                .filter(|w| !w.chars().all(|c| c.is_ascii_digit()))
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+}
+
+/// The groups of entries that are likely the same paper, in the order of
+/// the file: the ones with the same DOI, and the ones with the same title
+/// and year, ignoring case and punctuation, that the authors corroborate.
+/// Papers that only share a title are different papers. Entries linked
+/// through another one are in the same group.
+pub fn duplicate_groups(src: &str) -> Result<Vec<Vec<String>>> {
+    let bibliography =
+        biblatex::Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    let entries: Vec<&biblatex::Entry> = bibliography.iter().collect();
+    // the first entry of each group, for every entry
+    let mut group: Vec<usize> = (0..entries.len()).collect();
+    // the entries with each DOI, and with each title and year
+    let mut with: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let year: String = entry
+            .fields
+            .get("year")
+            .or(entry.fields.get("date"))
+            .map(|v| {
+                bib::normalize_value(&v.format_verbatim())
+                    .chars()
+                    .take(4)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let ids = [
+            similarity::doi(entry).map(|doi| format!("doi {}", doi)),
+            similarity::title(entry).map(|title| format!("title {} {}", title, year)),
+        ];
+        for id in ids.into_iter().flatten() {
+            let same = with.entry(id).or_default();
+            for &j in same.iter() {
+                if similarity::match_confidence(entries[i], entries[j]) != Confidence::High {
+                    continue;
+                }
+                let (from, to) = (group[i].max(group[j]), group[i].min(group[j]));
+                // merge the later group into the earlier one
+                for g in group.iter_mut() {
+                    if *g == from {
+                        *g = to;
+                    }
+                }
+            }
+            same.push(i);
+        }
+    }
+    let mut groups: Vec<Vec<String>> = vec![Vec::new(); entries.len()];
+    for (i, entry) in entries.iter().enumerate() {
+        groups[group[i]].push(entry.key.clone());
+    }
+    Ok(groups.into_iter().filter(|g| g.len() > 1).collect())
+}
+
+/// The journal versions found for conference entries, saved so that
+/// checking again does not search DBLP for the same entries
+#[derive(Default, Serialize, Deserialize)]
+pub struct JournalVersions {
+    versions: BTreeMap<String, Option<String>>,
+}
+
+impl JournalVersions {
+    fn path() -> PathBuf {
+        scratch::data_dir().join("journal-versions.json")
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(scratch::data_dir())?;
+        scratch::write_atomically(Self::path(), |f| Ok(serde_json::to_writer(f, self)?))
+    }
+
+    /// The key of the journal version of the entry, if it was already searched for
+    pub fn get(&self, key: &str) -> Option<Option<&str>> {
+        self.versions.get(key).map(|v| v.as_deref())
+    }
+
+    pub fn insert(&mut self, key: &str, journal_key: Option<String>) {
+        self.versions.insert(key.to_owned(), journal_key);
+    }
+}
+
+/// What the last check found for an entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryState {
+    pub fingerprint: String,
+    /// seconds since the epoch
+    pub checked_at: u64,
+    pub journal_version: Option<String>,
+}
+
+/// The results of the last check of a bib file, so that the next one can
+/// skip the entries that did not change
+#[derive(Default, Serialize, Deserialize)]
+pub struct CheckState {
+    path: PathBuf,
+    file_hash: String,
+    entries: BTreeMap<String, EntryState>,
+    /// whether the file is byte for byte the one of the last check
+    #[serde(skip)]
+    pub unchanged: bool,
+}
+
+impl CheckState {
+    fn state_path(bib_path: &Path) -> PathBuf {
+        let name = bib::hash_hex(bib_path.to_string_lossy().as_bytes());
+        scratch::data_dir()
+            .join("check-state")
+            .join(format!("{}.json", name))
+    }
+
+    pub fn load(bib_path: &Path, src: &str) -> Self {
+        let stored = std::fs::read_to_string(Self::state_path(bib_path))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok());
+        Self::validated(stored, bib_path, src)
+    }
+
+    /// The stored state if it is about the same file, otherwise an empty
+    /// one. When the content of the file changed, fingerprints tell which
+    /// entries did.
+    fn validated(stored: Option<Self>, bib_path: &Path, src: &str) -> Self {
+        let file_hash = bib::hash_hex(src.as_bytes());
+        match stored {
+            Some(state) if state.path == bib_path => Self {
+                unchanged: state.file_hash == file_hash,
+                file_hash,
+                ..state
+            },
+            _ => Self {
+                path: bib_path.to_owned(),
+                file_hash,
+                entries: BTreeMap::new(),
+                unchanged: false,
+            },
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::state_path(&self.path);
+        std::fs::create_dir_all(path.parent().expect("state path has a parent"))?;
+        scratch::write_atomically(path, |f| Ok(serde_json::to_writer(f, self)?))
+    }
+
+    /// The last results for the entry, if it did not change since, and they
+    /// are more recent than `stale_after`
+    pub fn fresh(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        now: u64,
+        stale_after: Duration,
+    ) -> Option<&EntryState> {
+        self.entries.get(key).filter(|e| {
+            e.fingerprint == fingerprint && now.saturating_sub(e.checked_at) < stale_after.as_secs()
+        })
+    }
+
+    pub fn record(&mut self, key: &str, state: EntryState) {
+        self.entries.insert(key.to_owned(), state);
+    }
+}
+
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// How to fix the venues of the groups that are not consistent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Fix {
+    Majority,
+    Refetch,
+}
+
+impl VenueGroup {
+    pub fn finding(&self, bib_path: &Path, src: &str) -> Finding {
+        Finding {
+            kind: FindingKind::VenueInconsistency,
+            entries: self
+                .members
+                .iter()
+                .map(|(span, _)| EntryRef {
+                    key: span.key.clone(),
+                    location: Some(Location::at(bib_path, src, span.value.start)),
+                })
+                .collect(),
+            message: format!(
+                "{} {} uses different {} strings",
+                self.stream, self.year, self.field
+            ),
+        }
+    }
+
+    /// The value to set the venue of the group to: the most common one, or
+    /// the one currently on DBLP
+    pub fn fixed_value(&self, fix: Fix) -> Result<String> {
+        let refetched = match fix {
+            Fix::Majority => None,
+            Fix::Refetch => self.refetched()?,
+        };
+        Ok(refetched.unwrap_or_else(|| self.majority().to_owned()))
+    }
+
+    /// How setting the venue to the value changes each entry
+    pub fn changes(&self, value: &str) -> Vec<(&FieldSpan, FieldChange)> {
+        let target = bib::normalize_value(value);
+        self.members
+            .iter()
+            .filter_map(|(span, raw)| {
+                let current = bib::normalize_value(raw);
+                (current != target).then(|| {
+                    let change = FieldChange::Changed(self.field.clone(), current, target.clone());
+                    (span, change)
+                })
+            })
+            .collect()
+    }
+
+    /// The value of the venue field of the group, as currently found on DBLP
+    fn refetched(&self) -> Result<Option<String>> {
+        let key = match self
+            .members
+            .iter()
+            .map(|(span, _)| &span.key)
+            .find(|k| k.starts_with("DBLP:"))
+        {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let fetched = dblp::fetch_record(key, Format::Standard)?;
+        Ok(bib::field_spans(&fetched)?
+            .into_iter()
+            .find(|s| s.key == *key && s.field == self.field)
+            .map(|s| fetched[s.value].to_owned()))
+    }
+}
+
+impl UnknownType {
+    pub fn finding(&self, bib_path: &Path, src: &str) -> Finding {
+        let hint = match &self.suggestion {
+            Some(known) => format!("did you mean @{}?", known),
+            None => "map it to a known type with type_aliases in the config".to_owned(),
+        };
+        Finding {
+            kind: FindingKind::UnknownEntryType,
+            entries: vec![EntryRef {
+                key: self.key.clone(),
+                location: Some(Location::at(bib_path, src, self.offset)),
+            }],
+            message: format!("unknown entry type @{}, {}", self.name, hint),
+        }
+    }
+}
+
+/// What fetching the records of the DBLP entries again found
+#[derive(Default)]
+pub struct KeyCheck {
+    pub findings: Vec<Finding>,
+    /// the entries whose record moved, with its new key, unless an entry of
+    /// the file has it already
+    pub renames: BTreeMap<String, String>,
+    pub ok: usize,
+    pub moved: usize,
+    pub missing: usize,
+    /// the entries that could not be checked, with why
+    pub failed: Vec<(String, DblpError)>,
+}
+
+impl KeyCheck {
+    pub fn problems(&self) -> usize {
+        self.moved + self.missing + self.failed.len()
+    }
+
+    /// The source with the moved entries renamed to their new key
+    pub fn renamed(&self, src: &str) -> Result<String> {
+        let mut renamed = src.to_owned();
+        for (key, new_key) in self.renames.iter() {
+            renamed = bib::rename_entry(&renamed, key, new_key)?;
+        }
+        Ok(renamed)
+    }
+}
+
+/// Fetches the record of every entry with a DBLP key, finding the ones
+/// DBLP moved to another key, and the ones it no longer has
+pub fn check_keys(bib_path: &Path, src: &str) -> Result<KeyCheck> {
+    let entries: Vec<(String, Range<usize>)> = bib::entry_spans(src)?
+        .into_iter()
+        .filter(|(key, _)| key.starts_with("DBLP:"))
+        .collect();
+    let mut check = KeyCheck::default();
+    for (i, (key, span)) in entries.iter().enumerate() {
+        if i > 0 {
+            std::thread::sleep(FETCH_DELAY);
+        }
+        let (kind, message) = match dblp::fetch_record(key, Format::Standard) {
+            Ok(fetched) => {
+                // DBLP answers for a moved record with the one of its new key
+                let keys: Vec<String> = bib::split_entries(&fetched)?
+                    .into_iter()
+                    .map(|(k, _)| k)
+                    .collect();
+                match keys.first() {
+                    Some(new_key) if !keys.contains(key) => {
+                        check.moved += 1;
+                        let mut message = format!("DBLP moved the record to {}", new_key);
+                        if entries.iter().any(|(k, _)| k == new_key) {
+                            // renaming would make two entries with the same key
+                            message.push_str(", which is in the file too");
+                        } else {
+                            check.renames.insert(key.clone(), new_key.clone());
+                        }
+                        (FindingKind::MovedKey, message)
+                    }
+                    _ => {
+                        check.ok += 1;
+                        continue;
+                    }
+                }
+            }
+            Err(DblpError::NotFound { .. }) => {
+                check.missing += 1;
+                (
+                    FindingKind::MissingKey,
+                    "DBLP no longer has the record".to_owned(),
+                )
+            }
+            Err(err) => {
+                check.failed.push((key.clone(), err));
+                continue;
+            }
+        };
+        check.findings.push(Finding {
+            kind,
+            entries: vec![EntryRef {
+                key: key.clone(),
+                location: Some(Location::at(bib_path, src, span.start)),
+            }],
+            message,
+        });
+    }
+    Ok(check)
+}
+
+/// How to search for the journal versions of the conference entries
+pub struct JournalSearch {
+    /// the results of the last check, to keep the new ones in, if any
+    pub state: Option<CheckState>,
+    /// reuse the results of the last check for the entries that did not
+    /// change since
+    pub since_last_run: bool,
+    /// how old the results reused can be
+    pub stale_after: Duration,
+    /// show the journal versions found along with the progress
+    pub show: bool,
+}
+
+/// A journal version of a conference entry of the file, which is not in the
+/// file
+pub struct JournalVersion {
+    pub key: String,
+    pub journal_key: String,
+    pub finding: Finding,
+}
+
+/// What searching for the journal versions found
+pub struct JournalCheck {
+    pub found: Vec<JournalVersion>,
+    /// the entries whose search failed, with why, to search again next time
+    pub failed: Vec<(String, DblpError)>,
+}
+
+/// Searches DBLP for the journal versions of the conference entries of the
+/// file, keeping what is found in the cache, in the pending suggestions and
+/// in the state of the search. A failed search fails only its entry.
+pub fn journal_versions(bib_path: &Path, src: &str, search: JournalSearch) -> Result<JournalCheck> {
+    let bibliography =
+        biblatex::Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    let spans: HashMap<String, Range<usize>> = bib::entry_spans(src)?.into_iter().collect();
+    let mut cache = JournalVersions::load();
+    let mut suggestions = suggestions::Store::load(bib_path, src)?;
+    let mut state = search.state;
+    let now = now();
+
+    let (mut found, mut failed) = (Vec::new(), Vec::new());
+    let conference: Vec<&Entry> = bibliography
+        .iter()
+        .filter(|entry| entry.key.starts_with("DBLP:conf/"))
+        .collect();
+    let progress = progress::Progress::new(conference.len(), FETCH_DELAY);
+    for entry in conference {
+        let fingerprint = bib::entry_fingerprint(entry);
+        let fresh = state
+            .as_ref()
+            .filter(|_| search.since_last_run)
+            .and_then(|s| s.fresh(&entry.key, &fingerprint, now, search.stale_after));
+        let mut evidence = None;
+        let (journal_key, cached_at) = match (fresh, cache.get(&entry.key)) {
+            (Some(fresh), _) => {
+                stats::STATS.cache_hit();
+                (fresh.journal_version.clone(), Some(fresh.checked_at))
+            }
+            (None, Some(journal_key)) if state.is_none() => {
+                stats::STATS.cache_hit();
+                (journal_key.map(|k| k.to_owned()), None)
+            }
+            _ => {
+                std::thread::sleep(FETCH_DELAY);
+                let hit = match find_journal_version(entry) {
+                    Ok(hit) => hit,
+                    Err(err) => {
+                        failed.push((entry.key.clone(), err));
+                        progress.tick(&entry.key);
+                        continue;
+                    }
+                };
+                evidence = hit.as_ref().map(|hit| {
+                    format!(
+                        "same title and authors as {} ({} {})",
+                        hit.title,
+                        hit.venue.join(", "),
+                        hit.year
+                    )
+                });
+                let journal_key = hit.map(|hit| hit.get_key());
+                cache.insert(&entry.key, journal_key.clone());
+                (journal_key, None)
+            }
+        };
+        if let Some(state) = state.as_mut() {
+            state.record(
+                &entry.key,
+                EntryState {
+                    fingerprint: fingerprint.clone(),
+                    checked_at: cached_at.unwrap_or(now),
+                    journal_version: journal_key.clone(),
+                },
+            );
+        }
+        if let Some(journal_key) = journal_key {
+            if bibliography.get(&journal_key).is_none()
+                && !suggestions.is_dismissed(&entry.key, &journal_key)
+            {
+                let age = cached_at.map_or(String::new(), |at| {
+                    let age = Duration::from_secs(now.saturating_sub(at));
+                    format!(" (cached, {} old)", progress::format_duration(age))
+                });
+                if search.show {
+                    progress.message(&format!("{} -> {} exists{}", entry.key, journal_key, age));
+                }
+                suggestions.add(suggestions::Suggestion {
+                    kind: suggestions::SuggestionKind::JournalVersion,
+                    source_key: entry.key.clone(),
+                    url: dblp::record_page(&journal_key),
+                    proposed_key: journal_key.clone(),
+                    evidence: evidence.unwrap_or_else(|| {
+                        "found by an earlier search of the title on DBLP".to_owned()
+                    }),
+                    discovered: now,
+                    fingerprint,
+                    dismissed: false,
+                });
+                found.push(JournalVersion {
+                    key: entry.key.clone(),
+                    finding: Finding {
+                        kind: FindingKind::JournalVersion,
+                        entries: vec![EntryRef {
+                            key: entry.key.clone(),
+                            location: spans
+                                .get(&entry.key)
+                                .map(|span| Location::at(bib_path, src, span.start)),
+                        }],
+                        message: format!("journal version {} exists{}", journal_key, age),
+                    },
+                    journal_key,
+                });
+            }
+        }
+        progress.tick(&entry.key);
+    }
+    cache.save()?;
+    suggestions.save()?;
+    if let Some(state) = state {
+        state.save()?;
+    }
+    Ok(JournalCheck { found, failed })
+}
+
+/// Searches DBLP for a journal version of the given conference entry
+fn find_journal_version(entry: &Entry) -> Result<Option<DblpHitInfo>, DblpError> {
+    let title = normalize_text(&bib::title(entry));
+    let hits = dblp::query(
+        &dblp::join_param_string(&[title]),
+        Format::Condensed,
+        dblp::TITLE_HITS,
+    )?;
+    let found = hits.into_iter().find(|hit| {
+        hit.key.starts_with("journals/")
+            && !hit.key.starts_with("journals/corr/")
+            && similarity::match_confidence(&hit.to_entry(), entry) == Confidence::High
+    });
+    Ok(found)
+}
+
+/// What to do with the conference entry that has a journal version
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JournalFix {
+    /// replace it with the journal version, keeping its local fields
+    Replace,
+    /// add the journal version besides it
+    Add,
+    /// link it to the journal version
+    Link,
+    Skip,
+}
+
+/// The edits fixing each journal version the way `choose` says, and the
+/// keys of the entries replaced with the key replacing them, for their
+/// citations to be renamed
+pub fn journal_version_edits(
+    src: &str,
+    found: &[JournalVersion],
+    mut choose: impl FnMut(&JournalVersion) -> Result<JournalFix>,
+) -> Result<(Vec<bib::Edit>, BTreeMap<String, String>)> {
+    let spans: HashMap<String, Range<usize>> = bib::entry_spans(src)?.into_iter().collect();
+    let (mut edits, mut renames) = (Vec::new(), BTreeMap::new());
+    for version in found.iter() {
+        let (key, journal_key) = (&version.key, &version.journal_key);
+        let span = &spans[key];
+        match choose(version)? {
+            JournalFix::Replace => {
+                edits.push(journal_replacement(src, key, span, journal_key)?);
+                renames.insert(key.clone(), journal_key.clone());
+            }
+            JournalFix::Add => {
+                let fetched = dblp::fetch_record(journal_key, Format::Standard)?;
+                edits.push((src.len()..src.len(), format!("\n{}\n", fetched.trim())));
+            }
+            JournalFix::Link => edits.push(bib::add_fields(
+                src,
+                span,
+                &[("related", journal_key), ("relatedtype", "journalversion")],
+            )),
+            JournalFix::Skip => {}
+        }
+    }
+    Ok((edits, renames))
+}
+
+/// The edits replacing the entries of the suggestions with the entries
+/// they propose, and the keys replaced with the keys replacing them
+pub fn suggestion_edits(
+    src: &str,
+    selected: &[suggestions::Suggestion],
+) -> Result<(Vec<bib::Edit>, BTreeMap<String, String>)> {
+    let spans: HashMap<String, Range<usize>> = bib::entry_spans(src)?.into_iter().collect();
+    let (mut edits, mut renames) = (Vec::new(), BTreeMap::new());
+    for (i, suggestion) in selected.iter().enumerate() {
+        // expired suggestions are dropped on load, so the entry is there
+        let span = &spans[&suggestion.source_key];
+        if i > 0 {
+            std::thread::sleep(FETCH_DELAY);
+        }
+        edits.push(match suggestion.kind {
+            suggestions::SuggestionKind::JournalVersion => {
+                journal_replacement(src, &suggestion.source_key, span, &suggestion.proposed_key)?
+            }
+        });
+        renames.insert(
+            suggestion.source_key.clone(),
+            suggestion.proposed_key.clone(),
+        );
+    }
+    Ok((edits, renames))
+}
+
+/// The edit replacing the conference entry with its journal version, keeping
+/// the local fields of the entry
+pub fn journal_replacement(
+    src: &str,
+    key: &str,
+    span: &Range<usize>,
+    journal_key: &str,
+) -> Result<(Range<usize>, String)> {
+    let fetched = dblp::fetch_record(journal_key, Format::Standard)?;
+    let fetched = bib::carry_preserved(src, key, &fetched, journal_key)?;
+    Ok((span.clone(), fetched.trim().to_owned()))
+}
+
+#[test]
+fn test_check_state() {
+    let day = 24 * 60 * 60;
+    let entry = |fingerprint: &str, checked_at| EntryState {
+        fingerprint: fingerprint.to_owned(),
+        checked_at,
+        journal_version: None,
+    };
+    let mut state = CheckState::validated(None, Path::new("refs.bib"), "a");
+    state.record("a", entry("fa", 10 * day));
+    state.record("b", entry("fb", day));
+    let window = Duration::from_secs(7 * day);
+    let now = 12 * day;
+    assert!(state.fresh("a", "fa", now, window).is_some());
+    assert!(state.fresh("a", "changed", now, window).is_none());
+    assert!(state.fresh("b", "fb", now, window).is_none());
+    assert!(state.fresh("c", "fc", now, window).is_none());
+
+    let json = serde_json::to_string(&state).unwrap();
+    let stored = || Some(serde_json::from_str::<CheckState>(&json).unwrap());
+    // the same file, even with a different content, keeps what is known
+    assert!(CheckState::validated(stored(), Path::new("refs.bib"), "a").unchanged);
+    let same = CheckState::validated(stored(), Path::new("refs.bib"), "b");
+    assert!(same.fresh("a", "fa", now, window).is_some());
+    assert!(!same.unchanged);
+    // any other file starts from scratch
+    let other = CheckState::validated(stored(), Path::new("other.bib"), "a");
+    assert!(other.fresh("a", "fa", now, window).is_none());
+}
+
+#[test]
+fn test_venue_groups() {
+    let src = "
+@inproceedings{DBLP:conf/stoc/Foo19,
+  booktitle = {{STOC}},
+  year = {2019}
+}
+@inproceedings{DBLP:conf/stoc/Bar19,
+  booktitle = {{STOC}},
+  year = {2019}
+}
+@inproceedings{baz19,
+  booktitle = {Proceedings of the 51st Annual {ACM} Symposium on Theory of Computing, STOC 2019},
+  year = 2019
+}
+@inproceedings{qux19,
+  booktitle = {STOC},
+  year = {2020}
+}
+@inproceedings{DBLP:conf/focs/Foo19,
+  booktitle = {{FOCS}},
+  year = {2019}
+}
+";
+    let groups = venue_groups(src).unwrap();
+    assert_eq!(groups.len(), 3);
+    let inconsistent: Vec<&VenueGroup> = groups.iter().filter(|g| !g.is_consistent()).collect();
+    assert_eq!(inconsistent.len(), 1);
+    let stoc = inconsistent[0];
+    assert_eq!((stoc.stream.as_str(), stoc.year.as_str()), ("stoc", "2019"));
+    assert_eq!(stoc.majority(), "{{STOC}}");
+    assert_eq!(stoc.fixed_value(Fix::Majority).unwrap(), "{{STOC}}");
+    let changes = stoc.changes(stoc.majority());
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].0.key, "baz19");
+    let finding = stoc.finding(Path::new("refs.bib"), src);
+    assert_eq!(
+        finding.message,
+        "stoc 2019 uses different booktitle strings"
+    );
+    let lines: Vec<usize> = finding
+        .entries
+        .iter()
+        .map(|e| e.location.as_ref().unwrap().line)
+        .collect();
+    assert_eq!(lines, [3, 7, 11]);
+
+    let fixed = bib::splice(src, stoc.fixes(stoc.majority()));
+    assert!(venue_groups(&fixed)
+        .unwrap()
+        .iter()
+        .all(|g| g.is_consistent()));
+    assert_eq!(fixed.matches("{{STOC}}").count(), 3);
+}
+
+#[test]
+fn test_duplicate_groups() {
+    let src = r#"
+@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan},
+  title = {All-Pairs Shortest Paths}, year = {2016}}
+@misc{unrelated, title = {Another Paper}, year = {2016}}
+@article{chan-journal, title = {All-Pairs Shortest Paths},
+  doi = {10.1137/X}, year = {2017}}
+@misc{chan16, author = {Chan, T.}, title = {All-pairs {S}hortest paths.}, year = 2016}
+@misc{doi-only, title = {Unlike the others}, doi = {https://doi.org/10.1137/x}}
+@misc{later, title = {Another Paper}, year = {2019}}
+@misc{unrelated-too, title = {Another Paper}, year = {2016}}
+"#;
+    assert_eq!(
+        duplicate_groups(src).unwrap(),
+        vec![
+            vec!["DBLP:conf/soda/Chan16".to_owned(), "chan16".to_owned()],
+            vec!["chan-journal".to_owned(), "doi-only".to_owned()],
+        ]
+    );
+
+    // papers that share a title and a year but not their authors
+    let src = r#"
+@article{lecun15, author = {Yann LeCun and Yoshua Bengio and Geoffrey Hinton},
+  title = {Deep Learning}, year = {2015}}
+@book{goodfellow15, author = {Ian Goodfellow and Aaron Courville},
+  title = {Deep learning}, year = {2015}}
+"#;
+    assert!(duplicate_groups(src).unwrap().is_empty());
+}
+
+#[test]
+fn test_entry_types() {
+    let src = include_str!("../tests/fixtures/types.bib");
+    let (unknown, counts) = entry_types(src, &BTreeMap::new()).unwrap();
+    let found: Vec<(&str, &str, Option<&str>)> = unknown
+        .iter()
+        .map(|u| (u.key.as_str(), u.name.as_str(), u.suggestion.as_deref()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("typo", "onlne", Some("online")),
+            ("alien", "BlogPost", None)
+        ]
+    );
+    assert!(src[unknown[1].offset..].starts_with("@BlogPost{alien,"));
+    let path = Path::new("types.bib");
+    assert_eq!(
+        unknown[0].finding(path, src).message,
+        "unknown entry type @onlne, did you mean @online?"
+    );
+    assert_eq!(
+        unknown[1].finding(path, src).message,
+        "unknown entry type @BlogPost, map it to a known type with type_aliases in the config"
+    );
+    let counts: Vec<(&str, usize)> = counts.iter().map(|(t, n)| (t.as_str(), *n)).collect();
+    assert_eq!(
+        counts,
+        [
+            ("article", 1),
+            ("blogpost", 1),
+            ("inproceedings", 2),
+            ("online", 2),
+            ("onlne", 1)
+        ]
+    );
+
+    let aliases = BTreeMap::from([("blogpost".to_owned(), "online".to_owned())]);
+    let (unknown, counts) = entry_types(src, &aliases).unwrap();
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(counts["online"], 3);
+}
+
+#[test]
+fn test_entry_type_names() {
+    for name in ENTRY_TYPES {
+        assert!(
+            !matches!(
+                biblatex::EntryType::new(name),
+                biblatex::EntryType::Unknown(_)
+            ),
+            "{}",
+            name
+        );
+    }
+}
@@ -0,0 +1,239 @@
+/// utilities to copy text to the clipboard, with the commands of the
+/// platform or, over SSH, with the OSC 52 escape sequence of the terminal
+use crate::bib;
+use crate::error::DblpError;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io::{IsTerminal, Write};
+
+/// How to copy to the clipboard
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// With the clipboard commands of the platform or, if none works and
+    /// standard output is a terminal, with OSC 52
+    #[default]
+    Auto,
+    /// Only with the clipboard commands of the platform
+    Command,
+    /// With the OSC 52 escape sequence, which terminals like the local one
+    /// of an SSH session understand
+    Osc52,
+}
+
+/// A command copying its standard input to the clipboard, with its arguments
+type Command = (&'static str, &'static [&'static str]);
+
+const WL_COPY: Command = ("wl-copy", &[]);
+const XCLIP: Command = ("xclip", &["-selection", "clipboard"]);
+const XSEL: Command = ("xsel", &["--clipboard", "--input"]);
+
+/// What is reported as copying the text, when OSC 52 does
+pub const OSC52: &str = "OSC 52";
+
+/// Bytes of text copied with OSC 52 at most, 100 KB once encoded, since some
+/// terminals ignore longer sequences
+const OSC52_LIMIT: usize = 75_000;
+
+/// The commands to try, in order, to copy to the clipboard on the platform:
+/// on Linux and the BSDs, the ones of the display server of the session
+/// come first, and the others after, since XWayland runs X11 tools too
+fn commands(os: &str, wayland: bool, x11: bool) -> Vec<Command> {
+    match os {
+        "macos" => vec![("pbcopy", &[])],
+        "windows" => vec![("clip.exe", &[]), ("cmd", &["/c", "clip"])],
+        _ if x11 && !wayland => vec![XCLIP, XSEL, WL_COPY],
+        _ => vec![WL_COPY, XCLIP, XSEL],
+    }
+}
+
+/// The programs of the clipboard commands of this platform and session
+pub fn programs() -> Vec<&'static str> {
+    current_commands()
+        .into_iter()
+        .map(|(program, _)| program)
+        .collect()
+}
+
+fn current_commands() -> Vec<Command> {
+    let set = |var: &str| std::env::var_os(var).is_some_and(|v| !v.is_empty());
+    commands(std::env::consts::OS, set("WAYLAND_DISPLAY"), set("DISPLAY"))
+}
+
+/// The program of the first command that `run` succeeds with, trying them
+/// in order
+fn first_working(
+    commands: &[Command],
+    mut run: impl FnMut(&str, &[&str]) -> Result<()>,
+) -> Option<&'static str> {
+    commands
+        .iter()
+        .find(|(program, args)| run(program, args).is_ok())
+        .map(|(program, _)| *program)
+}
+
+/// The error of the clipboard not being usable, for the reason
+fn unavailable(reason: String) -> DblpError {
+    DblpError::ClipboardUnavailable { reason }
+}
+
+/// Copies the text to the clipboard, returning the command that did it.
+/// Texts larger than `limit` are refused, since they freeze some terminals.
+pub fn write(what: &str, limit: usize, backend: Backend) -> crate::error::Result<&'static str> {
+    if what.len() > limit {
+        return Err(unavailable(format!(
+            "refusing to copy {} to the clipboard, the limit is {}",
+            bib::human_size(what.len()),
+            bib::human_size(limit)
+        )));
+    }
+    match backend {
+        Backend::Osc52 => write_osc52(what),
+        Backend::Command => write_command(what),
+        Backend::Auto => write_command(what).or_else(|err| {
+            if std::io::stdout().is_terminal() {
+                write_osc52(what)
+            } else {
+                Err(err)
+            }
+        }),
+    }
+}
+
+fn write_command(what: &str) -> crate::error::Result<&'static str> {
+    let run = |cmd: &str, args: &[&str]| -> Result<()> {
+        let mut child = std::process::Command::new(cmd)
+            .args(args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()?;
+        write!(child.stdin.take().context("no standard input")?, "{}", what)?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{} exited with {}", cmd, status);
+        }
+        Ok(())
+    };
+    let commands = current_commands();
+    first_working(&commands, run).ok_or_else(|| {
+        let names: Vec<&str> = commands.iter().map(|(program, _)| *program).collect();
+        unavailable(format!(
+            "none of {} copied to the clipboard",
+            names.join(", ")
+        ))
+    })
+}
+
+/// Asks the terminal of standard output to copy the text, warning if it is
+/// cut to the length terminals accept
+fn write_osc52(what: &str) -> crate::error::Result<&'static str> {
+    let mut out = std::io::stdout();
+    if !out.is_terminal() {
+        return Err(unavailable(
+            "cannot copy with OSC 52, standard output is not a terminal".to_owned(),
+        ));
+    }
+    let text = truncate(what, OSC52_LIMIT);
+    if text.len() < what.len() {
+        eprintln!(
+            "warning: copying only the first {} of {}, terminals may ignore longer texts",
+            bib::human_size(text.len()),
+            bib::human_size(what.len())
+        );
+    }
+    let tmux = std::env::var_os("TMUX").is_some_and(|v| !v.is_empty());
+    out.write_all(osc52_sequence(text, tmux).as_bytes())
+        .and_then(|()| out.flush())
+        .map_err(|e| unavailable(format!("cannot copy with OSC 52: {}", e)))?;
+    Ok(OSC52)
+}
+
+/// The longest start of the text of at most `limit` bytes
+fn truncate(text: &str, limit: usize) -> &str {
+    let end = (0..=limit.min(text.len()))
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// The escape sequence setting the clipboard to the text, wrapped for tmux
+/// to pass it on to the terminal
+fn osc52_sequence(text: &str, tmux: bool) -> String {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let sequence = format!("\x1b]52;c;{}\x07", encoded);
+    if tmux {
+        // the escapes inside the passthrough are doubled
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+#[test]
+fn test_commands() {
+    let programs = |os, wayland, x11| -> Vec<&str> {
+        commands(os, wayland, x11)
+            .into_iter()
+            .map(|(program, _)| program)
+            .collect()
+    };
+    assert_eq!(programs("linux", true, true), ["wl-copy", "xclip", "xsel"]);
+    assert_eq!(programs("linux", false, true), ["xclip", "xsel", "wl-copy"]);
+    assert_eq!(
+        programs("freebsd", false, false),
+        ["wl-copy", "xclip", "xsel"]
+    );
+    assert_eq!(programs("macos", false, true), ["pbcopy"]);
+    assert_eq!(programs("windows", false, false), ["clip.exe", "cmd"]);
+
+    // every command is tried until one succeeds, with its arguments
+    let mut tried = Vec::new();
+    let commands = commands("linux", false, true);
+    let used = first_working(&commands, |program, args| {
+        tried.push(format!("{} {}", program, args.join(" ")));
+        match program {
+            "xsel" => Ok(()),
+            _ => bail!("{} exited with 1", program),
+        }
+    });
+    assert_eq!(used, Some("xsel"));
+    assert_eq!(
+        tried,
+        ["xclip -selection clipboard", "xsel --clipboard --input"]
+    );
+    assert_eq!(first_working(&commands, |_, _| bail!("missing")), None);
+}
+
+#[test]
+fn test_write_refuses_huge() {
+    let huge = "x".repeat(2_100_000);
+    let err = write(&huge, bib::MAX_ENTRY_SIZE, Backend::Auto).unwrap_err();
+    assert_eq!(err.error_code(), "clipboard_unavailable");
+    assert_eq!(
+        err.to_string(),
+        "refusing to copy 2.1 MB to the clipboard, the limit is 100.0 KB"
+    );
+}
+
+#[test]
+fn test_osc52() {
+    assert_eq!(
+        osc52_sequence("DBLP:conf/soda/Chan16", false),
+        "\x1b]52;c;REJMUDpjb25mL3NvZGEvQ2hhbjE2\x07"
+    );
+    assert_eq!(
+        osc52_sequence("a", true),
+        "\x1bPtmux;\x1b\x1b]52;c;YQ==\x07\x1b\\"
+    );
+    assert_eq!(truncate("abc", 10), "abc");
+    assert_eq!(truncate("abc", 2), "ab");
+    // not in the middle of a character
+    assert_eq!(truncate("aé", 2), "a");
+    let long = "x".repeat(OSC52_LIMIT + 10);
+    let sequence = osc52_sequence(truncate(&long, OSC52_LIMIT), false);
+    assert_eq!(sequence.len(), "\x1b]52;c;\x07".len() + 100_000);
+}
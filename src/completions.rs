@@ -0,0 +1,173 @@
+/// utilities to write the completion scripts of the shells, which complete
+/// the keys of the bib file too, by running `dblp __complete-keys`
+use crate::keys::KeyIndex;
+use clap::Command;
+use clap_complete::Shell;
+
+/// The first argument that makes dblp print the keys of the bib files
+/// starting with the next one, which is not a subcommand for the scripts
+/// not to offer it
+pub const COMPLETE_KEYS: &str = "__complete-keys";
+
+/// The subcommands whose arguments are keys of the bib file, or queries
+/// that the keys match
+const KEY_COMMANDS: &str = "update remove info";
+
+/// The completion script of the shell for the command. Only the ones of
+/// bash, zsh and fish complete the keys.
+pub fn script(mut cmd: Command, shell: Shell) -> String {
+    let name = cmd.get_name().to_owned();
+    let mut out = Vec::new();
+    clap_complete::generate(shell, &mut cmd, &name, &mut out);
+    let script = String::from_utf8_lossy(&out).into_owned();
+    match shell {
+        Shell::Bash => format!("{}\n{}", script, bash_keys(&name)),
+        Shell::Zsh => zsh_with_keys(&script, &name),
+        Shell::Fish => format!("{}\n{}", script, fish_keys(&name)),
+        _ => script,
+    }
+}
+
+/// A function completing the keys after the subcommands that take them,
+/// and the rest as the generated one does, registered in its place
+fn bash_keys(name: &str) -> String {
+    format!(
+        r#"_{name}_keys() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" i
+    # keys have colons, which split words for bash
+    if declare -F _get_comp_words_by_ref >/dev/null; then
+        _get_comp_words_by_ref -n : cur
+    fi
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case " {commands} " in
+            *" ${{COMP_WORDS[i]}} "*)
+                if [[ "$cur" != -* ]]; then
+                    COMPREPLY=($({name} {helper} -- "$cur" 2>/dev/null))
+                    if declare -F __ltrim_colon_completions >/dev/null; then
+                        __ltrim_colon_completions "$cur"
+                    fi
+                    [[ ${{#COMPREPLY[@]}} -gt 0 ]] && return 0
+                fi
+                break
+                ;;
+        esac
+    done
+    _{name} "$@"
+}}
+complete -F _{name}_keys -o bashdefault -o default {name}
+"#,
+        name = name,
+        commands = KEY_COMMANDS,
+        helper = COMPLETE_KEYS
+    )
+}
+
+/// The generated script, calling a function completing the keys instead
+/// of the generated one, which it falls back to
+fn zsh_with_keys(script: &str, name: &str) -> String {
+    let function = format!(
+        r#"_{name}_keys() {{
+    local i
+    for ((i = 2; i < CURRENT; i++)); do
+        if [[ " {commands} " == *" ${{words[i]}} "* ]]; then
+            if [[ "$PREFIX" != -* ]]; then
+                local -a keys
+                keys=(${{(f)"$({name} {helper} -- "$PREFIX" 2>/dev/null)"}})
+                compadd -a keys && return 0
+            fi
+            break
+        fi
+    done
+    _{name} "$@"
+}}
+"#,
+        name = name,
+        commands = KEY_COMMANDS,
+        helper = COMPLETE_KEYS
+    );
+    // the end of the script either runs it, when autoloaded, or registers
+    // it, when sourced
+    let tail = format!("if [ \"$funcstack[1]\" = \"_{}\" ]; then", name);
+    match script.rfind(&tail) {
+        Some(start) => {
+            let end = script[start..]
+                .replace(
+                    &format!("_{} \"$@\"", name),
+                    &format!("_{}_keys \"$@\"", name),
+                )
+                .replace(
+                    &format!("compdef _{} {}", name, name),
+                    &format!("compdef _{}_keys {}", name, name),
+                );
+            format!("{}{}\n{}", &script[..start], function, end)
+        }
+        None => format!("{}\n{}compdef _{}_keys {}\n", script, function, name, name),
+    }
+}
+
+fn fish_keys(name: &str) -> String {
+    format!(
+        "complete -c {name} -n \"__fish_seen_subcommand_from {commands}\" -f \
+         -a \"({name} {helper} -- (commandline -ct) 2>/dev/null)\"\n",
+        name = name,
+        commands = KEY_COMMANDS,
+        helper = COMPLETE_KEYS
+    )
+}
+
+/// The keys, aliases and short keys of the index starting with the prefix,
+/// in order and each once
+pub fn matching_keys(index: &KeyIndex, prefix: &str) -> Vec<String> {
+    index
+        .inputs()
+        .into_iter()
+        .filter(|input| input.starts_with(prefix))
+        .collect()
+}
+
+#[test]
+fn test_matching_keys() {
+    let src = "@misc{DBLP:conf/soda/Chan16, title = {A}}\n\
+               @misc{DBLP:journals/talg/Chan18, title = {B}}\n\
+               @misc{DBLP:journals/corr/Chan18, title = {C}}\n\
+               @misc{local, title = {D}}\n";
+    let aliases = [("apsp".to_owned(), "DBLP:conf/soda/Chan16".to_owned())].into();
+    let index = KeyIndex::from_bib(src, aliases).unwrap();
+    assert_eq!(
+        matching_keys(&index, "DBLP:"),
+        [
+            "DBLP:conf/soda/Chan16",
+            "DBLP:journals/talg/Chan18",
+            "DBLP:journals/corr/Chan18"
+        ]
+    );
+    assert_eq!(matching_keys(&index, "Chan"), ["Chan16"]);
+    assert_eq!(
+        matching_keys(&index, "journals/t"),
+        ["journals/talg/Chan18"]
+    );
+    assert_eq!(matching_keys(&index, "a"), ["apsp"]);
+    assert_eq!(matching_keys(&index, "").len(), 9);
+    assert!(matching_keys(&index, "Local").is_empty());
+}
+
+#[test]
+fn test_script() {
+    use clap::CommandFactory;
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let script = script(crate::Cli::command(), shell);
+        assert!(
+            script.contains(&format!("dblp {} -- ", COMPLETE_KEYS)),
+            "{}",
+            script
+        );
+        assert!(script.contains("update remove info"), "{}", script);
+    }
+    let zsh = script(crate::Cli::command(), Shell::Zsh);
+    assert!(zsh.contains("    _dblp_keys \"$@\"\nelse\n    compdef _dblp_keys dblp\n"));
+    assert!(!zsh.contains("compdef _dblp dblp"));
+    let bash = script(crate::Cli::command(), Shell::Bash);
+    assert!(bash
+        .trim_end()
+        .ends_with("complete -F _dblp_keys -o bashdefault -o default dblp"));
+}
@@ -0,0 +1,347 @@
+/// the configuration of the user, read from
+/// `$XDG_CONFIG_HOME/dblp-rs/config.toml`, and of the project, read from
+/// the closest `.dblp.toml` and overriding the one of the user
+use crate::{clipboard, dblp};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// The bibtex file, or a list of them, as if given with --bibtex
+    pub bibtex: Option<Paths>,
+    /// The directory of the notes, as if given with --notes-dir
+    pub notes_dir: Option<PathBuf>,
+    /// The format of the entries clip copies, as if given with --format
+    pub format: Option<dblp::Format>,
+    /// How many hits to ask DBLP for, as if given with --max-hits
+    pub max_hits: Option<usize>,
+    /// How to copy to the clipboard, as if given with --clipboard
+    pub clipboard: Option<clipboard::Backend>,
+    /// A DBLP mirror to try first, as if given with --dblp-url
+    pub dblp_url: Option<String>,
+    /// Short names for keys, accepted wherever a key is
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Written at the top of the bib files created by dblp: the path of a
+    /// file, relative to the config directory, or the header itself
+    pub new_bib_template: Option<String>,
+    /// Insert added entries where they go in bib files sorted this way, by
+    /// `key`, `year`, `author` or `cited`, as if --sorted was given
+    pub insert_sorted: Option<String>,
+    /// The pattern of the keys of added entries, or the name of a preset,
+    /// like `authoryear`. Entries keep their DBLP key if missing.
+    pub key_pattern: Option<String>,
+    /// Known types that unknown entry types stand for, like
+    /// `webpage = "online"`
+    #[serde(default)]
+    pub type_aliases: BTreeMap<String, String>,
+    /// How many times to retry a request turned down by the rate limit of
+    /// DBLP. Errors of the server are retried at most twice.
+    pub retries: Option<u32>,
+    /// The wait before the first retry, doubling at each of the next ones,
+    /// unless DBLP says how long to wait
+    pub retry_backoff_ms: Option<u64>,
+    /// How long the hits of a search are reused, an hour by default
+    pub search_cache_ttl_s: Option<u64>,
+    /// How long fetched records are reused, 30 days by default
+    pub record_cache_ttl_s: Option<u64>,
+    /// How long to wait for another dblp process modifying the bib file to
+    /// be done, 10 seconds by default
+    pub lock_timeout_s: Option<u64>,
+    /// How much older than the bib file its backup can be for undo to
+    /// restore it, 10 minutes by default
+    pub undo_window_s: Option<u64>,
+    /// Fields removed from the entries that add and convert fetch, as if
+    /// --strip-fields was given, like `["timestamp", "biburl", "bibsource"]`
+    pub strip_fields: Option<Vec<String>>,
+    /// The template of the body of new notes, as if --template was given:
+    /// the path of a file, relative to the config directory
+    pub notes_template: Option<PathBuf>,
+    /// The extensions of the notes files, `["md"]` by default
+    pub notes_extensions: Option<Vec<String>>,
+    /// Lists of more authors are cut to the first one, and et al., in the
+    /// citations that `clip --what markdown` and `text` copy; 3 by default
+    pub citation_max_authors: Option<usize>,
+}
+
+/// A path, or a list of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Paths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl Paths {
+    pub fn to_vec(&self) -> Vec<PathBuf> {
+        match self {
+            Paths::One(path) => vec![path.clone()],
+            Paths::Many(paths) => paths.clone(),
+        }
+    }
+}
+
+impl Config {
+    /// The header of new bib files, read from the template file if
+    /// `new_bib_template` names one
+    pub fn new_bib_template(&self) -> Result<Option<String>> {
+        self.template_in(config_path().parent().unwrap_or(Path::new("")))
+    }
+
+    /// The path of the template of new notes, if there is one
+    pub fn notes_template(&self) -> Option<PathBuf> {
+        let config_dir = config_path().parent().unwrap_or(Path::new("")).to_owned();
+        self.notes_template
+            .as_ref()
+            .map(|path| config_dir.join(path))
+    }
+
+    /// The extensions of the notes files, `md` unless configured
+    pub fn notes_extensions(&self) -> Vec<String> {
+        self.notes_extensions
+            .clone()
+            .unwrap_or_else(|| vec!["md".to_owned()])
+    }
+
+    fn template_in(&self, config_dir: &Path) -> Result<Option<String>> {
+        let Some(template) = &self.new_bib_template else {
+            return Ok(None);
+        };
+        let path = config_dir.join(template);
+        if template.contains('\n') || !path.is_file() {
+            return Ok(Some(template.clone()));
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading the bib template {:?}", path))?;
+        Ok(Some(text))
+    }
+}
+
+/// The settings of a workspace, in the file at its root
+pub const WORKSPACE_CONFIG: &str = ".dblp.toml";
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Workspace {
+    /// Modify bib files outside the workspace without asking
+    #[serde(default)]
+    pub allow_outside: bool,
+}
+
+/// The settings of the workspace at the root, or the default ones
+pub fn load_workspace(root: &Path) -> Result<Workspace> {
+    let path = root.join(WORKSPACE_CONFIG);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => toml::from_str(&text).with_context(|| format!("reading {:?}", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Workspace::default()),
+        Err(e) => Err(e).with_context(|| format!("reading {:?}", path)),
+    }
+}
+
+pub fn config_path() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_default()
+        .join("dblp-rs")
+        .join("config.toml")
+}
+
+/// The config file of the project of the current directory: the closest
+/// `.dblp.toml` of it and its ancestors
+pub fn project_path() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(WORKSPACE_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// A config file, with its settings
+#[derive(Debug)]
+pub struct Layer {
+    pub path: PathBuf,
+    table: toml::Table,
+}
+
+/// The config files there are, the settings of the project overriding the
+/// ones of the user
+#[derive(Debug, Default)]
+pub struct Layers {
+    pub user: Option<Layer>,
+    pub project: Option<Layer>,
+}
+
+impl Layers {
+    /// The settings of the files, the tables like `aliases` merged
+    pub fn merged(&self) -> toml::Table {
+        let mut merged = toml::Table::new();
+        for layer in [&self.user, &self.project].into_iter().flatten() {
+            merge(&mut merged, &layer.table);
+        }
+        merged
+    }
+
+    pub fn config(&self) -> Result<Config> {
+        // each file parses on its own, so this only fails on a bug
+        Ok(toml::Value::Table(self.merged()).try_into()?)
+    }
+
+    /// The file the setting comes from, if any sets it
+    pub fn source(&self, key: &str) -> Option<&Path> {
+        [&self.project, &self.user]
+            .into_iter()
+            .flatten()
+            .find(|layer| layer.table.contains_key(key))
+            .map(|layer| layer.path.as_path())
+    }
+}
+
+fn merge(base: &mut toml::Table, over: &toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge(base, over),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// The settings of the file, if it exists. The paths of the file of a
+/// project are relative to its directory, to hold in its subdirectories.
+fn read_layer(path: &Path, project: bool) -> Result<Option<Layer>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {:?}", path)),
+    };
+    // parsed as a config first, for the errors to point at the setting
+    let config: Config = toml::from_str(&text).with_context(|| format!("reading {:?}", path))?;
+    if let Some(url) = &config.dblp_url {
+        dblp::parse_base_url(url).with_context(|| format!("reading {:?}: dblp_url", path))?;
+    }
+    let mut table: toml::Table = toml::from_str(&text)?;
+    if project {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let relative = |value: &mut toml::Value| {
+            if let toml::Value::String(s) = value {
+                *s = dir.join(&*s).display().to_string();
+            }
+        };
+        for key in ["bibtex", "notes_dir", "notes_template"] {
+            match table.get_mut(key) {
+                Some(toml::Value::Array(values)) => values.iter_mut().for_each(relative),
+                Some(value) => relative(value),
+                None => {}
+            }
+        }
+        // the template is the header itself unless it names a file
+        if let Some(toml::Value::String(template)) = table.get_mut("new_bib_template") {
+            if !template.contains('\n') && dir.join(&*template).is_file() {
+                *template = dir.join(&*template).display().to_string();
+            }
+        }
+    }
+    Ok(Some(Layer {
+        path: path.to_owned(),
+        table,
+    }))
+}
+
+/// The config files of the user and of the project
+pub fn layers() -> Result<Layers> {
+    Ok(Layers {
+        user: read_layer(&config_path(), false)?,
+        project: project_path()
+            .map(|path| read_layer(&path, true))
+            .transpose()?
+            .flatten(),
+    })
+}
+
+/// The configuration, or the default one if there is no config file
+pub fn load() -> Result<Config> {
+    layers()?.config()
+}
+
+#[test]
+fn test_parse() {
+    let config: Config = toml::from_str(
+        r#"
+[aliases]
+transformer = "DBLP:conf/nips/VaswaniSPUJGKP17"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.aliases["transformer"],
+        "DBLP:conf/nips/VaswaniSPUJGKP17"
+    );
+    assert!(toml::from_str::<Config>("").unwrap().aliases.is_empty());
+    assert!(toml::from_str::<Config>("aliases = 3").is_err());
+}
+
+#[test]
+fn test_new_bib_template() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("header.bib"), "% from the file\n").unwrap();
+    let template = |value: &str| {
+        let config: Config = toml::from_str(&format!("new_bib_template = {:?}", value)).unwrap();
+        config.template_in(&dir).unwrap()
+    };
+    assert_eq!(template("header.bib").as_deref(), Some("% from the file\n"));
+    assert_eq!(
+        template("% inline\n% header\n").as_deref(),
+        Some("% inline\n% header\n")
+    );
+    assert_eq!(template("% one line").as_deref(), Some("% one line"));
+    assert_eq!(Config::default().template_in(&dir).unwrap(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_layers() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-layers-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (user, project) = (dir.join("config.toml"), dir.join(WORKSPACE_CONFIG));
+    std::fs::write(
+        &user,
+        "max_hits = 10\nclipboard = \"osc52\"\n[aliases]\na = \"x\"\n",
+    )
+    .unwrap();
+    std::fs::write(
+        &project,
+        "max_hits = 20\nbibtex = [\"refs.bib\"]\nformat = \"condensed\"\n[aliases]\nb = \"y\"\n",
+    )
+    .unwrap();
+    let layers = Layers {
+        user: read_layer(&user, false).unwrap(),
+        project: read_layer(&project, true).unwrap(),
+    };
+    let config = layers.config().unwrap();
+    assert_eq!(config.max_hits, Some(20));
+    assert_eq!(config.clipboard, Some(clipboard::Backend::Osc52));
+    assert!(config.format == Some(dblp::Format::Condensed));
+    assert_eq!(config.aliases.len(), 2);
+    assert_eq!(config.bibtex.unwrap().to_vec(), [dir.join("refs.bib")]);
+    assert_eq!(layers.source("max_hits"), Some(project.as_path()));
+    assert_eq!(layers.source("clipboard"), Some(user.as_path()));
+    assert_eq!(layers.source("notes_dir"), None);
+    assert!(read_layer(&dir.join("none.toml"), false).unwrap().is_none());
+
+    // the errors name the file and the setting
+    for (text, bad) in [
+        ("max_hits = \"many\"\n", "max_hits"),
+        ("dblp_url = \"dblp.org\"\n", "dblp_url"),
+    ] {
+        std::fs::write(&user, text).unwrap();
+        let err = format!("{:#}", read_layer(&user, false).unwrap_err());
+        assert!(err.contains(&format!("{:?}", user)), "{}", err);
+        assert!(err.contains(bad), "{}", err);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
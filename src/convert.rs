@@ -0,0 +1,449 @@
+/// converting the entries of a bibtex file to another format of DBLP, by
+/// fetching them again
+use crate::dblp::{self, Format, FETCH_DELAY};
+use crate::error::{DblpError, Result};
+use crate::{bib, keys, progress};
+use biblatex::{Bibliography, Entry};
+use clap::ValueEnum;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    time::Duration,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OnFetchError {
+    /// Copy the entry as it is in the file
+    KeepOriginal,
+    /// Write the entry as parsed, which may change its formatting
+    Serialize,
+    /// Leave the entry out
+    Skip,
+    /// Leave the file untouched, after trying every entry
+    Fail,
+}
+
+impl OnFetchError {
+    /// What happened to the entries that could not be fetched
+    pub fn outcome(&self) -> &'static str {
+        match self {
+            OnFetchError::KeepOriginal => "kept as they were",
+            OnFetchError::Serialize => "written as parsed",
+            OnFetchError::Skip => "left out",
+            OnFetchError::Fail => "so the file was left untouched",
+        }
+    }
+}
+
+/// How to convert the entries
+pub struct Options {
+    pub to: Format,
+    pub on_fetch_error: OnFetchError,
+    /// How many entries to fetch at once
+    pub jobs: usize,
+    /// The DBLP entries to fetch again, the others are kept as written
+    pub filter: keys::KeyFilter,
+    /// Keep only the preserved local fields, rather than all the local ones
+    pub no_merge: bool,
+    /// The fields to remove from the fetched entries
+    pub strip: Vec<String>,
+    /// Report each entry, besides the progress
+    pub verbose: bool,
+}
+
+/// An entry that could not be fetched
+#[derive(Debug)]
+pub struct FetchFailure {
+    pub key: String,
+    pub error: DblpError,
+    /// The mirror that failed last, if the fetch failed rather than the
+    /// parsing of the record
+    pub mirror: Option<String>,
+}
+
+impl FetchFailure {
+    pub fn code(&self) -> &'static str {
+        self.error.error_code()
+    }
+}
+
+/// An entry of the converted file
+pub struct Converted {
+    pub key: String,
+    /// Where the entry is in the file, if it is there
+    pub span: Option<Range<usize>>,
+    /// What replaces it, if anything
+    pub text: Option<String>,
+}
+
+/// The converted file, with the counts of its summary
+pub struct Conversion {
+    /// The entries, in the order of the file, then the new ones
+    pub entries: Vec<Converted>,
+    pub failures: Vec<FetchFailure>,
+    /// The DBLP entries of the file
+    pub dblp: usize,
+    /// The DBLP entries the filter selects, which were fetched
+    pub fetched: usize,
+    pub not_dblp: usize,
+}
+
+impl Conversion {
+    /// The entries that could not be fetched but are in the converted file
+    pub fn kept(&self) -> usize {
+        self.failures.len() - self.entries.iter().filter(|c| c.text.is_none()).count()
+    }
+}
+
+/// What fetching an entry gives: its text, with the local fields carried
+/// and the stripped ones removed, or the error and the mirror that failed
+/// last, if one did
+type Fetched = Result<String, (DblpError, Option<String>)>;
+
+/// Fetches every DBLP entry of the file that the filter selects again,
+/// `jobs` at a time, giving the entries of the converted file in the order
+/// of the file. Converting and its dry run both go through here, so that
+/// the dry run shows what converting writes.
+pub fn convert_entries(src: &str, options: &Options) -> Result<Conversion> {
+    let (to, jobs) = (options.to, options.jobs);
+    let bibliography = parse(src)?;
+    let progress = progress::Progress::new(bibliography.len(), FETCH_DELAY / jobs as u32);
+    let (remote, local): (Vec<&Entry>, Vec<&Entry>) =
+        bibliography.iter().partition(|e| fetches(e, options));
+    for entry in local.iter() {
+        progress.tick(&entry.key);
+    }
+    // in the order of the file, like the entries they replace
+    let fetched = fetch_concurrently(&remote, jobs, FETCH_DELAY, |entry| {
+        let res = dblp::fetch_record_from_mirrors(&entry.key, to)
+            .map_err(|failed| (failed.error, failed.mirror));
+        let fetched = carry_local(src, entry, options, res);
+        progress.tick(&entry.key);
+        fetched
+    });
+    assemble(src, &bibliography, fetched, options, Some(&progress))
+}
+
+/// Like `convert_entries`, without blocking the thread, and without
+/// reporting progress. The requests, `jobs` at a time, go one per slot of
+/// the limiter, which other conversions running at the same time can
+/// share.
+#[cfg(feature = "async")]
+pub async fn convert_entries_async(
+    fetcher: &crate::fetcher::AsyncFetcher,
+    limiter: &crate::limiter::Limiter,
+    src: &str,
+    options: &Options,
+) -> Result<Conversion> {
+    let to = options.to;
+    let bibliography = parse(src)?;
+    let remote: Vec<&Entry> = bibliography
+        .iter()
+        .filter(|e| fetches(e, options))
+        .collect();
+    let fetched = fetch_concurrently_async(&remote, options.jobs, limiter, |entry| async move {
+        let res = fetcher
+            .get_from_mirrors(&dblp::record_path(&entry.key, to), Some(&entry.key))
+            .await
+            .map_err(|failed| (failed.error, failed.mirror));
+        carry_local(src, entry, options, res)
+    })
+    .await;
+    assemble(src, &bibliography, fetched, options, None)
+}
+
+fn parse(src: &str) -> Result<Bibliography> {
+    Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))
+}
+
+fn is_dblp(entry: &Entry) -> bool {
+    entry.key.starts_with("DBLP")
+}
+
+/// Whether the entry is fetched again
+fn fetches(entry: &Entry, options: &Options) -> bool {
+    is_dblp(entry) && options.filter.selects(&entry.key)
+}
+
+/// The fetched record, with the local fields of the entry carried over
+fn carry_local(
+    src: &str,
+    entry: &Entry,
+    options: &Options,
+    fetched: Result<String, (DblpError, String)>,
+) -> Fetched {
+    let bib = fetched.map_err(|(error, mirror)| (error, Some(mirror)))?;
+    let carry = if options.no_merge {
+        bib::carry_preserved
+    } else {
+        bib::merge_local
+    };
+    // after carrying, for the local values to go too
+    carry(src, &entry.key, &bib, &entry.key)
+        .and_then(|bib| bib::strip_fields(&bib, &options.strip))
+        .map_err(|err| (err, None))
+}
+
+/// The conversion, from what fetching gave for each entry that is fetched
+/// again, in the order of the file
+fn assemble(
+    src: &str,
+    bibliography: &Bibliography,
+    fetched: Vec<Fetched>,
+    options: &Options,
+    progress: Option<&progress::Progress>,
+) -> Result<Conversion> {
+    let message = |msg: String| {
+        if let Some(progress) = progress {
+            progress.message(&msg);
+        }
+    };
+    let spans: HashMap<String, Range<usize>> = bib::entry_spans(src)?.into_iter().collect();
+    let keys: HashSet<&str> = bibliography.iter().map(|e| e.key.as_str()).collect();
+    let fetched_count = fetched.len();
+    let mut fetched = fetched.into_iter();
+    let mut converted = Vec::new();
+    // the entries fetched along with the others, like proceedings
+    let mut parents: Vec<Converted> = Vec::new();
+    let mut failures = Vec::new();
+    for entry in bibliography.iter() {
+        let bibstr = entry
+            .to_bibtex_string()
+            .map_err(|e| DblpError::InvalidEntry {
+                reason: format!("cannot write {}: {}", entry.key, e),
+            })?;
+        let span = spans.get(&entry.key).cloned();
+        // the entries that are not fetched are kept as written
+        let original = span.clone().map(|span| src[span].to_owned());
+        let mut text = original.clone().or(Some(bibstr.clone()));
+        if fetches(entry, options) {
+            match fetched.next().expect("every selected entry was fetched") {
+                Ok(bib) => {
+                    if options.verbose && is_unchanged(entry, &bib) {
+                        message(format!("{} unchanged", entry.key));
+                    }
+                    let entries = bib::split_entries(&bib)?;
+                    if entries.len() <= 1 {
+                        text = Some(bib.clone());
+                    } else {
+                        for (key, fetched) in entries {
+                            if key == entry.key {
+                                text = Some(fetched.to_owned());
+                            } else if !keys.contains(key.as_str())
+                                && !parents.iter().any(|p| p.key == key)
+                            {
+                                parents.push(Converted {
+                                    key,
+                                    span: None,
+                                    text: Some(fetched.to_owned()),
+                                });
+                            }
+                        }
+                    }
+                }
+                Err((error, mirror)) => {
+                    let failure = FetchFailure {
+                        key: entry.key.clone(),
+                        error,
+                        mirror,
+                    };
+                    if options.verbose {
+                        message(format!(
+                            "Error in fetching data for {} ({}): {}",
+                            entry.key,
+                            failure.code(),
+                            failure.error
+                        ));
+                    }
+                    text = match options.on_fetch_error {
+                        OnFetchError::KeepOriginal => original,
+                        OnFetchError::Serialize => Some(bibstr),
+                        OnFetchError::Skip | OnFetchError::Fail => None,
+                    };
+                    failures.push(failure);
+                }
+            }
+        }
+        converted.push(Converted {
+            key: entry.key.clone(),
+            span,
+            text,
+        });
+    }
+    converted.extend(parents);
+    let not_dblp = bibliography.iter().filter(|e| !is_dblp(e)).count();
+    Ok(Conversion {
+        entries: converted,
+        failures,
+        dblp: bibliography.len() - not_dblp,
+        fetched: fetched_count,
+        not_dblp,
+    })
+}
+
+/// Calls `fetch` on every item with `jobs` workers, each waiting `delay`
+/// between its calls to stay polite to DBLP, giving the results in the
+/// order of the items
+pub fn fetch_concurrently<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    delay: Duration,
+    fetch: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs.max(1))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        if i >= items.len() {
+                            return done;
+                        }
+                        if !done.is_empty() {
+                            std::thread::sleep(delay);
+                        }
+                        done.push((i, fetch(&items[i])));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("a fetching worker panicked"))
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Like `fetch_concurrently`, with `jobs` tasks that take each call to
+/// `fetch` in turn from the limiter, polled together on the current task
+#[cfg(feature = "async")]
+pub async fn fetch_concurrently_async<'a, T: Sync, R: Send, F: std::future::Future<Output = R>>(
+    items: &'a [T],
+    jobs: usize,
+    limiter: &crate::limiter::Limiter,
+    fetch: impl Fn(&'a T) -> F,
+) -> Vec<R> {
+    use std::future::Future;
+    use std::sync::{atomic::AtomicUsize, atomic::Ordering, Mutex};
+    let next = AtomicUsize::new(0);
+    let done: Mutex<Vec<(usize, R)>> = Mutex::new(Vec::with_capacity(items.len()));
+    let worker = || async {
+        loop {
+            let i = next.fetch_add(1, Ordering::Relaxed);
+            if i >= items.len() {
+                return;
+            }
+            tokio::time::sleep(limiter.wait()).await;
+            let res = fetch(&items[i]).await;
+            done.lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((i, res));
+        }
+    };
+    let mut workers: Vec<_> = (0..jobs.max(1)).map(|_| Box::pin(worker())).collect();
+    std::future::poll_fn(|cx| {
+        workers.retain_mut(|w| w.as_mut().poll(cx).is_pending());
+        if workers.is_empty() {
+            std::task::Poll::Ready(())
+        } else {
+            std::task::Poll::Pending
+        }
+    })
+    .await;
+    drop(workers);
+    let mut results = done.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// The text of the converted file: the source with each entry replaced by
+/// its new text, so that comments, `@string`s and the entries left as they
+/// were stay byte for byte, followed by the entries that are new
+pub fn render_converted(src: &str, entries: &[Converted]) -> String {
+    let mut edits = Vec::new();
+    let mut appended = Vec::new();
+    for entry in entries {
+        match (&entry.span, &entry.text) {
+            (Some(span), Some(text)) => edits.push((span.clone(), text.trim().to_owned())),
+            (Some(span), None) => edits.push(bib::removal(src, span)),
+            (None, Some(text)) => appended.push(text.trim()),
+            (None, None) => {}
+        }
+    }
+    let out = bib::splice(src, edits);
+    if appended.is_empty() {
+        return out;
+    }
+    format!("{}\n\n{}\n", out.trim_end(), appended.join("\n\n"))
+}
+
+/// Whether the two texts of an entry have the same fields, up to the
+/// differences the fingerprint ignores
+pub fn same_fields(old: &str, new: &str) -> bool {
+    let fingerprint = |text: &str| {
+        Bibliography::parse(text)
+            .ok()
+            .and_then(|b| b.iter().next().map(bib::entry_fingerprint))
+    };
+    fingerprint(old).is_some_and(|fp| Some(fp) == fingerprint(new))
+}
+
+/// Tells whether the fetched bibtex contains an entry with the same key and
+/// the same fingerprint as the given one
+pub fn is_unchanged(entry: &Entry, fetched: &str) -> bool {
+    Bibliography::parse(fetched)
+        .ok()
+        .and_then(|b| b.get(&entry.key).map(bib::entry_fingerprint))
+        .is_some_and(|fp| fp == bib::entry_fingerprint(entry))
+}
+
+#[test]
+fn test_fetch_concurrently() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let items: Vec<usize> = (0..12).collect();
+    let results = fetch_concurrently(&items, 4, Duration::ZERO, |i| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(now, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(20));
+        running.fetch_sub(1, Ordering::SeqCst);
+        i * 10
+    });
+    assert_eq!(results, items.iter().map(|i| i * 10).collect::<Vec<_>>());
+    assert!(most.load(Ordering::SeqCst) > 1);
+    assert!(most.load(Ordering::SeqCst) <= 4);
+    assert!(fetch_concurrently(&[] as &[usize], 4, Duration::ZERO, |i| *i).is_empty());
+}
+
+#[test]
+fn test_render_converted() {
+    let src = "% kept\n@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n\n@misc{c, title = {C}}\n";
+    let spans: HashMap<String, Range<usize>> = bib::entry_spans(src).unwrap().into_iter().collect();
+    let entry = |key: &str, text: Option<&str>| Converted {
+        key: key.to_owned(),
+        span: spans.get(key).cloned(),
+        text: text.map(str::to_owned),
+    };
+    let entries = [
+        entry("a", Some("@misc{a, title = {New A}}\n")),
+        entry("b", None),
+        entry("c", Some("@misc{c, title = {C}}")),
+        entry("p", Some("@proceedings{p, title = {P}}")),
+    ];
+    assert_eq!(
+        render_converted(src, &entries),
+        "% kept\n@misc{a, title = {New A}}\n\n@misc{c, title = {C}}\n\n@proceedings{p, title = {P}}\n"
+    );
+    assert!(same_fields(
+        "@misc{a, title = {A}}",
+        "@misc{a,\n  title = {A}\n}"
+    ));
+    assert!(!same_fields(
+        "@misc{a, title = {A}}",
+        "@misc{a, title = {B}}"
+    ));
+}
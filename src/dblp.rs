@@ -1,91 +1,586 @@
 /// utilities to interface with DBLP
-use anyhow::{anyhow, Context, Result};
+use crate::error::{DblpError, Result};
+use crate::mirrors::Failed;
+use crate::stats::STATS;
 use clap::ValueEnum;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, Serialize};
+#[cfg(feature = "tui")]
 use skim::prelude::*;
-use std::borrow::Cow;
+use std::{
+    sync::{LazyLock, OnceLock},
+    time::Duration,
+};
 
-const URLS: [&str; 2] = ["https://dblp.org", "https://dblp.uni-trier.de"];
+pub const URLS: [&str; 2] = ["https://dblp.org", "https://dblp.uni-trier.de"];
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+/// Environment variable replacing the mirrors with a single base url, to
+/// point the tool at a test server
+pub const API_BASE_VAR: &str = "DBLP_RS_API_BASE";
+
+/// Environment variable of a mirror to try before the others, like a
+/// proxy in front of dblp.org
+pub const BASE_URL_VAR: &str = "DBLP_BASE_URL";
+
+static BASE_URL: OnceLock<String> = OnceLock::new();
+
+/// Adds a mirror to try before the others, before the first request
+pub fn set_base_url(url: String) {
+    let _ = BASE_URL.set(url);
+}
+
+/// The base url in `url`, which has to be an http or https address
+/// without query, like `https://dblp.example.org/dblp/`
+pub fn parse_base_url(url: &str) -> anyhow::Result<String> {
+    let url = url.trim().trim_end_matches('/');
+    let host = url
+        .strip_prefix("https://")
+        .or(url.strip_prefix("http://"))
+        .map(|rest| rest.split('/').next().unwrap_or(rest));
+    match host {
+        Some(host) if !host.is_empty() && !url.contains(['?', '#', ' ']) => Ok(url.to_owned()),
+        _ => anyhow::bail!(
+            "{:?} is not the base url of a DBLP mirror, like https://dblp.org",
+            url
+        ),
+    }
+}
+
+/// The mirrors to query, in order
+pub fn mirrors() -> Vec<String> {
+    let defaults = match std::env::var(API_BASE_VAR) {
+        Ok(base) => vec![base.trim_end_matches('/').to_owned()],
+        Err(_) => URLS.iter().map(|u| u.to_string()).collect(),
+    };
+    let mut urls: Vec<String> = BASE_URL.get().cloned().into_iter().collect();
+    for url in defaults {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// How long to wait for a connection, and then for each read, by default
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+static TIMEOUT_SET: OnceLock<Duration> = OnceLock::new();
+
+/// Replaces the default timeout, before the first request
+pub fn set_timeout(timeout: Duration) {
+    let _ = TIMEOUT_SET.set(timeout);
+}
+
+/// The timeout of the requests, the default one unless replaced
+pub fn timeout() -> Duration {
+    *TIMEOUT_SET.get().unwrap_or(&TIMEOUT)
+}
+
+/// The agent making every request, so that a server that accepts the
+/// connection and then hangs fails the request, instead of the program
+pub static AGENT: LazyLock<ureq::Agent> = LazyLock::new(|| {
+    let timeout = timeout();
+    ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .build()
+});
+
+/// The formats of the bibtex of DBLP records
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Format {
+    /// With the short names of venues, like `{SODA}`
     Condensed,
+    /// With the full names of venues
     Standard,
+    /// Standard, with the proceedings or book of each entry as an entry of
+    /// its own, which the entry refers to with `crossref`
+    Crossref,
 }
 
 impl Format {
+    /// The query of the url of a record in this format
     pub fn get_param(&self) -> &str {
         match self {
             Format::Standard => "?param=1",
             Format::Condensed => "?param=0",
+            Format::Crossref => "?param=2",
         }
     }
 }
 
-#[derive(Deserialize, Debug)]
+/// A response of the search api of DBLP, with the JSON it comes as
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DblpResponse {
     result: DblpResult,
 }
 
 impl DblpResponse {
+    /// The hits, in the order DBLP ranks them
     pub fn matches(&self) -> impl Iterator<Item = DblpHitInfo> + '_ {
         self.result.hits.hit.iter().map(|hit| hit.info.clone())
     }
 
-    pub fn query(query: &str, bibformat: Format) -> Result<Self> {
-        URLS.iter()
-            .map(|url| {
-                let url = format!(
-                    "{}/search/publ/api?q={}&format=json&{}",
-                    url,
-                    query,
-                    bibformat.get_param()
-                );
-                ureq::get(&url).call()
-            })
-            .find(|r| r.is_ok())
-            .context("no successful response")??
-            .into_json()
-            .context("error converting from json")
+    /// The hits of any of the given types, or all of them if none is given
+    pub fn matches_of<'a>(
+        &'a self,
+        types: &'a [HitType],
+    ) -> impl Iterator<Item = DblpHitInfo> + 'a {
+        self.matches()
+            .filter(move |hit| types.is_empty() || types.iter().any(|t| hit.is_a(*t)))
+    }
+
+    /// Queries the first healthy mirror that responds, failing with the
+    /// error of the last one tried if none does. Asks for successive pages
+    /// until there are `max_hits` hits, or no more.
+    pub fn query(query: &str, bibformat: Format, max_hits: usize) -> Result<Self> {
+        Self::query_pages(query, bibformat, max_hits, get_search)
+    }
+
+    fn query_pages(
+        query: &str,
+        bibformat: Format,
+        max_hits: usize,
+        mut fetch: impl FnMut(&str) -> Result<String>,
+    ) -> Result<Self> {
+        let mut pages = Pages::new(query, bibformat, max_hits);
+        while let Some(path) = pages.next_path() {
+            pages.add(&fetch(&path)?)?;
+        }
+        Ok(pages.finish())
     }
+
+    fn from_hits(hits: DblpHits) -> Self {
+        Self {
+            result: DblpResult { hits },
+        }
+    }
+
+    /// Parses the body of a response of the search api, independently of
+    /// how it was fetched
+    pub fn parse(body: &str) -> Result<Self> {
+        serde_json::from_str(body).map_err(|e| DblpError::from_json(e, body, None))
+    }
+}
+
+/// The pages of the hits of a query, asked one after the other until there
+/// are `max_hits` hits or no more, however they are fetched
+pub struct Pages<'a> {
+    query: &'a str,
+    bibformat: Format,
+    max_hits: usize,
+    /// the first hit of the next page
+    first: usize,
+    done: bool,
+    resp: Option<DblpResponse>,
+}
+
+impl<'a> Pages<'a> {
+    pub fn new(query: &'a str, bibformat: Format, max_hits: usize) -> Self {
+        Self {
+            query,
+            bibformat,
+            max_hits,
+            first: 0,
+            done: false,
+            resp: None,
+        }
+    }
+
+    /// The path of the search api of the next page, if it is needed
+    pub fn next_path(&self) -> Option<String> {
+        if self.done {
+            return None;
+        }
+        let wanted = (self.max_hits - self.first).min(PAGE_HITS);
+        Some(search_path(self.query, self.bibformat, self.first, wanted))
+    }
+
+    /// Adds the hits in the body of the response for the next page
+    pub fn add(&mut self, body: &str) -> Result<()> {
+        let hits = DblpResponse::parse(body)?.result.hits;
+        let sent = hits.sent.unwrap_or(hits.hit.len());
+        self.first += sent;
+        self.done = sent == 0 || self.first >= self.max_hits || self.first >= hits.total;
+        match self.resp.as_mut() {
+            Some(resp) => resp.result.hits.hit.extend(hits.hit),
+            None => self.resp = Some(DblpResponse::from_hits(hits)),
+        }
+        Ok(())
+    }
+
+    /// The response with the hits of all the pages
+    pub fn finish(self) -> DblpResponse {
+        let mut resp = self.resp.expect("there is at least a page");
+        resp.result.hits.hit.truncate(self.max_hits);
+        resp
+    }
+}
+
+/// The hits of the query, at most `max_hits` of them, from the first
+/// healthy mirror that responds
+pub fn query(query: &str, bibformat: Format, max_hits: usize) -> Result<Vec<DblpHitInfo>> {
+    Ok(DblpResponse::query(query, bibformat, max_hits)?
+        .matches()
+        .collect())
 }
 
-#[derive(Deserialize, Debug)]
+/// A search of DBLP, in the given format and for at most `max_hits` hits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub text: String,
+    pub format: Format,
+    pub max_hits: usize,
+}
+
+impl Query {
+    /// The search for the words, for the default number of hits in the
+    /// condensed format
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            format: Format::Condensed,
+            max_hits: MAX_HITS,
+        }
+    }
+
+    /// The hits, from the first healthy mirror that responds
+    pub fn execute(&self) -> Result<Vec<DblpHitInfo>> {
+        query(&self.text, self.format, self.max_hits)
+    }
+
+    /// Like `execute`, without blocking the thread
+    #[cfg(feature = "async")]
+    pub async fn execute_async(
+        &self,
+        fetcher: &crate::fetcher::AsyncFetcher,
+    ) -> Result<Vec<DblpHitInfo>> {
+        let mut pages = Pages::new(&self.text, self.format, self.max_hits);
+        while let Some(path) = pages.next_path() {
+            pages.add(&fetcher.get_search(&path).await?)?;
+        }
+        Ok(pages.finish().matches().collect())
+    }
+}
+
+/// Default number of hits to ask
+pub const MAX_HITS: usize = 100;
+/// Hits to ask when looking a paper up by its title, which puts it among
+/// the first ones
+pub const TITLE_HITS: usize = 30;
+/// The most hits DBLP sends in a single response
+const PAGE_HITS: usize = 1000;
+
+/// Delay between successive requests to DBLP in batch operations
+pub const FETCH_DELAY: Duration = Duration::from_millis(500);
+
+/// The words of the query, joined as the search api expects them
+pub fn join_param_string(strings: &[String]) -> String {
+    strings
+        .iter()
+        .flat_map(|v| v.split(' '))
+        .map(|v| v.trim())
+        .collect::<Vec<&str>>()
+        .join("+")
+}
+
+/// The path of the search api, on any mirror, for `hits` hits from the
+/// `first` one, counting from 0
+pub fn search_path(query: &str, bibformat: Format, first: usize, hits: usize) -> String {
+    format!(
+        "/search/publ/api?q={}&format=json&h={}&f={}&{}",
+        query,
+        hits,
+        first,
+        bibformat.get_param().trim_start_matches('?')
+    )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DblpResult {
     hits: DblpHits,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DblpHits {
+    /// Of the query, in all pages
+    #[serde(rename = "@total", default, deserialize_with = "number")]
+    total: usize,
+    /// In this page
+    #[serde(
+        rename = "@sent",
+        default,
+        deserialize_with = "optional_number",
+        skip_serializing_if = "Option::is_none"
+    )]
+    sent: Option<usize>,
+    /// Missing without hits
+    #[serde(default, deserialize_with = "one_or_many")]
     hit: Vec<DblpHit>,
 }
 
-#[derive(Deserialize, Debug)]
+/// A number, that DBLP sends as a string
+fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Number(usize),
+        String(String),
+    }
+    match Number::deserialize(deserializer)? {
+        Number::Number(n) => Ok(n),
+        Number::String(s) => s.trim().parse().map_err(serde::de::Error::custom),
+    }
+}
+
+fn optional_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    number(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DblpHit {
     info: DblpHitInfo,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// The kinds of publications, told apart by the prefix of their key
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum HitType {
+    Conf,
+    Journal,
+    Phd,
+    Book,
+}
+
+impl HitType {
+    fn key_prefix(&self) -> &'static str {
+        match self {
+            HitType::Conf => "conf/",
+            HitType::Journal => "journals/",
+            HitType::Phd => "phd/",
+            HitType::Book => "books/",
+        }
+    }
+}
+
+/// Lists of more people are cut to the first one in citations, by default
+pub const CITATION_MAX_AUTHORS: usize = 3;
+
+/// A publication found by a search, as DBLP describes it
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DblpHitInfo {
+    /// Like `conf/soda/Chan16`, without the `DBLP:` of keys of bib files
     pub key: String,
-    pub authors: DblpAuthorEntry,
+    /// Missing for proceedings and other edited volumes
+    #[serde(default)]
+    pub authors: Option<DblpAuthorEntry>,
+    #[serde(default)]
+    pub editors: Option<DblpEditorEntry>,
     pub title: String,
-    pub venue: String,
+    /// Missing for theses and books
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub venue: Vec<String>,
+    /// Of books
+    #[serde(default)]
+    pub publisher: Option<String>,
+    /// Of theses
+    #[serde(default)]
+    pub school: Option<String>,
     pub year: String,
+    /// The page of the record
     pub url: String,
+    /// The electronic editions, like the page of the DOI
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub ee: Vec<String>,
+    #[serde(default)]
+    pub doi: Option<String>,
 }
 
 impl DblpHitInfo {
+    /// The url of the bibtex of the record, in the format
     pub fn bib_url(&self, bibtype: Format) -> String {
-        match bibtype {
-            Format::Standard => format!("{}.bib?param=1", self.url),
-            Format::Condensed => format!("{}.bib?param=0", self.url),
-        }
+        format!("{}.bib{}", self.url, bibtype.get_param())
     }
 
+    /// The key of the record in bib files, like `DBLP:conf/soda/Chan16`
     pub fn get_key(&self) -> String {
         format!("DBLP:{}", self.key)
     }
+
+    /// The authors or, for edited volumes without authors, the editors
+    pub fn people(&self) -> Vec<String> {
+        match (&self.authors, &self.editors) {
+            (Some(authors), _) => authors.as_vec(),
+            (None, Some(editors)) => editors.as_vec(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// The text that queries are matched against, in the picker too
+    pub fn search_text(&self) -> String {
+        format!("{} {}", self.title, self.people().join(" "))
+    }
+
+    /// The people, for showing, like `(editors) Foo, Bar` for edited volumes
+    pub fn byline(&self) -> String {
+        let people = self.people().join(", ");
+        match (&self.authors, &self.editors) {
+            (None, Some(_)) => format!("(editors) {}", people),
+            _ => people,
+        }
+    }
+
+    /// The hit as printed by `search --json`
+    pub fn to_output(&self) -> crate::output::Hit {
+        crate::output::Hit {
+            key: self.key.clone(),
+            cite_key: self.get_key(),
+            title: self.title.clone(),
+            authors: self
+                .authors
+                .as_ref()
+                .map(|a| a.as_vec())
+                .unwrap_or_default(),
+            editors: self
+                .editors
+                .as_ref()
+                .map(|e| e.as_vec())
+                .unwrap_or_default(),
+            venue: self.venue.clone(),
+            publisher: self.publisher.clone(),
+            school: self.school.clone(),
+            year: self.year.clone(),
+            url: self.url.clone(),
+        }
+    }
+
+    pub fn is_a(&self, hit_type: HitType) -> bool {
+        self.key.starts_with(hit_type.key_prefix())
+    }
+
+    /// Where and when it was published, like `SODA 2016`, or
+    /// `PhD thesis, MIT, 2019` for theses, which have no venue
+    pub fn published_in(&self) -> String {
+        if !self.venue.is_empty() {
+            return format!("{} {}", self.venue.join(", "), self.year);
+        }
+        let mut parts: Vec<&str> = Vec::new();
+        if self.is_a(HitType::Phd) {
+            parts.push("PhD thesis");
+        }
+        parts.extend(self.school.as_deref().or(self.publisher.as_deref()));
+        parts.push(&self.year);
+        parts.join(", ")
+    }
+
+    /// A bibliography entry with the main information of the hit, to compare
+    /// it with other entries
+    pub fn to_entry(&self) -> biblatex::Entry {
+        let field = |s: &str| {
+            vec![biblatex::Spanned::zero(biblatex::Chunk::Normal(
+                s.to_owned(),
+            ))]
+        };
+        let mut entry = biblatex::Entry::new(self.get_key(), biblatex::EntryType::Misc);
+        entry.set("title", field(&self.title));
+        entry.set("author", field(&self.people().join(" and ")));
+        entry.set("year", field(&self.year));
+        if let Some(doi) = self.link().strip_prefix("https://doi.org/") {
+            entry.set("doi", field(doi));
+        }
+        entry
+    }
+
+    /// The electronic edition of the paper if any, preferring its DOI,
+    /// otherwise the DBLP record
+    pub fn link(&self) -> &str {
+        self.ee
+            .iter()
+            .find(|ee| ee.starts_with("https://doi.org/"))
+            .or(self.ee.first())
+            .unwrap_or(&self.url)
+    }
+
+    /// A citation to read, like `Foo, Bar. Title. SODA 2016.` and the
+    /// address of the DOI, in markdown or in plain text. Lists of more than
+    /// `max_authors` people keep only the first one, and et al.
+    pub fn citation(&self, markdown: bool, max_authors: usize) -> String {
+        let escape = |text: &str| {
+            if markdown {
+                escape_markdown(text)
+            } else {
+                text.to_owned()
+            }
+        };
+        let people = self.people();
+        let names: Vec<&str> = people.iter().map(|p| without_homonym_number(p)).collect();
+        let mut byline = match names.len() {
+            n if n > max_authors => format!("{} et al.", names[0]),
+            _ => names.join(", "),
+        };
+        if self.authors.is_none() && self.editors.is_some() {
+            byline.push_str(" (editors)");
+        }
+        let title = self.title.trim_end_matches('.');
+        let shown_title = if markdown {
+            format!("*{}*", escape_markdown(title))
+        } else {
+            title.to_owned()
+        };
+        let published = self.published_in();
+        let mut citation = String::new();
+        // each part ends with a period, unless it already ends a sentence
+        for (shown, text) in [
+            (escape(&byline), byline.as_str()),
+            (shown_title, title),
+            (escape(&published), &published),
+        ] {
+            if text.trim().is_empty() {
+                continue;
+            }
+            if !citation.is_empty() {
+                citation.push(' ');
+            }
+            citation.push_str(&shown);
+            if !text.ends_with(['.', '?', '!']) {
+                citation.push('.');
+            }
+        }
+        let doi = self
+            .doi
+            .as_deref()
+            .or_else(|| self.link().strip_prefix("https://doi.org/"));
+        if let Some(doi) = doi {
+            let url = format!("https://doi.org/{}", doi);
+            if markdown {
+                citation.push_str(&format!(" [doi]({})", url));
+            } else {
+                citation.push_str(&format!(" {}", url));
+            }
+        }
+        citation
+    }
+}
+
+/// The name without the number DBLP tells people with the same name apart
+/// with, like `Wei Wang 0001`
+fn without_homonym_number(name: &str) -> &str {
+    match name.rsplit_once(' ') {
+        Some((name, number)) if number.len() == 4 && number.chars().all(|c| c.is_ascii_digit()) => {
+            name
+        }
+        _ => name,
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '[' | ']' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 pub fn bold(s: &str) -> String {
@@ -96,13 +591,118 @@ pub fn underline(s: &str) -> String {
     format!("\x1b[4m{}\x1b[0m", s)
 }
 
+/// The hits as aligned columns, one hit per line, with the titles in bold
+/// and the authors underlined if `styled`
+pub fn hits_table(hits: &[DblpHitInfo], styled: bool) -> String {
+    let rows: Vec<[String; 6]> = hits
+        .iter()
+        .map(|hit| {
+            let venue = if hit.venue.is_empty() {
+                let venue = hit.school.as_ref().or(hit.publisher.as_ref());
+                venue.cloned().unwrap_or_default()
+            } else {
+                hit.venue.join(", ")
+            };
+            [
+                hit.get_key(),
+                hit.year.clone(),
+                venue,
+                hit.title.clone(),
+                hit.byline(),
+                hit.url.clone(),
+            ]
+        })
+        .collect();
+    let mut widths = [0; 6];
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in rows.iter() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                let pad = " ".repeat(width - cell.chars().count());
+                match (i, styled) {
+                    (3, true) => format!("{}{}", bold(cell), pad),
+                    (4, true) => format!("{}{}", underline(cell), pad),
+                    (5, _) => cell.clone(),
+                    _ => format!("{}{}", cell, pad),
+                }
+            })
+            .collect();
+        table.push_str(&cells.join("  "));
+        table.push('\n');
+    }
+    table
+}
+
+/// The record for people, with its authors on the first line, its title
+/// in bold on the second, where it was published on the third and then its
+/// pages and links, styled only if `styled`
+pub fn render_record(entry: &biblatex::Entry, styled: bool) -> String {
+    use biblatex::ChunksExt;
+    let field = |name: &str| {
+        entry
+            .fields
+            .get(name)
+            .map(|v| crate::bib::normalize_value(&v.format_verbatim()))
+            .filter(|v| !v.is_empty())
+    };
+    let style = |text: String, with: fn(&str) -> String| {
+        if styled {
+            with(&text)
+        } else {
+            text
+        }
+    };
+    let people = field("author").or(field("editor")).unwrap_or_default();
+    let mut venue = field("booktitle")
+        .or(field("journal"))
+        .or(field("school"))
+        .or(field("publisher"))
+        .unwrap_or_default();
+    if let Some(volume) = field("volume") {
+        venue = format!("{} {}", venue, volume);
+        if let Some(number) = field("number") {
+            venue = format!("{}({})", venue, number);
+        }
+    }
+    let published = [Some(venue).filter(|v| !v.is_empty()), field("year")];
+    let mut lines = vec![
+        style(people.replace(" and ", ", "), underline),
+        style(field("title").unwrap_or_default(), bold),
+        published
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", "),
+    ];
+    let details = [
+        ("pages", field("pages")),
+        (
+            "doi",
+            field("doi").map(|doi| format!("https://doi.org/{}", doi)),
+        ),
+        ("ee", field("url")),
+        ("type", Some(crate::bib::entry_type_name(&entry.entry_type))),
+    ];
+    for (name, value) in details {
+        if let Some(value) = value {
+            lines.push(format!("{:<6} {}", format!("{}:", name), value));
+        }
+    }
+    lines.join("\n")
+}
+
+#[cfg(feature = "tui")]
 impl SkimItem for DblpHitInfo {
     fn text(&self) -> Cow<'_, str> {
-        Cow::Owned(format!(
-            "{} {}",
-            self.title,
-            self.authors.as_vec().join(" ")
-        ))
+        Cow::Owned(self.search_text())
     }
 
     fn display<'a>(&'a self, _context: DisplayContext<'a>) -> AnsiString<'a> {
@@ -111,54 +711,479 @@ impl SkimItem for DblpHitInfo {
 
     fn preview(&self, _context: PreviewContext) -> ItemPreview {
         ItemPreview::AnsiText(format!(
-            "{}\n{}\n{} {}",
-            underline(&self.authors.as_vec().join(", ")),
+            "{}\n{}\n{}",
+            underline(&self.byline()),
             bold(&self.title),
-            self.venue,
-            self.year
+            self.published_in()
         ))
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// A person, as an author or an editor
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DblpAuthor {
     #[serde(rename = "text")]
     pub name: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DblpAuthorEntry {
-    pub author: DblpAuthorList,
+    #[serde(deserialize_with = "one_or_many")]
+    pub author: Vec<DblpAuthor>,
 }
 
 impl DblpAuthorEntry {
     pub fn as_vec(&self) -> Vec<String> {
-        match &self.author {
-            DblpAuthorList::Single(author) => vec![author.name.clone()],
-            DblpAuthorList::List(authors) => authors.iter().map(|a| a.name.clone()).collect(),
-        }
+        self.author.iter().map(|a| a.name.clone()).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DblpEditorEntry {
+    #[serde(deserialize_with = "one_or_many")]
+    pub editor: Vec<DblpAuthor>,
+}
+
+impl DblpEditorEntry {
+    pub fn as_vec(&self) -> Vec<String> {
+        self.editor.iter().map(|e| e.name.clone()).collect()
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(untagged)]
-pub enum DblpAuthorList {
-    Single(DblpAuthor),
-    List(Vec<DblpAuthor>),
+/// Deserializes either a single value or a list of values into a list,
+/// since DBLP gives a single value rather than a list of one element
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
 }
 
-pub fn fetch_bibtex(key: &str) -> anyhow::Result<hayagriva::Entry> {
+/// The path of the bibtex of the record with the given key, on any mirror
+pub fn record_path(key: &str, format: Format) -> String {
     let key = key.replace("DBLP:", "");
-    let fmt = Format::Condensed;
-    let k = key.replace("DBLP:", "");
-    let url = format!("https://dblp.uni-trier.de/rec/{}.bib{}", k, fmt.get_param());
-    let resp = ureq::get(&url).call()?;
-    let s = resp.into_string().context("error converting into string")?;
-    let entry = hayagriva::io::from_biblatex_str(&s)
-        .map_err(|e| anyhow!(e.first().unwrap().clone()))
-        .context("parsing bibtex")?
-        .into_iter()
-        .next()
-        .context("getting first bibliography entry")?;
-    Ok(entry)
+    format!("/rec/{}.bib{}", key, format.get_param())
+}
+
+/// The DBLP key in `DBLP:conf/soda/Chan16`, `conf/soda/Chan16`, or the
+/// address of the record, like `https://dblp.org/rec/conf/soda/Chan16.html`
+pub fn parse_key(key: &str) -> String {
+    let key = key.trim();
+    let key = key.strip_prefix("DBLP:").unwrap_or(key);
+    let key = match key.find("/rec/") {
+        Some(i) if key.contains("://") => &key[i + "/rec/".len()..],
+        _ => key,
+    };
+    [".html", ".bib", ".xml"]
+        .iter()
+        .fold(key, |key, ext| key.strip_suffix(ext).unwrap_or(key))
+        .to_owned()
+}
+
+/// The page of the record with the given key, for people
+pub fn record_page(key: &str) -> String {
+    format!("https://dblp.org/rec/{}", key.trim_start_matches("DBLP:"))
+}
+
+/// Gets the body at the url, from the cache if fresh enough, counting the
+/// request in the stats, and retrying after rate limits and errors of the
+/// server. Fails with
+/// `NotFound` if the url is the one of a record with the given key, and
+/// there is no such record.
+pub fn get(url: &str, key: Option<&str>) -> Result<String> {
+    let ttl = crate::cache::TTLS.of(url);
+    if let Some(body) = cached(url, ttl) {
+        return Ok(body);
+    }
+    let body = STATS.phase("network", || {
+        crate::retry::with_retries(
+            &crate::retry::POLICY,
+            || {
+                let resp = AGENT
+                    .get(url)
+                    .call()
+                    .map_err(|e| DblpError::from_http(e, key))?;
+                let body = resp.into_string()?;
+                STATS.request(body.len());
+                Ok(body)
+            },
+            std::thread::sleep,
+        )
+    })?;
+    if ttl.is_some() {
+        crate::cache::put(url, &body);
+    }
+    Ok(body)
+}
+
+/// The body of the url in the cache, if it is cached for `ttl` and fresh
+pub fn cached(url: &str, ttl: Option<Duration>) -> Option<String> {
+    let body = ttl.and_then(|ttl| crate::cache::get(url, ttl))?;
+    STATS.cache_hit();
+    Some(body)
+}
+
+/// Gets the path of the search api from the mirrors, skipping the ones
+/// that answer with something other than the JSON of DBLP
+fn get_search(path: &str) -> Result<String> {
+    crate::mirrors::get_from_mirrors(&crate::mirrors::HEALTH, path, |url| {
+        check_search(url, get(url, None)?)
+    })
+    .map_err(|failed| failed.error)
+}
+
+/// The body of the search api at the url, or `NotDblp` if it is not the
+/// JSON of DBLP at all
+pub fn check_search(url: &str, body: String) -> Result<String> {
+    let value: Option<serde_json::Value> = serde_json::from_str(&body).ok();
+    if value.as_ref().and_then(|v| v.get("result")).is_none() {
+        return Err(DblpError::NotDblp {
+            url: url.to_owned(),
+            snippet: body.chars().take(40).collect(),
+        });
+    }
+    Ok(body)
+}
+
+/// Gets the path from the mirrors, skipping the ones that keep failing
+fn get_from_mirrors(path: &str, key: Option<&str>) -> Result<String, Failed> {
+    crate::mirrors::get_from_mirrors(&crate::mirrors::HEALTH, path, |url| get(url, key))
+}
+
+/// Fetches the bibtex of the record with the given key. Fails with
+/// `NotFound` if DBLP has no such record.
+pub fn fetch_record(key: &str, format: Format) -> Result<String> {
+    fetch_record_from_mirrors(key, format).map_err(|failed| failed.error)
+}
+
+/// Like `fetch_record`, telling which mirror failed
+pub fn fetch_record_from_mirrors(key: &str, format: Format) -> Result<String, Failed> {
+    get_from_mirrors(&record_path(key, format), Some(key))
+}
+
+/// Like `fetch_record`, without blocking the thread
+#[cfg(feature = "async")]
+pub async fn fetch_record_async(
+    fetcher: &crate::fetcher::AsyncFetcher,
+    key: &str,
+    format: Format,
+) -> Result<String> {
+    fetcher
+        .get_from_mirrors(&record_path(key, format), Some(key))
+        .await
+        .map_err(|failed| failed.error)
+}
+
+pub fn fetch_bibtex(key: &str) -> Result<hayagriva::Entry> {
+    let s = fetch_record(key, Format::Condensed)?;
+    crate::bib::hayagriva_entry(&s)
+}
+
+#[test]
+fn test_editors() {
+    let body = include_str!("../tests/fixtures/editors-search.json");
+    let hits: Vec<DblpHitInfo> = DblpResponse::parse(body).unwrap().matches().collect();
+    assert_eq!(hits.len(), 2);
+    assert!(hits[0].people().is_empty());
+    assert_eq!(hits[0].byline(), "");
+    assert_eq!(
+        hits[1].people(),
+        vec!["Michael T. Goodrich", "Catherine C. McGeoch"]
+    );
+    assert_eq!(
+        hits[1].byline(),
+        "(editors) Michael T. Goodrich, Catherine C. McGeoch"
+    );
+    assert_eq!(hits[1].to_output().authors, Vec::<String>::new());
+    assert_eq!(hits[1].to_output().editors.len(), 2);
+    assert!(hits[1].search_text().contains("Goodrich"));
+}
+
+#[test]
+fn test_zero_hits() {
+    let captured = r#"{"result":{"query":"nosuchpaperxyz*","status":{"@code":"200","text":"OK"},
+        "time":{"@unit":"msecs","text":"1.23"},"completions":{"@total":"0","@computed":"0","@sent":"0"},
+        "hits":{"@total":"0","@computed":"0","@sent":"0","@first":"0"}}}"#;
+    let resp = DblpResponse::parse(captured).unwrap();
+    assert_eq!(resp.matches().count(), 0);
+    assert_eq!(resp.result.hits.total, 0);
+    // nor does asking for more pages
+    let resp =
+        DblpResponse::query_pages("q", Format::Condensed, 5000, |_| Ok(captured.into())).unwrap();
+    assert_eq!(resp.matches().count(), 0);
+
+    let empty_list = r#"{"result": {"hits": {"@total": "0", "hit": []}}}"#;
+    assert_eq!(
+        DblpResponse::parse(empty_list).unwrap().matches().count(),
+        0
+    );
+}
+
+#[test]
+fn test_query_pages() {
+    // a query with 2500 hits, served as DBLP does
+    let total = 2500;
+    let mut paths = Vec::new();
+    let mut fetch = |path: &str| {
+        paths.push(path.to_owned());
+        let param = |name: &str| -> usize {
+            let start = path.find(&format!("&{}=", name)).unwrap() + name.len() + 2;
+            path[start..].split('&').next().unwrap().parse().unwrap()
+        };
+        let (h, f) = (param("h"), param("f"));
+        let hits: Vec<String> = (f..(f + h).min(total))
+            .map(|i| {
+                format!(
+                    r#"{{"info": {{"key": "conf/x/{}", "authors": {{"author": {{"text": "A"}}}},
+                    "title": "T", "venue": "X", "year": "2020", "url": "u"}}}}"#,
+                    i
+                )
+            })
+            .collect();
+        Ok(format!(
+            r#"{{"result": {{"hits": {{"@total": "{}", "@sent": "{}", "@first": "{}", "hit": [{}]}}}}}}"#,
+            total,
+            hits.len(),
+            f,
+            hits.join(",")
+        ))
+    };
+    let resp = DblpResponse::query_pages("q", Format::Condensed, 1200, &mut fetch).unwrap();
+    let keys: Vec<String> = resp.matches().map(|hit| hit.key).collect();
+    assert_eq!(keys.len(), 1200);
+    assert_eq!(keys[1000], "conf/x/1000");
+    let resp = DblpResponse::query_pages("q", Format::Condensed, 5000, &mut fetch).unwrap();
+    assert_eq!(resp.matches().count(), total);
+    assert_eq!(
+        paths,
+        [
+            "/search/publ/api?q=q&format=json&h=1000&f=0&param=0",
+            "/search/publ/api?q=q&format=json&h=200&f=1000&param=0",
+            "/search/publ/api?q=q&format=json&h=1000&f=0&param=0",
+            "/search/publ/api?q=q&format=json&h=1000&f=1000&param=0",
+            "/search/publ/api?q=q&format=json&h=1000&f=2000&param=0",
+        ]
+    );
+}
+
+#[test]
+fn test_hits_table() {
+    let body = r#"{"result": {"hits": {"@total": "2", "hit": [{"info": {
+        "key": "conf/soda/Chan16",
+        "authors": {"author": {"@pid": "1", "text": "Timothy M. Chan"}},
+        "title": "A Title.", "venue": "SODA", "year": "2016",
+        "url": "https://dblp.org/rec/conf/soda/Chan16"}}, {"info": {
+        "key": "phd/Doe19",
+        "authors": {"author": [{"@pid": "2", "text": "Jane Doe"}, {"@pid": "3", "text": "J. Roe"}]},
+        "title": "A Thesis.", "school": "MIT", "year": "2019",
+        "url": "https://dblp.org/rec/phd/Doe19"}}]}}}"#;
+    let hits: Vec<DblpHitInfo> = DblpResponse::parse(body).unwrap().matches().collect();
+    assert_eq!(
+        hits_table(&hits, false),
+        "DBLP:conf/soda/Chan16  2016  SODA  A Title.   Timothy M. Chan   https://dblp.org/rec/conf/soda/Chan16\n\
+         DBLP:phd/Doe19         2019  MIT   A Thesis.  Jane Doe, J. Roe  https://dblp.org/rec/phd/Doe19\n"
+    );
+    assert!(hits_table(&hits, true).contains(&bold("A Title.")));
+}
+
+#[test]
+fn test_render_record() {
+    let src = include_str!("../tests/fixtures/Chan16.bib").replace(
+        "  year",
+        "  url          = {https://doi.org/10.1137/1.9781611974331.ch38},\n  year",
+    );
+    let bibliography = biblatex::Bibliography::parse(&src).unwrap();
+    let entry = bibliography.iter().next().unwrap();
+    let rendered = render_record(entry, false);
+    assert_eq!(
+        rendered,
+        "Timothy M. Chan\n\
+         All-Pairs Shortest Paths for Unweighted Undirected Graphs in o(mn) Time\n\
+         Proceedings of the Twenty-Seventh Annual ACM-SIAM Symposium on Discrete Algorithms, SODA 2016, Arlington, VA, USA, January 10-12, 2016, 2016\n\
+         pages: 514--523\n\
+         doi:   https://doi.org/10.1137/1.9781611974331.ch38\n\
+         ee:    https://doi.org/10.1137/1.9781611974331.ch38\n\
+         type:  inproceedings"
+    );
+    assert!(render_record(entry, true).starts_with(&format!(
+        "{}\n{}\n",
+        underline("Timothy M. Chan"),
+        bold("All-Pairs Shortest Paths for Unweighted Undirected Graphs in o(mn) Time")
+    )));
+}
+
+#[test]
+fn test_parse_key() {
+    for given in [
+        "conf/soda/Chan16",
+        "DBLP:conf/soda/Chan16",
+        " conf/soda/Chan16\n",
+        "https://dblp.org/rec/conf/soda/Chan16.html",
+        "https://dblp.uni-trier.de/rec/conf/soda/Chan16.bib",
+    ] {
+        assert_eq!(parse_key(given), "conf/soda/Chan16", "{:?}", given);
+    }
+}
+
+#[test]
+fn test_parse_response() {
+    let body = r#"{"result": {"hits": {"@total": "1", "hit": [{"info": {
+        "key": "conf/soda/Chan16",
+        "authors": {"author": {"@pid": "1", "text": "Timothy M. Chan"}},
+        "title": "A Title.",
+        "venue": "SODA",
+        "year": "2016",
+        "url": "https://dblp.org/rec/conf/soda/Chan16",
+        "ee": "https://doi.org/10.1137/1.9781611974331.ch100"
+    }}]}}}"#;
+    let hits: Vec<DblpHitInfo> = DblpResponse::parse(body).unwrap().matches().collect();
+    assert_eq!(
+        DblpResponse::parse(&body[1..]).unwrap_err().error_code(),
+        "parse"
+    );
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].people(), vec!["Timothy M. Chan"]);
+    assert_eq!(
+        hits[0].link(),
+        "https://doi.org/10.1137/1.9781611974331.ch100"
+    );
+}
+
+#[test]
+fn test_one_or_many() {
+    let info = |authors: &str, venue: &str, ee: &str| {
+        format!(
+            r#"{{"info": {{"key": "conf/soda/Chan16", "authors": {{"author": {}}},
+            "title": "A Title.", "venue": {}, "year": "2016",
+            "url": "https://dblp.org/rec/conf/soda/Chan16" {}}}}}"#,
+            authors, venue, ee
+        )
+    };
+    let single = info(
+        r#"{"text": "Timothy M. Chan"}"#,
+        r#""SODA""#,
+        r#", "ee": "https://doi.org/1""#,
+    );
+    let many = info(
+        r#"[{"text": "Timothy M. Chan"}, {"text": "Foo Bar"}]"#,
+        r#"["SODA", "SIAM"]"#,
+        r#", "ee": ["https://doi.org/1", "https://arxiv.org/1"]"#,
+    );
+    let missing = info(r#"{"text": "Timothy M. Chan"}"#, r#""SODA""#, "");
+
+    let parse = |hits: &str| -> Vec<DblpHitInfo> {
+        let body = format!(r#"{{"result": {{"hits": {{"hit": {}}}}}}}"#, hits);
+        DblpResponse::parse(&body).unwrap().matches().collect()
+    };
+
+    let hits = parse(&single);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].people(), vec!["Timothy M. Chan"]);
+    assert_eq!(hits[0].venue, vec!["SODA"]);
+    assert_eq!(hits[0].ee, vec!["https://doi.org/1"]);
+
+    let hits = parse(&format!("[{}, {}]", many, missing));
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].people(), vec!["Timothy M. Chan", "Foo Bar"]);
+    assert_eq!(hits[0].venue, vec!["SODA", "SIAM"]);
+    assert_eq!(hits[0].link(), "https://doi.org/1");
+    let mut arxiv_first = hits[0].clone();
+    arxiv_first.ee.reverse();
+    assert_eq!(arxiv_first.link(), "https://doi.org/1");
+    assert!(hits[1].ee.is_empty());
+    assert_eq!(hits[1].link(), "https://dblp.org/rec/conf/soda/Chan16");
+}
+
+#[test]
+fn test_books_and_theses() {
+    let body = include_str!("../tests/fixtures/books-search.json");
+    let response = DblpResponse::parse(body).unwrap();
+    let hits: Vec<DblpHitInfo> = response.matches().collect();
+    assert_eq!(hits.len(), 3);
+    let published: Vec<String> = hits.iter().map(|h| h.published_in()).collect();
+    assert_eq!(
+        published,
+        ["SODA 2016", "PhD thesis, MIT, 2019", "Addison-Wesley, 1997"]
+    );
+    assert_eq!(hits[2].link(), "{base}/rec/books/aw/Knuth97");
+
+    let keys =
+        |types: &[HitType]| -> Vec<String> { response.matches_of(types).map(|h| h.key).collect() };
+    assert_eq!(keys(&[]).len(), 3);
+    assert_eq!(keys(&[HitType::Phd]), ["phd/us/Doe19"]);
+    assert_eq!(
+        keys(&[HitType::Book, HitType::Conf]),
+        ["conf/soda/Chan16", "books/aw/Knuth97"]
+    );
+    assert_eq!(keys(&[HitType::Journal]), Vec::<String>::new());
+}
+
+#[test]
+fn test_citation() {
+    let body = include_str!("../tests/fixtures/books-search.json");
+    let hits: Vec<DblpHitInfo> = DblpResponse::parse(body).unwrap().matches().collect();
+    assert_eq!(
+        hits[0].citation(true, CITATION_MAX_AUTHORS),
+        "Timothy M. Chan. *All-Pairs Shortest Paths for Unweighted Undirected Graphs in o(mn) Time*. SODA 2016. [doi](https://doi.org/10.1137/1.9781611974331.CH38)"
+    );
+    // no DOI, and no venue
+    assert_eq!(
+        hits[1].citation(false, CITATION_MAX_AUTHORS),
+        "Jane Doe. Shortest Paths in Sparse Graphs. PhD thesis, MIT, 2019."
+    );
+    let mut hit = hits[0].clone();
+    hit.title = "Is P = NP_2?".into();
+    hit.doi = None;
+    hit.ee.clear();
+    hit.authors = Some(DblpAuthorEntry {
+        author: ["Wei Wang 0001", "Foo Bar", "Jane Doe", "John Roe"]
+            .iter()
+            .map(|name| DblpAuthor {
+                name: name.to_string(),
+            })
+            .collect(),
+    });
+    assert_eq!(
+        hit.citation(true, 3),
+        "Wei Wang et al. *Is P = NP\\_2?* SODA 2016."
+    );
+    assert_eq!(
+        hit.citation(false, 4),
+        "Wei Wang, Foo Bar, Jane Doe, John Roe. Is P = NP_2? SODA 2016."
+    );
+    hit.authors = None;
+    hit.editors = None;
+    assert_eq!(hit.citation(false, 3), "Is P = NP_2? SODA 2016.");
+}
+
+#[test]
+fn test_parse_base_url() {
+    for (given, expected) in [
+        ("https://dblp.org", "https://dblp.org"),
+        (
+            " http://proxy.example.org:8080/dblp/ ",
+            "http://proxy.example.org:8080/dblp",
+        ),
+    ] {
+        assert_eq!(parse_base_url(given).unwrap(), expected);
+    }
+    for wrong in [
+        "dblp.org",
+        "ftp://dblp.org",
+        "https://",
+        "https://dblp.org/?q=x",
+    ] {
+        assert!(parse_base_url(wrong).is_err(), "{}", wrong);
+    }
 }
@@ -0,0 +1,240 @@
+/// field by field differences between two versions of an entry
+use crate::bib;
+use biblatex::{ChunksExt, Entry};
+use std::{borrow::Cow, collections::HashMap};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    Type(String, String),
+    Added(String, String),
+    Removed(String, String),
+    Changed(String, String, String),
+}
+
+/// The changes turning `old` into `new`. Values differing only in ways the
+/// fingerprint ignores, like whitespace and braces, are unchanged.
+pub fn field_diff(old: &Entry, new: &Entry) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let (old_type, new_type) = (
+        old.entry_type.to_string().to_lowercase(),
+        new.entry_type.to_string().to_lowercase(),
+    );
+    if old_type != new_type {
+        changes.push(FieldChange::Type(old_type, new_type));
+    }
+    let value = |entry: &Entry, name: &str| {
+        entry
+            .fields
+            .get(name)
+            .map(|v| bib::normalize_value(&v.format_verbatim()))
+    };
+    let mut names: Vec<&String> = old.fields.keys().chain(new.fields.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        match (value(old, name), value(new, name)) {
+            (None, Some(v)) => changes.push(FieldChange::Added(name.clone(), v)),
+            (Some(v), None) => changes.push(FieldChange::Removed(name.clone(), v)),
+            (Some(o), Some(n)) if o != n => changes.push(FieldChange::Changed(name.clone(), o, n)),
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// The keys of the entries added, removed and changed from one version of
+/// a bib file to the next
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EntryChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// The entries that differ between the two sources, in the order of `new`,
+/// and then of `old` for the removed ones. Entries changed by a single
+/// character count as changed.
+pub fn entry_changes<'a>(old: &'a str, new: &'a str) -> anyhow::Result<EntryChanges> {
+    let (old, new) = (bib::split_entries(old)?, bib::split_entries(new)?);
+    let texts = |entries: &[bib::SourceEntry<'a>]| -> HashMap<String, &'a str> {
+        entries.iter().cloned().collect()
+    };
+    let (old_texts, new_texts) = (texts(&old), texts(&new));
+    let mut changes = EntryChanges::default();
+    for (key, text) in &new {
+        match old_texts.get(key) {
+            None => changes.added.push(key.clone()),
+            Some(old_text) if old_text != text => changes.changed.push(key.clone()),
+            Some(_) => {}
+        }
+    }
+    changes.removed = old
+        .into_iter()
+        .map(|(key, _)| key)
+        .filter(|key| !new_texts.contains_key(key))
+        .collect();
+    Ok(changes)
+}
+
+/// Values longer than this many bytes are truncated when rendered
+pub const RENDER_LIMIT: usize = 500;
+
+/// Truncates values too long to be shown, with a marker telling their size
+pub fn truncate_field(value: &str) -> Cow<'_, str> {
+    if value.len() <= RENDER_LIMIT {
+        return Cow::Borrowed(value);
+    }
+    let mut end = RENDER_LIMIT;
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}… (field truncated, {})",
+        &value[..end],
+        bib::human_size(value.len())
+    ))
+}
+
+/// Renders the changes one per line, colored if asked to
+pub fn render(changes: &[FieldChange], color: bool) -> String {
+    let paint = |code: &str, s: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, s)
+        } else {
+            s
+        }
+    };
+    changes
+        .iter()
+        .map(|change| match change {
+            FieldChange::Type(o, n) => paint("33", format!("~ type: {} → {}", o, n)),
+            FieldChange::Added(name, v) => {
+                paint("32", format!("+ {} = {}", name, truncate_field(v)))
+            }
+            FieldChange::Removed(name, v) => {
+                paint("31", format!("- {} = {}", name, truncate_field(v)))
+            }
+            FieldChange::Changed(name, o, n) => paint(
+                "33",
+                format!("~ {}: {} → {}", name, truncate_field(o), truncate_field(n)),
+            ),
+        })
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// The lines of `old` and `new` like in a unified diff, with `-` before
+/// the removed ones, `+` before the added ones and a space before the
+/// others, colored if asked to. Meant for entries, small enough to be
+/// shown whole.
+pub fn unified(old: &str, new: &str, color: bool) -> String {
+    let (old, new): (Vec<&str>, Vec<&str>) = (old.lines().collect(), new.lines().collect());
+    // lcs[i][j] is the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let paint = |code: &str, s: String| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m\n", code, s)
+        } else {
+            s + "\n"
+        }
+    };
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push_str(&format!(" {}\n", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&paint("31", format!("-{}", old[i])));
+            i += 1;
+        } else {
+            out.push_str(&paint("32", format!("+{}", new[j])));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+fn parse_pair(a: &str, b: &str) -> (Entry, Entry) {
+    let parse = |s| {
+        biblatex::Bibliography::parse(s)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+    };
+    (parse(a), parse(b))
+}
+
+#[test]
+fn test_render_field_diff() {
+    let cases = [
+        (
+            "@inproceedings{a, title = {{A} Title}, pages = {101-110}, note = {foo}}",
+            "@inproceedings{a, title = {A   Title}, pages = {101--112}, volume = {58}}",
+            "- note = foo\n~ pages: 101-110 → 101--112\n+ volume = 58\n",
+        ),
+        (
+            "@inproceedings{a, title = {A Title}, booktitle = {SODA}}",
+            "@article{a, title = {A Title}, journal = {TALG}}",
+            "~ type: inproceedings → article\n- booktitle = SODA\n+ journal = TALG\n",
+        ),
+        (
+            "@misc{a, title = {A Title}, year = 2021}",
+            "@misc{a,\n  year = {2021},\n  title = {{A Title}}\n}",
+            "",
+        ),
+    ];
+    for (old, new, expected) in cases {
+        let (old, new) = parse_pair(old, new);
+        assert_eq!(render(&field_diff(&old, &new), false), expected);
+    }
+}
+
+#[test]
+fn test_truncate_field() {
+    assert_eq!(truncate_field("short"), "short");
+    let huge = "é".repeat(1_000_000);
+    let truncated = truncate_field(&huge);
+    assert!(truncated.len() < 1000);
+    assert!(truncated.ends_with("… (field truncated, 2.0 MB)"));
+}
+
+#[test]
+fn test_unified() {
+    let old = "@misc{a,\n  title = {Old},\n  year = {2016}\n}";
+    let new = "@misc{a,\n  title = {New},\n  year = {2016},\n  pages = {1--2}\n}";
+    assert_eq!(
+        unified(old, new, false),
+        " @misc{a,\n-  title = {Old},\n-  year = {2016}\n+  title = {New},\n+  year = {2016},\n+  pages = {1--2}\n }\n"
+    );
+    assert_eq!(unified(old, old, false).lines().count(), 4);
+    assert!(unified(old, old, true).starts_with(" @misc{a,\n"));
+    assert_eq!(unified("", "x", true), "\x1b[32m+x\x1b[0m\n");
+}
+
+#[test]
+fn test_entry_changes() {
+    let old = "@misc{a, title = {A}}\n\n@misc{b, title = {B}}\n\n@misc{c, title = {C}}\n";
+    let new = "@misc{d, title = {D}}\n\n@misc{a, title = {A}}\n\n@misc{c, title = {C.}}\n";
+    assert_eq!(
+        entry_changes(old, new).unwrap(),
+        EntryChanges {
+            added: vec!["d".into()],
+            removed: vec!["b".into()],
+            changed: vec!["c".into()],
+        }
+    );
+    assert_eq!(entry_changes(old, old).unwrap(), EntryChanges::default());
+}
@@ -0,0 +1,222 @@
+/// diagnostics about the tool and its environment, to be pasted in bug reports
+use crate::config;
+use crate::output::{Backend, BibStatus, CacheStatus, DoctorReport, MirrorStatus, Provenance};
+use clap::{parser::ValueSource, ArgMatches};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for each mirror before declaring it unreachable
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where the value of the argument with the given id comes from, the
+/// config setting the defaults of the ones it has
+pub fn provenance(matches: &ArgMatches, id: &str, layers: &config::Layers) -> Provenance {
+    match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => Provenance::Flag,
+        Some(ValueSource::EnvVariable) => Provenance::Env,
+        _ if layers.source(id).is_some() => Provenance::Config,
+        _ => Provenance::Default,
+    }
+}
+
+/// The number of entries of the bib file, and why it does not parse
+pub fn bib_status(path: anyhow::Result<PathBuf>) -> BibStatus {
+    let path = match path {
+        Ok(path) => path,
+        Err(err) => {
+            return BibStatus {
+                path: None,
+                entries: 0,
+                errors: vec![err.to_string()],
+            }
+        }
+    };
+    let parsed = std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|src| biblatex::Bibliography::parse(&src).map_err(|e| e.to_string()));
+    match parsed {
+        Ok(bibliography) => BibStatus {
+            path: Some(path),
+            entries: bibliography.len(),
+            errors: Vec::new(),
+        },
+        Err(err) => BibStatus {
+            path: Some(path),
+            entries: 0,
+            errors: vec![err],
+        },
+    }
+}
+
+pub fn cache_status(dir: PathBuf) -> CacheStatus {
+    let (files, bytes) = walkdir::WalkDir::new(&dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .fold((0, 0), |(files, bytes), m| (files + 1, bytes + m.len()));
+    CacheStatus {
+        path: dir,
+        files,
+        bytes,
+    }
+}
+
+/// Times a single lightweight request to each mirror
+pub fn probe_mirrors(urls: &[String]) -> Vec<MirrorStatus> {
+    let agent = ureq::AgentBuilder::new().timeout(MIRROR_TIMEOUT).build();
+    urls.iter()
+        .map(|url| {
+            let start = Instant::now();
+            match agent.head(url).call() {
+                Ok(_) => MirrorStatus {
+                    url: url.to_string(),
+                    millis: Some(start.elapsed().as_millis()),
+                    error: None,
+                },
+                Err(err) => MirrorStatus {
+                    url: url.to_string(),
+                    millis: None,
+                    error: Some(err.to_string()),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Looks for the command in the directories of `PATH`
+pub fn probe_command(command: &str) -> Backend {
+    let path =
+        std::env::var_os("PATH").and_then(|dirs| find_in(std::env::split_paths(&dirs), command));
+    Backend {
+        command: command.to_owned(),
+        path,
+    }
+}
+
+fn find_in(dirs: impl Iterator<Item = PathBuf>, command: &str) -> Option<PathBuf> {
+    dirs.map(|dir| dir.join(command)).find(|p| is_executable(p))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file() || path.with_extension("exe").is_file()
+    }
+}
+
+/// The report, for people
+pub fn render(report: &DoctorReport) -> String {
+    let mut out = format!(
+        "dblp {} ({})\nos: {}\n",
+        report.version,
+        report.git_hash.unwrap_or("unknown commit"),
+        report.os
+    );
+    out.push_str("settings:\n");
+    for s in report.settings.iter() {
+        out.push_str(&format!("  {} = {} ({})\n", s.name, s.value, s.source));
+    }
+    match &report.bib.path {
+        Some(path) => out.push_str(&format!(
+            "bib file: {} ({} entries, {} errors)\n",
+            path.display(),
+            report.bib.entries,
+            report.bib.errors.len()
+        )),
+        None => out.push_str("bib file: none\n"),
+    }
+    for err in report.bib.errors.iter() {
+        out.push_str(&format!("  {}\n", err));
+    }
+    out.push_str(&format!(
+        "cache: {} ({} files, {})\n",
+        report.cache.path.display(),
+        report.cache.files,
+        crate::bib::human_size(report.cache.bytes as usize)
+    ));
+    match &report.mirrors {
+        None => out.push_str("mirrors: not checked, offline\n"),
+        Some(mirrors) => {
+            out.push_str("mirrors:\n");
+            for m in mirrors {
+                match (&m.millis, &m.error) {
+                    (Some(ms), _) => out.push_str(&format!("  {} ok in {} ms\n", m.url, ms)),
+                    (None, err) => out.push_str(&format!(
+                        "  {} unreachable: {}\n",
+                        m.url,
+                        err.as_deref().unwrap_or("unknown error")
+                    )),
+                }
+            }
+        }
+    }
+    out.push_str("mirror health:\n");
+    for m in report.mirror_health.iter() {
+        match m.retry_in_s {
+            None if m.failures == 0 => out.push_str(&format!("  {} healthy\n", m.url)),
+            None => out.push_str(&format!("  {} healthy, {} failures\n", m.url, m.failures)),
+            Some(s) => out.push_str(&format!(
+                "  {} skipped after {} failures, retried in {} s\n",
+                m.url, m.failures, s
+            )),
+        }
+    }
+    let backend = |b: &Backend| match &b.path {
+        Some(path) => format!("  {} found at {}\n", b.command, path.display()),
+        None => format!("  {} not found\n", b.command),
+    };
+    out.push_str("clipboard:\n");
+    for b in report.clipboard.iter() {
+        out.push_str(&backend(b));
+    }
+    out.push_str("opener:\n");
+    out.push_str(&backend(&report.opener));
+    out
+}
+
+#[test]
+fn test_provenance() {
+    use clap::CommandFactory;
+    let matches = |args: &[&str]| {
+        crate::Cli::command()
+            .try_get_matches_from(std::iter::once("dblp").chain(args.iter().copied()))
+            .unwrap()
+    };
+    for (args, expected) in [
+        (&["doctor"][..], Provenance::Default),
+        (&["--max-entry-size", "10", "doctor"][..], Provenance::Flag),
+    ] {
+        assert_eq!(
+            provenance(&matches(args), "max_entry_size", &Default::default()),
+            expected,
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn test_find_in() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-doctor-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let command = dir.join("fake-copy");
+    std::fs::write(&command, "#!/bin/sh\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let dirs = || vec![dir.join("missing"), dir.clone()].into_iter();
+    assert_eq!(find_in(dirs(), "fake-copy"), Some(command));
+    assert_eq!(find_in(dirs(), "other-copy"), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
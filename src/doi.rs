@@ -0,0 +1,123 @@
+/// utilities to fetch the bibtex of papers by DOI, for the ones DBLP lacks
+use crate::error::DblpError;
+use crate::stats::STATS;
+use anyhow::{anyhow, bail, Context, Result};
+use biblatex::Bibliography;
+
+pub const URL: &str = "https://doi.org";
+
+/// Environment variable replacing doi.org, to point the tool at a test server
+pub const BASE_VAR: &str = "DBLP_RS_DOI_BASE";
+
+/// Bibtex, or failing that the bibtex style of the formatted citations,
+/// which is what some publishers answer with
+const ACCEPT: &str = "application/x-bibtex, text/bibliography; style=bibtex; q=0.5";
+
+fn base() -> String {
+    std::env::var(BASE_VAR)
+        .map(|base| base.trim_end_matches('/').to_owned())
+        .unwrap_or_else(|_| URL.to_owned())
+}
+
+/// The DOI in `10.1145/3313276.3316350`, `doi:10.1145/...` or an address
+/// like `https://doi.org/10.1145/...`
+pub fn parse_doi(doi: &str) -> Result<String> {
+    let doi = doi.trim();
+    let doi = match doi.find("doi.org/") {
+        Some(i) => &doi[i + "doi.org/".len()..],
+        None => doi,
+    };
+    let doi = doi
+        .strip_prefix("doi:")
+        .or(doi.strip_prefix("DOI:"))
+        .unwrap_or(doi);
+    match doi.split_once('/') {
+        Some((prefix, suffix)) if prefix.starts_with("10.") && !suffix.is_empty() => {
+            Ok(doi.to_owned())
+        }
+        _ => bail!(
+            "{:?} is not a DOI, which look like 10.1145/3313276.3316350",
+            doi
+        ),
+    }
+}
+
+/// The key of the entry of the DOI, which is the same whatever the
+/// publisher calls it, since DOIs do not depend on case
+pub fn key(doi: &str) -> String {
+    format!("doi:{}", doi.to_lowercase())
+}
+
+/// Fetches the bibtex of the DOI from doi.org, following the redirects to
+/// the publisher and keying the entry with `key`
+pub fn fetch(doi: &str) -> Result<String> {
+    let url = format!("{}/{}", base(), doi);
+    let resp = STATS.phase("network", || {
+        match crate::dblp::AGENT.get(&url).set("Accept", ACCEPT).call() {
+            Ok(resp) => Ok(resp),
+            Err(ureq::Error::Status(404, _)) => Err(DblpError::UnknownDoi {
+                doi: doi.to_owned(),
+            }
+            .into()),
+            Err(err) => Err(anyhow!(err).context(format!("resolving DOI {}", doi))),
+        }
+    })?;
+    let content_type = resp.content_type().to_owned();
+    let body = resp.into_string()?;
+    STATS.request(body.len());
+    normalize(&body, doi)
+        .with_context(|| format!("{} answered with {}, not bibtex", url, content_type))
+}
+
+/// Keys the single entry of the bibtex with the key of the DOI, formatted
+/// one field per line
+fn normalize(bibtex: &str, doi: &str) -> Result<String> {
+    let bibliography = Bibliography::parse(bibtex).map_err(|e| DblpError::from_bib(e, bibtex))?;
+    let mut entry = bibliography
+        .into_iter()
+        .next()
+        .context("there is no entry")?;
+    entry.key = key(doi);
+    let bibtex = entry.to_bibtex_string().map_err(|e| anyhow!(e))?;
+    // indented like the entries of DBLP
+    Ok(bibtex
+        .lines()
+        .map(|line| {
+            if line.starts_with(['@', '}']) {
+                line.to_owned()
+            } else {
+                format!("  {}", line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+#[test]
+fn test_parse_doi() {
+    for given in [
+        "10.1145/3313276.3316350",
+        " doi:10.1145/3313276.3316350\n",
+        "https://doi.org/10.1145/3313276.3316350",
+        "http://dx.doi.org/10.1145/3313276.3316350",
+    ] {
+        assert_eq!(parse_doi(given).unwrap(), "10.1145/3313276.3316350");
+    }
+    for wrong in ["conf/soda/Chan16", "10.1145", "10.1145/", "3313276"] {
+        assert!(parse_doi(wrong).is_err(), "{}", wrong);
+    }
+}
+
+#[test]
+fn test_normalize() {
+    let crossref = " @inproceedings{Chan_2019, title={A Paper}, \
+        DOI={10.1145/3313276.3316350}, booktitle={STOC}, year={2019}}";
+    let normalized = normalize(crossref, "10.1145/3313276.3316350").unwrap();
+    assert!(normalized.starts_with("@inproceedings{doi:10.1145/3313276.3316350,"));
+    assert!(
+        normalized.contains("\n  title = {A Paper},\n"),
+        "{}",
+        normalized
+    );
+    assert!(normalize("A. Author, A Paper, STOC 2019.", "10.1/x").is_err());
+}
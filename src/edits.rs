@@ -0,0 +1,268 @@
+/// the changes to the entries of a bibtex file made by update, remove and
+/// dedup, with the picking left to the caller
+use crate::convert::{FetchFailure, OnFetchError};
+use crate::dblp::{self, Format};
+use crate::error::DblpError;
+use crate::keys::{self, KeyIndex};
+use crate::local::{self, LocalEntry};
+use crate::{bib, diff, tex};
+use anyhow::{anyhow, bail, Context, Result};
+use biblatex::{Bibliography, Entry};
+use std::{collections::BTreeMap, path::Path};
+
+/// An entry of the file fetched again from DBLP
+#[derive(Debug)]
+pub struct Update {
+    /// The key of the entry in the file
+    pub key: String,
+    /// The key DBLP has for it, another one if DBLP superseded it
+    pub new_key: String,
+    /// The entry as written in the file
+    pub old: String,
+    /// The fetched entry, with the local fields of the old one, or what the
+    /// policy on fetch errors puts in its place; None if it leaves it out
+    pub new: Option<String>,
+    /// Why the entry could not be fetched, if it could not
+    pub failure: Option<FetchFailure>,
+}
+
+impl Update {
+    pub fn is_up_to_date(&self) -> bool {
+        self.new.as_deref() == Some(self.old.trim())
+    }
+
+    /// How the fields of the entry change, nothing if it is left out
+    pub fn changes(&self) -> Result<Vec<diff::FieldChange>> {
+        let parse = |text: &str| -> Result<Entry> {
+            Bibliography::parse(text)
+                .ok()
+                .and_then(|b| b.into_iter().next())
+                .with_context(|| format!("parsing {}", text))
+        };
+        match &self.new {
+            Some(new) => Ok(diff::field_diff(&parse(&self.old)?, &parse(new)?)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The edit replacing or removing the entry in the source, if the entry
+    /// is still there as it was when it was fetched again
+    pub fn edit(&self, src: &str) -> Result<Option<bib::Edit>> {
+        Ok(bib::entry_spans(src)?
+            .into_iter()
+            .find(|(k, span)| *k == self.key && src[span.clone()] == *self.old)
+            .map(|(_, span)| match &self.new {
+                Some(new) => (span, new.clone()),
+                None => bib::removal(src, &span),
+            }))
+    }
+}
+
+/// Fetches the entry of the bib file with the key again from DBLP, keeping
+/// its local fields. Without a key, the entry is the one `pick` picks among
+/// the ones from DBLP. If it cannot be fetched, the entry becomes what
+/// `on_fetch_error` says, as it would when converting, and the original one
+/// when failing.
+pub fn update_entry(
+    bib_path: &Path,
+    src: &str,
+    key: Option<&str>,
+    index: &KeyIndex,
+    on_fetch_error: OnFetchError,
+    pick: impl FnOnce(Vec<LocalEntry>) -> Result<LocalEntry>,
+) -> Result<Update> {
+    let key = match key {
+        Some(key) => {
+            let key = keys::resolve_key(key, index)?;
+            if !key.starts_with("DBLP:") {
+                bail!("{} is not an entry from DBLP", key);
+            }
+            key
+        }
+        None => {
+            let from_dblp = local::entries(src)?
+                .into_iter()
+                .filter(|entry| entry.key.starts_with("DBLP:"))
+                .collect();
+            pick(from_dblp)?.key
+        }
+    };
+    let span = bib::entry_spans(src)?
+        .into_iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, span)| span)
+        .with_context(|| format!("there is no entry {} in {}", key, bib_path.display()))?;
+    let old = src[span].to_owned();
+    let fetched = match dblp::fetch_record_from_mirrors(&key, Format::Standard) {
+        Ok(fetched) => fetched,
+        Err(failed) => {
+            let failure = FetchFailure {
+                key: key.clone(),
+                error: failed.error,
+                mirror: Some(failed.mirror),
+            };
+            let new = match on_fetch_error {
+                OnFetchError::KeepOriginal | OnFetchError::Fail => Some(old.trim().to_owned()),
+                OnFetchError::Serialize => {
+                    let bibliography =
+                        Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+                    let entry = bibliography.get(&key).expect("the entry is in the file");
+                    Some(entry.to_bibtex_string().map_err(|e| anyhow!(e))?)
+                }
+                OnFetchError::Skip => None,
+            };
+            return Ok(Update {
+                new_key: key.clone(),
+                key,
+                old,
+                new,
+                failure: Some(failure),
+            });
+        }
+    };
+    // the record of a superseded key is the one of its new key
+    let entries = bib::split_entries(&fetched)?;
+    let new_key = entries
+        .iter()
+        .find(|(k, _)| *k == key)
+        .or(entries.first())
+        .map(|(k, _)| k.clone())
+        .with_context(|| format!("DBLP has no entry in the record of {}", key))?;
+    let merged = bib::merge_local(&old, &key, &fetched, &new_key)?;
+    let new = bib::split_entries(&merged)?
+        .into_iter()
+        .find(|(k, _)| *k == new_key)
+        .map(|(_, text)| text.trim().to_owned())
+        .unwrap_or_default();
+    Ok(Update {
+        key,
+        new_key,
+        old,
+        new: Some(new),
+        failure: None,
+    })
+}
+
+/// The keys of the entries to remove: the entry of the query, if it
+/// resolves to a key, or else the ones `pick` picks among the entries
+/// matching it
+pub fn keys_to_remove(
+    src: &str,
+    query: &str,
+    index: &KeyIndex,
+    pick: impl FnOnce(Vec<LocalEntry>) -> Result<Vec<LocalEntry>>,
+) -> Result<Vec<String>> {
+    if let Ok(key) = keys::resolve_key(query, index) {
+        return Ok(vec![key]);
+    }
+    let candidates: Vec<LocalEntry> = local::entries(src)?
+        .into_iter()
+        .filter(|entry| entry.matches(query))
+        .collect();
+    if candidates.is_empty() {
+        bail!("no entries match {:?}", query);
+    }
+    Ok(pick(candidates)?
+        .into_iter()
+        .map(|entry| entry.key)
+        .collect())
+}
+
+/// The first citations of the keys in the .tex files next to the bib file
+pub fn still_cited(bib_path: &Path, keys: &[String]) -> Result<Vec<tex::Citation>> {
+    Ok(tex::first_citations(tex::citations(tex::dir_of(bib_path))?)
+        .into_iter()
+        .filter(|citation| keys.contains(&citation.key))
+        .collect())
+}
+
+/// The source without the entries of each group of duplicates but the one
+/// `keep` picks, and the keys removed with the key kept in their place.
+/// The groups `keep` picks none of are not duplicates, and are kept whole.
+pub fn dedup(
+    src: &str,
+    groups: &[Vec<String>],
+    mut keep: impl FnMut(&[String], Vec<LocalEntry>) -> Result<Option<String>>,
+) -> Result<(String, BTreeMap<String, String>)> {
+    let entries = local::entries(src)?;
+    let mut renames = BTreeMap::new();
+    for group in groups.iter() {
+        let members = entries
+            .iter()
+            .filter(|e| group.contains(&e.key))
+            .cloned()
+            .collect();
+        let Some(kept) = keep(group, members)? else {
+            continue;
+        };
+        for key in group.iter().filter(|k| **k != kept) {
+            renames.insert(key.clone(), kept.clone());
+        }
+    }
+    let removed: Vec<String> = renames.keys().cloned().collect();
+    Ok((bib::remove_entries(src, &removed)?, renames))
+}
+
+#[test]
+fn test_update_edit() {
+    let src = "@misc{DBLP:a, title = {A}}\n\n@misc{b, title = {B}}\n";
+    let update = Update {
+        key: "DBLP:a".into(),
+        new_key: "DBLP:a".into(),
+        old: "@misc{DBLP:a, title = {A}}".into(),
+        new: Some("@misc{DBLP:a, title = {New A}}".into()),
+        failure: None,
+    };
+    assert!(!update.is_up_to_date());
+    assert_eq!(update.changes().unwrap().len(), 1);
+    assert_eq!(
+        update.edit(src).unwrap(),
+        Some((0..26, "@misc{DBLP:a, title = {New A}}".to_owned()))
+    );
+    // changed by someone else meanwhile
+    let changed = "@misc{DBLP:a, title = {Other}}\n";
+    assert_eq!(update.edit(changed).unwrap(), None);
+    // left out
+    let skipped = Update {
+        new: None,
+        ..update
+    };
+    assert!(skipped.changes().unwrap().is_empty());
+    assert_eq!(skipped.edit(src).unwrap(), Some((0..28, String::new())));
+}
+
+#[test]
+fn test_keys_to_remove() {
+    let src = "@misc{DBLP:conf/a/Doe19, title = {Sorting}}\n\n@misc{b, title = {Sorting again}}\n";
+    let index = KeyIndex::from_bib(src, BTreeMap::new()).unwrap();
+    let unpicked = |_: Vec<LocalEntry>| -> Result<Vec<LocalEntry>> { panic!("picking") };
+    assert_eq!(
+        keys_to_remove(src, "Doe19", &index, unpicked).unwrap(),
+        ["DBLP:conf/a/Doe19"]
+    );
+    let picked = keys_to_remove(src, "sorting", &index, |candidates| {
+        assert_eq!(candidates.len(), 2);
+        Ok(candidates[1..].to_vec())
+    });
+    assert_eq!(picked.unwrap(), ["b"]);
+    assert!(keys_to_remove(src, "missing", &index, unpicked).is_err());
+}
+
+#[test]
+fn test_dedup() {
+    let src = "@misc{a, title = {A}}\n\n@misc{b, title = {A}}\n\n@misc{c, title = {C}}\n";
+    let groups = vec![
+        vec!["a".to_owned(), "b".to_owned()],
+        vec!["b".to_owned(), "c".to_owned()],
+    ];
+    let (deduped, renames) = dedup(src, &groups, |group, members| {
+        assert_eq!(members.len(), 2);
+        // the second group is not duplicates
+        Ok((group == ["a", "b"]).then(|| "b".to_owned()))
+    })
+    .unwrap();
+    assert_eq!(deduped, "@misc{b, title = {A}}\n\n@misc{c, title = {C}}\n");
+    assert_eq!(renames, BTreeMap::from([("a".to_owned(), "b".to_owned())]));
+    let (kept, renames) = dedup(src, &groups[..1], |_, _| Ok(None)).unwrap();
+    assert_eq!((kept.as_str(), renames.len()), (src, 0));
+}
@@ -0,0 +1,368 @@
+/// errors of the operations on DBLP and on bibtex files, that callers can
+/// tell apart by variant or by code
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub type Result<T, E = DblpError> = std::result::Result<T, E>;
+
+/// The exit codes of the errors, for scripts. Errors of other kinds exit
+/// with 1, and clap exits with 2 on wrong arguments.
+pub mod exit {
+    pub const OTHER: u8 = 1;
+    pub const NO_RESULTS: u8 = 3;
+    pub const NOT_FOUND: u8 = 4;
+    pub const NETWORK: u8 = 5;
+    pub const RATE_LIMITED: u8 = 6;
+    pub const BIB_FILE: u8 = 7;
+    pub const PARSE: u8 = 8;
+    pub const CLIPBOARD: u8 = 9;
+    /// like being interrupted, the user did not want to go on
+    pub const ABORTED: u8 = 130;
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DblpError {
+    /// DBLP has no record with the key
+    #[error("no DBLP record with key {key}")]
+    NotFound { key: String },
+    /// doi.org does not know the DOI
+    #[error("doi.org knows no DOI {doi}")]
+    UnknownDoi { doi: String },
+    /// arXiv has no preprint with the identifier
+    #[error("arXiv has no preprint {id}")]
+    UnknownArxiv { id: String },
+    /// The search found nothing, or nothing of the types asked for
+    #[error("no hits for {query:?}")]
+    NoResults { query: String },
+    /// DBLP asks to wait before making more requests
+    #[error("{}", rate_limited(.retry_after))]
+    RateLimited { retry_after: Option<Duration> },
+    /// The request did not get a response
+    #[error("error contacting DBLP: {0}")]
+    Transport(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// A mirror answered with something that is not the JSON of DBLP at
+    /// all, like the login page of a proxy
+    #[error("{url} did not answer with the JSON of DBLP, but with `{snippet}`")]
+    NotDblp { url: String, snippet: String },
+    /// A response or a file could not be parsed. The path is missing for
+    /// responses of DBLP.
+    #[error("{}: {message} near `{snippet}`", parsed(.path))]
+    Parse {
+        path: Option<PathBuf>,
+        message: String,
+        snippet: String,
+    },
+    /// Bibtex that does not parse, at a line and column counting from 1
+    #[error("line {line}, column {column}: {message}")]
+    BibParse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("{}", kind.describe(path))]
+    BibFile { kind: BibFileKind, path: PathBuf },
+    /// The bibtex has no entry with the key
+    #[error("there is no entry {key}")]
+    NoEntry { key: String },
+    /// Bibtex that parses, but not into the entry expected, like text with
+    /// several entries or with a key that cannot be written back
+    #[error("{reason}")]
+    InvalidEntry { reason: String },
+    /// None of the ways to copy to the clipboard works here
+    #[error("{reason}")]
+    ClipboardUnavailable { reason: String },
+    /// The user did not select anything
+    #[error("No entry selected! Aborting...")]
+    Aborted,
+}
+
+fn rate_limited(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(wait) => format!("rate limited by DBLP, retry in {}s", wait.as_secs()),
+        None => "rate limited by DBLP".to_owned(),
+    }
+}
+
+fn parsed(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => format!("cannot parse {:?}", path),
+        None => "cannot parse the response of DBLP".to_owned(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BibFileKind {
+    /// There is no bibtex file in the directory, its ancestors or the
+    /// search path of BibTeX
+    Missing,
+    /// There are several bibtex files in the directory, these ones, and
+    /// none was picked to modify
+    Several(Vec<PathBuf>),
+    Directory,
+    Inaccessible(std::io::ErrorKind),
+    /// The path is a symlink, and symlinks are not to be followed
+    Symlink,
+    /// The file does not exist, and cannot be created since its directory
+    /// does not either
+    MissingDirectory,
+}
+
+impl BibFileKind {
+    fn describe(&self, path: &Path) -> String {
+        match self {
+            BibFileKind::Missing => format!("missing bibtex file in {:?}", path),
+            BibFileKind::Several(paths) => {
+                let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+                format!(
+                    "several bibtex files in {:?}: {}, pass --into to pick one",
+                    path,
+                    paths.join(", ")
+                )
+            }
+            BibFileKind::Directory => format!("{:?} is a directory, not a bibtex file", path),
+            BibFileKind::Symlink => format!(
+                "{:?} is a symlink, and --no-follow-symlinks was given",
+                path
+            ),
+            BibFileKind::MissingDirectory => {
+                format!("cannot create {:?}, its directory does not exist", path)
+            }
+            BibFileKind::Inaccessible(kind) => {
+                format!("cannot access bibtex file {:?}: {}", path, kind)
+            }
+        }
+    }
+}
+
+impl DblpError {
+    /// A code identifying the variant, that does not change across releases
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            DblpError::NotFound { .. } => "not_found",
+            DblpError::UnknownDoi { .. } => "unknown_doi",
+            DblpError::UnknownArxiv { .. } => "unknown_arxiv",
+            DblpError::NoResults { .. } => "no_results",
+            DblpError::RateLimited { .. } => "rate_limited",
+            DblpError::Transport(_) => "transport",
+            DblpError::NotDblp { .. } => "not_dblp",
+            DblpError::Parse { .. } => "parse",
+            DblpError::BibParse { .. } => "bib_parse",
+            DblpError::BibFile { .. } => "bib_file",
+            DblpError::NoEntry { .. } => "no_entry",
+            DblpError::InvalidEntry { .. } => "invalid_entry",
+            DblpError::ClipboardUnavailable { .. } => "clipboard_unavailable",
+            DblpError::Aborted => "aborted",
+        }
+    }
+
+    /// The status the binary exits with, one of the codes in `exit`
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            DblpError::NoResults { .. } => exit::NO_RESULTS,
+            DblpError::NotFound { .. }
+            | DblpError::UnknownDoi { .. }
+            | DblpError::UnknownArxiv { .. } => exit::NOT_FOUND,
+            DblpError::Transport(_) | DblpError::NotDblp { .. } => exit::NETWORK,
+            DblpError::RateLimited { .. } => exit::RATE_LIMITED,
+            DblpError::BibFile { .. } => exit::BIB_FILE,
+            DblpError::Parse { .. } | DblpError::BibParse { .. } => exit::PARSE,
+            // about a key or a text the user gave, more often than a file
+            DblpError::NoEntry { .. } | DblpError::InvalidEntry { .. } => exit::OTHER,
+            DblpError::ClipboardUnavailable { .. } => exit::CLIPBOARD,
+            DblpError::Aborted => exit::ABORTED,
+        }
+    }
+
+    /// What the user can do about the error, for the ones where it is not
+    /// obvious from the message
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            DblpError::BibFile {
+                kind: BibFileKind::Missing,
+                ..
+            } => Some("pass the bibtex file with --bibtex, or set bibtex in .dblp.toml"),
+            DblpError::NoResults { .. } => {
+                Some("try fewer or other words, or without --type, or on dblp.org")
+            }
+            DblpError::Transport(_) | DblpError::NotDblp { .. } => {
+                Some("check the connection, or try another mirror with --dblp-url")
+            }
+            DblpError::RateLimited { .. } => Some("wait a little, then try again"),
+            DblpError::ClipboardUnavailable { .. } => {
+                Some("pass --no-clipboard to print instead, or --clipboard osc52 over SSH")
+            }
+            _ => None,
+        }
+    }
+
+    /// The status of the response, for requests that got one
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            DblpError::NotFound { .. } | DblpError::UnknownDoi { .. } => Some(404),
+            DblpError::RateLimited { .. } => Some(429),
+            DblpError::Transport(err) => {
+                if let Some(ureq::Error::Status(status, _)) = err.downcast_ref::<ureq::Error>() {
+                    return Some(*status);
+                }
+                #[cfg(feature = "async")]
+                if let Some(status) = err
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(|e| e.status())
+                {
+                    return Some(status.as_u16());
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Classifies the error of a request. A missing page is reported as
+    /// a missing record only if the request is about a key.
+    pub fn from_http(err: ureq::Error, key: Option<&str>) -> Self {
+        let classified = match &err {
+            ureq::Error::Status(status, resp) => {
+                Self::from_status(*status, resp.header("retry-after"), key)
+            }
+            _ => None,
+        };
+        classified.unwrap_or_else(|| DblpError::Transport(Box::new(err)))
+    }
+
+    /// The error of a response with the status and `Retry-After` header,
+    /// if the status is one of a missing record or of the rate limit
+    pub fn from_status(status: u16, retry_after: Option<&str>, key: Option<&str>) -> Option<Self> {
+        match (status, key) {
+            (404, Some(key)) => Some(DblpError::NotFound {
+                key: key.to_owned(),
+            }),
+            (429, _) => Some(DblpError::RateLimited {
+                retry_after: retry_after
+                    .and_then(|s| s.trim().parse().ok())
+                    .map(Duration::from_secs),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The error of serde_json on the given text, with the text around it
+    pub fn from_json(err: serde_json::Error, text: &str, path: Option<PathBuf>) -> Self {
+        let line = text.lines().nth(err.line().saturating_sub(1)).unwrap_or("");
+        let start = err.column().saturating_sub(20);
+        DblpError::Parse {
+            path,
+            message: err.to_string(),
+            snippet: line.chars().skip(start).take(40).collect(),
+        }
+    }
+
+    /// The error of biblatex on the source, at the line and column where
+    /// it starts rather than at a byte offset
+    pub fn from_bib(err: biblatex::ParseError, src: &str) -> Self {
+        let before = &src[..err.span.start.min(src.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        DblpError::BibParse {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: err.kind.to_string(),
+        }
+    }
+}
+
+impl From<std::io::Error> for DblpError {
+    fn from(err: std::io::Error) -> Self {
+        DblpError::Transport(Box::new(err))
+    }
+}
+
+#[test]
+fn test_from_http() {
+    let status = |code, headers: &str| {
+        let text = format!("HTTP/1.1 {} Status\r\n{}\r\n", code, headers);
+        ureq::Error::Status(code, text.parse::<ureq::Response>().unwrap())
+    };
+    let key = Some("conf/soda/Chan16");
+    let cases = [
+        (status(404, ""), key, "not_found", Some(404)),
+        (status(404, ""), None, "transport", Some(404)),
+        (
+            status(429, "Retry-After: 30\r\n"),
+            key,
+            "rate_limited",
+            Some(429),
+        ),
+        (status(500, ""), key, "transport", Some(500)),
+        (
+            ureq::get("not a url").call().unwrap_err(),
+            key,
+            "transport",
+            None,
+        ),
+    ];
+    for (err, key, code, http_status) in cases {
+        let err = DblpError::from_http(err, key);
+        assert_eq!(err.error_code(), code);
+        assert_eq!(err.http_status(), http_status, "{}", code);
+    }
+    match DblpError::from_http(status(429, "Retry-After: 30\r\n"), None) {
+        DblpError::RateLimited { retry_after } => {
+            assert_eq!(retry_after, Some(Duration::from_secs(30)))
+        }
+        err => panic!("unexpected {:?}", err),
+    }
+}
+
+#[test]
+fn test_from_json() {
+    let text = r#"{"result": {"hits": oops}}"#;
+    let err = serde_json::from_str::<serde_json::Value>(text).unwrap_err();
+    match DblpError::from_json(err, text, None) {
+        DblpError::Parse { path, snippet, .. } => {
+            assert_eq!(path, None);
+            assert!(snippet.contains("oops"), "{}", snippet);
+        }
+        err => panic!("unexpected {:?}", err),
+    }
+    assert_eq!(DblpError::Aborted.error_code(), "aborted");
+}
+
+#[test]
+fn test_from_bib() {
+    let src = "@misc{a, title = {A}}\n\n@misc{b,\n  title = {B}\n";
+    let err = biblatex::Bibliography::parse(src).unwrap_err();
+    let err = DblpError::from_bib(err, src);
+    assert_eq!(err.to_string(), "line 5, column 1: expected comma");
+    assert_eq!(err.exit_code(), exit::PARSE);
+}
+
+#[test]
+fn test_exit_codes() {
+    let errors = [
+        DblpError::NoResults { query: "q".into() },
+        DblpError::NotFound { key: "k".into() },
+        DblpError::Transport("refused".into()),
+        DblpError::RateLimited { retry_after: None },
+        DblpError::BibFile {
+            kind: BibFileKind::Missing,
+            path: PathBuf::from("."),
+        },
+        DblpError::BibParse {
+            line: 1,
+            column: 1,
+            message: "m".into(),
+        },
+        DblpError::ClipboardUnavailable {
+            reason: "none".into(),
+        },
+        DblpError::Aborted,
+    ];
+    let mut codes: Vec<u8> = errors.iter().map(DblpError::exit_code).collect();
+    codes.dedup();
+    assert_eq!(codes.len(), errors.len());
+    assert!(!codes.contains(&1) && !codes.contains(&2));
+    assert!(errors[4].hint().unwrap().contains("--bibtex"));
+}
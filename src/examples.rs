@@ -0,0 +1,362 @@
+/// annotated example command lines, shown by `dblp examples` and in the
+/// help of each subcommand
+use clap::Command;
+
+pub struct Example {
+    pub workflow: &'static str,
+    /// the subcommand the example is about
+    pub subcommand: &'static str,
+    /// the arguments following `dblp`
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "add kmeans coreset streaming",
+        description: "search DBLP, pick a paper, add it to the only .bib file here",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "--bibtex paper/refs.bib add --print-key attention is all you need",
+        description: "add to a specific file, also printing the key to stdout",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "add --type phd --type book shortest paths",
+        description: "only list the PhD theses and books among the hits",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "add --key-pattern '{venue}{yy}-{author1}' attention is all you need",
+        description: "key the added entry like nips17-Vaswani, with a suffix if taken",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "add --first kmeans coreset streaming",
+        description: "add the first hit without asking, say from a Makefile",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add-key",
+        args: "add-key conf/soda/Chan16",
+        description: "add a record whose DBLP key, or page, you already have",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "doi",
+        args: "doi 10.1145/3313276.3316350",
+        description: "add a paper DBLP lacks, with the bibtex of its DOI",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "arxiv",
+        args: "arxiv 2301.01234",
+        description: "add a preprint, unless DBLP already has the paper",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "remove",
+        args: "remove shortest paths",
+        description: "pick entries matching the words and remove them from the file",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "search",
+        args: "search kmeans coreset streaming | grep DBLP:conf",
+        description: "look papers up, without touching any file",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "search",
+        args: "--no-cache search kmeans coreset streaming",
+        description: "ask DBLP again, instead of reusing the hits of the last hour",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "search",
+        args: "--dblp-url https://dblp.example.org search kmeans coreset",
+        description: "go through a proxy of DBLP, or through dblp.org if it fails",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "list",
+        args: "list",
+        description: "find an entry of the bibtex file and copy its key",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "open",
+        args: "open all-pairs shortest paths chan",
+        description: "read a paper, opening its DOI in the browser",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "info",
+        args: "info conf/soda/Chan16",
+        description: "show the pages and links of a record, and whether the file has it",
+    },
+    Example {
+        workflow: "looking up",
+        subcommand: "info",
+        args: "info --bibtex conf/soda/Chan16 > chan.bib",
+        description: "save the bibtex of a record, without the clipboard",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "clip",
+        args: "clip attention is all you need",
+        description: "copy the bibtex of a paper, without touching any file",
+    },
+    Example {
+        workflow: "adding papers",
+        subcommand: "add",
+        args: "--no-clipboard add --first all-pairs shortest paths chan",
+        description: "add a paper on a server without a clipboard, printing its key",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "convert standard",
+        description: "refetch every DBLP entry in the standard format",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "--bibtex refs.bib convert condensed",
+        description: "refetch every DBLP entry in the condensed format",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "convert standard --strip-fields",
+        description: "refetch every DBLP entry without its timestamp, biburl and bibsource",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "convert standard --dry-run",
+        description: "show what converting would change, failing if anything would",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "convert standard --only 'DBLP:conf/soda/*' --skip 'DBLP:journals/corr/*'",
+        description: "refetch only some of the entries, leaving the others as they are",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "convert",
+        args: "convert standard --backup-dir ~/bib-backups",
+        description: "keep a timestamped copy of every version replaced, out of the way",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "update",
+        args: "update conf/soda/Chan16",
+        description: "fetch a single entry again, say once DBLP fixed its pages",
+    },
+    Example {
+        workflow: "converting formats",
+        subcommand: "undo",
+        args: "undo",
+        description: "put back the file as it was before the last convert",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "check",
+        args: "check --venue-consistency --fix --dry-run",
+        description: "show how inconsistent venue strings would be fixed",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "check",
+        args: "check --journal-versions",
+        description: "list conference papers that also have a journal version",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "check",
+        args: "check --keys --fix --rename-citations",
+        description: "give moved DBLP records their new key, in the .bib and .tex files",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "dedup",
+        args: "dedup --report",
+        description: "list the entries that are the same paper, failing if any, for CI",
+    },
+    Example {
+        workflow: "collaborating",
+        subcommand: "merge",
+        args: "merge --base shared-v1.bib --no-interactive coauthor.bib",
+        description: "merge a co-author's edits, marking conflicts with comments",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "sort",
+        args: "sort --by cited --tex paper --dry-run",
+        description: "show the entries in the order they are first cited in paper/",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "sort",
+        args: "sort --by key --check",
+        description: "fail if the entries are not in the order of their keys, for CI",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "tidy",
+        args: "tidy --check",
+        description: "fail if some entries are not formatted like the others, for CI",
+    },
+    Example {
+        workflow: "checking",
+        subcommand: "suggestions",
+        args: "suggestions apply",
+        description: "pick some of the journal versions found by earlier checks, and apply them",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "note",
+        args: "note coresets for k-means",
+        description: "create a notes file for a paper",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "note",
+        args: "note --local --notes-dir papers chan",
+        description: "create the notes of a paper of the bibtex file, in ./papers",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "note",
+        args: "note --template paper.md --no-edit coresets for k-means",
+        description: "start the notes from a template with {{title}} and {{authors}} in it",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes create-missing --tag thesis --dry-run",
+        description: "list the notes that would be created for entries with a keyword",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes validate --fix-typos --extra-key project",
+        description: "report typos in the front matter, offering to rename the keys",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes find shortest paths",
+        description: "pick the notes of a paper by its title or key, and open them",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes sync --dry-run",
+        description: "list the notes whose front matter is older than their entry",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes status --missing-only",
+        description: "list the entries of the bibtex file that have no notes yet",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes list --tag streaming --status unread",
+        description: "list the papers with a tag that are still to read",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes index --group-by tag",
+        description: "write a table of the notes, by tag, in notes/index.md",
+    },
+    Example {
+        workflow: "notes",
+        subcommand: "notes",
+        args: "notes links --graph",
+        description: "print the [[key]] links between the notes, for Graphviz to draw",
+    },
+    Example {
+        workflow: "reporting bugs",
+        subcommand: "doctor",
+        args: "doctor --json --offline",
+        description: "describe the setup, to paste in an issue",
+    },
+    Example {
+        workflow: "packaging",
+        subcommand: "man",
+        args: "man --out target/man",
+        description: "write dblp.1, and one page per subcommand, to target/man",
+    },
+    Example {
+        workflow: "packaging",
+        subcommand: "completions",
+        args: "completions zsh",
+        description:
+            "print the script completing subcommands, flags and keys, to save as _dblp in $fpath",
+    },
+];
+
+fn render<'a>(examples: impl Iterator<Item = &'a Example>) -> String {
+    examples
+        .map(|e| format!("  # {}\n  dblp {}\n", e.description, e.args))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// All the examples, grouped by workflow
+pub fn render_all() -> String {
+    let mut workflows: Vec<&str> = EXAMPLES.iter().map(|e| e.workflow).collect();
+    workflows.dedup();
+    workflows
+        .into_iter()
+        .map(|w| {
+            let examples = EXAMPLES.iter().filter(|e| e.workflow == w);
+            format!("{}:\n{}", w, render(examples))
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Adds the examples of each subcommand to its help
+pub fn with_examples(mut cmd: Command) -> Command {
+    let mut subcommands: Vec<&str> = EXAMPLES.iter().map(|e| e.subcommand).collect();
+    subcommands.sort();
+    subcommands.dedup();
+    for name in subcommands {
+        let examples = EXAMPLES.iter().filter(|e| e.subcommand == name);
+        let help = format!("Examples:\n{}", render(examples));
+        cmd = cmd.mut_subcommand(name, |sub| sub.after_help(help));
+    }
+    cmd
+}
+
+#[test]
+fn test_examples_parse() {
+    use clap::{CommandFactory, FromArgMatches};
+    for example in EXAMPLES {
+        let args = std::iter::once("dblp").chain(example.args.split_whitespace());
+        let matches = with_examples(crate::Cli::command())
+            .try_get_matches_from(args)
+            .unwrap_or_else(|e| panic!("{}: {}", example.args, e));
+        assert!(crate::Cli::from_arg_matches(&matches).is_ok());
+        assert_eq!(
+            matches.subcommand_name(),
+            Some(example.subcommand),
+            "{}",
+            example.args
+        );
+    }
+}
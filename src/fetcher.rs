@@ -0,0 +1,112 @@
+/// requests to DBLP that do not block the thread, for async applications,
+/// going through the same mirrors, retries and cache as the blocking ones
+use crate::dblp;
+use crate::error::{DblpError, Result};
+use crate::mirrors::{Attempts, Failed, HEALTH};
+use crate::retry::{Retries, POLICY};
+use crate::stats::STATS;
+
+/// The client of the requests that do not block. It needs to run on a
+/// tokio runtime.
+#[derive(Debug, Clone)]
+pub struct AsyncFetcher {
+    client: reqwest::Client,
+}
+
+impl Default for AsyncFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncFetcher {
+    /// A fetcher with the timeout of the blocking requests, for connecting
+    /// and then for each read
+    pub fn new() -> Self {
+        let timeout = dblp::timeout();
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .build()
+            .expect("building the http client");
+        Self::with_client(client)
+    }
+
+    /// A fetcher making the requests with the client, to share it with the
+    /// rest of the application
+    pub fn with_client(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+
+    /// Like `dblp::get`
+    pub async fn get(&self, url: &str, key: Option<&str>) -> Result<String> {
+        let ttl = crate::cache::TTLS.of(url);
+        if let Some(body) = dblp::cached(url, ttl) {
+            return Ok(body);
+        }
+        let mut retries = Retries::new(&POLICY);
+        let body = loop {
+            match self.request(url, key).await {
+                Ok(body) => break body,
+                Err(err) => tokio::time::sleep(retries.after(err)?).await,
+            }
+        };
+        if ttl.is_some() {
+            crate::cache::put(url, &body);
+        }
+        Ok(body)
+    }
+
+    async fn request(&self, url: &str, key: Option<&str>) -> Result<String> {
+        let transport = |err: reqwest::Error| DblpError::Transport(Box::new(err));
+        let resp = self.client.get(url).send().await.map_err(transport)?;
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok());
+        if let Some(err) = DblpError::from_status(resp.status().as_u16(), retry_after, key) {
+            return Err(err);
+        }
+        let body = resp
+            .error_for_status()
+            .map_err(transport)?
+            .text()
+            .await
+            .map_err(transport)?;
+        STATS.request(body.len());
+        Ok(body)
+    }
+
+    /// Gets the path from the mirrors, as the blocking requests do
+    pub async fn get_from_mirrors(&self, path: &str, key: Option<&str>) -> Result<String, Failed> {
+        self.get_checked(path, key, false).await
+    }
+
+    /// Gets the path of the search api from the mirrors, skipping the ones
+    /// that answer with something other than the JSON of DBLP
+    pub async fn get_search(&self, path: &str) -> Result<String> {
+        self.get_checked(path, None, true)
+            .await
+            .map_err(|failed| failed.error)
+    }
+
+    async fn get_checked(
+        &self,
+        path: &str,
+        key: Option<&str>,
+        search: bool,
+    ) -> Result<String, Failed> {
+        let mut attempts = Attempts::new(&HEALTH);
+        while let Some(base) = attempts.next_mirror() {
+            let url = format!("{}{}", base, path);
+            let mut res = self.get(&url, key).await;
+            if search {
+                res = res.and_then(|body| dblp::check_search(&url, body));
+            }
+            if let Some(res) = attempts.outcome(&base, res) {
+                return res;
+            }
+        }
+        Err(attempts.failed())
+    }
+}
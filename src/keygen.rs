@@ -0,0 +1,397 @@
+/// utilities to generate the keys of entries from patterns like
+/// `{venue}{yy}-{author1}`
+use anyhow::{bail, Result};
+use biblatex::{ChunksExt, Entry};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The built-in key styles, as patterns
+pub const PRESETS: &[(&str, &str)] = &[
+    ("authoryear", "{author1:lower}{year}"),
+    ("authoryeartitle", "{author1:lower}{year}{title1:lower}"),
+];
+
+/// Words skipped when looking for the significant words of a title
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "by", "for", "from", "how", "in", "is", "of", "on", "or",
+    "the", "to", "towards", "via", "what", "when", "with",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Keep,
+    Lower,
+    Upper,
+    Title,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// The surname of the n-th author
+    Author(usize),
+    /// The initials of the surnames of the first n authors
+    Authors(usize),
+    Year,
+    /// The last two digits of the year
+    Yy,
+    /// The stream of a DBLP key, like `soda` in `DBLP:conf/soda/Chan16`
+    Venue,
+    /// The first n significant words of the title
+    Title(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(Field, Case),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    parts: Vec<Part>,
+}
+
+impl Pattern {
+    /// Parses a pattern, or the name of one of the presets
+    pub fn parse(pattern: &str) -> Result<Self> {
+        if let Some((_, preset)) = PRESETS.iter().find(|(name, _)| *name == pattern) {
+            return Self::parse(preset);
+        }
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            let Some(start) = rest.find('{') else {
+                parts.push(Part::Literal(rest.to_owned()));
+                break;
+            };
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                bail!("unclosed {{ in the key pattern {:?}", pattern);
+            };
+            parts.push(placeholder(&rest[start + 1..start + len])?);
+            rest = &rest[start + len + 1..];
+        }
+        if parts.is_empty() {
+            bail!("the key pattern is empty");
+        }
+        Ok(Self { parts })
+    }
+}
+
+fn placeholder(text: &str) -> Result<Part> {
+    let (name, case) = match text.split_once(':') {
+        Some((name, case)) => (name, Some(case)),
+        None => (text, None),
+    };
+    let case = match case {
+        None => Case::Keep,
+        Some("lower") => Case::Lower,
+        Some("upper") => Case::Upper,
+        Some("title") => Case::Title,
+        Some(other) => bail!("unknown modifier :{} in {{{}}}", other, text),
+    };
+    let numbered = |prefix: &str| -> Option<usize> {
+        name.strip_prefix(prefix)
+            .and_then(|n| n.parse().ok())
+            .filter(|n| *n > 0)
+    };
+    let field = if name == "year" {
+        Field::Year
+    } else if name == "yy" {
+        Field::Yy
+    } else if name == "venue" {
+        Field::Venue
+    } else if let Some(n) = numbered("authors") {
+        Field::Authors(n)
+    } else if let Some(n) = numbered("author") {
+        Field::Author(n)
+    } else if let Some(n) = numbered("title") {
+        Field::Title(n)
+    } else {
+        bail!("unknown placeholder {{{}}} in the key pattern", text);
+    };
+    Ok(Part::Placeholder(field, case))
+}
+
+/// Removes LaTeX markup and diacritics, keeping letters and digits
+pub fn strip(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            // the name of the command goes, its argument stays
+            while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                chars.next();
+            }
+            if chars
+                .peek()
+                .is_some_and(|c| !c.is_alphanumeric() && *c != '{')
+            {
+                chars.next();
+            }
+            continue;
+        }
+        match c {
+            'ß' => out.push_str("ss"),
+            'æ' => out.push_str("ae"),
+            'Æ' => out.push_str("AE"),
+            'œ' => out.push_str("oe"),
+            'Œ' => out.push_str("OE"),
+            'ø' => out.push('o'),
+            'Ø' => out.push('O'),
+            'ł' => out.push('l'),
+            'Ł' => out.push('L'),
+            'đ' => out.push('d'),
+            'Đ' => out.push('D'),
+            'ı' => out.push('i'),
+            c => out.extend(
+                std::iter::once(c)
+                    .nfd()
+                    .filter(|c| !is_combining_mark(*c) && c.is_alphanumeric()),
+            ),
+        }
+    }
+    out
+}
+
+fn apply_case(s: &str, case: Case) -> String {
+    match case {
+        Case::Keep => s.to_owned(),
+        Case::Lower => s.to_lowercase(),
+        Case::Upper => s.to_uppercase(),
+        Case::Title => {
+            let mut chars = s.chars();
+            match chars.next() {
+                Some(first) => first
+                    .to_uppercase()
+                    .chain(chars.flat_map(char::to_lowercase))
+                    .collect(),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+fn field_value(entry: &Entry, name: &str) -> String {
+    entry
+        .get(name)
+        .map(|chunks| chunks.format_verbatim())
+        .unwrap_or_default()
+}
+
+/// The value of the field, in pieces that case modifiers apply to one by
+/// one, so that `{title2:title}` gives `AllPairs`
+fn value(entry: &Entry, field: Field) -> Vec<String> {
+    let surnames = || -> Vec<String> {
+        entry
+            .author()
+            .unwrap_or_default()
+            .iter()
+            .map(|p| {
+                strip(&p.name)
+                    .chars()
+                    .filter(|c| c.is_alphabetic())
+                    .collect()
+            })
+            .filter(|s: &String| !s.is_empty())
+            .collect()
+    };
+    let year = || {
+        let year = field_value(entry, "year");
+        let year = if year.is_empty() {
+            field_value(entry, "date")
+        } else {
+            year
+        };
+        strip(&year).chars().take(4).collect::<String>()
+    };
+    match field {
+        Field::Author(n) => surnames().into_iter().skip(n - 1).take(1).collect(),
+        Field::Authors(n) => surnames()
+            .iter()
+            .take(n)
+            .filter_map(|s| s.chars().next())
+            .map(String::from)
+            .collect(),
+        Field::Year => vec![year()],
+        Field::Yy => {
+            let year = year();
+            vec![year[year.len().saturating_sub(2)..].to_owned()]
+        }
+        Field::Venue => entry
+            .key
+            .strip_prefix("DBLP:")
+            .and_then(|key| key.split('/').nth(1))
+            .map(strip)
+            .into_iter()
+            .collect(),
+        Field::Title(n) => field_value(entry, "title")
+            .split(|c: char| c.is_whitespace() || c == '-' || c == '/')
+            .map(strip)
+            .filter(|w| !w.is_empty() && !STOP_WORDS.contains(&w.to_lowercase().as_str()))
+            .take(n)
+            .collect(),
+    }
+}
+
+/// The key of the entry following the pattern, with the first of the
+/// suffixes `a`, `b`, ..., `z`, `aa`, ... that makes it differ from the
+/// existing keys, regardless of case
+pub fn generate(pattern: &Pattern, entry: &Entry, existing: &[String]) -> String {
+    let base: String = pattern
+        .parts
+        .iter()
+        .map(|part| match part {
+            Part::Literal(s) => s.clone(),
+            Part::Placeholder(field, case) => value(entry, *field)
+                .iter()
+                .map(|piece| apply_case(piece, *case))
+                .collect(),
+        })
+        .collect();
+    let taken = |key: &str| existing.iter().any(|k| k.eq_ignore_ascii_case(key));
+    if !taken(&base) {
+        return base;
+    }
+    (1..)
+        .map(|i| format!("{}{}", base, suffix(i)))
+        .find(|key| !taken(key))
+        .expect("there are infinitely many suffixes")
+}
+
+/// The n-th alphabetic suffix, from 1: `a`, ..., `z`, `aa`, `ab`, ...
+fn suffix(mut n: usize) -> String {
+    let mut out = Vec::new();
+    while n > 0 {
+        n -= 1;
+        out.push(b'a' + (n % 26) as u8);
+        n /= 26;
+    }
+    out.reverse();
+    String::from_utf8(out).expect("ascii letters")
+}
+
+#[cfg(test)]
+fn parse_entry(src: &str) -> Entry {
+    biblatex::Bibliography::parse(src)
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap()
+}
+
+#[test]
+fn test_generate() {
+    let chan = parse_entry(
+        "@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan}, title = {All-Pairs Shortest Paths for Unweighted Undirected Graphs}, year = {2016}}",
+    );
+    let vaswani = parse_entry(
+        "@inproceedings{DBLP:conf/nips/VaswaniSPUJGKP17, author = {Ashish Vaswani and Noam Shazeer and Niki Parmar and Jakob Uszkoreit}, title = {Attention is All you Need}, year = {2017}}",
+    );
+    let accents = parse_entry(
+        r#"@article{local, author = {Paul Erd{\H{o}}s and J{\"o}rg M{\"u}ller and Øystein Ørbæk and José Núñez}, title = {The {\LaTeX} Way of Śtrings}, date = {1999-05}}"#,
+    );
+    let anonymous = parse_entry("@misc{anon, title = {On Nothing}}");
+    for (pattern, entry, expected) in [
+        ("authoryear", &chan, "chan2016"),
+        ("authoryeartitle", &chan, "chan2016all"),
+        ("authoryeartitle", &vaswani, "vaswani2017attention"),
+        ("{venue}{yy}-{author1}", &chan, "soda16-Chan"),
+        (
+            "{venue:upper}{yy}-{author1:lower}",
+            &vaswani,
+            "NIPS17-vaswani",
+        ),
+        ("{authors3}{yy}", &vaswani, "VSP17"),
+        ("{authors9}{yy}", &vaswani, "VSPU17"),
+        ("{author2}", &vaswani, "Shazeer"),
+        ("{author5}{year}", &vaswani, "2017"),
+        ("{title2:title}", &chan, "AllPairs"),
+        ("{title3:lower}", &vaswani, "attentionallyou"),
+        (
+            "{author1}{author2}{author3}{author4}",
+            &accents,
+            "ErdosMullerOrbaekNunez",
+        ),
+        ("{authors4:lower}_{year}", &accents, "emon_1999"),
+        ("{title2}", &accents, "LaTeXWay"),
+        ("{venue}{yy}{title1:upper}", &accents, "99LATEX"),
+        ("{author1:title}{year}{title1}", &anonymous, "Nothing"),
+        ("static", &chan, "static"),
+    ] {
+        let pattern = Pattern::parse(pattern).unwrap();
+        assert_eq!(generate(&pattern, entry, &[]), expected, "{:?}", pattern);
+    }
+}
+
+#[test]
+fn test_strip() {
+    for (raw, expected) in [
+        (r#"Erd{\H{o}}s"#, "Erdos"),
+        (r#"J{\"o}rg"#, "Jorg"),
+        (r#"\'{E}mile"#, "Emile"),
+        ("Øystein Ørbæk", "OysteinOrbaek"),
+        ("Núñez-García", "NunezGarcia"),
+        ("Łukasz Straße", "LukaszStrasse"),
+        ("o(mn)", "omn"),
+    ] {
+        assert_eq!(strip(raw), expected, "{}", raw);
+    }
+}
+
+#[test]
+fn test_collisions() {
+    let chan = parse_entry(
+        "@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan}, year = {2016}}",
+    );
+    let pattern = Pattern::parse("authoryear").unwrap();
+    let existing = |keys: &[&str]| -> Vec<String> { keys.iter().map(|k| k.to_string()).collect() };
+    for (keys, expected) in [
+        (vec![], "chan2016"),
+        (vec!["chan2016"], "chan2016a"),
+        (vec!["Chan2016"], "chan2016a"),
+        (vec!["chan2016", "chan2016a"], "chan2016b"),
+        (vec!["chan2016", "chan2016b"], "chan2016a"),
+        (vec!["chan2016a"], "chan2016"),
+    ] {
+        assert_eq!(
+            generate(&pattern, &chan, &existing(&keys)),
+            expected,
+            "{:?}",
+            keys
+        );
+    }
+    assert_eq!(suffix(1), "a");
+    assert_eq!(suffix(26), "z");
+    assert_eq!(suffix(27), "aa");
+    assert_eq!(suffix(28), "ab");
+    assert_eq!(suffix(26 * 27 + 1), "aaa");
+}
+
+#[test]
+fn test_parse_pattern() {
+    for (pattern, error) in [
+        ("", "the key pattern is empty"),
+        ("{author1", "unclosed {"),
+        ("{author}", "unknown placeholder {author}"),
+        ("{author0}", "unknown placeholder {author0}"),
+        ("{journal}", "unknown placeholder {journal}"),
+        ("{year:snake}", "unknown modifier :snake in {year:snake}"),
+    ] {
+        let err = Pattern::parse(pattern).unwrap_err().to_string();
+        assert!(err.contains(error), "{:?}: {}", pattern, err);
+    }
+    assert_eq!(
+        Pattern::parse("authoryear").unwrap(),
+        Pattern::parse("{author1:lower}{year}").unwrap()
+    );
+    assert_eq!(
+        Pattern::parse("x-{yy}-").unwrap().parts,
+        [
+            Part::Literal("x-".into()),
+            Part::Placeholder(Field::Yy, Case::Keep),
+            Part::Literal("-".into())
+        ]
+    );
+}
@@ -0,0 +1,211 @@
+/// utilities to resolve the keys typed by the user to the keys of the bib
+/// file, through aliases and unambiguous suffixes
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+/// The keys of a bib file, and the aliases of the user
+pub struct KeyIndex {
+    keys: Vec<String>,
+    aliases: BTreeMap<String, String>,
+}
+
+impl KeyIndex {
+    pub fn new(keys: Vec<String>, aliases: BTreeMap<String, String>) -> Self {
+        Self { keys, aliases }
+    }
+
+    pub fn from_bib(src: &str, aliases: BTreeMap<String, String>) -> Result<Self> {
+        let keys = crate::bib::entry_spans(src)?
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        Ok(Self::new(keys, aliases))
+    }
+
+    /// What `resolve_key` resolves, each once: the aliases, then each key,
+    /// without its `DBLP:` prefix, and its last segment if no other key ends
+    /// with it
+    pub fn inputs(&self) -> Vec<String> {
+        let mut inputs: Vec<String> = self.aliases.keys().cloned().collect();
+        for key in &self.keys {
+            let bare = key.strip_prefix("DBLP:").filter(|bare| !bare.is_empty());
+            let last = key.rsplit_once('/').map(|(_, last)| last);
+            let unique = last.filter(|last| {
+                let suffix = format!("/{}", last);
+                self.keys.iter().filter(|k| k.ends_with(&suffix)).count() == 1
+            });
+            for input in [Some(key.as_str()), bare, unique].into_iter().flatten() {
+                if !inputs.iter().any(|i| i == input) {
+                    inputs.push(input.to_owned());
+                }
+            }
+        }
+        inputs
+    }
+}
+
+/// The key of the bib file meant by the input, which is, in order of
+/// precedence: an alias, a key with or without the `DBLP:` prefix, or the
+/// end of a single key after a `/`, like `Chan16` for `DBLP:conf/soda/Chan16`
+pub fn resolve_key(input: &str, index: &KeyIndex) -> Result<String> {
+    let input = input.trim();
+    let bare = input.strip_prefix("DBLP:").unwrap_or(input);
+    if let Some(key) = index.aliases.get(input).or_else(|| index.aliases.get(bare)) {
+        return Ok(key.clone());
+    }
+    let prefixed = format!("DBLP:{}", bare);
+    if let Some(key) = index.keys.iter().find(|k| **k == input || **k == prefixed) {
+        return Ok(key.clone());
+    }
+    let suffix = format!("/{}", bare);
+    let matching: Vec<&String> = index.keys.iter().filter(|k| k.ends_with(&suffix)).collect();
+    match matching[..] {
+        [key] => Ok(key.clone()),
+        [] => bail!("no entry of the bib file matches {}", input),
+        _ => bail!(
+            "{} is ambiguous, it could be any of {}",
+            input,
+            matching
+                .iter()
+                .map(|k| k.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Whether the key matches the pattern: as a glob if the pattern has a `*`,
+/// for any run of characters, or a `?`, for any one, and as a substring
+/// otherwise
+pub fn matches(pattern: &str, key: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return key.contains(pattern);
+    }
+    let (pattern, key): (Vec<char>, Vec<char>) = (pattern.chars().collect(), key.chars().collect());
+    // where the last star was, and the part of the key it covers so far
+    let (mut p, mut k, mut star) = (0, 0, None);
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((star_p, star_k)) = star {
+            p = star_p + 1;
+            k = star_k + 1;
+            star = Some((star_p, star_k + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// The keys to work on: the ones matching any of `only`, or all if it is
+/// empty, except the ones matching any of `skip`
+#[derive(Debug, Clone, Default)]
+pub struct KeyFilter {
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+impl KeyFilter {
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty()
+    }
+
+    pub fn selects(&self, key: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|p| matches(p, key)))
+            && !self.skip.iter().any(|p| matches(p, key))
+    }
+}
+
+#[test]
+fn test_resolve_key() {
+    let index = KeyIndex::new(
+        [
+            "DBLP:conf/nips/VaswaniSPUJGKP17",
+            "DBLP:conf/soda/Chan16",
+            "DBLP:journals/talg/Chan16",
+            "DBLP:conf/stoc/Foo19",
+            "local",
+        ]
+        .map(str::to_owned)
+        .to_vec(),
+        BTreeMap::from([
+            (
+                "transformer".to_owned(),
+                "DBLP:conf/nips/VaswaniSPUJGKP17".to_owned(),
+            ),
+            // an alias wins over the key it is the suffix of
+            ("Foo19".to_owned(), "DBLP:conf/soda/Chan16".to_owned()),
+        ]),
+    );
+    let resolve = |input| resolve_key(input, &index);
+    for (input, expected) in [
+        ("transformer", "DBLP:conf/nips/VaswaniSPUJGKP17"),
+        ("DBLP:transformer", "DBLP:conf/nips/VaswaniSPUJGKP17"),
+        ("Foo19", "DBLP:conf/soda/Chan16"),
+        ("DBLP:conf/stoc/Foo19", "DBLP:conf/stoc/Foo19"),
+        ("conf/stoc/Foo19", "DBLP:conf/stoc/Foo19"),
+        ("VaswaniSPUJGKP17", "DBLP:conf/nips/VaswaniSPUJGKP17"),
+        ("DBLP:VaswaniSPUJGKP17", "DBLP:conf/nips/VaswaniSPUJGKP17"),
+        ("soda/Chan16", "DBLP:conf/soda/Chan16"),
+        (" local ", "local"),
+    ] {
+        assert_eq!(resolve(input).unwrap(), expected, "{}", input);
+    }
+    let ambiguous = resolve("Chan16").unwrap_err().to_string();
+    assert_eq!(
+        ambiguous,
+        "Chan16 is ambiguous, it could be any of DBLP:conf/soda/Chan16, DBLP:journals/talg/Chan16"
+    );
+    assert_eq!(
+        resolve("Missing17").unwrap_err().to_string(),
+        "no entry of the bib file matches Missing17"
+    );
+    // suffixes are whole segments
+    assert!(resolve("han16").is_err());
+
+    let inputs = index.inputs();
+    assert_eq!(
+        inputs[..6],
+        [
+            "Foo19",
+            "transformer",
+            "DBLP:conf/nips/VaswaniSPUJGKP17",
+            "conf/nips/VaswaniSPUJGKP17",
+            "VaswaniSPUJGKP17",
+            "DBLP:conf/soda/Chan16"
+        ]
+    );
+    assert!(!inputs.contains(&"Chan16".to_owned()));
+    for input in &inputs {
+        assert!(resolve(input).is_ok(), "{}", input);
+    }
+}
+
+#[test]
+fn test_matches() {
+    for (pattern, key, expected) in [
+        ("soda", "DBLP:conf/soda/Chan16", true),
+        ("stoc", "DBLP:conf/soda/Chan16", false),
+        ("DBLP:conf/soda/*", "DBLP:conf/soda/Chan16", true),
+        ("DBLP:journals/corr/*", "DBLP:conf/soda/Chan16", false),
+        ("*/Chan1?", "DBLP:conf/soda/Chan16", true),
+        ("*/Chan1?", "DBLP:conf/soda/Chan160", false),
+        ("*soda*16", "DBLP:conf/soda/Chan16", true),
+        ("conf/*", "DBLP:conf/soda/Chan16", false),
+    ] {
+        assert_eq!(matches(pattern, key), expected, "{} {}", pattern, key);
+    }
+    let filter = KeyFilter {
+        only: vec!["DBLP:conf/*".into()],
+        skip: vec!["soda".into()],
+    };
+    assert!(filter.selects("DBLP:conf/stoc/Doe19"));
+    assert!(!filter.selects("DBLP:conf/soda/Chan16"));
+    assert!(!filter.selects("DBLP:journals/corr/Doe19"));
+    assert!(KeyFilter::default().selects("anything"));
+}
@@ -0,0 +1,56 @@
+//! Querying DBLP, and keeping bib files and reading notes in sync with it,
+//! as the `dblp` command line tool does.
+//!
+//! The entry points are [`dblp::query`] and [`dblp::fetch_record`] to get
+//! hits and records, [`bib::is_present`], [`bib::presence`] and
+//! [`bib::append`] to add records to bib files without duplicates, and
+//! [`notes::create_notes_file`] and [`notes::files_with_metadata`] for the
+//! notes of the papers. Tests and tools can point the library at another
+//! server with the `DBLP_RS_API_BASE` environment variable, and hear of
+//! the retries and changes of mirror through [`notices::set_handler`].
+//!
+//! The `tui` feature, on by default, makes the hits, entries and notes
+//! items of the skim pickers of the binary. The `async` feature adds
+//! [`fetcher::AsyncFetcher`], to run [`dblp::Query::execute_async`],
+//! [`dblp::fetch_record_async`] and [`convert::convert_entries_async`]
+//! without blocking the thread, through the same mirrors, retries and cache
+//! as the blocking requests.
+pub mod add;
+pub mod arxiv;
+pub mod bib;
+pub mod cache;
+pub mod check;
+pub mod clipboard;
+pub mod config;
+pub mod convert;
+pub mod dblp;
+pub mod diff;
+pub mod doi;
+pub mod edits;
+pub mod error;
+#[cfg(feature = "async")]
+pub mod fetcher;
+pub mod keygen;
+pub mod keys;
+pub mod limiter;
+pub mod local;
+pub mod lock;
+pub mod merge;
+pub mod mirrors;
+pub mod notes;
+pub mod notes_index;
+pub mod notices;
+pub mod output;
+pub mod paths;
+pub mod progress;
+pub mod retry;
+pub mod scratch;
+pub mod similarity;
+pub mod sort;
+pub mod stats;
+pub mod suggestions;
+pub mod tex;
+pub mod tidy;
+
+pub use dblp::{DblpHitInfo, DblpResponse, Format, Query};
+pub use error::DblpError;
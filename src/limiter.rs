@@ -0,0 +1,60 @@
+/// spacing of the requests of batch operations, which tells how long to
+/// wait rather than sleeping, for blocking and async callers alike
+use crate::progress::{Clock, SystemClock};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Lets a request go every `delay`, however many workers or tasks share it
+pub struct Limiter<C: Clock = SystemClock> {
+    delay: Duration,
+    clock: C,
+    /// when, on the clock, the next request may go
+    next: Mutex<Option<Duration>>,
+}
+
+impl Limiter {
+    pub fn new(delay: Duration) -> Self {
+        Self::with_clock(delay, SystemClock(Instant::now()))
+    }
+}
+
+impl<C: Clock> Limiter<C> {
+    pub fn with_clock(delay: Duration, clock: C) -> Self {
+        Self {
+            delay,
+            clock,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// How long to wait before making a request, which takes the next
+    /// slot: a caller that waits less goes too early
+    pub fn wait(&self) -> Duration {
+        let now = self.clock.now();
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let at = next.map_or(now, |next| next.max(now));
+        *next = Some(at + self.delay);
+        at - now
+    }
+}
+
+#[test]
+fn test_wait() {
+    use crate::progress::FakeClock;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    let limiter = Limiter::with_clock(Duration::from_millis(500), FakeClock(AtomicU64::new(0)));
+    let ms = Duration::from_millis;
+    // the first at once, the ones right after in turn
+    assert_eq!(limiter.wait(), ms(0));
+    assert_eq!(limiter.wait(), ms(500));
+    assert_eq!(limiter.wait(), ms(1000));
+    // time passing uses up the slots taken
+    limiter.clock.0.store(1200, Ordering::Relaxed);
+    assert_eq!(limiter.wait(), ms(300));
+    // and after a pause the next request goes at once
+    limiter.clock.0.store(5000, Ordering::Relaxed);
+    assert_eq!(limiter.wait(), ms(0));
+    assert_eq!(limiter.wait(), ms(500));
+}
@@ -0,0 +1,122 @@
+/// the entries of the bibtex file, to pick some of them
+use crate::bib;
+use crate::error::DblpError;
+use anyhow::Result;
+use biblatex::{Bibliography, ChunksExt, Entry};
+#[cfg(feature = "tui")]
+use skim::prelude::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalEntry {
+    pub key: String,
+    pub title: String,
+    pub authors: String,
+    pub year: String,
+    /// the booktitle or journal
+    pub venue: String,
+    /// the DOI or address of the paper, or else its DBLP record
+    pub link: String,
+    /// the entry as written in the file
+    pub bibtex: String,
+}
+
+impl LocalEntry {
+    fn new(entry: &Entry, bibtex: &str) -> Self {
+        let field = |name: &str| {
+            entry
+                .fields
+                .get(name)
+                .map(|v| bib::normalize_value(&v.format_verbatim()))
+                .unwrap_or_default()
+        };
+        let link = match (field("doi"), field("url")) {
+            (doi, _) if !doi.is_empty() => format!("https://doi.org/{}", doi),
+            (_, url) if !url.is_empty() => url,
+            _ => entry
+                .key
+                .strip_prefix("DBLP:")
+                .map(|key| format!("https://dblp.org/rec/{}", key))
+                .unwrap_or_default(),
+        };
+        Self {
+            key: entry.key.clone(),
+            title: field("title"),
+            authors: field("author").replace(" and ", ", "),
+            year: field("year"),
+            venue: [field("booktitle"), field("journal")].concat(),
+            link,
+            bibtex: bibtex.to_owned(),
+        }
+    }
+
+    /// Whether every word of the query is in the key, title, authors, venue
+    /// or year, in any case
+    pub fn matches(&self, query: &str) -> bool {
+        let text = self.search_text().to_lowercase();
+        query
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+
+    /// The text that queries are matched against, in the picker too
+    pub fn search_text(&self) -> String {
+        format!(
+            "{} {} {} {} {}",
+            self.key, self.title, self.authors, self.venue, self.year
+        )
+    }
+}
+
+/// The entries of the source, in order
+pub fn entries(src: &str) -> Result<Vec<LocalEntry>> {
+    let bibliography = Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    let spans: HashMap<String, std::ops::Range<usize>> =
+        bib::entry_spans(src)?.into_iter().collect();
+    Ok(bibliography
+        .iter()
+        .map(|entry| {
+            let bibtex = spans.get(&entry.key).map_or("", |span| &src[span.clone()]);
+            LocalEntry::new(entry, bibtex)
+        })
+        .collect())
+}
+
+#[cfg(feature = "tui")]
+impl SkimItem for LocalEntry {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Owned(self.search_text())
+    }
+
+    fn display<'a>(&'a self, _context: DisplayContext<'a>) -> AnsiString<'a> {
+        AnsiString::from(format!("{}  {}", self.key, self.title))
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        ItemPreview::Text(self.bibtex.clone())
+    }
+}
+
+#[test]
+fn test_entries() {
+    let src = "@inproceedings{DBLP:conf/soda/Chan16,\n  author = {Timothy M. Chan and Jane Doe},\n  title = {{All-Pairs} Shortest Paths},\n  year = {2016}\n}\n\n@article{local, title = {Local}, journal = {J. Local}, doi = {10.1/x}}\n";
+    let entries = entries(src).unwrap();
+    assert_eq!(
+        entries[0],
+        LocalEntry {
+            key: "DBLP:conf/soda/Chan16".into(),
+            title: "All-Pairs Shortest Paths".into(),
+            authors: "Timothy M. Chan, Jane Doe".into(),
+            year: "2016".into(),
+            venue: "".into(),
+            link: "https://dblp.org/rec/conf/soda/Chan16".into(),
+            bibtex: src[..src.find("\n\n").unwrap()].into(),
+        }
+    );
+    assert_eq!(entries[1].link, "https://doi.org/10.1/x");
+    assert_eq!(entries[1].venue, "J. Local");
+    assert!(entries[0].matches("chan shortest"));
+    assert!(entries[0].matches("soda 2016"));
+    assert!(!entries[0].matches("chan local"));
+    assert!(entries[1].matches(""));
+}
@@ -0,0 +1,296 @@
+/// utilities to lock a bib file while it is modified, with a lock of the
+/// operating system on a file next to it, which also holds the PID of the
+/// process modifying it
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for another process to release the lock, by default
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to check whether the lock was released
+const POLL: Duration = Duration::from_millis(100);
+
+/// The lock files this process holds, with the open file the lock of the
+/// operating system is on, and how many `Lock`s there are of each
+static HELD: LazyLock<Mutex<HashMap<PathBuf, (File, usize)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The lock of a bib file, released when dropped, also while unwinding
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((_, count)) = held.get_mut(&self.path) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            // removed while still locked, so that no other process locks
+            // the file that is going away
+            interrupt::release(&self.path);
+            let _ = std::fs::remove_file(&self.path);
+            held.remove(&self.path);
+        }
+    }
+}
+
+/// The lock file of the bib file, next to the file it links to
+pub fn lock_path(bib: &Path) -> PathBuf {
+    let real = crate::paths::real_path(bib);
+    let mut name = real.file_name().unwrap_or_default().to_owned();
+    name.push(".lock");
+    real.with_file_name(name)
+}
+
+/// Locks the bib file, waiting up to the timeout for another process to
+/// release it. The lock of a process that died goes with it, and asking
+/// again for a lock the process holds succeeds at once.
+pub fn lock(bib: &Path, timeout: Duration) -> Result<Lock> {
+    let path = lock_path(bib);
+    let start = Instant::now();
+    loop {
+        {
+            let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((_, count)) = held.get_mut(&path) {
+                *count += 1;
+                return Ok(Lock { path });
+            }
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("creating the lock file {:?}", path))?;
+        match file.try_lock() {
+            Ok(()) => {
+                // the holder may have removed the file between our opening
+                // and locking it, then the lock is on a file that is gone
+                if !is_file_at(&file, &path) {
+                    continue;
+                }
+                if let Some(pid) = holder(&mut file).filter(|&pid| pid != std::process::id()) {
+                    eprintln!(
+                        "warning: taking over {}, left by process {} which is not running",
+                        path.display(),
+                        pid
+                    );
+                }
+                file.set_len(0)
+                    .and_then(|()| file.rewind())
+                    .and_then(|()| write!(file, "{}", std::process::id()))
+                    .with_context(|| format!("writing the lock file {:?}", path))?;
+                interrupt::hold(&path);
+                let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+                held.insert(path.clone(), (file, 1));
+                return Ok(Lock { path });
+            }
+            Err(std::fs::TryLockError::WouldBlock) => {}
+            Err(std::fs::TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("locking {:?}", path))
+            }
+        }
+        if start.elapsed() >= timeout {
+            let by = holder(&mut file)
+                .map(|pid| format!(" ({})", pid))
+                .unwrap_or_default();
+            bail!(
+                "{} is locked by another dblp process{}: try again once it is done",
+                bib.display(),
+                by
+            );
+        }
+        std::thread::sleep(POLL);
+    }
+}
+
+/// The PID written in the lock file, if any
+fn holder(file: &mut File) -> Option<u32> {
+    let mut text = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut text).ok()?;
+    text.trim().parse().ok()
+}
+
+/// Whether the open file is still the one at the path
+#[cfg(unix)]
+fn is_file_at(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(open), Ok(current)) => open.dev() == current.dev() && open.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+/// Whether the open file is still the one at the path, which it always is
+/// where open files cannot be removed
+#[cfg(not(unix))]
+fn is_file_at(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+/// Removing the lock files when the process is interrupted, since their
+/// guards are not dropped then. The lock of the operating system goes with
+/// the process anyway, this only tidies up.
+#[cfg(unix)]
+mod interrupt {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+    use std::sync::Once;
+
+    /// The lock files the process holds, in slots that the handler can read
+    /// without locking. More than this many are not removed on signals.
+    static HELD: [AtomicPtr<libc::c_char>; 8] = [const { AtomicPtr::new(std::ptr::null_mut()) }; 8];
+    static HANDLERS: Once = Once::new();
+
+    extern "C" fn on_signal(signal: libc::c_int) {
+        // only calls that are safe in a signal handler, then dying of the
+        // signal as if it was not handled
+        for slot in HELD.iter() {
+            let path = slot.swap(std::ptr::null_mut(), Ordering::SeqCst);
+            if !path.is_null() {
+                unsafe {
+                    libc::unlink(path);
+                }
+            }
+        }
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+
+    pub fn hold(path: &Path) {
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            return;
+        };
+        HANDLERS.call_once(|| {
+            for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                unsafe {
+                    let handler: extern "C" fn(libc::c_int) = on_signal;
+                    libc::signal(signal, handler as libc::sighandler_t);
+                }
+            }
+        });
+        let path = path.into_raw();
+        let stored = HELD.iter().any(|slot| {
+            slot.compare_exchange(
+                std::ptr::null_mut(),
+                path,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+        });
+        if !stored {
+            free(path);
+        }
+    }
+
+    pub fn release(path: &Path) {
+        let bytes = path.as_os_str().as_bytes();
+        for slot in HELD.iter() {
+            let current = slot.load(Ordering::SeqCst);
+            if current.is_null() || unsafe { CStr::from_ptr(current) }.to_bytes() != bytes {
+                continue;
+            }
+            if slot
+                .compare_exchange(
+                    current,
+                    std::ptr::null_mut(),
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                free(current);
+            }
+        }
+    }
+
+    fn free(path: *mut libc::c_char) {
+        drop(unsafe { CString::from_raw(path) });
+    }
+}
+
+#[cfg(not(unix))]
+mod interrupt {
+    pub fn hold(_path: &std::path::Path) {}
+    pub fn release(_path: &std::path::Path) {}
+}
+
+#[cfg(unix)]
+#[test]
+fn test_lock() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-lock-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let bib = dir.join("refs.bib");
+    let path = lock_path(&bib);
+    assert_eq!(path, dir.join("refs.bib.lock"));
+
+    let first = lock(&bib, Duration::ZERO).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        std::process::id().to_string()
+    );
+    // held already, and still held once the second one is dropped
+    let second = lock(&bib, Duration::ZERO).unwrap();
+    drop(first);
+    assert!(path.is_file());
+    drop(second);
+    assert!(!path.exists());
+
+    // released while unwinding
+    let unwound = std::panic::catch_unwind(|| {
+        let _lock = lock(&bib, Duration::ZERO).unwrap();
+        panic!("while locked");
+    });
+    assert!(unwound.is_err());
+    assert!(!path.exists());
+
+    // taken over when left without a lock, whatever PID it holds, even
+    // the one of this process, which another process may have had
+    for left in ["1", "", &std::process::id().to_string()] {
+        std::fs::write(&path, left).unwrap();
+        drop(lock(&bib, Duration::ZERO).unwrap());
+        assert!(!path.exists());
+    }
+
+    // but not while another open file holds the lock, as another process
+    // would
+    std::fs::write(&path, "1").unwrap();
+    let other = File::open(&path).unwrap();
+    other.lock().unwrap();
+    let err = lock(&bib, Duration::from_millis(150)).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "{} is locked by another dblp process (1): try again once it is done",
+            bib.display()
+        )
+    );
+    drop(other);
+    drop(lock(&bib, Duration::ZERO).unwrap());
+
+    // releasing a lock keeps the other ones held
+    let other_bib = dir.join("other.bib");
+    let a = lock(&bib, Duration::ZERO).unwrap();
+    let b = lock(&other_bib, Duration::ZERO).unwrap();
+    drop(a);
+    assert!(lock_path(&other_bib).is_file());
+    drop(b);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
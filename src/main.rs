@@ -1,235 +1,3649 @@
 use anyhow::{anyhow, bail, Context, Result};
 use biblatex::*;
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, ValueHint};
+use regex::Regex;
 use skim::prelude::*;
-use std::{fs::File, io::BufReader, path::PathBuf};
-use std::{fs::OpenOptions, io::prelude::*};
+use std::{fs::File, path::PathBuf};
+use std::{io::prelude::*, io::IsTerminal};
 
-mod dblp;
-mod notes;
+mod completions;
+mod doctor;
+mod examples;
+mod man;
+mod picker;
 use crate::dblp::*;
+use crate::similarity::Confidence;
+use dblp_rs::{
+    add, arxiv, bib, cache, check, clipboard, config, convert, dblp, diff, doi, edits, error,
+    keygen, keys, local, lock, merge, mirrors, notes, notes_index, notices, output, paths,
+    progress, scratch, similarity, sort, stats, suggestions, tex, tidy,
+};
 
-/// gets the path to the only bibtex file in a directory. If there is none
-/// or if there are multiple, return None
-fn get_unique_bib() -> Result<Option<PathBuf>> {
-    let paths: Vec<PathBuf> = std::fs::read_dir(".")?
-        .filter_map(|s| {
-            let p = s.as_ref().unwrap().path();
-            if let Some(ext) = p.extension() {
-                if ext == "bib" {
-                    return Some(p);
+/// Find papers on DBLP and manage the bibtex file of a paper
+///
+/// Entries are searched on DBLP, selected interactively, and added to the
+/// bibtex file or copied to the clipboard. The bibtex files are the ones
+/// given with --bibtex or in the config or, if missing, the .bib files of
+/// the current directory or of the closest of its parents that has some.
+#[derive(Parser)]
+#[command(name = "dblp")]
+struct Cli {
+    #[command(subcommand)]
+    subcommand: Actions,
+
+    /// The bibtex file to work on, can be repeated
+    ///
+    /// Defaults to the .bib files in the current directory or else in the
+    /// closest of its parents, up to the root of the repository, that has
+    /// some, or else in the first directory of BIBINPUTS that has some.
+    /// When there are several, entries added are checked against all of
+    /// them, and convert converts each of them. The file to modify is the
+    /// one given with --into or else the one picked, which is remembered.
+    #[arg(short, long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    bibtex: Vec<String>,
+
+    /// The bibtex file to add entries to and to modify, when there are
+    /// several
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    into: Option<String>,
+
+    /// Size beyond which entries are reported by check and refused by the clipboard
+    #[arg(long, value_name = "BYTES", default_value_t = bib::MAX_ENTRY_SIZE)]
+    max_entry_size: usize,
+
+    /// How to copy to the clipboard: `osc52` asks the terminal to, which
+    /// works over SSH
+    #[arg(long, value_name = "HOW", value_enum, default_value_t)]
+    clipboard: clipboard::Backend,
+
+    /// Print keys and entries to standard output instead of copying them,
+    /// as is done anyway when it is not a terminal, unless --clipboard is given
+    #[arg(long, visible_alias = "stdout")]
+    no_clipboard: bool,
+
+    /// Print a summary of the work done at the end, and report every
+    /// entry of bulk operations
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Write statistics about the requests made, and the time taken, as json
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    stats_out: Option<PathBuf>,
+
+    /// Fail if the bibtex file is a symlink, rather than writing to the
+    /// file it points to
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Modify the bibtex file without asking even if it is outside the
+    /// repository of the current directory
+    #[arg(long)]
+    trust_config: bool,
+
+    /// Neither reuse nor keep the responses of DBLP
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Seconds to wait for a server to connect, and then to send each
+    /// part of its answer, before trying the next mirror
+    #[arg(long, value_name = "SECS", default_value_t = dblp::TIMEOUT.as_secs(),
+          value_parser = clap::value_parser!(u64).range(1..))]
+    timeout: u64,
+
+    /// A DBLP mirror to try before dblp.org and dblp.uni-trier.de, like
+    /// a proxy in front of them
+    #[arg(long, value_name = "URL", env = dblp::BASE_URL_VAR,
+          value_parser = dblp::parse_base_url)]
+    dblp_url: Option<String>,
+
+    /// The bibtex file to modify, once known
+    #[arg(skip)]
+    target: std::cell::OnceCell<PathBuf>,
+}
+
+impl Cli {
+    /// The bibtex file to modify, asking which one it is the first time
+    /// there are several
+    fn get_bib_path(&self) -> Result<PathBuf> {
+        self.target_bib(true)
+    }
+
+    /// Like `get_bib_path`, failing rather than asking if `ask` is false
+    fn target_bib(&self, ask: bool) -> Result<PathBuf> {
+        if let Some(path) = self.target.get() {
+            return Ok(path.clone());
+        }
+        let path = match &self.into {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let (dir, candidates) = self.bib_candidates()?;
+                let error = |kind| error::DblpError::BibFile {
+                    kind,
+                    path: dir.clone(),
+                };
+                let remembered = paths::remembered_target(&dir).and_then(|remembered| {
+                    candidates
+                        .iter()
+                        .find(|p| p.strip_prefix(&dir).unwrap_or(p) == remembered)
+                        .cloned()
+                });
+                match candidates.as_slice() {
+                    [] => return Err(error(error::BibFileKind::Missing).into()),
+                    [only] => only.clone(),
+                    _ => match remembered {
+                        Some(path) => path,
+                        None if ask => pick_target(&dir, &candidates)?,
+                        None => return Err(error(error::BibFileKind::Several(candidates)).into()),
+                    },
                 }
             }
-            None
-        })
+        };
+        paths::check_bib_path(&path)?;
+        let path = paths::resolve_bib_path(path, !self.no_follow_symlinks)?;
+        Ok(self.target.get_or_init(|| path).clone())
+    }
+
+    /// The bibtex files given, or else the ones of the current directory
+    /// or of the closest of its ancestors or of `BIBINPUTS` that has some,
+    /// with the directory to remember the one to modify in
+    fn bib_candidates(&self) -> Result<(PathBuf, Vec<PathBuf>)> {
+        let here = PathBuf::from(".");
+        if !self.bibtex.is_empty() {
+            return Ok((here, self.bibtex.iter().map(PathBuf::from).collect()));
+        }
+        let bibinputs = std::env::var_os("BIBINPUTS");
+        Ok(paths::locate_bibs(&here, bibinputs.as_deref())?.unwrap_or((here, Vec::new())))
+    }
+
+    /// All the bibtex files, each once, the one given with --into included
+    fn get_bib_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut all = Vec::new();
+        let into = self.into.iter().map(PathBuf::from);
+        for path in self.bib_candidates()?.1.into_iter().chain(into) {
+            paths::check_bib_path(&path)?;
+            let path = paths::resolve_bib_path(path, !self.no_follow_symlinks)?;
+            if !all.contains(&path) {
+                all.push(path);
+            }
+        }
+        if all.is_empty() {
+            return Err(error::DblpError::BibFile {
+                kind: error::BibFileKind::Missing,
+                path: PathBuf::from("."),
+            }
+            .into());
+        }
+        Ok(all)
+    }
+
+    /// The bibtex files other than the one to modify that exist, with their
+    /// content
+    fn other_bibs(&self, bib_path: &std::path::Path) -> Result<Vec<(PathBuf, String)>> {
+        let mut others = Vec::new();
+        for path in self.get_bib_paths()? {
+            if path != bib_path && path.is_file() {
+                let src = std::fs::read_to_string(&path)
+                    .with_context(|| format!("reading {:?}", path))?;
+                others.push((path, src));
+            }
+        }
+        Ok(others)
+    }
+
+    /// Copies the text to the clipboard the way the options say, unless
+    /// the output goes to another program
+    fn copy(&self, what: &str) -> Copied {
+        let off = self.no_clipboard
+            || self.clipboard == clipboard::Backend::Auto && !std::io::stdout().is_terminal();
+        if off {
+            return Copied::Off;
+        }
+        match clipboard::write(what, self.max_entry_size, self.clipboard) {
+            Ok(cmd) => Copied::Via(cmd),
+            Err(err) => Copied::Failed(err.into()),
+        }
+    }
+
+    fn get_backup_bib_path(&self) -> Result<PathBuf> {
+        Ok(paths::backup_path(self.get_bib_path()?))
+    }
+
+    /// Asks before modifying a bib file outside the repository of the
+    /// current directory, which is likely the one of another paper
+    fn check_workspace(&self, bib_path: &PathBuf) -> Result<()> {
+        if self.trust_config {
+            return Ok(());
+        }
+        let cwd = std::env::current_dir()?;
+        let Some(root) = paths::outside_workspace(bib_path, &cwd) else {
+            return Ok(());
+        };
+        if config::load_workspace(&root)?.allow_outside {
+            return Ok(());
+        }
+        let bib = paths::display_home(cwd.join(bib_path));
+        let root = paths::display_home(root);
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "refusing to modify {} from {} without asking, pass --trust-config or set allow_outside = true in {}/{}",
+                bib,
+                root,
+                root,
+                config::WORKSPACE_CONFIG
+            );
+        }
+        let question = format!(
+            "WARNING: about to modify {} while you are in {}, continue?",
+            bib, root
+        );
+        if !confirm(&question)? {
+            return Err(error::DblpError::Aborted.into());
+        }
+        Ok(())
+    }
+
+    /// Where to back up the bib file, once sure it is the file to modify,
+    /// checked before taking the lock not to keep other processes waiting
+    /// on the prompt
+    fn backup(&self) -> Result<Backup> {
+        self.backup_with(&self.get_bib_path()?, &BackupArgs::default())
+    }
+
+    /// Like `backup`, for the given bib file and where the options say, if
+    /// at all
+    fn backup_with(&self, bib_path: &PathBuf, args: &BackupArgs) -> Result<Backup> {
+        self.check_workspace(bib_path)?;
+        if args.no_backup {
+            return Ok(Backup(None));
+        }
+        let backup = match &args.backup_dir {
+            Some(dir) => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                paths::backup_path_in(bib_path, Some(dir), now.as_secs())
+            }
+            None => paths::backup_path(bib_path),
+        };
+        paths::check_backup_path(&backup)?;
+        Ok(Backup(Some(backup)))
+    }
+}
+
+/// Where to back up the bib file before writing it, if at all
+struct Backup(Option<PathBuf>);
+
+impl Backup {
+    fn write(&self, src: &str) -> Result<()> {
+        let Some(backup) = &self.0 else {
+            return Ok(());
+        };
+        let mut f = File::create(backup).with_context(|| format!("writing {:?}", backup))?;
+        // exactly the original, for undo to restore it as it was
+        write!(f, "{}", src)?;
+        Ok(())
+    }
+}
+
+#[derive(Subcommand)]
+enum Actions {
+    /// Add bibliographic info to a bibtex file
+    ///
+    /// Searches DBLP for the query and lets you pick one or, with tab, more
+    /// of the hits. Their entries, and the entries they cross-reference, are
+    /// appended to the bibtex file unless already there, and their keys are
+    /// copied to the clipboard, separated by commas.
+    Add {
+        /// Print the key to standard output, besides copying it
+        #[arg(long)]
+        print_key: bool,
+        /// Take the first hit rather than asking, for scripts
+        #[arg(long)]
+        first: bool,
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        /// Open each entry in $VISUAL or $EDITOR, or else vi, before adding
+        /// it, and do not add the ones left empty
+        #[arg(long)]
+        edit: bool,
+        #[command(flatten)]
+        keys: KeyArgs,
+        #[command(flatten)]
+        insert: InsertArgs,
+        #[command(flatten)]
+        strip: StripArgs,
+        query: Vec<String>,
+    },
+    /// Add the record with the given DBLP key to a bibtex file
+    ///
+    /// Like `add`, skipping the search. The key can also be given as
+    /// `DBLP:conf/soda/Chan16`, or as the address of its DBLP page.
+    AddKey {
+        /// Print the key to standard output, besides copying it
+        #[arg(long)]
+        print_key: bool,
+        #[command(flatten)]
+        keys: KeyArgs,
+        #[command(flatten)]
+        insert: InsertArgs,
+        key: String,
+    },
+    /// Add the paper with the given DOI to a bibtex file
+    ///
+    /// For papers DBLP does not have: the bibtex is asked to doi.org, and
+    /// keyed like `doi:10.1145/3313276.3316350`.
+    Doi {
+        /// Print the key to standard output, besides copying it
+        #[arg(long)]
+        print_key: bool,
+        #[command(flatten)]
+        keys: KeyArgs,
+        #[command(flatten)]
+        insert: InsertArgs,
+        doi: String,
+    },
+    /// Add the arXiv preprint with the given identifier to a bibtex file
+    ///
+    /// For preprints DBLP does not have yet: if DBLP has a paper with the
+    /// same title, its key is suggested instead.
+    Arxiv {
+        /// Print the key to standard output, besides copying it
+        #[arg(long)]
+        print_key: bool,
+        /// Add the preprint even if DBLP has the paper
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        keys: KeyArgs,
+        #[command(flatten)]
+        insert: InsertArgs,
+        id: String,
+    },
+    /// Search DBLP and print the hits, without touching any file
+    Search {
+        /// Print the hits as json
+        #[arg(long)]
+        json: bool,
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        query: Vec<String>,
+    },
+    /// Copy a bibtex entry to the clipboard
+    ///
+    /// Besides the entries of the hits picked, copies their keys or a
+    /// citation of them, to paste in a LaTeX document.
+    Clip {
+        /// What to copy
+        #[arg(long, value_enum, default_value_t = ClipWhat::Bibtex)]
+        what: ClipWhat,
+        /// The format of the entries copied
+        #[arg(long, value_enum, default_value_t = Format::Standard)]
+        format: Format,
+        /// Take the first hit rather than asking, for scripts
+        #[arg(long)]
+        first: bool,
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        query: Vec<String>,
+    },
+    /// Open the DOI of a paper, or else its DBLP page, in the browser
+    ///
+    /// Without a query, the paper is picked among the entries of the bibtex
+    /// file, and their doi or url is opened.
+    Open {
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        query: Vec<String>,
+    },
+    /// Show the details of a record, and whether the bibtex file has it
+    ///
+    /// The record is given by its DBLP key, like `conf/soda/Chan16`, or
+    /// picked among the hits of the query.
+    Info {
+        /// Print the bibtex of the record instead, as DBLP formats it
+        #[arg(long)]
+        bibtex: bool,
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Create a markdown file, with metadata, to take notes on a paper
+    ///
+    /// The paper is added to the bibtex file first, if missing. Papers that
+    /// already have notes keep them. The path of the notes is printed, and
+    /// they are opened in $VISUAL or $EDITOR, or else vi.
+    Note {
+        /// Only show hits of this type, can be repeated
+        #[arg(long = "type", value_enum)]
+        types: Vec<HitType>,
+        /// Ask DBLP for up to this many hits
+        #[arg(long, default_value_t = dblp::MAX_HITS)]
+        max_hits: usize,
+        /// Pick the paper among the entries of the bibtex file matching the
+        /// query, instead of searching DBLP
+        #[arg(long)]
+        local: bool,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+        #[command(flatten)]
+        template: TemplateArgs,
+        /// Tag the new notes, can be repeated
+        #[arg(long = "tag", value_name = "TAG")]
+        tags: Vec<String>,
+        /// Do not open the notes in the editor
+        #[arg(long)]
+        no_edit: bool,
+        query: Vec<String>,
+    },
+    /// Convert a bibtex file between the `Standard`, `Condensed` and
+    /// `Crossref` formats
+    ///
+    /// Every entry with a DBLP key is fetched again in the given format.
+    /// The entries other entries refer to with `crossref` are written once,
+    /// after all the others, as bibtex requires.
+    /// Local fields, like keywords and notes, are kept, and so are the
+    /// fields DBLP does not have, like abstracts. The original file is
+    /// saved next to it with a .bak suffix, or in --backup-dir.
+    Convert(ConvertArgs),
+    /// Look for problems in the bibtex file
+    Check(CheckArgs),
+    /// Manage the notes of many entries at once
+    Notes {
+        #[command(subcommand)]
+        action: NotesActions,
+    },
+    /// Merge the changes a collaborator made to a copy of the bibtex file
+    Merge {
+        /// The version both copies started from
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        base: PathBuf,
+        /// The collaborator's version
+        #[arg(value_hint = ValueHint::FilePath)]
+        theirs: PathBuf,
+        /// Keep both versions of conflicting entries, between marker
+        /// comments, rather than asking which one to keep
+        #[arg(long)]
+        no_interactive: bool,
+    },
+    /// Fetch one entry again from DBLP, leaving the others as they are
+    ///
+    /// Prints the fields that changed. Local fields, like keywords, notes
+    /// and abstracts, are kept. If DBLP moved the record to another key,
+    /// the entry takes the new key.
+    Update {
+        /// The key of the entry, with or without `DBLP:`, its end after a
+        /// `/` or an alias; picked among the entries from DBLP if missing
+        key: Option<String>,
+        /// What to write for the entry if it cannot be fetched
+        #[arg(long, value_enum, default_value_t = convert::OnFetchError::KeepOriginal)]
+        on_fetch_error: convert::OnFetchError,
+    },
+    /// Remove entries from the bibtex file
+    ///
+    /// Lets you pick one or, with tab, more of the entries whose key, title
+    /// or authors contain all the words of the query, and removes them,
+    /// leaving the rest of the file as it is. A query that is a key, or
+    /// what resolves to one as for update, removes that entry without
+    /// asking. Warns about the entries still cited by the .tex files next
+    /// to the bibtex file.
+    Remove { query: Vec<String> },
+    /// Pick an entry of the bibtex file and copy its key
+    ///
+    /// The entries are searched by key, title, authors, venue and year.
+    List {
+        /// Print the key to standard output, besides copying it
+        #[arg(long)]
+        print_key: bool,
+    },
+    /// Find the entries that are the same paper, and keep one of each
+    ///
+    /// Entries with the same DOI, or with the same title, year and
+    /// authors, are duplicates. For each group you pick the entry to keep,
+    /// or that they are not duplicates after all. The others are removed,
+    /// and their citations in the .tex files next to the bibtex file are
+    /// renamed to the entry kept.
+    Dedup {
+        /// Only list the groups of duplicates, failing if there are any
+        #[arg(long)]
+        report: bool,
+    },
+    /// Restore the bibtex file from its backup
+    ///
+    /// Lists the entries restoring would add, remove and change, and asks
+    /// before restoring. Backups much older than the file are refused,
+    /// since the file may have been edited by hand after the backup.
+    Undo {
+        /// Restore without asking
+        #[arg(long)]
+        yes: bool,
+        /// Restore the latest of the backups saved in this directory by
+        /// convert --backup-dir
+        #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+        backup_dir: Option<PathBuf>,
+    },
+    /// Reorder the entries of the bibtex file
+    ///
+    /// The text of each entry is kept as it is, and so are the comments
+    /// right above it, which move along with it.
+    Sort(SortArgs),
+    /// Format every entry the same way
+    ///
+    /// Fields are indented and aligned like in DBLP, with the author, title,
+    /// booktitle, journal, volume, number, pages, year, doi and url first,
+    /// and the others after them in alphabetical order. Entries that cannot
+    /// be parsed are left as they are.
+    Tidy(TidyArgs),
+    /// Show examples of how to use the tool
+    Examples,
+    /// Print the json schema of the output of a command
+    Schema { command: SchemaCommand },
+    /// Write the man pages of the tool and of each of its subcommands
+    Man {
+        /// Directory where the pages are written
+        #[arg(long, value_name = "DIR", default_value = ".", value_hint = ValueHint::DirPath)]
+        out: PathBuf,
+    },
+    /// Review the replacements suggested by the checks and not applied yet
+    ///
+    /// The checks save what they find, so that it can be applied later.
+    /// Suggestions about entries that changed since, or were removed, expire.
+    Suggestions {
+        #[command(subcommand)]
+        action: SuggestionsActions,
+    },
+    /// Manage the files the tool keeps for itself
+    Cache {
+        #[command(subcommand)]
+        action: CacheActions,
+    },
+    /// Print information about the tool and its environment, for bug reports
+    Doctor {
+        /// Print the report as json
+        #[arg(long)]
+        json: bool,
+        /// Don't check whether the DBLP mirrors are reachable
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Print the completion script of the shell
+    ///
+    /// Besides the subcommands and flags, the ones of bash, zsh and fish
+    /// complete the keys of the bibtex file for update, remove, open and
+    /// note. For bash, add `source <(dblp completions bash)` to ~/.bashrc.
+    Completions { shell: clap_complete::Shell },
+    /// Print the settings in effect, and where each of them comes from
+    ///
+    /// Settings are read from $XDG_CONFIG_HOME/dblp-rs/config.toml and from
+    /// the closest .dblp.toml, which overrides it, and flags override both.
+    Config,
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    to: Format,
+    /// What to write for the entries that cannot be fetched
+    #[arg(long, value_enum, default_value_t = convert::OnFetchError::KeepOriginal)]
+    on_fetch_error: convert::OnFetchError,
+    /// Show how each entry would change instead of writing the file,
+    /// failing if any would
+    #[arg(long)]
+    dry_run: bool,
+    /// How many entries to fetch at once
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=16))]
+    jobs: u32,
+    /// Fetch only the entries whose key contains this, or matches it if
+    /// it has `*` or `?`, like `DBLP:conf/soda/*`; can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    only: Vec<String>,
+    /// Do not fetch the entries whose key matches this, like
+    /// `DBLP:journals/corr/*`; can be repeated
+    #[arg(long, value_name = "PATTERN")]
+    skip: Vec<String>,
+    /// Keep only keywords, notes and the other fields added by hand,
+    /// dropping the local fields DBLP does not have
+    #[arg(long)]
+    no_merge: bool,
+    #[command(flatten)]
+    strip: StripArgs,
+    #[command(flatten)]
+    backup: BackupArgs,
+}
+
+#[derive(Args)]
+struct NotesDirArgs {
+    /// Directory of the notes, searched with its subdirectories
+    #[arg(long, value_name = "DIR", default_value = NOTES_DIR, value_hint = ValueHint::DirPath)]
+    notes_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct NotesFilterArgs {
+    /// Only consider the notes with this tag, can be repeated
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+    /// Only consider the notes with this reading status
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(notes::STATUSES))]
+    status: Option<String>,
+}
+
+impl NotesFilterArgs {
+    /// Whether the notes pass the filters, which the ones without metadata
+    /// only do if there are none
+    fn keeps(&self, meta: Option<&notes::ShortMetadata>) -> bool {
+        match meta {
+            Some(meta) => meta.matches(&self.tags, self.status.as_deref()),
+            None => self.tags.is_empty() && self.status.is_none(),
+        }
+    }
+}
+
+#[derive(Args)]
+struct TemplateArgs {
+    /// Write the body of new notes from this template, where {{title}},
+    /// {{authors}}, {{year}}, {{venue}}, {{key}} and {{doi}} stand for the
+    /// fields of the entry
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    template: Option<PathBuf>,
+}
+
+impl TemplateArgs {
+    /// The template of the flag, or else of the config. A template of the
+    /// config that does not exist gets a warning, and no body is written.
+    fn load(&self) -> Result<Option<notes::Template>> {
+        let path = match &self.template {
+            Some(path) => path.clone(),
+            None => match config::load()?.notes_template() {
+                Some(path) if !path.is_file() => {
+                    eprintln!(
+                        "warning: the notes template {} does not exist, leaving the notes empty",
+                        path.display()
+                    );
+                    return Ok(None);
+                }
+                Some(path) => path,
+                None => return Ok(None),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading the notes template {}", path.display()))?;
+        let template = notes::Template::parse(&text)
+            .with_context(|| format!("parsing the notes template {}", path.display()))?;
+        Ok(Some(template))
+    }
+}
+
+#[derive(Args, Default)]
+struct StripArgs {
+    /// Remove the fields DBLP adds about its own records, timestamp, biburl
+    /// and bibsource, or the ones of `strip_fields` in the config, and
+    /// this field too if given; can be repeated
+    #[arg(long, value_name = "FIELD", num_args = 0..=1)]
+    strip_fields: Option<Vec<String>>,
+}
+
+impl StripArgs {
+    /// The fields to remove from fetched entries, if any
+    fn fields(&self) -> Result<Vec<String>> {
+        let configured = config::load()?.strip_fields;
+        if self.strip_fields.is_none() && configured.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut fields =
+            configured.unwrap_or_else(|| bib::DBLP_FIELDS.iter().map(|f| f.to_string()).collect());
+        fields.extend(self.strip_fields.iter().flatten().cloned());
+        Ok(fields)
+    }
+}
+
+#[derive(Args, Default)]
+struct KeyArgs {
+    /// Key the entry following this pattern, like `{venue}{yy}-{author1}`,
+    /// or a preset, overriding the `key_pattern` of the config
+    #[arg(long)]
+    key_pattern: Option<String>,
+    /// Key the entry with this key, which must not be taken yet
+    #[arg(long = "key", value_name = "KEY", conflicts_with = "key_pattern")]
+    cite_key: Option<String>,
+}
+
+impl KeyArgs {
+    fn keying(&self) -> Result<add::Keying> {
+        if let Some(key) = &self.cite_key {
+            bib::check_key(key)?;
+            return Ok(add::Keying::Exact(key.clone()));
+        }
+        Ok(match key_pattern_of(self.key_pattern.as_deref())? {
+            Some(pattern) => add::Keying::Pattern(pattern),
+            None => add::Keying::Dblp,
+        })
+    }
+}
+
+#[derive(Args, Default)]
+struct InsertArgs {
+    /// Insert the entries where they go in the bib file sorted by key, or
+    /// in the given order, overriding the `insert_sorted` of the config.
+    /// They are appended, with a warning, to files not sorted that way.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "BY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "key"
+    )]
+    sorted: Option<sort::SortBy>,
+}
+
+impl InsertArgs {
+    fn sorted(&self) -> Result<Option<sort::SortBy>> {
+        if self.sorted.is_some() {
+            return Ok(self.sorted);
+        }
+        config::load()?
+            .insert_sorted
+            .map(|by| {
+                sort::SortBy::from_str(&by, true)
+                    .map_err(|_| anyhow!("insert_sorted cannot be {:?} in the config", by))
+            })
+            .transpose()
+    }
+}
+
+#[derive(Args, Default)]
+struct BackupArgs {
+    /// Save the original file in this directory, with the time in its
+    /// name, rather than next to it with a .bak suffix
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    backup_dir: Option<PathBuf>,
+    /// Do not save the original file, for files kept in git
+    #[arg(long, conflicts_with = "backup_dir")]
+    no_backup: bool,
+}
+
+impl ConvertArgs {
+    fn options(&self, verbose: bool) -> Result<convert::Options> {
+        Ok(convert::Options {
+            to: self.to,
+            on_fetch_error: self.on_fetch_error,
+            jobs: self.jobs as usize,
+            filter: keys::KeyFilter {
+                only: self.only.clone(),
+                skip: self.skip.clone(),
+            },
+            no_merge: self.no_merge,
+            strip: self.strip.fields()?,
+            verbose,
+        })
+    }
+}
+
+#[derive(Args)]
+struct SortArgs {
+    #[arg(long, value_enum)]
+    by: sort::SortBy,
+    /// Sort in the opposite order. The entries without the field sorted by
+    /// stay last
+    #[arg(long)]
+    reverse: bool,
+    /// Directory with the TeX sources, to sort by citation order
+    #[arg(long, value_name = "DIR", default_value = ".", value_hint = ValueHint::DirPath)]
+    tex: PathBuf,
+    /// Only print the new order
+    #[arg(long)]
+    dry_run: bool,
+    /// Fail if the entries are not sorted, without changing the file
+    #[arg(long, conflicts_with = "dry_run")]
+    check: bool,
+}
+
+#[derive(Args)]
+struct TidyArgs {
+    /// Fail if some entries are not tidy, without changing the file
+    #[arg(long)]
+    check: bool,
+    #[command(flatten)]
+    backup: BackupArgs,
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// Check that the entries of the same venue and year use the same venue
+    /// string. Runs by default if no other check is selected
+    #[arg(long)]
+    venue_consistency: bool,
+    /// Look for journal versions of conference papers
+    #[arg(long)]
+    journal_versions: bool,
+    /// Report the entries of types that are neither known nor in the
+    /// `type_aliases` of the config, and count the entries of each type
+    #[arg(long)]
+    entry_types: bool,
+    /// Check that the DBLP keys still resolve, reporting the records DBLP
+    /// moved to another key and the ones it no longer has
+    #[arg(long)]
+    keys: bool,
+    /// Fix the problems found
+    ///
+    /// Inconsistent venues are rewritten to the most common string, or to
+    /// the one currently on DBLP. For each journal version found you choose
+    /// whether to replace the conference entry, add it besides, or link the two;
+    /// replacing renames its citations in the .tex files next to the bibtex
+    /// file. Entries whose record moved take its new key.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "majority")]
+    fix: Option<check::Fix>,
+    /// Only show the changes that would be made
+    #[arg(long)]
+    dry_run: bool,
+    /// With --keys and --fix, also rename the moved keys in the citations
+    /// of the .tex files next to the bibtex file
+    #[arg(long)]
+    rename_citations: bool,
+    /// Only search again for entries changed since the last run, or
+    /// checked too long ago, reusing the results of the last run for the rest
+    #[arg(long, conflicts_with = "full")]
+    since_last_run: bool,
+    /// Check all entries, refreshing the results saved for --since-last-run
+    #[arg(long)]
+    full: bool,
+    /// Print the problems found as json
+    #[arg(long)]
+    json: bool,
+    /// With --since-last-run, results older than this many days are stale
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    stale_after: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ClipWhat {
+    /// The entries, as DBLP formats them
+    Bibtex,
+    /// The keys, separated by commas
+    Key,
+    /// `\cite{...}` of the keys
+    Cite,
+    /// `\autocite{...}` of the keys, for biblatex
+    Autocite,
+    /// A citation to read, in markdown, with a link to the DOI
+    Markdown,
+    /// A citation to read, in plain text
+    Text,
+}
+
+impl ClipWhat {
+    fn describe(self) -> &'static str {
+        match self {
+            ClipWhat::Bibtex => "the entry",
+            ClipWhat::Key => "the key",
+            ClipWhat::Cite | ClipWhat::Autocite | ClipWhat::Markdown | ClipWhat::Text => {
+                "the citation"
+            }
+        }
+    }
+}
+
+/// The LaTeX command citing the keys, all in one
+fn cite(command: &str, keys: &[String]) -> String {
+    format!("\\{}{{{}}}", command, keys.join(","))
+}
+
+/// The commands with json output
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaCommand {
+    Check,
+    Doctor,
+    /// The statistics written by --stats-out
+    Stats,
+    Search,
+}
+
+/// Converts the entries of the bib file, rewriting it unless only showing
+/// the conversion
+fn convert(cli: &Cli, bib_path: &PathBuf, args: &ConvertArgs) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let options = args.options(cli.verbose)?;
+    let conversion = convert::convert_entries(&src, &options)?;
+    let (entries, failures) = (&conversion.entries, &conversion.failures);
+    if !options.filter.is_empty() {
+        eprintln!(
+            "{} of {} DBLP entries match the filter",
+            conversion.fetched, conversion.dblp
+        );
+    }
+    let failed: Vec<&str> = failures.iter().map(|f| f.key.as_str()).collect();
+    eprintln!(
+        "{} refreshed from DBLP, {} kept as local text, {} not from DBLP, {} failed{}{}",
+        conversion.fetched - failures.len(),
+        conversion.kept(),
+        conversion.not_dblp,
+        failures.len(),
+        if failed.is_empty() { "" } else { ": " },
+        failed.join(", ")
+    );
+    if !failures.is_empty() {
+        eprint!("{}", fetch_failures_summary(failures, args.on_fetch_error));
+    }
+    if args.on_fetch_error == convert::OnFetchError::Fail && !failures.is_empty() {
+        bail!("some entries could not be fetched");
+    }
+    if args.dry_run {
+        show_conversion(bib_path, &src, entries, failures, args.to)?;
+    } else {
+        let backup = cli.backup_with(bib_path, &args.backup)?;
+        let _lock = lock_unchanged(bib_path, Some(&src))?;
+        backup.write(&src)?;
+        // overwrite the file, only once everything has been written
+        scratch::write_atomically(bib_path, |f| {
+            Ok(write!(f, "{}", convert::render_converted(&src, entries))?)
+        })?;
+    }
+    Ok(())
+}
+
+fn show_conversion(
+    bib_path: &std::path::Path,
+    src: &str,
+    entries: &[convert::Converted],
+    failures: &[convert::FetchFailure],
+    to: Format,
+) -> Result<()> {
+    let color = std::io::stdout().is_terminal();
+    let format = to
+        .to_possible_value()
+        .map_or(String::new(), |v| v.get_name().to_owned());
+    let (mut unchanged, mut reformatted, mut changed) = (0, 0, 0);
+    for entry in entries.iter() {
+        let original = entry.span.clone().map(|span| &src[span]);
+        let new = entry.text.as_deref();
+        if failures.iter().any(|f| f.key == entry.key) {
+            continue;
+        }
+        match (original, new) {
+            (Some(o), Some(n)) if o.trim() == n.trim() => {
+                unchanged += 1;
+                continue;
+            }
+            (Some(o), Some(n)) if convert::same_fields(o, n) => reformatted += 1,
+            _ => changed += 1,
+        }
+        let source = match &entry.span {
+            Some(span) => {
+                let at = output::Location::at(bib_path, src, span.start);
+                format!("{} ({}:{})", entry.key, at.file.display(), at.line)
+            }
+            None => "/dev/null".to_owned(),
+        };
+        let target = match new {
+            Some(_) => format!("{} ({})", entry.key, format),
+            None => "/dev/null".to_owned(),
+        };
+        print!(
+            "--- {}\n+++ {}\n{}",
+            source,
+            target,
+            diff::unified(original.unwrap_or(""), new.unwrap_or(""), color)
+        );
+    }
+    eprintln!(
+        "{} entries unchanged, {} reformatted, {} changed, {} fetch failures",
+        unchanged,
+        reformatted,
+        changed,
+        failures.len()
+    );
+    if reformatted + changed > 0 {
+        bail!("{} entries would change", reformatted + changed);
+    }
+    Ok(())
+}
+
+/// The section listing the entries that could not be fetched
+fn fetch_failures_summary(
+    failures: &[convert::FetchFailure],
+    policy: convert::OnFetchError,
+) -> String {
+    let mut out = format!(
+        "{} entries could not be fetched, {}:\n",
+        failures.len(),
+        policy.outcome()
+    );
+    for f in failures {
+        let status = f
+            .error
+            .http_status()
+            .map_or("no response".to_owned(), |s| format!("HTTP {}", s));
+        let source = match &f.mirror {
+            Some(mirror) => format!("{} from {}", status, mirror),
+            None => "unreadable record".to_owned(),
+        };
+        out.push_str(&format!(
+            "  {}: {} ({}): {}\n",
+            f.key,
+            source,
+            f.code(),
+            f.error
+        ));
+    }
+    out
+}
+
+#[derive(Subcommand)]
+enum SuggestionsActions {
+    /// List the pending suggestions
+    List {
+        /// Also list the dismissed ones
+        #[arg(long)]
+        all: bool,
+    },
+    /// Apply the suggestions about the given entries, or the ones picked
+    Apply { keys: Vec<String> },
+    /// Stop suggesting the current replacements of the given entries
+    Dismiss {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheActions {
+    /// Remove scratch files left behind by interrupted runs
+    Gc,
+    /// Remove the cached responses of DBLP
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum NotesActions {
+    /// Create notes for the entries of the bibtex file that have none
+    CreateMissing {
+        /// Only consider entries whose key or title matches the regex
+        #[arg(long = "match", value_name = "REGEX")]
+        pattern: Option<Regex>,
+        /// Only consider entries with the given keyword
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only consider the keys listed, one per line, in the file
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        keys_from: Option<PathBuf>,
+        /// Only list the notes that would be created
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+        #[command(flatten)]
+        template: TemplateArgs,
+    },
+    /// Pick one of the notes, by the key and title of their paper, and open
+    /// it in $VISUAL or $EDITOR, or else vi
+    ///
+    /// Notes without metadata are listed too, by their file name.
+    Find {
+        /// Print the path of the notes instead of opening them
+        #[arg(long)]
+        path_only: bool,
+        #[command(flatten)]
+        filter: NotesFilterArgs,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+        query: Vec<String>,
+    },
+    /// List the notes, with their key, status and tags
+    ///
+    /// With --tag and --status, this is a reading list.
+    List {
+        #[command(flatten)]
+        filter: NotesFilterArgs,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+    /// List the papers that the notes of a paper link to, with `[[key]]`,
+    /// and the notes linking to it
+    Links {
+        /// The key of the paper, with or without the DBLP: prefix
+        #[arg(required_unless_present = "graph")]
+        key: Option<String>,
+        /// Print the links in the DOT language of Graphviz instead, all of
+        /// them unless a key is given
+        #[arg(long)]
+        graph: bool,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+    /// Write a table of the notes in index.md, in the notes directory
+    ///
+    /// Only the part of the index between its markers is replaced, or added
+    /// at its end if there are none. Years and venues come from the bibtex
+    /// file.
+    Index {
+        /// Split the table by year or by tag
+        #[arg(long, value_enum, default_value = "none")]
+        group_by: notes_index::GroupBy,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+    /// Make the front matter of the notes again from the entries of the
+    /// bibtex file, leaving the rest of the notes as it is
+    ///
+    /// The keys of the front matter that are not fields of the entry, like
+    /// `tags` and `status`, are kept.
+    Sync {
+        /// Only list the notes that would be updated
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+    /// List the notes whose key is not in the bibtex file, and the entries
+    /// that have no notes
+    ///
+    /// Fails if there are orphaned notes, the ones whose key is missing.
+    Status {
+        /// Only list the keys of the entries without notes
+        #[arg(long, conflicts_with = "orphans_only")]
+        missing_only: bool,
+        /// Only list the paths of the orphaned notes
+        #[arg(long)]
+        orphans_only: bool,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+    /// Check the front matter of the notes for typos and invalid values
+    ///
+    /// Unknown keys are reported only when close to a known one, as probable
+    /// typos. Known keys are checked for the type of their value, and
+    /// `status` for being one of the allowed ones.
+    Validate {
+        /// Ask whether to rename each misspelled key to the known one
+        #[arg(long)]
+        fix_typos: bool,
+        /// Also accept this key, with any value
+        #[arg(long = "extra-key", value_name = "KEY")]
+        extra_keys: Vec<String>,
+        #[command(flatten)]
+        notes: NotesDirArgs,
+    },
+}
+
+/// Directory where notes are created, by default
+const NOTES_DIR: &str = "notes";
+
+/// How much older than the bib file its backup can be for undo to restore
+/// it, by default
+const UNDO_WINDOW: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// What became of a text to copy to the clipboard
+#[derive(Debug)]
+enum Copied {
+    /// copied by this command
+    Via(&'static str),
+    Failed(anyhow::Error),
+    /// not copied, with --no-clipboard or with the output going to a program
+    Off,
+}
+
+/// Tells the user where the key went. The key is printed to `out` if it
+/// was not copied or if asked to, and nothing else is ever printed there.
+/// Never fails, since the bib file may already be modified.
+fn report_key(key: &str, copied: &Copied, print_key: bool, mut out: impl Write) {
+    match copied {
+        Copied::Via(cmd) => eprintln!("key {} copied via {}", key, cmd),
+        Copied::Failed(_) => eprintln!("clipboard unavailable, key printed below"),
+        Copied::Off => {}
+    }
+    if print_key || !matches!(copied, Copied::Via(_)) {
+        if let Err(err) = writeln!(out, "{}", key) {
+            eprintln!("warning: could not print the key: {}", err);
+        }
+    }
+}
+
+/// Runs the command, exiting with the code of the error if it fails, so
+/// that scripts can tell errors apart
+fn main() -> std::process::ExitCode {
+    match try_main() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            let known = err
+                .chain()
+                .find_map(|e| e.downcast_ref::<error::DblpError>());
+            if let Some(hint) = known.and_then(error::DblpError::hint) {
+                eprintln!("hint: {}", hint);
+            }
+            std::process::ExitCode::from(
+                known.map_or(error::exit::OTHER, error::DblpError::exit_code),
+            )
+        }
+    }
+}
+
+fn try_main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(rest) = args.strip_prefix(&[completions::COMPLETE_KEYS.to_owned()]) {
+        let rest = rest.strip_prefix(&["--".to_owned()]).unwrap_or(rest);
+        complete_keys(rest.first().map_or("", String::as_str));
+        return Ok(());
+    }
+    let start = std::time::Instant::now();
+    let command = with_config(Cli::command(), &config_defaults(&config::load()?));
+    let matches = examples::with_examples(command).get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+    let (verbose, stats_out) = (cli.verbose, cli.stats_out.clone());
+    if cli.no_cache {
+        cache::disable();
+    }
+    dblp::set_timeout(std::time::Duration::from_secs(cli.timeout));
+    notices::set_handler(|notice| eprintln!("{}", notice));
+    if let Some(url) = cli.dblp_url.clone() {
+        dblp::set_base_url(url);
+    }
+
+    let res = run(cli, &matches);
+    let report = stats::STATS.report(
+        matches.subcommand_name().unwrap_or(""),
+        start.elapsed(),
+        mirrors::HEALTH.status(),
+    );
+    if verbose {
+        eprintln!("{}", report.summary());
+    }
+    if let Some(path) = stats_out {
+        std::fs::write(&path, output::to_json(&report)?)
+            .with_context(|| format!("writing stats to {:?}", path))?;
+    }
+    res
+}
+
+fn run(cli: Cli, matches: &clap::ArgMatches) -> Result<()> {
+    // only asked for when needed, since picking it may ask the user
+    let bib_path = || cli.get_bib_path();
+
+    match cli.subcommand {
+        Actions::Add {
+            print_key,
+            first,
+            ref types,
+            max_hits,
+            edit,
+            ref keys,
+            ref insert,
+            ref strip,
+            ref query,
+        } => {
+            let bib_path = bib_path()?;
+            cli.check_workspace(&bib_path)?;
+            let adding = add::Adding {
+                keying: keys.keying()?,
+                edit,
+                sorted: insert.sorted()?,
+                strip: strip.fields()?,
+            };
+            let query = join_param_string(query);
+            let hits = query_hits(&query, types, max_hits)?;
+            let selections = if first {
+                vec![first_hit(hits)]
+            } else {
+                stats::STATS.phase("select", || show_and_select_many(hits.into_iter()))?
+            };
+            if matches!(adding.keying, add::Keying::Exact(_)) && selections.len() > 1 {
+                bail!(
+                    "--key keys a single entry, but {} were picked",
+                    selections.len()
+                );
+            }
+
+            let added = add_records(
+                &cli,
+                &bib_path,
+                &adding,
+                selections.iter().map(|s| s.get_key()),
+                |key| {
+                    let hit = selections
+                        .iter()
+                        .find(|s| s.get_key() == key)
+                        .expect("selected");
+                    Ok(dblp::get(&hit.bib_url(Format::Standard), Some(&hit.key))?)
+                },
+            )?;
+            if added.is_empty() {
+                return Ok(());
+            }
+            let key = added.join(", ");
+            report_key(&key, &cli.copy(&key), print_key, std::io::stdout());
+        }
+        Actions::AddKey {
+            print_key,
+            ref keys,
+            ref insert,
+            ref key,
+        } => {
+            let bib_path = bib_path()?;
+            cli.check_workspace(&bib_path)?;
+            let adding = add::Adding {
+                keying: keys.keying()?,
+                edit: false,
+                sorted: insert.sorted()?,
+                strip: StripArgs::default().fields()?,
+            };
+            let record = dblp::parse_key(key);
+            let added = add_records(
+                &cli,
+                &bib_path,
+                &adding,
+                std::iter::once(format!("DBLP:{}", record)),
+                |_| Ok(dblp::fetch_record(&record, Format::Standard)?),
+            )?;
+            let key = added.join(", ");
+            report_key(&key, &cli.copy(&key), print_key, std::io::stdout());
+        }
+        Actions::Doi {
+            print_key,
+            ref keys,
+            ref insert,
+            ref doi,
+        } => {
+            let bib_path = bib_path()?;
+            cli.check_workspace(&bib_path)?;
+            let adding = add::Adding {
+                keying: keys.keying()?,
+                edit: false,
+                sorted: insert.sorted()?,
+                strip: StripArgs::default().fields()?,
+            };
+            let doi = doi::parse_doi(doi)?;
+            let added = add_records(
+                &cli,
+                &bib_path,
+                &adding,
+                std::iter::once(doi::key(&doi)),
+                |_| doi::fetch(&doi),
+            )?;
+            let key = added.join(", ");
+            report_key(&key, &cli.copy(&key), print_key, std::io::stdout());
+        }
+        Actions::Arxiv {
+            print_key,
+            force,
+            ref keys,
+            ref insert,
+            ref id,
+        } => {
+            let bib_path = bib_path()?;
+            cli.check_workspace(&bib_path)?;
+            let adding = add::Adding {
+                keying: keys.keying()?,
+                edit: false,
+                sorted: insert.sorted()?,
+                strip: StripArgs::default().fields()?,
+            };
+            let id = arxiv::parse_id(id)?;
+            let preprint = arxiv::fetch(&id)?;
+            if !force {
+                if let Some(hit) = dblp_version(&preprint.title) {
+                    bail!(
+                        "DBLP has this paper as {}, add it with `dblp add-key {}`, \
+                        or pass --force to add the preprint anyway",
+                        hit.get_key(),
+                        hit.key
+                    );
+                }
+            }
+            let added = add_records(
+                &cli,
+                &bib_path,
+                &adding,
+                std::iter::once(arxiv::key(&id)),
+                |_| Ok(preprint.to_bibtex()),
+            )?;
+            let key = added.join(", ");
+            report_key(&key, &cli.copy(&key), print_key, std::io::stdout());
+        }
+        Actions::Search {
+            json,
+            types,
+            max_hits,
+            query,
+        } => {
+            let query = join_param_string(&query);
+            let resp = DblpResponse::query(&query, Format::Condensed, max_hits)?;
+            let hits: Vec<DblpHitInfo> = resp.matches_of(&types).collect();
+            if json {
+                let report = output::SearchReport {
+                    query: query.clone(),
+                    hits: hits.iter().map(DblpHitInfo::to_output).collect(),
+                };
+                println!("{}", output::to_json(&report)?);
+            } else {
+                print!(
+                    "{}",
+                    dblp::hits_table(&hits, std::io::stdout().is_terminal())
+                );
+            }
+            if hits.is_empty() {
+                return Err(error::DblpError::NoResults { query }.into());
+            }
+        }
+        Actions::Open {
+            ref types,
+            max_hits,
+            ref query,
+        } => {
+            let url = if query.is_empty() {
+                let bib_path = bib_path()?;
+                let entries = local::entries(&std::fs::read_to_string(&bib_path)?)?;
+                if entries.is_empty() {
+                    bail!("there are no entries in {}", bib_path.display());
+                }
+                let entry = show_and_select(entries.into_iter())?;
+                if entry.link.is_empty() {
+                    bail!("{} has neither a doi nor a url", entry.key);
+                }
+                entry.link
+            } else {
+                let hits = query_hits(&join_param_string(query), types, max_hits)?;
+                show_and_select(hits.into_iter())?.link().to_owned()
+            };
+            eprintln!("opening {}", url);
+            open_url(&url)?;
+        }
+        Actions::Info {
+            bibtex,
+            ref types,
+            max_hits,
+            ref query,
+        } => {
+            // a key, rather than words of a title
+            let key = match query.as_slice() {
+                [key] if key.contains('/') => dblp::parse_key(key),
+                _ => {
+                    let hits = query_hits(&join_param_string(query), types, max_hits)?;
+                    show_and_select(hits.into_iter())?.key
+                }
+            };
+            let record = dblp::fetch_record(&key, Format::Standard)?;
+            if bibtex {
+                println!("{}", record.trim_end());
+                return Ok(());
+            }
+            let bibliography =
+                Bibliography::parse(&record).map_err(|e| error::DblpError::from_bib(e, &record))?;
+            // DBLP answers with the new record of superseded keys
+            let entry = bibliography
+                .get(&format!("DBLP:{}", key))
+                .or(bibliography.iter().next())
+                .with_context(|| format!("DBLP has no entry for {}", key))?;
+            println!(
+                "{}",
+                dblp::render_record(entry, std::io::stdout().is_terminal())
+            );
+            match bib_path() {
+                Ok(bib_path) => {
+                    let src = std::fs::read_to_string(&bib_path).unwrap_or_default();
+                    match bib::presence(&src, &entry.key) {
+                        bib::Presence::PresentAs(key) if key == entry.key => {
+                            println!("key:   {}, in {}", entry.key, bib_path.display())
+                        }
+                        bib::Presence::PresentAs(key) => {
+                            println!("key:   {}, in {} as {}", entry.key, bib_path.display(), key)
+                        }
+                        bib::Presence::NotPresent => {
+                            println!("key:   {}, not in {}", entry.key, bib_path.display())
+                        }
+                    }
+                }
+                Err(_) => println!("key:   {}", entry.key),
+            }
+        }
+        Actions::Clip {
+            what,
+            format,
+            first,
+            ref types,
+            max_hits,
+            ref query,
+        } => {
+            let query = join_param_string(query);
+            let hits = query_hits(&query, types, max_hits)?;
+            let selections = if first {
+                vec![first_hit(hits)]
+            } else {
+                stats::STATS.phase("select", || show_and_select_many(hits.into_iter()))?
+            };
+            let keys: Vec<String> = selections.iter().map(|s| s.get_key()).collect();
+            let text = match what {
+                ClipWhat::Bibtex => selections
+                    .iter()
+                    .map(|s| {
+                        Ok(dblp::get(&s.bib_url(format), Some(&s.key))?
+                            .trim_end()
+                            .to_owned())
+                    })
+                    .collect::<Result<Vec<String>>>()?
+                    .join("\n\n"),
+                ClipWhat::Key => keys.join(", "),
+                ClipWhat::Cite => cite("cite", &keys),
+                ClipWhat::Autocite => cite("autocite", &keys),
+                ClipWhat::Markdown | ClipWhat::Text => {
+                    let max_authors = config::load()?
+                        .citation_max_authors
+                        .unwrap_or(dblp::CITATION_MAX_AUTHORS);
+                    selections
+                        .iter()
+                        .map(|s| s.citation(what == ClipWhat::Markdown, max_authors))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            };
+            match cli.copy(&text) {
+                Copied::Via(_) => {}
+                Copied::Failed(err) => {
+                    eprintln!("warning: {}, printing {} instead", err, what.describe());
+                    println!("{}", text);
+                }
+                Copied::Off => println!("{}", text),
+            }
+        }
+        Actions::Note {
+            ref types,
+            max_hits,
+            local,
+            ref notes,
+            ref template,
+            ref tags,
+            no_edit,
+            ref query,
+        } => {
+            let bib_path = bib_path()?;
+            let template = template.load()?;
+            let (key, title) = if local {
+                pick_local(&bib_path, &query.join(" "))?
+            } else {
+                add_for_notes(&cli, &bib_path, &join_param_string(query), types, max_hits)?
+            };
+            let new = NewNotes {
+                tags,
+                template: template.as_ref(),
+            };
+            let path = take_notes(&bib_path, &notes.notes_dir, &new, &key, &title)?;
+            println!("{}", path.display());
+            cli.copy(&path.display().to_string());
+            if !no_edit {
+                edit(&path)?;
+            }
+        }
+        Actions::Convert(ref args) => {
+            let bib_paths = cli.get_bib_paths()?;
+            for bib_path in &bib_paths {
+                if bib_paths.len() > 1 {
+                    eprintln!("converting {}", bib_path.display());
+                }
+                convert(&cli, bib_path, args)?;
+            }
+        }
+        Actions::Check(ref args) => run_check(&cli, args)?,
+        Actions::Merge {
+            ref base,
+            ref theirs,
+            no_interactive,
+        } => merge_bibs(&cli, &bib_path()?, base, theirs, no_interactive)?,
+        Actions::Update {
+            ref key,
+            on_fetch_error,
+        } => update_entry(&cli, &bib_path()?, key.as_deref(), on_fetch_error)?,
+        Actions::Remove { ref query } => remove_entries(&cli, &bib_path()?, &query.join(" "))?,
+        Actions::List { print_key } => {
+            let bib_path = bib_path()?;
+            let entries = local::entries(&std::fs::read_to_string(&bib_path)?)?;
+            if entries.is_empty() {
+                bail!("there are no entries in {}", bib_path.display());
+            }
+            let key = show_and_select(entries.into_iter())?.key;
+            report_key(&key, &cli.copy(&key), print_key, std::io::stdout());
+        }
+        Actions::Dedup { report } => dedup(&cli, &bib_path()?, report)?,
+        Actions::Undo {
+            yes,
+            ref backup_dir,
+        } => undo(&cli, &bib_path()?, yes, backup_dir.as_deref())?,
+        Actions::Sort(ref args) => sort(&cli, &bib_path()?, args)?,
+        Actions::Tidy(ref args) => tidy(&cli, &bib_path()?, args)?,
+        Actions::Examples => println!("{}", examples::render_all()),
+        Actions::Man { out } => {
+            let cmd = examples::with_examples(Cli::command());
+            for path in man::write_pages(cmd, &out)? {
+                println!("{}", path.display());
+            }
+        }
+        Actions::Schema { command } => println!(
+            "{}",
+            match command {
+                SchemaCommand::Check => output::schema::<output::CheckReport>(),
+                SchemaCommand::Doctor => output::schema::<output::DoctorReport>(),
+                SchemaCommand::Stats => output::schema::<output::StatsReport>(),
+                SchemaCommand::Search => output::schema::<output::SearchReport>(),
+            }
+        ),
+        Actions::Suggestions { ref action } => {
+            let bib_path = bib_path()?;
+            let src = std::fs::read_to_string(&bib_path)?;
+            let mut store = suggestions::Store::load(&bib_path, &src)?;
+            let index = key_index(&src)?;
+            let resolve = |keys: &[String]| -> Result<Vec<String>> {
+                keys.iter().map(|k| keys::resolve_key(k, &index)).collect()
+            };
+            match action {
+                SuggestionsActions::List { all } => {
+                    let now = check::now();
+                    for s in store.list(*all) {
+                        let age = std::time::Duration::from_secs(now.saturating_sub(s.discovered));
+                        println!(
+                            "{} -> {} (found {} ago{})\n    {}",
+                            s.source_key,
+                            s.proposed_key,
+                            progress::format_duration(age),
+                            if s.dismissed { ", dismissed" } else { "" },
+                            s.evidence
+                        );
+                    }
+                }
+                SuggestionsActions::Apply { keys } => {
+                    apply_suggestions(&cli, &bib_path, &src, &mut store, &resolve(keys)?)?
+                }
+                SuggestionsActions::Dismiss { keys } => {
+                    for key in resolve(keys)? {
+                        if store.dismiss(&key) == 0 {
+                            eprintln!("no pending suggestion about {}", key);
+                        }
+                    }
+                }
+            }
+            store.save()?;
+        }
+        Actions::Cache {
+            action: CacheActions::Gc,
+        } => {
+            let removed = scratch::gc(scratch::ORPHAN_AGE)?;
+            eprintln!("removed {} orphaned scratch files", removed);
+        }
+        Actions::Cache {
+            action: CacheActions::Clear,
+        } => {
+            let removed = cache::clear()?;
+            eprintln!("removed {} cached responses", removed);
+        }
+        Actions::Config => print!("{}", render_config(&cli, matches)?),
+        Actions::Completions { shell } => {
+            print!("{}", completions::script(Cli::command(), shell))
+        }
+
+        Actions::Doctor { json, offline } => {
+            let report = doctor_report(&cli, matches, offline);
+            if json {
+                println!("{}", output::to_json(&report)?);
+            } else {
+                print!("{}", doctor::render(&report));
+            }
+        }
+        Actions::Notes {
+            action:
+                NotesActions::CreateMissing {
+                    ref pattern,
+                    ref tag,
+                    ref keys_from,
+                    dry_run,
+                    ref notes,
+                    ref template,
+                },
+        } => {
+            let src = std::fs::read_to_string(bib_path()?)?;
+            let bibliography =
+                Bibliography::parse(&src).map_err(|e| error::DblpError::from_bib(e, &src))?;
+            let index = key_index(&src)?;
+            // a key that does not resolve fails alone, like the entries
+            let mut unknown = 0;
+            let keys = keys_from
+                .as_ref()
+                .map(|p| -> Result<Vec<String>> {
+                    Ok(std::fs::read_to_string(p)?
+                        .lines()
+                        .map(str::trim)
+                        .filter(|l| !l.is_empty())
+                        .filter_map(|l| match keys::resolve_key(l, &index) {
+                            Ok(key) => Some(key),
+                            Err(err) => {
+                                eprintln!("Error creating notes for {}: {}", l, err);
+                                unknown += 1;
+                                None
+                            }
+                        })
+                        .collect())
+                })
+                .transpose()?;
+            let entries = bibliography.iter().filter(|entry| {
+                let title = bib::title(entry);
+                keys.as_ref().is_none_or(|keys| keys.contains(&entry.key))
+                    && pattern
+                        .as_ref()
+                        .is_none_or(|re| re.is_match(&entry.key) || re.is_match(&title))
+                    && tag.as_ref().is_none_or(|tag| has_keyword(entry, tag))
+            });
+            create_missing_notes(
+                &notes.notes_dir,
+                &config::load()?.notes_extensions(),
+                entries,
+                unknown,
+                template.load()?.as_ref(),
+                dry_run,
+            );
+        }
+        Actions::Notes {
+            action:
+                NotesActions::Validate {
+                    fix_typos,
+                    extra_keys,
+                    notes,
+                },
+        } => validate_notes(&notes.notes_dir, &extra_keys, fix_typos)?,
+        Actions::Notes {
+            action:
+                NotesActions::Status {
+                    missing_only,
+                    orphans_only,
+                    ref notes,
+                },
+        } => notes_status(&bib_path()?, &notes.notes_dir, missing_only, orphans_only)?,
+        Actions::Notes {
+            action: NotesActions::List { filter, notes },
+        } => {
+            for note in notes::notes(&notes.notes_dir, &config::load()?.notes_extensions()) {
+                if !filter.keeps(note.meta.as_ref()) {
+                    continue;
+                }
+                match &note.meta {
+                    Some(meta) => {
+                        let status = if meta.status.is_empty() {
+                            "-"
+                        } else {
+                            &meta.status
+                        };
+                        let line = format!(
+                            "{}  {}  {}  {}",
+                            note.path.display(),
+                            meta.key,
+                            status,
+                            meta.tags.join(",")
+                        );
+                        println!("{}", line.trim_end());
+                    }
+                    None => println!("{}  (no metadata)", note.path.display()),
+                }
+            }
+        }
+        Actions::Notes {
+            action:
+                NotesActions::Links {
+                    ref key,
+                    graph,
+                    ref notes,
+                },
+        } => notes_links(bib_path().ok(), &notes.notes_dir, key.as_deref(), graph)?,
+        Actions::Notes {
+            action:
+                NotesActions::Index {
+                    group_by,
+                    ref notes,
+                },
+        } => index_notes(bib_path().ok(), &notes.notes_dir, group_by)?,
+        Actions::Notes {
+            action: NotesActions::Sync { dry_run, ref notes },
+        } => sync_notes(&bib_path()?, &notes.notes_dir, dry_run)?,
+        Actions::Notes {
+            action:
+                NotesActions::Find {
+                    path_only,
+                    filter,
+                    notes,
+                    query,
+                },
+        } => {
+            let query = query.join(" ");
+            let found: Vec<notes::Note> =
+                notes::notes(&notes.notes_dir, &config::load()?.notes_extensions())
+                    .into_iter()
+                    .filter(|note| filter.keeps(note.meta.as_ref()) && note.matches(&query))
+                    .collect();
+            if found.is_empty() {
+                bail!(
+                    "no notes in {} match {:?}",
+                    notes.notes_dir.display(),
+                    query
+                );
+            }
+            let note = show_and_select(found.into_iter())?;
+            if path_only {
+                println!("{}", note.path.display());
+            } else {
+                edit(&note.path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the keys of the bib files starting with the prefix, for the
+/// completion scripts. Never fails, not to show errors in the prompt.
+fn complete_keys(prefix: &str) {
+    let defaults = config::load()
+        .map(|config| config_defaults(&config))
+        .unwrap_or_default();
+    // the options in effect when none is given
+    let matches = with_config(Cli::command(), &defaults).try_get_matches_from(["dblp", "config"]);
+    let Some(cli) = matches.ok().and_then(|m| Cli::from_arg_matches(&m).ok()) else {
+        return;
+    };
+    let mut keys = Vec::new();
+    for path in cli.get_bib_paths().unwrap_or_default() {
+        let src = std::fs::read_to_string(path).unwrap_or_default();
+        let Ok(index) = key_index(&src) else {
+            continue;
+        };
+        for key in completions::matching_keys(&index, prefix) {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    for key in keys {
+        println!("{}", key);
+    }
+}
+
+/// The defaults the config sets for the arguments, by id
+fn config_defaults(config: &config::Config) -> Vec<(&'static str, Vec<String>)> {
+    let mut defaults = Vec::new();
+    if let Some(paths) = &config.bibtex {
+        let paths = paths.to_vec().into_iter().map(|p| p.display().to_string());
+        defaults.push(("bibtex", paths.collect()));
+    }
+    if let Some(dir) = &config.notes_dir {
+        defaults.push(("notes_dir", vec![dir.display().to_string()]));
+    }
+    if let Some(format) = config.format {
+        defaults.push(("format", vec![name_of(format.to_possible_value())]));
+    }
+    if let Some(max_hits) = config.max_hits {
+        defaults.push(("max_hits", vec![max_hits.to_string()]));
+    }
+    if let Some(backend) = config.clipboard {
+        defaults.push(("clipboard", vec![name_of(backend.to_possible_value())]));
+    }
+    if let Some(url) = &config.dblp_url {
+        defaults.push(("dblp_url", vec![url.clone()]));
+    }
+    defaults
+}
+
+/// The command, and its subcommands, with the defaults of their arguments
+/// replaced, so that flags still override them
+fn with_config(mut command: clap::Command, defaults: &[(&str, Vec<String>)]) -> clap::Command {
+    for (id, values) in defaults {
+        if command.get_arguments().any(|arg| arg.get_id() == id) {
+            command = command.mut_arg(id, |arg| arg.default_values(values.clone()));
+        }
+    }
+    let names: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_owned())
+        .collect();
+    for name in names {
+        command = command.mut_subcommand(name, |sub| with_config(sub, defaults));
+    }
+    command
+}
+
+/// The settings in effect as TOML, each with where it comes from
+fn render_config(cli: &Cli, matches: &clap::ArgMatches) -> Result<String> {
+    let layers = config::layers()?;
+    let source = |key: &str| {
+        layers
+            .source(key)
+            .map_or("default".to_owned(), |path| path.display().to_string())
+    };
+    let mut settings: std::collections::BTreeMap<String, (toml::Value, String)> = layers
+        .merged()
+        .into_iter()
+        .map(|(key, value)| {
+            let source = source(&key);
+            (key, (value, source))
+        })
+        .collect();
+    let given = [
+        (
+            "bibtex",
+            toml::Value::Array(cli.bibtex.iter().cloned().map(toml::Value::from).collect()),
+        ),
+        (
+            "clipboard",
+            toml::Value::from(name_of(cli.clipboard.to_possible_value())),
+        ),
+        (
+            "dblp_url",
+            toml::Value::from(cli.dblp_url.clone().unwrap_or_default()),
+        ),
+    ];
+    for (id, value) in given {
+        let provenance = doctor::provenance(matches, id, &layers);
+        if matches!(
+            provenance,
+            output::Provenance::Flag | output::Provenance::Env
+        ) {
+            settings.insert(id.to_owned(), (value, provenance.to_string()));
+        }
+    }
+    let defaults = [
+        ("clipboard", toml::Value::from("auto")),
+        ("format", toml::Value::from("standard")),
+        ("max_hits", toml::Value::from(dblp::MAX_HITS as i64)),
+        ("notes_dir", toml::Value::from(NOTES_DIR)),
+    ];
+    for (key, value) in defaults {
+        settings
+            .entry(key.to_owned())
+            .or_insert((value, "default".to_owned()));
+    }
+    Ok(settings
+        .into_iter()
+        .map(|(key, (value, source))| format!("{} = {}  # {}\n", key, value, source))
+        .collect())
+}
+
+/// The name of the value of an enum argument
+fn name_of(value: Option<clap::builder::PossibleValue>) -> String {
+    value.map(|v| v.get_name().to_owned()).unwrap_or_default()
+}
+
+fn doctor_report(cli: &Cli, matches: &clap::ArgMatches, offline: bool) -> output::DoctorReport {
+    use output::{Provenance, Setting};
+    let layers = config::layers().unwrap_or_default();
+    let bib_path = cli.target_bib(false);
+    let mut settings = vec![Setting {
+        name: "bibtex",
+        value: bib_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| "none".to_owned()),
+        source: match doctor::provenance(matches, "bibtex", &layers) {
+            _ if cli.into.is_some() => Provenance::Flag,
+            Provenance::Default => Provenance::Detected,
+            provenance => provenance,
+        },
+    }];
+    settings.push(Setting {
+        name: "max_entry_size",
+        value: cli.max_entry_size.to_string(),
+        source: doctor::provenance(matches, "max_entry_size", &layers),
+    });
+    let notes_dir = layers.config().ok().and_then(|config| config.notes_dir);
+    settings.push(Setting {
+        name: "notes_dir",
+        value: notes_dir
+            .as_ref()
+            .map_or(NOTES_DIR.to_owned(), |dir| dir.display().to_string()),
+        source: match notes_dir {
+            Some(_) => Provenance::Config,
+            None => Provenance::Default,
+        },
+    });
+    settings.push(Setting {
+        name: "data_dir",
+        value: scratch::data_dir().display().to_string(),
+        source: if std::env::var_os(scratch::DATA_DIR_VAR).is_some()
+            || std::env::var_os("XDG_DATA_HOME").is_some()
+        {
+            Provenance::Env
+        } else {
+            Provenance::Default
+        },
+    });
+    output::DoctorReport {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("DBLP_GIT_HASH"),
+        os: std::env::consts::OS,
+        settings,
+        bib: doctor::bib_status(bib_path),
+        cache: doctor::cache_status(scratch::data_dir()),
+        mirrors: (!offline).then(|| doctor::probe_mirrors(&dblp::mirrors())),
+        mirror_health: mirrors::HEALTH.status(),
+        clipboard: clipboard::programs()
+            .into_iter()
+            .map(doctor::probe_command)
+            .collect(),
+        opener: doctor::probe_command(opener().0),
+    }
+}
+
+fn merge_bibs(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    base: &std::path::Path,
+    theirs: &std::path::Path,
+    no_interactive: bool,
+) -> Result<()> {
+    let (ours, merged, summary) = merge::merge_files(base, bib_path, theirs, |conflict| {
+        if no_interactive {
+            return Ok(merge::Resolution::Both);
+        }
+        match (conflict.ours, conflict.theirs) {
+            (Some(o), Some(t)) => eprint!(
+                "{} changed on both sides:\n{}",
+                conflict.key,
+                diff::render(
+                    &diff::field_diff(&o.entry, &t.entry),
+                    std::io::stderr().is_terminal()
+                )
+            ),
+            (Some(_), None) => eprintln!("{} changed by us, removed by them", conflict.key),
+            (None, _) => eprintln!("{} removed by us, changed by them", conflict.key),
+        }
+        Ok(
+            match ask("keep [o]urs, [t]heirs, or [b]oth?", &['o', 't', 'b'])? {
+                'o' => merge::Resolution::Ours,
+                't' => merge::Resolution::Theirs,
+                _ => merge::Resolution::Both,
+            },
+        )
+    })?;
+    if merged != ours {
+        let backup = cli.backup()?;
+        let _lock = lock_unchanged(bib_path, Some(&ours))?;
+        backup.write(&ours)?;
+        scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", merged)?))?;
+    }
+    eprintln!(
+        "taken ours: {}, taken theirs: {}, merged: {}, conflicted: {}",
+        summary.taken_ours, summary.taken_theirs, summary.merged, summary.conflicted
+    );
+    Ok(())
+}
+
+/// Fetches the entry with the key, or the one the user picks, from DBLP,
+/// and replaces it in the bib file, printing how it changed
+fn update_entry(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    key: Option<&str>,
+    on_fetch_error: convert::OnFetchError,
+) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let index = key_index(&src)?;
+    let update = edits::update_entry(bib_path, &src, key, &index, on_fetch_error, |from_dblp| {
+        show_and_select(from_dblp.into_iter())
+    })?;
+    if let Some(failure) = &update.failure {
+        eprint!(
+            "{}",
+            fetch_failures_summary(std::slice::from_ref(failure), on_fetch_error)
+        );
+        if on_fetch_error == convert::OnFetchError::Fail {
+            bail!("{} could not be fetched", update.key);
+        }
+    }
+    if update.is_up_to_date() {
+        if update.failure.is_none() {
+            eprintln!("{} is up to date", update.key);
+        }
+        return Ok(());
+    }
+    print!(
+        "{}",
+        diff::render(&update.changes()?, std::io::stdout().is_terminal())
+    );
+    // the entry is replaced in the file as it is now, if it is still the
+    // one that was fetched again
+    let backup = cli.backup()?;
+    let _lock = lock_bib(bib_path)?;
+    let src = std::fs::read_to_string(bib_path)?;
+    let edit = update
+        .edit(&src)?
+        .with_context(|| changed_meanwhile(bib_path))?;
+    backup.write(&src)?;
+    scratch::write_atomically(bib_path, |f| {
+        Ok(write!(f, "{}", bib::splice(&src, vec![edit]))?)
+    })?;
+    if update.new_key != update.key {
+        eprintln!(
+            "warning: DBLP replaced {} with {}, which is now the key of the entry; the \\cite of the old key need renaming",
+            update.key, update.new_key
+        );
+    }
+    Ok(())
+}
+
+/// Removes the entries the user picks among the ones matching the query,
+/// warning about the ones still cited
+fn remove_entries(cli: &Cli, bib_path: &PathBuf, query: &str) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let keys = edits::keys_to_remove(&src, query, &key_index(&src)?, |candidates| {
+        show_and_select_many(candidates.into_iter())
+    })?;
+    // removed from the file as it is now, which may have changed while the
+    // user was picking
+    let backup = cli.backup()?;
+    let _lock = lock_bib(bib_path)?;
+    let src = std::fs::read_to_string(bib_path)?;
+    let removed = bib::remove_entries(&src, &keys)?;
+    backup.write(&src)?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", removed)?))?;
+    eprintln!("removed {}", keys.join(", "));
+    for citation in edits::still_cited(bib_path, &keys)? {
+        eprintln!(
+            "warning: {} is still cited at {}:{}",
+            citation.key,
+            citation.file.display(),
+            citation.line
+        );
+    }
+    Ok(())
+}
+
+/// Lists the groups of duplicates or, unless only reporting, removes all
+/// the entries of each group but the one the user picks
+fn dedup(cli: &Cli, bib_path: &PathBuf, report: bool) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let groups = check::duplicate_groups(&src)?;
+    if groups.is_empty() {
+        eprintln!("no duplicates");
+        return Ok(());
+    }
+    let spans = bib::entry_spans(&src)?;
+    let located = |key: &String| {
+        let line = spans.iter().find(|(k, _)| k == key).map_or(0, |(_, span)| {
+            output::Location::at(bib_path, &src, span.start).line
+        });
+        format!("{} ({}:{})", key, bib_path.display(), line)
+    };
+    if report {
+        for group in groups.iter() {
+            let entries: Vec<String> = group.iter().map(located).collect();
+            println!("{}", entries.join(", "));
+        }
+        bail!("found {} groups of duplicates", groups.len());
+    }
+    let (deduped, renames) = edits::dedup(&src, &groups, |group, members| {
+        eprintln!("same paper: {}", group.join(", "));
+        let choices = members.into_iter().map(KeepChoice::Entry);
+        match show_and_select(choices.chain([KeepChoice::All]))? {
+            KeepChoice::Entry(kept) => {
+                eprintln!("keeping {}", kept.key);
+                Ok(Some(kept.key))
+            }
+            KeepChoice::All => {
+                eprintln!("keeping all of them");
+                Ok(None)
+            }
+        }
+    })?;
+    if renames.is_empty() {
+        eprintln!("no duplicates removed");
+        return Ok(());
+    }
+    let removed: Vec<&str> = renames.keys().map(String::as_str).collect();
+    let backup = cli.backup()?;
+    let _lock = lock_unchanged(bib_path, Some(&src))?;
+    backup.write(&src)?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", deduped)?))?;
+    eprintln!("removed {}", removed.join(", "));
+    rename_citations(bib_path, &renames)
+}
+
+/// Restores the bib file from its backup, or from the latest backup in the
+/// directory, after showing what would change
+fn undo(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    yes: bool,
+    backup_dir: Option<&std::path::Path>,
+) -> Result<()> {
+    let backup = match backup_dir {
+        Some(dir) => paths::latest_backup_in(bib_path, dir)?,
+        None => Some(cli.get_backup_bib_path()?).filter(|path| path.is_file()),
+    }
+    .ok_or_else(|| anyhow!("there is no backup of {} to restore", bib_path.display()))?;
+    let window = config::load()?
+        .undo_window_s
+        .map_or(UNDO_WINDOW, std::time::Duration::from_secs);
+    let modified = |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified());
+    if let (Ok(current), Ok(saved)) = (modified(bib_path), modified(&backup)) {
+        let age = current.duration_since(saved).unwrap_or_default();
+        if age > window {
+            bail!(
+                "{} is {} older than {}, which may have been edited since: copy it by hand if you are sure, or raise undo_window_s in the config",
+                backup.display(),
+                progress::format_duration(age),
+                bib_path.display()
+            );
+        }
+    }
+    let current = std::fs::read_to_string(bib_path)?;
+    let saved = std::fs::read_to_string(&backup)?;
+    if saved == current {
+        eprintln!("{} is the same as {}", bib_path.display(), backup.display());
+        return Ok(());
+    }
+    let changes = diff::entry_changes(&current, &saved)?;
+    eprintln!(
+        "restoring {} adds {} entries, removes {} and changes {}",
+        backup.display(),
+        changes.added.len(),
+        changes.removed.len(),
+        changes.changed.len()
+    );
+    for (sign, keys) in [
+        ('+', &changes.added),
+        ('-', &changes.removed),
+        ('~', &changes.changed),
+    ] {
+        for key in keys {
+            eprintln!("  {} {}", sign, key);
+        }
+    }
+    cli.check_workspace(bib_path)?;
+    if !yes && !confirm(&format!("restore {}?", bib_path.display()))? {
+        return Err(error::DblpError::Aborted.into());
+    }
+    let _lock = lock_unchanged(bib_path, Some(&current))?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", saved)?))?;
+    eprintln!("restored {}", bib_path.display());
+    Ok(())
+}
+
+fn sort(cli: &Cli, bib_path: &PathBuf, args: &SortArgs) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let first = match args.by {
+        sort::SortBy::Cited => tex::first_citations(tex::citations(&args.tex)?),
+        _ => Vec::new(),
+    };
+    let sorting = sort::Sorting::new(&src, args.by, args.reverse, &first)?;
+    if args.dry_run {
+        for (key, value) in &sorting.sorted {
+            let cited = first.iter().position(|c| &c.key == key);
+            match (args.by, cited) {
+                (sort::SortBy::Cited, Some(i)) => println!(
+                    "{:>4}  {}  {}:{}",
+                    i + 1,
+                    key,
+                    first[i].file.display(),
+                    first[i].line
+                ),
+                (sort::SortBy::Cited, None) => println!("{:>4}  {}  uncited", "-", key),
+                (_, _) => println!("{}  {}", key, value.as_deref().unwrap_or("-")),
+            }
+        }
+        return Ok(());
+    }
+    if sorting.is_sorted() {
+        return Ok(());
+    }
+    if args.check {
+        match sorting.misplaced() {
+            Some(key) => bail!(
+                "{} is not sorted by {}, {} should come earlier",
+                bib_path.display(),
+                args.by.name(),
+                key
+            ),
+            None => bail!("{} is not sorted by {}", bib_path.display(), args.by.name()),
+        }
+    }
+    let reordered = sorting.reorder(&src)?;
+    let backup = cli.backup()?;
+    let _lock = lock_unchanged(bib_path, Some(&src))?;
+    backup.write(&src)?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", reordered)?))
+}
+
+fn tidy(cli: &Cli, bib_path: &PathBuf, args: &TidyArgs) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let untidy = tidy::untidy_entries(&src)?;
+    let mut count = 0;
+    for untidy in untidy.iter() {
+        match untidy {
+            tidy::Untidy::Changed { key, range, .. } => {
+                if args.check {
+                    let line = output::Location::at(bib_path, &src, range.start).line;
+                    eprintln!("{} ({}:{}) is not tidy", key, bib_path.display(), line);
+                }
+                count += 1;
+            }
+            tidy::Untidy::Unparsable { range } => eprintln!(
+                "warning: cannot parse the entry at {}:{}, leaving it as it is",
+                bib_path.display(),
+                output::Location::at(bib_path, &src, range.start).line
+            ),
+        }
+    }
+    if count == 0 {
+        eprintln!("all entries are tidy");
+        return Ok(());
+    }
+    if args.check {
+        bail!("found {} entries that are not tidy", count);
+    }
+    let tidied = tidy::apply(&src, &untidy);
+    let backup = cli.backup_with(bib_path, &args.backup)?;
+    let _lock = lock_unchanged(bib_path, Some(&src))?;
+    backup.write(&src)?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", tidied)?))?;
+    eprintln!("tidied {} entries", count);
+    Ok(())
+}
+
+fn run_check(cli: &Cli, args: &CheckArgs) -> Result<()> {
+    let bib_path = cli.get_bib_path()?;
+    let mut problems = 0;
+    let src = std::fs::read_to_string(&bib_path)?;
+    for (key, size) in bib::oversized_entries(&src, cli.max_entry_size)? {
+        eprintln!("warning: {} is {} large", key, bib::human_size(size));
+    }
+    drop(src);
+    let mut report = output::CheckReport::default();
+    if args.venue_consistency || !(args.journal_versions || args.entry_types || args.keys) {
+        problems += check_venues(cli, &bib_path, args, &mut report.findings)?;
+    }
+    if args.journal_versions {
+        problems += check_journal_versions(cli, &bib_path, args, &mut report.findings)?;
+    }
+    if args.entry_types {
+        problems += check_entry_types(&bib_path, args, &mut report.findings)?;
+    }
+    if args.keys {
+        problems += check_keys(cli, &bib_path, args, &mut report.findings)?;
+    }
+    if args.json {
+        println!("{}", output::to_json(&report)?);
+    }
+    if problems > 0 && (args.fix.is_none() || args.dry_run) {
+        bail!("found {} problems", problems);
+    }
+    Ok(())
+}
+
+/// The hit of DBLP with the given title, if any. Failing to search is not
+/// an error, the search is only a hint.
+fn dblp_version(title: &str) -> Option<DblpHitInfo> {
+    let hits = dblp::query(title, Format::Condensed, dblp::TITLE_HITS).ok()?;
+    let title = similarity::normalize_text(title);
+    hits.into_iter()
+        .find(|hit| similarity::normalize_text(&hit.title) == title)
+}
+
+/// The pattern to key added entries with, the given one or the one of the
+/// config, if any
+fn key_pattern_of(given: Option<&str>) -> Result<Option<keygen::Pattern>> {
+    given
+        .map(str::to_owned)
+        .or(config::load()?.key_pattern)
+        .map(|p| keygen::Pattern::parse(&p))
+        .transpose()
+}
+
+/// Fetches the entries with the given keys that are not in the bib file
+/// yet, and adds them in one go, returning the keys to cite them. Entries
+/// of the file that are the same paper as a fetched one are kept, or
+/// replaced by it if the user says so. The other bib files of the project
+/// are checked too, but never changed.
+fn add_records(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    adding: &add::Adding,
+    records: impl Iterator<Item = String>,
+    fetch: impl FnMut(&str) -> Result<String>,
+) -> Result<Vec<String>> {
+    let existing = std::fs::read_to_string(bib_path).ok();
+    let others = cli.other_bibs(bib_path)?;
+    let added = add::add_records(
+        existing.as_deref(),
+        &others,
+        adding,
+        records,
+        fetch,
+        resolve_duplicate,
+        edit_entry,
+    )?;
+    if added.changes_file() {
+        // replacing entries keeps a backup, only adding new ones does not
+        let backup = if added.renames.is_empty() {
+            Backup(None)
+        } else {
+            cli.backup()?
+        };
+        let _lock = lock_unchanged(bib_path, existing.as_deref())?;
+        backup.write(existing.as_deref().unwrap_or_default())?;
+        let (text, unsorted) = add::insert_entries(
+            bib_path,
+            added.base.as_deref(),
+            &added.missing,
+            adding.sorted,
+        )?;
+        if let Some(by) = unsorted {
+            eprintln!(
+                "warning: {} is not sorted by {}, appending the entries",
+                bib_path.display(),
+                by.name()
+            );
+        }
+        scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", text)?))
+            .with_context(|| format!("writing {:?}", bib_path))?;
+        if added.base.is_none() {
+            eprintln!("created {}", bib_path.display());
+        }
+    }
+    if !added.report.is_empty() {
+        eprintln!("{}", added.report.join("; "));
+    }
+    rename_citations(bib_path, &added.renames)?;
+    Ok(added.keys)
+}
+
+/// Opens the entry in the editor, giving what it is saved as
+fn edit_entry(text: &str) -> Result<String> {
+    // removed when dropped, or by the gc if the run is killed meanwhile
+    let mut scratch =
+        scratch::ScratchFile::create_with_extension_in(scratch::scratch_dir(), "add", Some("bib"))?;
+    write!(scratch, "{}", text)?;
+    scratch.flush()?;
+    let (program, status) = run_editor(scratch.path())?;
+    // editors may save by replacing the file, so it is read again by path
+    let edited = std::fs::read_to_string(scratch.path());
+    if !status.success() {
+        bail!(
+            "the editor {} exited with {}, not adding the entry",
+            program,
+            status
+        );
+    }
+    Ok(edited?)
+}
+
+/// Locks the bib file until the lock is dropped, waiting for another
+/// process modifying it for at most the `lock_timeout_s` of the config
+fn lock_bib(bib_path: &std::path::Path) -> Result<lock::Lock> {
+    let timeout = config::load()?
+        .lock_timeout_s
+        .map_or(lock::TIMEOUT, std::time::Duration::from_secs);
+    lock::lock(bib_path, timeout)
+}
+
+/// Locks the bib file to write what was made of it as it was read, `None`
+/// if it did not exist, failing if it changed since. The fetching, prompts
+/// and picking happen before, not to keep other processes waiting on them.
+fn lock_unchanged(bib_path: &std::path::Path, read: Option<&str>) -> Result<lock::Lock> {
+    let lock = lock_bib(bib_path)?;
+    if std::fs::read_to_string(bib_path).ok().as_deref() != read {
+        bail!(changed_meanwhile(bib_path));
+    }
+    Ok(lock)
+}
+
+/// The error of a bib file changed by another process while this one was
+/// fetching or asking the user
+fn changed_meanwhile(bib_path: &std::path::Path) -> String {
+    format!(
+        "{} changed meanwhile, leaving it as it is: try again",
+        bib_path.display()
+    )
+}
+
+/// The keys of the bib file, to resolve the ones given by the user
+fn key_index(src: &str) -> Result<keys::KeyIndex> {
+    keys::KeyIndex::from_bib(src, config::load()?.aliases)
+}
+
+/// Writes the edited source of the bib file, after making a backup, if the
+/// file is still the source
+fn write_edits(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    src: &str,
+    edits: Vec<(std::ops::Range<usize>, String)>,
+) -> Result<()> {
+    if edits.is_empty() {
+        return Ok(());
+    }
+    let backup = cli.backup()?;
+    let _lock = lock_unchanged(bib_path, Some(src))?;
+    backup.write(src)?;
+    scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", bib::splice(src, edits))?))
+}
+
+fn print_finding(finding: &output::Finding) {
+    for entry in finding.entries.iter() {
+        match &entry.location {
+            Some(location) => println!(
+                "{} ({}:{}): {}",
+                entry.key,
+                location.file.display(),
+                location.line,
+                finding.message
+            ),
+            None => println!("{}: {}", entry.key, finding.message),
+        }
+    }
+}
+
+fn check_venues(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    args: &CheckArgs,
+    findings: &mut Vec<output::Finding>,
+) -> Result<usize> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let groups = check::venue_groups(&src)?;
+    let inconsistent: Vec<&check::VenueGroup> =
+        groups.iter().filter(|g| !g.is_consistent()).collect();
+    for group in inconsistent.iter() {
+        let finding = group.finding(bib_path, &src);
+        if !args.json {
+            println!("{}:", finding.message);
+            for (value, keys) in group.variants() {
+                println!("  {} ({})", value, keys.join(", "));
+            }
+        }
+        findings.push(finding);
+    }
+    let fix = match args.fix {
+        Some(fix) => fix,
+        None => return Ok(inconsistent.len()),
+    };
+
+    let mut edits = Vec::new();
+    for group in inconsistent.iter() {
+        let value = group.fixed_value(fix)?;
+        if args.dry_run {
+            for (span, change) in group.changes(&value) {
+                let location = output::Location::at(bib_path, &src, span.value.start);
+                print!(
+                    "{} ({}:{}):\n{}",
+                    span.key,
+                    bib_path.display(),
+                    location.line,
+                    diff::render(&[change], std::io::stdout().is_terminal())
+                );
+            }
+        }
+        edits.extend(group.fixes(&value));
+    }
+    if !args.dry_run {
+        write_edits(cli, bib_path, &src, edits)?;
+    }
+    Ok(inconsistent.len())
+}
+
+fn check_entry_types(
+    bib_path: &PathBuf,
+    args: &CheckArgs,
+    findings: &mut Vec<output::Finding>,
+) -> Result<usize> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let (unknown, counts) = check::entry_types(&src, &config::load()?.type_aliases)?;
+    let counts: Vec<String> = counts
+        .iter()
+        .map(|(name, n)| format!("{} {}", name, n))
         .collect();
+    eprintln!("entry types: {}", counts.join(", "));
+    for entry in unknown.iter() {
+        let finding = entry.finding(bib_path, &src);
+        if !args.json {
+            print_finding(&finding);
+        }
+        findings.push(finding);
+    }
+    Ok(unknown.len())
+}
+
+/// Reports the DBLP entries whose record moved or is gone. Fixing gives the
+/// moved entries their new key.
+fn check_keys(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    args: &CheckArgs,
+    findings: &mut Vec<output::Finding>,
+) -> Result<usize> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let checked = check::check_keys(bib_path, &src)?;
+    for (key, err) in checked.failed.iter() {
+        eprintln!("cannot check {}: {}", key, err);
+    }
+    if !args.json {
+        checked.findings.iter().for_each(print_finding);
+    }
+    eprintln!(
+        "keys: {} ok, {} moved, {} missing, {} could not be checked",
+        checked.ok,
+        checked.moved,
+        checked.missing,
+        checked.failed.len()
+    );
+    if args.fix.is_some() && !args.dry_run && !checked.renames.is_empty() {
+        let renamed = checked.renamed(&src)?;
+        let backup = cli.backup()?;
+        let _lock = lock_unchanged(bib_path, Some(&src))?;
+        backup.write(&src)?;
+        scratch::write_atomically(bib_path, |f| Ok(write!(f, "{}", renamed)?))?;
+        eprintln!("renamed {} keys", checked.renames.len());
+        if args.rename_citations {
+            rename_citations(bib_path, &checked.renames)?;
+        }
+    }
+    let problems = checked.problems();
+    findings.extend(checked.findings);
+    Ok(problems)
+}
+
+fn check_journal_versions(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    args: &CheckArgs,
+    findings: &mut Vec<output::Finding>,
+) -> Result<usize> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let state = (args.since_last_run || args.full).then(|| check::CheckState::load(bib_path, &src));
+    if state
+        .as_ref()
+        .is_some_and(|s| s.unchanged && args.since_last_run)
+    {
+        eprintln!("the bibtex file did not change since the last check");
+    }
+    let search = check::JournalSearch {
+        state,
+        since_last_run: args.since_last_run,
+        stale_after: std::time::Duration::from_secs(args.stale_after * 24 * 60 * 60),
+        show: !args.json,
+    };
+    let check::JournalCheck { found, failed } = check::journal_versions(bib_path, &src, search)?;
+    for (key, err) in failed.iter() {
+        eprintln!("cannot check {}: {}", key, err);
+    }
+    findings.extend(found.iter().map(|version| version.finding.clone()));
+    let problems = found.len() + failed.len();
+
+    if args.fix.is_none() || args.dry_run {
+        return Ok(problems);
+    }
+    let (edits, renames) = check::journal_version_edits(&src, &found, |version| {
+        let choice = ask(
+            &format!("{}: [r]eplace, [a]dd, [l]ink, or [s]kip?", version.key),
+            &['r', 'a', 'l', 's'],
+        )?;
+        Ok(match choice {
+            'r' => check::JournalFix::Replace,
+            'a' => check::JournalFix::Add,
+            'l' => check::JournalFix::Link,
+            _ => check::JournalFix::Skip,
+        })
+    })?;
+    write_edits(cli, bib_path, &src, edits)?;
+    rename_citations(bib_path, &renames)?;
+    Ok(problems)
+}
+
+/// Renames the citations of the keys replaced in the bib file, in the .tex
+/// files next to it
+fn rename_citations(
+    bib_path: &std::path::Path,
+    renames: &std::collections::BTreeMap<String, String>,
+) -> Result<()> {
+    for file in tex::rename_citations_in(tex::dir_of(bib_path), renames)? {
+        eprintln!("renamed the citations in {}", file.display());
+    }
+    Ok(())
+}
 
-    if paths.len() == 1 {
-        Ok(Some(paths[0].clone()))
+/// Applies the pending suggestions about the given entries or, if none is
+/// given, the ones picked by the user
+fn apply_suggestions(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    src: &str,
+    store: &mut suggestions::Store,
+    keys: &[String],
+) -> Result<()> {
+    let pending: Vec<suggestions::Suggestion> = store.list(false).cloned().collect();
+    if let Some(key) = keys
+        .iter()
+        .find(|k| !pending.iter().any(|s| &s.source_key == *k))
+    {
+        bail!("no pending suggestion about {}", key);
+    }
+    let selected = if keys.is_empty() {
+        if pending.is_empty() {
+            eprintln!("no pending suggestions");
+            return Ok(());
+        }
+        stats::STATS.phase("select", || pick(pending, true))?
     } else {
-        Ok(None)
+        pending
+            .into_iter()
+            .filter(|s| keys.contains(&s.source_key))
+            .collect()
+    };
+    let (edits, renames) = check::suggestion_edits(src, &selected)?;
+    for suggestion in selected.iter() {
+        eprintln!(
+            "replaced {} with {}",
+            suggestion.source_key, suggestion.proposed_key
+        );
+        store.remove(suggestion);
     }
+    write_edits(cli, bib_path, src, edits)?;
+    rename_citations(bib_path, &renames)
 }
 
-#[derive(Parser)]
-struct Cli {
-    #[command(subcommand)]
-    subcommand: Actions,
+/// Asks the user to pick one of the choices, the last being the default
+fn ask(question: &str, choices: &[char]) -> Result<char> {
+    eprint!("{} ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().chars().next();
+    Ok(answer
+        .filter(|c| choices.contains(c))
+        .unwrap_or(choices[choices.len() - 1]))
+}
 
-    #[arg(short, long, value_name = "FILE")]
-    bibtex: Option<String>,
+fn has_keyword(entry: &Entry, keyword: &str) -> bool {
+    entry
+        .keywords()
+        .map(|k| k.format_verbatim().split(',').any(|k| k.trim() == keyword))
+        .unwrap_or(false)
 }
 
-impl Cli {
-    fn get_bib_path(&self) -> Result<PathBuf> {
-        self.bibtex
-            .as_ref()
-            .map(PathBuf::from)
-            .or_else(|| get_unique_bib().unwrap())
-            .context("missing bibtex file")
+/// The key and title of the entry of the bib file matching the query that
+/// the user picks
+fn pick_local(bib_path: &PathBuf, query: &str) -> Result<(String, String)> {
+    let candidates: Vec<local::LocalEntry> = local::entries(&std::fs::read_to_string(bib_path)?)?
+        .into_iter()
+        .filter(|entry| entry.matches(query))
+        .collect();
+    if candidates.is_empty() {
+        bail!("no entries match {:?}", query);
     }
+    let entry = show_and_select(candidates.into_iter())?;
+    Ok((entry.key, entry.title))
+}
 
-    fn get_backup_bib_path(&self) -> Result<PathBuf> {
-        let orig = self.get_bib_path()?;
-        Ok(orig.with_extension("bib.bak"))
+/// Adds the hit of the query that the user picks to the bib file, unless it
+/// is there already, giving its key in the file and its title
+fn add_for_notes(
+    cli: &Cli,
+    bib_path: &PathBuf,
+    query: &str,
+    types: &[HitType],
+    max_hits: usize,
+) -> Result<(String, String)> {
+    cli.check_workspace(bib_path)?;
+    let hits = query_hits(query, types, max_hits)?;
+    let hit = stats::STATS.phase("select", || show_and_select(hits.into_iter()))?;
+    let keys = add_records(
+        cli,
+        bib_path,
+        &add::Adding {
+            keying: KeyArgs::default().keying()?,
+            edit: false,
+            sorted: InsertArgs::default().sorted()?,
+            strip: StripArgs::default().fields()?,
+        },
+        std::iter::once(hit.get_key()),
+        |_| Ok(dblp::get(&hit.bib_url(Format::Standard), Some(&hit.key))?),
+    )?;
+    let key = keys.into_iter().next().context("no key was added")?;
+    Ok((key, hit.title))
+}
+
+/// What goes in new notes, besides the metadata of their entry
+struct NewNotes<'a> {
+    tags: &'a [String],
+    template: Option<&'a notes::Template>,
+}
+
+/// Creates the notes of the entry of the bib file in the directory, unless
+/// it has some already, giving their path
+fn take_notes(
+    bib_path: &PathBuf,
+    dir: &std::path::Path,
+    new: &NewNotes,
+    key: &str,
+    title: &str,
+) -> Result<PathBuf> {
+    let extensions = config::load()?.notes_extensions();
+    let created =
+        notes::create_notes_file(dir, &extensions, key, title, new.tags, new.template, || {
+            let src =
+                std::fs::read_to_string(bib_path).map_err(|err| error::DblpError::BibFile {
+                    kind: error::BibFileKind::Inaccessible(err.kind()),
+                    path: bib_path.clone(),
+                })?;
+            let (_, text) = bib::split_entries(&src)?
+                .into_iter()
+                .find(|(k, _)| k == key)
+                .ok_or_else(|| error::DblpError::NoEntry {
+                    key: key.to_owned(),
+                })?;
+            bib::hayagriva_entry(text)
+        })?;
+    match created {
+        notes::NotesFile::Created(path) => {
+            eprintln!("created notes for {}", key);
+            Ok(path)
+        }
+        notes::NotesFile::Existing(path) => Ok(path),
     }
 }
 
-#[derive(Subcommand)]
-enum Actions {
-    /// Add bibliographic info to a bibtex file
-    Add { query: Vec<String> },
-    /// Copy a bibtex entry to the clipboard
-    Clip { query: Vec<String> },
-    /// Create a markdown file, with metadata, to take notes on a paper
-    Note { query: Vec<String> },
-    /// Convert a bibtex file between `Standard` and `Condensed` format
-    Convert { to: Format },
+/// Creates notes for the given entries, skipping the ones that already
+/// have one. Failures on single entries are reported without stopping, and
+/// counted along with the `failed` ones before.
+fn create_missing_notes<'a, P: AsRef<std::path::Path>>(
+    dir: P,
+    extensions: &[String],
+    entries: impl Iterator<Item = &'a Entry>,
+    failed: usize,
+    template: Option<&notes::Template>,
+    dry_run: bool,
+) {
+    // scan the notes once, rather than once per created note
+    let existing = notes::noted_keys(&dir, extensions);
+    let (mut created, mut skipped, mut failed) = (0, 0, failed);
+    for entry in entries {
+        if existing.contains(notes::strip_dblp_prefix(&entry.key)) {
+            skipped += 1;
+            continue;
+        }
+        let title = bib::title(entry);
+        if dry_run {
+            println!("would create notes for {}", entry.key);
+            created += 1;
+            continue;
+        }
+        let meta = if entry.key.starts_with("DBLP:") {
+            std::thread::sleep(FETCH_DELAY);
+            dblp::fetch_bibtex(&entry.key)
+        } else {
+            bib::hayagriva_entry(&entry.to_biblatex_string())
+        };
+        match meta.and_then(|meta| {
+            notes::write_notes_file(&dir, &entry.key, &title, &[], template, &meta)
+        }) {
+            Ok(path) => {
+                println!("created {:?}", path);
+                created += 1;
+            }
+            Err(err) => {
+                eprintln!("Error creating notes for {}: {:?}", entry.key, err);
+                failed += 1;
+            }
+        }
+    }
+    eprintln!(
+        "{} created, {} skipped, {} failed",
+        created, skipped, failed
+    );
 }
 
-fn join_param_string(strings: &[String]) -> String {
-    strings
-        .iter()
-        .flat_map(|v| v.split(' '))
-        .map(|v| v.trim())
-        .collect::<Vec<&str>>()
-        .join("+")
-}
-
-fn write_clipboard(what: &str) -> Result<()> {
-    fn run(cmd: &str, what: &str) -> Result<()> {
-        let mut child = std::process::Command::new(cmd)
-            .stdin(std::process::Stdio::piped())
-            .spawn()?;
-        write!(child.stdin.take().context("no standard input")?, "{}", what)?;
-        Ok(())
+/// Prints the links between the notes of the paper and the others, or,
+/// with `graph`, the links of the notes as a Graphviz graph, only the ones
+/// of the paper if there is one
+fn notes_links(
+    bib_path: Option<PathBuf>,
+    dir: &std::path::Path,
+    key: Option<&str>,
+    graph: bool,
+) -> Result<()> {
+    // titles from the notes first, then from the bib file
+    let mut titles: std::collections::HashMap<String, String> =
+        match bib_path.filter(|path| path.exists()) {
+            Some(path) => local::entries(&std::fs::read_to_string(path)?)?
+                .into_iter()
+                .map(|e| (notes::strip_dblp_prefix(&e.key).to_owned(), e.title))
+                .collect(),
+            None => Default::default(),
+        };
+    let extensions = config::load()?.notes_extensions();
+    let mut edges: Vec<(String, String)> = Vec::new();
+    for (path, meta) in notes::files_with_metadata(dir, &extensions) {
+        let from = notes::strip_dblp_prefix(&meta.key).to_owned();
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading {}", path.display()))?;
+        for to in notes::links(&content) {
+            edges.push((from.clone(), to));
+        }
+        titles.insert(from, meta.title);
+    }
+    edges.sort();
+    edges.dedup();
+    let key = key.map(notes::strip_dblp_prefix);
+    if let Some(key) = key {
+        edges.retain(|(from, to)| from == key || to == key);
+    }
+    let title = |key: &str| titles.get(key).cloned().unwrap_or_default();
+    if graph {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut nodes: Vec<&str> = edges
+            .iter()
+            .flat_map(|(from, to)| [from.as_str(), to.as_str()])
+            .chain(key)
+            .collect();
+        nodes.sort();
+        nodes.dedup();
+        println!("digraph notes {{");
+        for node in nodes {
+            let label = match title(node) {
+                title if title.is_empty() => node.to_owned(),
+                title => title,
+            };
+            println!("  {} [label={}];", quote(node), quote(&label));
+        }
+        for (from, to) in &edges {
+            println!("  {} -> {};", quote(from), quote(to));
+        }
+        println!("}}");
+        return Ok(());
     }
-    ["wl-copy", "pbcopy"]
+    let key = key.context("a key is needed without --graph")?;
+    let to: Vec<&String> = edges
         .iter()
-        .map(|cmd| run(cmd, what))
-        .next()
-        .context("no clipboard command ran successfully")?
+        .filter(|(f, _)| f == key)
+        .map(|(_, t)| t)
+        .collect();
+    let from: Vec<&String> = edges
+        .iter()
+        .filter(|(_, t)| t == key)
+        .map(|(f, _)| f)
+        .collect();
+    let paper = |key: &str| format!("{}  {}", key, title(key)).trim_end().to_owned();
+    println!("{}", paper(key));
+    println!("links to ({}):", to.len());
+    for other in to {
+        println!("  {}", paper(other));
+    }
+    println!("linked from ({}):", from.len());
+    for other in from {
+        println!("  {}", paper(other));
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let bib_path = cli.get_bib_path();
+/// Writes the table of the notes in the index of the directory, with the
+/// year and venue of their entry in the bib file, if there is one
+fn index_notes(
+    bib_path: Option<PathBuf>,
+    dir: &std::path::Path,
+    group_by: notes_index::GroupBy,
+) -> Result<()> {
+    let entries = match bib_path.filter(|path| path.exists()) {
+        Some(path) => local::entries(&std::fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    let entries: std::collections::HashMap<String, local::LocalEntry> = entries
+        .into_iter()
+        .map(|entry| (notes::strip_dblp_prefix(&entry.key).to_owned(), entry))
+        .collect();
+    let extensions = config::load()?.notes_extensions();
+    let rows: Vec<notes_index::Row> = notes::files_with_metadata(dir, &extensions)
+        .map(|(path, meta)| {
+            let entry = entries.get(notes::strip_dblp_prefix(&meta.key));
+            let link = path.strip_prefix(dir).unwrap_or(&path);
+            notes_index::Row {
+                title: meta.title,
+                year: entry.map(|e| e.year.clone()).unwrap_or_default(),
+                venue: entry.map(|e| e.venue.clone()).unwrap_or_default(),
+                key: meta.key,
+                status: meta.status,
+                tags: meta.tags,
+                link: link
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            }
+        })
+        .collect();
+    let index = dir.join(notes_index::INDEX_FILE);
+    let existing = match std::fs::read_to_string(&index) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", index.display())),
+    };
+    let updated = notes_index::splice(&existing, &notes_index::render(&rows, group_by));
+    if updated == existing {
+        eprintln!("{} is up to date", index.display());
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    scratch::write_atomically(&index, |f| Ok(f.write_all(updated.as_bytes())?))?;
+    eprintln!("indexed {} notes in {}", rows.len(), index.display());
+    Ok(())
+}
 
-    match cli.subcommand {
-        Actions::Add { query } => {
-            let bib_path = bib_path?;
-            let query = join_param_string(&query);
-            let bibformat = Format::Condensed;
-            let resp = DblpResponse::query(&query, bibformat)?;
-            let selection = show_and_select(resp.matches())?;
-
-            if !is_present(&bib_path, &selection)? {
-                let bib = ureq::get(&selection.bib_url(Format::Standard))
-                    .call()?
-                    .into_string()?;
-                let mut writer = OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&bib_path)?;
-                writeln!(writer, "{}", bib)?;
-            }
-            write_clipboard(&format!("DBLP:{}", selection.key))?;
-        }
-        Actions::Clip { query } => {
-            let query = join_param_string(&query);
-            let bibformat = Format::Condensed;
-            let resp = DblpResponse::query(&query, bibformat)?;
+/// Lists the orphaned notes, whose key is not in the bib file, and the
+/// entries without notes, in sections unless only one of them is asked for
+fn notes_status(
+    bib_path: &PathBuf,
+    dir: &std::path::Path,
+    missing_only: bool,
+    orphans_only: bool,
+) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let keys: Vec<String> = bib::split_entries(&src)?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    let in_bib: std::collections::HashSet<&str> =
+        keys.iter().map(|k| notes::strip_dblp_prefix(k)).collect();
+    let extensions = config::load()?.notes_extensions();
+    let noted = notes::noted_keys(dir, &extensions);
+    let orphans: Vec<(PathBuf, String)> = notes::files_with_metadata(dir, &extensions)
+        .filter(|(_, meta)| !in_bib.contains(notes::strip_dblp_prefix(&meta.key)))
+        .map(|(path, meta)| (path, meta.key))
+        .collect();
+    let missing: Vec<&String> = keys
+        .iter()
+        .filter(|key| !noted.contains(notes::strip_dblp_prefix(key)))
+        .collect();
+    if missing_only {
+        missing.iter().for_each(|key| println!("{}", key));
+        return Ok(());
+    }
+    if orphans_only {
+        orphans
+            .iter()
+            .for_each(|(path, _)| println!("{}", path.display()));
+    } else {
+        println!(
+            "orphaned notes, whose key is not in {} ({}):",
+            bib_path.display(),
+            orphans.len()
+        );
+        for (path, key) in &orphans {
+            println!("  {}  {}", path.display(), key);
+        }
+        println!("entries without notes ({}):", missing.len());
+        for key in &missing {
+            println!("  {}", key);
+        }
+    }
+    if !orphans.is_empty() {
+        bail!("found {} orphaned notes", orphans.len());
+    }
+    Ok(())
+}
 
-            let selection = show_and_select(resp.matches())?;
-            let bib = ureq::get(&selection.bib_url(Format::Standard))
-                .call()?
-                .into_string()?;
-            write_clipboard(&bib)?;
+/// Updates the front matter of the notes from the entries of the bib file,
+/// listing each note as updated, current, or missing from the bib file
+fn sync_notes(bib_path: &PathBuf, dir: &std::path::Path, dry_run: bool) -> Result<()> {
+    let src = std::fs::read_to_string(bib_path)?;
+    let entries: std::collections::HashMap<String, &str> = bib::split_entries(&src)?
+        .into_iter()
+        .map(|(key, text)| (notes::strip_dblp_prefix(&key).to_owned(), text))
+        .collect();
+    let extensions = config::load()?.notes_extensions();
+    let (mut updated, mut current, mut missing) = (0, 0, 0);
+    for (path, meta) in notes::files_with_metadata(dir, &extensions) {
+        let Some(text) = entries.get(notes::strip_dblp_prefix(&meta.key)) else {
+            println!(
+                "missing  {} ({} is not in {})",
+                path.display(),
+                meta.key,
+                bib_path.display()
+            );
+            missing += 1;
+            continue;
+        };
+        let content = std::fs::read_to_string(&path)?;
+        let synced = notes::sync_header(&content, &bib::hayagriva_entry(text)?)
+            .with_context(|| format!("updating {}", path.display()))?
+            .unwrap_or_else(|| content.clone());
+        if synced == content {
+            println!("current  {}", path.display());
+            current += 1;
+            continue;
         }
-        Actions::Note { query } => {
-            let query = join_param_string(&query);
-            let bibformat = Format::Condensed;
-            let resp = DblpResponse::query(&query, bibformat)?;
-            let selection = show_and_select(resp.matches())?;
-            let path = notes::create_notes_file(
-                "/home/matteo/Notes/Papers/",
-                &selection.key,
-                &selection.title,
-            )?;
-            write_clipboard(path.to_str().context("converting path to string")?)?;
-        }
-        Actions::Convert { to } => {
-            let bib_path = bib_path?;
-            let mut f = File::open(&bib_path)?;
-            let mut src = String::new();
-            f.read_to_string(&mut src)?;
-            drop(f);
-
-            // backup the content
-            let mut f = File::create(cli.get_backup_bib_path()?)?;
-            writeln!(f, "{}", src)?;
-            drop(f);
-
-            // overwrite the file
-            let mut f = File::create(bib_path)?;
-
-            let bibliography = Bibliography::parse(&src).unwrap();
-            for entry in bibliography.iter() {
-                let bibstr = entry.to_bibtex_string().map_err(|e| anyhow!(e))?;
-                eprintln!("{}", entry.key);
-                if entry.key.starts_with("DBLP") {
-                    let k = entry.key.replace("DBLP:", "");
-                    let url = format!("https://dblp.uni-trier.de/rec/{}.bib{}", k, to.get_param());
-                    if let Err(err) = ureq::get(&url)
-                        .call()
-                        .and_then(|res| Ok(res.into_string()?))
-                        .and_then(|bib| Ok(writeln!(f, "{}\n", bib)?))
-                        .or_else(|_| writeln!(f, "{}\n", bibstr))
-                    {
-                        eprintln!("Error in fetching data for {}: {:?}", entry.key, err);
-                    }
-                } else {
-                    writeln!(f, "{}\n", bibstr)?;
+        if dry_run {
+            println!("would update  {}", path.display());
+        } else {
+            scratch::write_atomically(&path, |f| Ok(f.write_all(synced.as_bytes())?))?;
+            println!("updated  {}", path.display());
+        }
+        updated += 1;
+    }
+    eprintln!(
+        "{} updated, {} current, {} missing from {}",
+        updated,
+        current,
+        missing,
+        bib_path.display()
+    );
+    Ok(())
+}
+
+/// Reports the problems in the front matter of the notes, renaming the
+/// misspelled keys confirmed if `fix_typos`
+fn validate_notes<P: AsRef<std::path::Path>>(
+    dir: P,
+    extra_keys: &[String],
+    fix_typos: bool,
+) -> Result<()> {
+    let mut problems = 0;
+    for path in notes::note_files(dir, &config::load()?.notes_extensions()) {
+        let mut content = std::fs::read_to_string(&path)?;
+        let Some(issues) = notes::validate(&content, extra_keys) else {
+            continue;
+        };
+        let mut renamed = false;
+        for issue in issues {
+            println!("{}:{}: {}", path.display(), issue.line, issue.problem);
+            let notes::Problem::Typo { key, suggestion } = &issue.problem else {
+                problems += 1;
+                continue;
+            };
+            if !fix_typos || !confirm(&format!("rename {} to {}?", key, suggestion))? {
+                problems += 1;
+                continue;
+            }
+            // renaming to a key that is already there makes the header invalid
+            let fixed = notes::rename_key(&content, issue.line, key, suggestion).filter(|c| {
+                notes::validate(c, extra_keys).is_some_and(|issues| {
+                    !issues
+                        .iter()
+                        .any(|i| matches!(i.problem, notes::Problem::Header(_)))
+                })
+            });
+            match fixed {
+                Some(fixed) => {
+                    content = fixed;
+                    renamed = true;
+                }
+                None => {
+                    eprintln!("cannot rename {}, {} is already there", key, suggestion);
+                    problems += 1;
                 }
             }
         }
+        if renamed {
+            scratch::write_atomically(&path, |f| Ok(write!(f, "{}", content)?))?;
+        }
+    }
+    if problems > 0 {
+        bail!("found {} problems", problems);
+    }
+    Ok(())
+}
+
+/// Shows the entry that may be the same paper as the fetched one, and asks
+/// what to do with the fetched one, skipping it by default
+fn resolve_duplicate(duplicate: &add::Duplicate) -> Result<add::Resolution> {
+    let (entry, fetched) = (duplicate.entry, duplicate.fetched);
+    let likely = match duplicate.confidence {
+        Confidence::High => "is",
+        _ => "may be",
+    };
+    let place = duplicate
+        .elsewhere
+        .map(|path| format!(" in {}", path.display()))
+        .unwrap_or_default();
+    eprintln!(
+        "{}{} {} the same paper:\n{}",
+        entry.key, place, likely, duplicate.text
+    );
+    let changes = diff::field_diff(entry, fetched);
+    eprint!(
+        "{}",
+        diff::render(&changes, std::io::stderr().is_terminal())
+    );
+    loop {
+        match duplicate.elsewhere {
+            Some(_) => eprint!("[s]kip and cite {}, or [a]dd anyway? [S/a] ", entry.key),
+            None => eprint!(
+                "[s]kip and cite {}, [a]dd anyway, or [r]eplace it with {}? [S/a/r] ",
+                entry.key, fetched.key
+            ),
+        }
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            eprintln!();
+            return Ok(add::Resolution::Skip(entry.key.clone()));
+        }
+        match add::parse_resolution(&answer, &entry.key) {
+            Some(add::Resolution::Replace(_)) if duplicate.elsewhere.is_some() => {}
+            Some(resolution) => return Ok(resolution),
+            None => {}
+        }
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// The command opening urls on this platform, with its arguments
+fn opener() -> (&'static str, &'static [&'static str]) {
+    if cfg!(target_os = "macos") {
+        ("open", &[])
+    } else if cfg!(target_os = "windows") {
+        ("cmd", &["/c", "start", ""])
+    } else {
+        ("xdg-open", &[])
     }
+}
 
+/// Opens the given url with the platform's default handler
+fn open_url(url: &str) -> Result<()> {
+    if url.is_empty() {
+        bail!("there is nothing to open");
+    }
+    let (cmd, args) = opener();
+    std::process::Command::new(cmd)
+        .args(args)
+        .arg(url)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("running {}", cmd))?;
     Ok(())
 }
 
-fn is_present(path: &PathBuf, item: &DblpHitInfo) -> Result<bool> {
-    let bib_key = item.get_key();
+/// The editor of $VISUAL, or else $EDITOR, or else vi, with its arguments
+fn editor_command(visual: Option<String>, editor: Option<String>) -> (String, Vec<String>) {
+    let command = [visual, editor]
+        .into_iter()
+        .flatten()
+        .find(|command| !command.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned());
+    let mut words = command.split_whitespace().map(str::to_owned);
+    let program = words.next().unwrap_or_default();
+    (program, words.collect())
+}
 
-    if path.is_file() {
-        let reader = BufReader::new(File::open(path)?);
-        for line in reader.lines() {
-            let line = line?;
-            if line.contains(&bib_key) {
-                return Ok(true);
-            }
+/// Opens the file in the editor, waiting for it to exit. Editors failing
+/// only get a warning, since the file exists anyway.
+///
+/// The picker has given the terminal back by the time this runs: the
+/// terminal of skim is dropped before `Skim::run_with` returns, leaving the
+/// alternate screen and raw mode.
+fn edit(path: &std::path::Path) -> Result<()> {
+    let (program, status) = run_editor(path)?;
+    if !status.success() {
+        eprintln!("warning: the editor {} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+/// Runs the editor on the file, returning its program and how it exited
+fn run_editor(path: &std::path::Path) -> Result<(String, std::process::ExitStatus)> {
+    let (program, args) =
+        editor_command(std::env::var("VISUAL").ok(), std::env::var("EDITOR").ok());
+    std::io::stderr().flush()?;
+    let status = std::process::Command::new(&program)
+        .args(&args)
+        .arg(path)
+        .status()
+        .with_context(|| format!("running the editor {}", program))?;
+    Ok((program, status))
+}
+
+/// Asks which of the bib files to use, and remembers it in their directory
+fn pick_target(dir: &std::path::Path, candidates: &[PathBuf]) -> Result<PathBuf> {
+    eprintln!("which bibtex file should be used?");
+    let choices = candidates
+        .iter()
+        .map(|path| BibChoice(path.display().to_string()));
+    let path = PathBuf::from(show_and_select(choices)?.0);
+    match paths::remember_target(dir, &path) {
+        Ok(()) => eprintln!(
+            "using {} from now on, as remembered in {}",
+            path.display(),
+            dir.join(paths::TARGET_FILE).display()
+        ),
+        Err(err) => eprintln!("warning: cannot remember the bibtex file to use: {}", err),
+    }
+    Ok(path)
+}
+
+/// A bib file, to pick the one to modify
+#[derive(Clone)]
+struct BibChoice(String);
+
+impl SkimItem for BibChoice {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.0)
+    }
+}
+
+impl Linked for BibChoice {
+    fn link(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The entry of a group of duplicates to keep, or all of them if they are
+/// not the same paper after all
+#[derive(Clone)]
+enum KeepChoice {
+    Entry(local::LocalEntry),
+    All,
+}
+
+impl SkimItem for KeepChoice {
+    fn text(&self) -> Cow<'_, str> {
+        match self {
+            KeepChoice::Entry(entry) => entry.text(),
+            KeepChoice::All => Cow::Borrowed(KEEP_ALL),
+        }
+    }
+
+    fn display<'a>(&'a self, context: DisplayContext<'a>) -> AnsiString<'a> {
+        match self {
+            KeepChoice::Entry(entry) => entry.display(context),
+            KeepChoice::All => AnsiString::from(KEEP_ALL),
+        }
+    }
+
+    fn preview(&self, context: PreviewContext) -> ItemPreview {
+        match self {
+            KeepChoice::Entry(entry) => entry.preview(context),
+            KeepChoice::All => ItemPreview::Text("remove none of the entries".to_owned()),
+        }
+    }
+}
+
+impl Linked for KeepChoice {
+    fn link(&self) -> &str {
+        match self {
+            KeepChoice::Entry(entry) => &entry.link,
+            KeepChoice::All => "",
+        }
+    }
+}
+
+/// The choice of the dedup picker keeping every entry of the group
+const KEEP_ALL: &str = "not duplicates, keep all";
+
+/// Items of the picker that can be opened in the browser
+trait Linked {
+    fn link(&self) -> &str;
+}
+
+impl Linked for DblpHitInfo {
+    fn link(&self) -> &str {
+        DblpHitInfo::link(self)
+    }
+}
+
+impl Linked for local::LocalEntry {
+    fn link(&self) -> &str {
+        &self.link
+    }
+}
+
+impl Linked for notes::Note {
+    fn link(&self) -> &str {
+        &self.link
+    }
+}
+
+impl Linked for suggestions::Suggestion {
+    fn link(&self) -> &str {
+        &self.url
+    }
+}
+
+/// Key that, with ctrl, opens the highlighted item in the browser, without
+/// leaving the picker
+const OPEN_KEY: char = 'o';
+
+/// The hits of the query of any of the given types, failing if there are
+/// none rather than offering nothing to pick from
+fn query_hits(query: &str, types: &[HitType], max_hits: usize) -> Result<Vec<DblpHitInfo>> {
+    let resp = DblpResponse::query(query, Format::Condensed, max_hits)?;
+    let hits: Vec<DblpHitInfo> = resp.matches_of(types).collect();
+    if hits.is_empty() {
+        return Err(error::DblpError::NoResults {
+            query: query.to_owned(),
         }
+        .into());
     }
-    Ok(false)
+    Ok(hits)
+}
+
+/// The first of the hits, telling which one it is
+fn first_hit(hits: Vec<DblpHitInfo>) -> DblpHitInfo {
+    let hit = hits.into_iter().next().expect("there is at least a hit");
+    eprintln!("picked {} {}", hit.title, hit.published_in());
+    hit
 }
 
 // copied from https://github.com/Mountlex/xivar/blob/main/src/finder.rs
 fn show_and_select<I, T>(iter: T) -> Result<I>
 where
     T: Iterator<Item = I>,
-    I: SkimItem + Clone,
+    I: SkimItem + Clone + Linked,
+{
+    let selected = pick(iter.collect(), false)?;
+    selected
+        .into_iter()
+        .next()
+        .ok_or_else(|| error::DblpError::Aborted.into())
+}
+
+/// Like `show_and_select`, but the user can pick more than one item
+fn show_and_select_many<I, T>(iter: T) -> Result<Vec<I>>
+where
+    T: Iterator<Item = I>,
+    I: SkimItem + Clone + Linked,
 {
-    let options = SkimOptionsBuilder::default()
-        .height(Some("100%"))
-        .preview(Some(""))
-        .build()
-        .expect("building fuzzy selector");
+    pick(iter.collect(), true)
+}
 
-    let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
-    for item in iter {
-        let _ = tx_item.send(Arc::new(item));
+/// Lets the user pick one or, with `multi`, more of the items. Accepting
+/// while no item matches the query asks again. In multi mode the items
+/// selected stay selected after opening one, which, when none was, is then
+/// the one selected.
+fn pick<I: SkimItem + Clone + Linked>(items: Vec<I>, multi: bool) -> Result<Vec<I>> {
+    if items.is_empty() {
+        bail!("nothing to select from");
     }
+    // keep the items around, so that the picker can be re-launched
+    // after opening an item without fetching them again
+    if let picker::Picker::List(reason) = picker::decide(&picker::Environment::current()) {
+        eprintln!("not using the fuzzy picker: {}", reason);
+        return select_from_list(&items, multi);
+    }
+    let open_key = format!("ctrl-{}", OPEN_KEY);
+    let header = if multi {
+        format!("tab: select, {}: open in browser", open_key)
+    } else {
+        format!("{}: open in browser", open_key)
+    };
+    let mut query = String::new();
+    // the items selected before opening one, by their text
+    let mut preset: Vec<String> = Vec::new();
 
-    drop(tx_item); // so that skim could know when to stop waiting for more items.
+    loop {
+        let options = SkimOptionsBuilder::default()
+            .height(Some("100%"))
+            .preview(Some(""))
+            .header(Some(&header))
+            .expect(Some(open_key.clone()))
+            .selector(Some(Rc::new(
+                DefaultSkimSelector::default().preset(preset.clone()),
+            )))
+            .query(Some(&query))
+            .multi(multi)
+            .build()
+            .expect("building fuzzy selector");
 
-    if let Some(output) = Skim::run_with(&options, Some(rx_item)) {
-        if !output.is_abort {
-            output
-                .selected_items
-                .into_iter()
-                .next()
-                .map(move |item| {
-                    (*item)
-                        .as_any()
-                        .downcast_ref::<I>() // downcast to concrete type
-                        .expect("something wrong with downcast")
-                        .clone()
-                })
-                .ok_or(anyhow!("Internal error"))
+        let (tx_item, rx_item): (SkimItemSender, SkimItemReceiver) = unbounded();
+        for item in items.iter() {
+            let _ = tx_item.send(Arc::new(item.clone()));
+        }
+
+        drop(tx_item); // so that skim could know when to stop waiting for more items.
+
+        let Some(output) = run_skim(&options, rx_item) else {
+            eprintln!("the fuzzy picker failed to start, falling back to a numbered list");
+            return select_from_list(&items, multi);
+        };
+        if output.is_abort {
+            return Err(error::DblpError::Aborted.into());
+        }
+        let selected: Vec<I> = output
+            .selected_items
+            .into_iter()
+            .map(move |item| {
+                (*item)
+                    .as_any()
+                    .downcast_ref::<I>() // downcast to concrete type
+                    .expect("something wrong with downcast")
+                    .clone()
+            })
+            .collect();
+        let Some(first) = selected.first() else {
+            // nothing matches the query, so there is nothing to accept
+            query = output.query;
+            continue;
+        };
+
+        if output.final_key == Key::Ctrl(OPEN_KEY) {
+            // skim offers no way to seed the cursor position, so we
+            // only restore the query and the selection
+            if let Err(err) = open_url(first.link()) {
+                eprintln!("Error opening {}: {:?}", first.link(), err);
+            }
+            query = output.query;
+            if multi {
+                preset = selected
+                    .iter()
+                    .map(|item| item.text().into_owned())
+                    .collect();
+            }
         } else {
-            bail!("No entry selected! Aborting...")
+            return Ok(selected);
         }
-    } else {
-        bail!("Internal error")
+    }
+}
+
+/// Runs skim, turning its panics on terminals it cannot handle into `None`
+fn run_skim(options: &SkimOptions, source: SkimItemReceiver) -> Option<SkimOutput> {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let output = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        Skim::run_with(options, Some(source))
+    }));
+    std::panic::set_hook(hook);
+    output.ok().flatten()
+}
+
+/// Lists the items on standard error, and asks for one, or more with
+/// `multi`, of them on standard input, for when the fuzzy picker cannot run
+fn select_from_list<I: SkimItem + Clone + Linked>(items: &[I], multi: bool) -> Result<Vec<I>> {
+    if items.is_empty() {
+        bail!("nothing to select from");
+    }
+    for (i, item) in items.iter().enumerate() {
+        eprintln!("{:>3}. {}", i + 1, item.text());
+    }
+    loop {
+        eprint!("{}: ", picker::list_prompt(multi));
+        std::io::stderr().flush()?;
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Err(error::DblpError::Aborted.into());
+        }
+        match picker::parse_choice(&answer, items.len(), multi) {
+            picker::Choice::Pick(picked) => {
+                return Ok(picked.into_iter().map(|i| items[i].clone()).collect())
+            }
+            picker::Choice::Open(i) => {
+                if let Err(err) = open_url(items[i].link()) {
+                    eprintln!("Error opening {}: {:?}", items[i].link(), err);
+                }
+            }
+            picker::Choice::Abort => return Err(error::DblpError::Aborted.into()),
+            picker::Choice::Invalid => {
+                eprintln!("not a number between 1 and {}", items.len())
+            }
+        }
+    }
+}
+
+#[test]
+fn test_editor_command() {
+    let some = |s: &str| Some(s.to_owned());
+    assert_eq!(
+        editor_command(some("code --wait"), some("nano")),
+        ("code".to_owned(), vec!["--wait".to_owned()])
+    );
+    assert_eq!(editor_command(some(" "), some("nano")).0, "nano");
+    assert_eq!(editor_command(None, None), ("vi".to_owned(), vec![]));
+}
+
+#[test]
+fn test_cite() {
+    let keys = ["DBLP:conf/soda/Chan16".to_owned(), "other".to_owned()];
+    assert_eq!(cite("cite", &keys[..1]), "\\cite{DBLP:conf/soda/Chan16}");
+    assert_eq!(
+        cite("autocite", &keys),
+        "\\autocite{DBLP:conf/soda/Chan16,other}"
+    );
+}
+
+#[test]
+fn test_report_key() {
+    let key = "DBLP:conf/soda/Chan16";
+    for (copied, print_key, expected) in [
+        (Copied::Via("wl-copy"), false, ""),
+        (Copied::Via("wl-copy"), true, "DBLP:conf/soda/Chan16\n"),
+        (
+            Copied::Failed(anyhow!("none")),
+            false,
+            "DBLP:conf/soda/Chan16\n",
+        ),
+        (
+            Copied::Failed(anyhow!("none")),
+            true,
+            "DBLP:conf/soda/Chan16\n",
+        ),
+        (Copied::Off, false, "DBLP:conf/soda/Chan16\n"),
+    ] {
+        let mut out = Vec::new();
+        report_key(key, &copied, print_key, &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
     }
 }
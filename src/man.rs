@@ -0,0 +1,49 @@
+/// utilities to generate the man pages from the definition of the command line
+use anyhow::{Context, Result};
+use clap::Command;
+use std::path::{Path, PathBuf};
+
+/// Writes the page of the command, named after it, and one page for each of
+/// its subcommands, recursively, named like `dblp-notes-create-missing.1`
+pub fn write_pages(cmd: Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {:?}", dir))?;
+    let mut written = Vec::new();
+    write_page(cmd.name("dblp"), dir, &mut written)?;
+    Ok(written)
+}
+
+fn write_page(cmd: Command, dir: &Path, written: &mut Vec<PathBuf>) -> Result<()> {
+    let name = cmd.get_name().to_owned();
+    let path = dir.join(format!("{}.1", name));
+    let mut page = Vec::new();
+    clap_mangen::Man::new(cmd.clone()).render(&mut page)?;
+    std::fs::write(&path, page).with_context(|| format!("writing {:?}", path))?;
+    written.push(path);
+    for sub in cmd.get_subcommands() {
+        // the name of the page is also the one shown in its synopsis
+        let full = format!("{}-{}", name, sub.get_name());
+        write_page(sub.clone().name(full), dir, written)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_pages() {
+    use clap::CommandFactory;
+    let dir = std::env::temp_dir().join(format!("dblp-rs-man-{}", std::process::id()));
+    let cmd = crate::examples::with_examples(crate::Cli::command());
+    let written = write_pages(cmd.clone(), &dir).unwrap();
+    let main_page = std::fs::read_to_string(dir.join("dblp.1")).unwrap();
+    for sub in cmd.get_subcommands() {
+        // roff escapes the dashes of names like add-key
+        let listed = sub.get_name().replace('-', "\\-");
+        assert!(main_page.contains(&listed), "{}", sub.get_name());
+        let page = dir.join(format!("dblp-{}.1", sub.get_name()));
+        assert!(written.contains(&page), "{:?}", page);
+    }
+    assert!(written.contains(&dir.join("dblp-notes-create-missing.1")));
+    for path in written.iter() {
+        assert!(std::fs::metadata(path).unwrap().len() > 0, "{:?}", path);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,313 @@
+/// three-way merge of bibtex files at the level of entries
+use crate::bib;
+use crate::error::DblpError;
+use crate::similarity;
+use anyhow::{anyhow, Context, Result};
+use biblatex::{Bibliography, ChunksExt, Entry};
+use std::{ops::Range, path::Path};
+
+/// An entry of one of the files being merged
+pub struct Side<'a> {
+    pub key: String,
+    pub text: &'a str,
+    pub entry: Entry,
+    range: Range<usize>,
+    fingerprint: String,
+}
+
+/// An entry changed differently on the two sides, or changed on one side
+/// and removed on the other
+pub struct Conflict<'s, 'a> {
+    pub key: &'s str,
+    pub ours: Option<&'s Side<'a>>,
+    pub theirs: Option<&'s Side<'a>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    /// Keep both versions, between conflict markers
+    Both,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub taken_ours: usize,
+    pub taken_theirs: usize,
+    pub merged: usize,
+    pub conflicted: usize,
+}
+
+fn sides(src: &str) -> Result<Vec<Side<'_>>> {
+    let bibliography = Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    bib::entry_spans(src)?
+        .into_iter()
+        .map(|(key, range)| {
+            let entry = bibliography
+                .get(&key)
+                .ok_or_else(|| anyhow!("cannot parse entry {}", key))?
+                .clone();
+            Ok(Side {
+                fingerprint: bib::entry_fingerprint(&entry),
+                text: &src[range.clone()],
+                key,
+                entry,
+                range,
+            })
+        })
+        .collect()
+}
+
+/// The ways of recognizing an entry, even if its key was changed: its key,
+/// its DOI, and the DBLP record it comes from
+fn identities(side: &Side) -> Vec<String> {
+    let mut ids = vec![format!("key:{}", side.key)];
+    if let Some(doi) = similarity::doi(&side.entry) {
+        ids.push(format!("doi:{}", doi));
+    }
+    let record = side
+        .key
+        .strip_prefix("DBLP:")
+        .map(str::to_owned)
+        .or_else(|| {
+            side.entry.get("biburl").and_then(|url| {
+                url.format_verbatim()
+                    .split("/rec/")
+                    .nth(1)
+                    .and_then(|r| r.strip_suffix(".bib"))
+                    .map(str::to_owned)
+            })
+        });
+    if let Some(record) = record {
+        ids.push(format!("dblp:{}", record));
+    }
+    ids
+}
+
+fn find(entries: &[Side], side: &Side) -> Option<usize> {
+    let ids = identities(side);
+    entries
+        .iter()
+        .position(|e| identities(e).iter().any(|id| ids.contains(id)))
+}
+
+fn conflict_block(key: &str, ours: Option<&Side>, theirs: Option<&Side>) -> String {
+    let version = |name, side: Option<&Side>| match side {
+        Some(side) => format!(
+            "% dblp merge conflict on {}: {}\n{}\n",
+            key, name, side.text
+        ),
+        None => format!("% dblp merge conflict on {}: {} removed it\n", key, name),
+    };
+    format!(
+        "{}{}% dblp merge conflict on {}: end",
+        version("ours", ours),
+        version("theirs", theirs),
+        key
+    )
+}
+
+/// Merges into `ours` the changes made in `theirs`, both starting from
+/// `base`. Entries changed on one side only take that side, entries changed
+/// equally on both sides merge silently, and `resolve` decides the others.
+pub fn merge(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    mut resolve: impl FnMut(&Conflict) -> Result<Resolution>,
+) -> Result<(String, Summary)> {
+    let (base_sides, our_sides, their_sides) = (sides(base)?, sides(ours)?, sides(theirs)?);
+    let mut summary = Summary::default();
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+    let mut appended: Vec<String> = Vec::new();
+    let mut paired = vec![false; our_sides.len()];
+    let same = |a: Option<&Side>, b: Option<&Side>| match (a, b) {
+        (Some(a), Some(b)) => a.fingerprint == b.fingerprint,
+        (None, None) => true,
+        _ => false,
+    };
+
+    // the base, our and their version of each entry
+    let mut pairs: Vec<(Option<&Side>, Option<&Side>, Option<&Side>)> = Vec::new();
+    for t in their_sides.iter() {
+        let o = find(&our_sides, t);
+        if let Some(i) = o {
+            paired[i] = true;
+        }
+        let o = o.map(|i| &our_sides[i]);
+        let b = find(&base_sides, t)
+            .or_else(|| o.and_then(|o| find(&base_sides, o)))
+            .map(|i| &base_sides[i]);
+        pairs.push((b, o, Some(t)));
+    }
+    for (o, _) in our_sides.iter().zip(paired.iter()).filter(|(_, p)| !**p) {
+        let b = find(&base_sides, o).map(|i| &base_sides[i]);
+        pairs.push((b, Some(o), None));
+    }
+
+    for (b, o, t) in pairs {
+        let key = o.or(t).map(|s| s.key.as_str()).unwrap_or_default();
+        let resolution = if same(o, t) {
+            if b.is_some() && !same(b, o) {
+                summary.merged += 1;
+            }
+            Resolution::Ours
+        } else if b.is_none() && o.is_none() {
+            // added by them
+            summary.taken_theirs += 1;
+            Resolution::Theirs
+        } else if b.is_none() && t.is_none() {
+            // added by us
+            Resolution::Ours
+        } else if same(b, o) {
+            summary.taken_theirs += 1;
+            Resolution::Theirs
+        } else if same(b, t) {
+            summary.taken_ours += 1;
+            Resolution::Ours
+        } else {
+            summary.conflicted += 1;
+            resolve(&Conflict {
+                key,
+                ours: o,
+                theirs: t,
+            })?
+        };
+        // keep our key, in case they renamed the entry
+        let their_text = t.map(|t| match o {
+            Some(o) if o.key != t.key => t.text.replacen(&t.key, &o.key, 1),
+            _ => t.text.to_owned(),
+        });
+        let replacement = match resolution {
+            Resolution::Ours => continue,
+            Resolution::Theirs => their_text,
+            Resolution::Both => Some(conflict_block(key, o, t)),
+        };
+        match (o, replacement) {
+            (Some(o), Some(text)) => edits.push((o.range.clone(), text)),
+            (Some(o), None) => {
+                let end = o.range.end
+                    + (ours[o.range.end..].len() - ours[o.range.end..].trim_start().len());
+                edits.push((o.range.start..end, String::new()))
+            }
+            (None, Some(text)) => appended.push(text),
+            (None, None) => {}
+        }
+    }
+
+    let mut out = bib::splice(ours, edits);
+    for text in appended {
+        if !out.is_empty() && !out.ends_with("\n\n") {
+            out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+        out.push_str(&text);
+        out.push('\n');
+    }
+    Ok((out, summary))
+}
+
+/// Merges the files as `merge` does, giving the text of ours as it was
+/// read along with the merged text
+pub fn merge_files(
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    resolve: impl FnMut(&Conflict) -> Result<Resolution>,
+) -> Result<(String, String, Summary)> {
+    let read = |p: &Path| std::fs::read_to_string(p).with_context(|| format!("reading {:?}", p));
+    let our_src = read(ours)?;
+    let (merged, summary) = merge(&read(base)?, &our_src, &read(theirs)?, resolve)?;
+    Ok((our_src, merged, summary))
+}
+
+#[test]
+fn test_merge() {
+    let base = "@misc{same, title = {Same}}
+
+@misc{ours_only, title = {Old}}
+
+@misc{theirs_only, title = {Old}}
+
+@misc{both_same, title = {Old}}
+
+@misc{conflict, title = {Old}}
+
+@misc{removed, title = {Old}}
+";
+    let ours = "@misc{same, title = {Same}}
+
+@misc{ours_only, title = {Ours}}
+
+@misc{theirs_only, title = {Old}}
+
+@misc{both_same, title = {New}}
+
+@misc{conflict, title = {Ours}}
+
+@misc{removed, title = {Old}}
+
+@misc{added_by_us, title = {Ours}}
+";
+    let theirs = "@misc{same, title = {Same}}
+
+@misc{ours_only, title = {Old}}
+
+@misc{renamed, title = {Theirs}, doi = {10.1/x}}
+
+@misc{both_same, title = {New}}
+
+@misc{conflict, title = {Theirs}}
+
+@misc{added_by_them, title = {Theirs}}
+";
+    // the entry renamed by them is recognized by its DOI
+    let base = base.replace(
+        "@misc{theirs_only, title = {Old}}",
+        "@misc{theirs_only, title = {Old}, doi = {10.1/X}}",
+    );
+    let ours = ours.replace(
+        "@misc{theirs_only, title = {Old}}",
+        "@misc{theirs_only, title = {Old}, doi = {10.1/X}}",
+    );
+
+    let (merged, summary) = merge(&base, &ours, theirs, |c| {
+        assert_eq!(c.key, "conflict");
+        Ok(Resolution::Both)
+    })
+    .unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            taken_ours: 1,
+            taken_theirs: 3,
+            merged: 1,
+            conflicted: 1,
+        }
+    );
+    assert_eq!(
+        merged,
+        "@misc{same, title = {Same}}
+
+@misc{ours_only, title = {Ours}}
+
+@misc{theirs_only, title = {Theirs}, doi = {10.1/x}}
+
+@misc{both_same, title = {New}}
+
+% dblp merge conflict on conflict: ours
+@misc{conflict, title = {Ours}}
+% dblp merge conflict on conflict: theirs
+@misc{conflict, title = {Theirs}}
+% dblp merge conflict on conflict: end
+
+@misc{added_by_us, title = {Ours}}
+
+@misc{added_by_them, title = {Theirs}}
+"
+    );
+
+    let (merged, _) = merge(&base, &ours, theirs, |_| Ok(Resolution::Theirs)).unwrap();
+    assert!(merged.contains("@misc{conflict, title = {Theirs}}"));
+    assert!(!merged.contains("{Ours}}\n%"));
+}
@@ -0,0 +1,403 @@
+/// health of the DBLP mirrors, so that once a mirror keeps failing requests
+/// go straight to one that works, in this run and in the next few minutes
+use crate::error::{DblpError, Result};
+use crate::notices::{notify, Notice};
+use crate::output::MirrorHealth;
+use crate::progress::Clock;
+use crate::scratch;
+use crate::stats::STATS;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+/// Failures in a row after which a mirror is skipped...
+pub const MAX_FAILURES: u32 = 3;
+/// ...for this long, after which a single request probes whether it is back
+pub const COOLDOWN: Duration = Duration::from_secs(60);
+/// How long the health of the mirrors is remembered across commands
+pub const REMEMBER_FOR: Duration = Duration::from_secs(5 * 60);
+
+/// The health of the mirrors for the running command
+pub static HEALTH: LazyLock<Health> = LazyLock::new(|| Health::load(&crate::dblp::mirrors()));
+
+/// The time since the epoch, which unlike `SystemClock` means the same
+/// thing to the next command
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct MirrorState {
+    url: String,
+    /// in a row
+    failures: u32,
+    /// milliseconds, on the clock, of the failure that made it unhealthy
+    down_since: Option<u64>,
+    /// whether the probe after the cool-down is in flight
+    #[serde(skip)]
+    probing: bool,
+    /// milliseconds, on the clock, of the last change
+    updated: u64,
+}
+
+pub struct Health<C: Clock = WallClock> {
+    mirrors: Mutex<Vec<MirrorState>>,
+    clock: C,
+    /// where the state is saved after each change, if anywhere
+    path: Option<PathBuf>,
+}
+
+impl Health {
+    fn path() -> PathBuf {
+        scratch::data_dir().join("mirror-health.json")
+    }
+
+    /// The health of the mirrors, as left by recent commands
+    pub fn load(urls: &[String]) -> Self {
+        let stored = std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        let mut health = Self::with_clock(urls, WallClock, Some(Self::path()));
+        health.restore(stored);
+        health
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+impl<C: Clock> Health<C> {
+    pub fn with_clock(urls: &[String], clock: C, path: Option<PathBuf>) -> Self {
+        Self {
+            mirrors: Mutex::new(
+                urls.iter()
+                    .map(|url| MirrorState {
+                        url: url.clone(),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            clock,
+            path,
+        }
+    }
+
+    /// Takes over the stored state of the same mirrors, if recent enough
+    fn restore(&mut self, stored: Vec<MirrorState>) {
+        let now = millis(self.clock.now());
+        let mirrors = self.mirrors.get_mut().unwrap_or_else(|e| e.into_inner());
+        for mirror in mirrors.iter_mut() {
+            let recent = stored.iter().find(|s| {
+                s.url == mirror.url && now.saturating_sub(s.updated) < millis(REMEMBER_FOR)
+            });
+            if let Some(recent) = recent {
+                *mirror = recent.clone();
+            }
+        }
+    }
+
+    fn update(&self, url: &str, f: impl FnOnce(&mut MirrorState, u64) -> bool) {
+        let now = millis(self.clock.now());
+        let mut mirrors = self.mirrors.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(mirror) = mirrors.iter_mut().find(|m| m.url == url) else {
+            return;
+        };
+        if f(mirror, now) {
+            mirror.updated = now;
+            self.save(&mirrors);
+        }
+    }
+
+    /// Whether a request can go to the mirror: it is healthy, or its
+    /// cool-down is over and no other request is probing it
+    fn begin(&self, url: &str) -> bool {
+        let mut allowed = false;
+        self.update(url, |m, now| {
+            match m.down_since {
+                None => allowed = true,
+                Some(since) if !m.probing && now >= since + millis(COOLDOWN) => {
+                    m.probing = true;
+                    allowed = true;
+                }
+                Some(_) => {}
+            }
+            false
+        });
+        allowed
+    }
+
+    fn success(&self, url: &str) {
+        self.update(url, |m, _| {
+            let changed = m.failures > 0 || m.down_since.is_some();
+            m.failures = 0;
+            m.down_since = None;
+            m.probing = false;
+            changed
+        });
+    }
+
+    fn failure(&self, url: &str) {
+        self.update(url, |m, now| {
+            m.failures += 1;
+            m.probing = false;
+            // a failed probe starts a new cool-down
+            if m.failures >= MAX_FAILURES {
+                m.down_since = Some(now);
+            }
+            true
+        });
+    }
+
+    pub fn status(&self) -> Vec<MirrorHealth> {
+        let now = millis(self.clock.now());
+        let mirrors = self.mirrors.lock().unwrap_or_else(|e| e.into_inner());
+        mirrors
+            .iter()
+            .map(|m| MirrorHealth {
+                url: m.url.clone(),
+                failures: m.failures,
+                healthy: m.down_since.is_none(),
+                retry_in_s: m
+                    .down_since
+                    .map(|since| (since + millis(COOLDOWN)).saturating_sub(now) / 1000),
+            })
+            .collect()
+    }
+
+    /// The health is only an optimization, failing to save it is fine
+    fn save(&self, mirrors: &[MirrorState]) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::create_dir_all(path.parent().expect("health path has a parent"));
+            let _ = scratch::write_atomically(path, |f| Ok(serde_json::to_writer(f, mirrors)?));
+        }
+    }
+
+    fn urls(&self) -> Vec<String> {
+        let mirrors = self.mirrors.lock().unwrap_or_else(|e| e.into_inner());
+        mirrors.iter().map(|m| m.url.clone()).collect()
+    }
+}
+
+/// The error of the last mirror tried
+#[derive(Debug)]
+pub struct Failed {
+    pub error: DblpError,
+    pub mirror: String,
+}
+
+/// Fetches the path from the first mirror that responds, skipping the
+/// unhealthy ones, or trying all of them if all are unhealthy. A missing
+/// record is not a failure of the mirror, and is returned at once.
+pub fn get_from_mirrors<C: Clock>(
+    health: &Health<C>,
+    path: &str,
+    mut fetch: impl FnMut(&str) -> Result<String>,
+) -> Result<String, Failed> {
+    let mut attempts = Attempts::new(health);
+    while let Some(base) = attempts.next_mirror() {
+        let res = fetch(&format!("{}{}", base, path));
+        if let Some(res) = attempts.outcome(&base, res) {
+            return res;
+        }
+    }
+    Err(attempts.failed())
+}
+
+/// The mirrors a request goes to in turn, as `get_from_mirrors` does,
+/// for requests made some other way to report their outcome to
+pub struct Attempts<'a, C: Clock = WallClock> {
+    health: &'a Health<C>,
+    urls: Vec<String>,
+    next: usize,
+    /// whether every mirror is tried, since none was healthy
+    all: bool,
+    last: Option<Failed>,
+    attempts: u32,
+}
+
+impl<'a, C: Clock> Attempts<'a, C> {
+    pub fn new(health: &'a Health<C>) -> Self {
+        Self {
+            health,
+            urls: health.urls(),
+            next: 0,
+            all: false,
+            last: None,
+            attempts: 0,
+        }
+    }
+
+    /// The base url of the next mirror to try, if any is left
+    pub fn next_mirror(&mut self) -> Option<String> {
+        loop {
+            let Some(url) = self.urls.get(self.next) else {
+                // no mirror was tried, since none was healthy
+                if self.all || self.last.is_some() {
+                    return None;
+                }
+                self.all = true;
+                self.next = 0;
+                continue;
+            };
+            self.next += 1;
+            if self.all || self.health.begin(url) {
+                self.attempts += 1;
+                if self.attempts > 1 {
+                    STATS.retry();
+                }
+                return Some(url.clone());
+            }
+        }
+    }
+
+    /// Records the outcome of the request to the mirror, returning it if it
+    /// is final, and None if the next mirror is to be tried
+    pub fn outcome(&mut self, base: &str, res: Result<String>) -> Option<Result<String, Failed>> {
+        match res {
+            Ok(body) => {
+                self.health.success(base);
+                if let Some(failed) = &self.last {
+                    notify(Notice::Failover {
+                        mirror: base,
+                        failed,
+                    });
+                }
+                Some(Ok(body))
+            }
+            Err(error @ DblpError::NotFound { .. }) => {
+                self.health.success(base);
+                Some(Err(Failed {
+                    error,
+                    mirror: base.to_owned(),
+                }))
+            }
+            Err(error) => {
+                self.health.failure(base);
+                self.last = Some(Failed {
+                    error,
+                    mirror: base.to_owned(),
+                });
+                None
+            }
+        }
+    }
+
+    /// The error of the last mirror tried, once none is left
+    pub fn failed(self) -> Failed {
+        self.last.expect("there is at least a mirror")
+    }
+}
+
+#[cfg(test)]
+fn scripted(
+    health: &Health<crate::progress::FakeClock>,
+    log: &mut Vec<String>,
+    down: &[&str],
+) -> Result<String, Failed> {
+    get_from_mirrors(health, "/rec/x.bib", |url| {
+        log.push(url.to_owned());
+        if down.iter().any(|d| url.starts_with(d)) {
+            Err(DblpError::Transport("connection refused".into()))
+        } else {
+            Ok(url.to_owned())
+        }
+    })
+}
+
+#[test]
+fn test_failover() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    let urls = ["a".to_owned(), "b".to_owned()];
+    let health = Health::with_clock(&urls, crate::progress::FakeClock(AtomicU64::new(0)), None);
+    let advance = |d: Duration| {
+        health.clock.0.fetch_add(millis(d), Ordering::Relaxed);
+    };
+    let mut log = Vec::new();
+
+    // a fails, and is tried first until it failed enough times in a row
+    for _ in 0..MAX_FAILURES {
+        assert_eq!(scripted(&health, &mut log, &["a"]).unwrap(), "b/rec/x.bib");
+    }
+    assert_eq!(log.len(), 2 * MAX_FAILURES as usize);
+    log.clear();
+    assert_eq!(scripted(&health, &mut log, &["a"]).unwrap(), "b/rec/x.bib");
+    assert_eq!(log, ["b/rec/x.bib"]);
+    assert!(!health.status()[0].healthy);
+    assert_eq!(health.status()[0].retry_in_s, Some(60));
+
+    // after the cool-down a single request probes a, which is still down
+    advance(COOLDOWN);
+    log.clear();
+    scripted(&health, &mut log, &["a"]).unwrap();
+    scripted(&health, &mut log, &["a"]).unwrap();
+    assert_eq!(log, ["a/rec/x.bib", "b/rec/x.bib", "b/rec/x.bib"]);
+
+    // then it recovers
+    advance(COOLDOWN);
+    log.clear();
+    assert_eq!(scripted(&health, &mut log, &[]).unwrap(), "a/rec/x.bib");
+    scripted(&health, &mut log, &[]).unwrap();
+    assert_eq!(log, ["a/rec/x.bib", "a/rec/x.bib"]);
+    assert!(health.status()[0].healthy);
+    assert_eq!(health.status()[0].failures, 0);
+
+    // with every mirror unhealthy, all are tried rather than none
+    for _ in 0..MAX_FAILURES {
+        assert!(scripted(&health, &mut log, &["a", "b"]).is_err());
+    }
+    log.clear();
+    assert!(scripted(&health, &mut log, &["a", "b"]).is_err());
+    assert_eq!(log, ["a/rec/x.bib", "b/rec/x.bib"]);
+}
+
+#[test]
+fn test_not_found_is_healthy() {
+    use std::sync::atomic::AtomicU64;
+    let urls = ["b".to_owned()];
+    let health = Health::with_clock(&urls, crate::progress::FakeClock(AtomicU64::new(0)), None);
+    for _ in 0..MAX_FAILURES + 1 {
+        let err = get_from_mirrors(&health, "/missing", |url| {
+            Err(DblpError::NotFound { key: url.into() })
+        });
+        let failed = err.unwrap_err();
+        assert_eq!(failed.error.error_code(), "not_found");
+        assert_eq!(failed.mirror, "b");
+    }
+    assert!(health.status()[0].healthy);
+}
+
+#[test]
+fn test_restore() {
+    use std::sync::atomic::AtomicU64;
+    let now = millis(REMEMBER_FOR) * 10;
+    let state = |url: &str, updated| MirrorState {
+        url: url.to_owned(),
+        failures: MAX_FAILURES,
+        down_since: Some(updated),
+        probing: false,
+        updated,
+    };
+    let urls = ["a".to_owned(), "b".to_owned()];
+    let mut health =
+        Health::with_clock(&urls, crate::progress::FakeClock(AtomicU64::new(now)), None);
+    health.restore(vec![
+        state("a", now - 1000),
+        state("b", now - millis(REMEMBER_FOR)),
+        state("gone", now),
+    ]);
+    let status = health.status();
+    assert!(!status[0].healthy);
+    assert!(status[1].healthy);
+    assert_eq!(status.len(), 2);
+}
@@ -1,37 +1,289 @@
 use anyhow::{bail, Result};
-use serde::{de::Error, Deserialize};
+use serde::{de::Error, Deserialize, Serialize};
 use serde_yaml::Error as YamlError;
+#[cfg(feature = "tui")]
+use skim::{AnsiString, DisplayContext, ItemPreview, PreviewContext, SkimItem};
+#[cfg(feature = "tui")]
+use std::borrow::Cow;
 use std::{
-    fs::File,
-    io::{Read, Write},
+    collections::{BTreeMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
     path::{Path, PathBuf},
 };
 
-use crate::dblp;
+use crate::error::{self, DblpError};
+use crate::similarity::TYPO_DISTANCE;
 
-pub fn create_notes_file<P: AsRef<Path>>(dir: P, bib_key: &str, title: &str) -> Result<PathBuf> {
-    if let Some(existing) =
-        files_with_metadata(dir.as_ref()).find(|(_path, meta)| dbg!(&meta.key) == bib_key)
+/// The notes file of an entry
+#[derive(Debug, PartialEq, Serialize)]
+pub enum NotesFile {
+    Created(PathBuf),
+    /// the entry already had notes, which were left as they were
+    Existing(PathBuf),
+}
+
+/// The placeholders of notes templates, like `{{title}}`
+const PLACEHOLDERS: [&str; 6] = ["title", "authors", "year", "venue", "key", "doi"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+/// The template of the body of new notes, written after their front matter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            parts.push(Part::Literal(rest[..start].to_owned()));
+            let Some(len) = rest[start..].find("}}") else {
+                bail!("unclosed {{{{ in the notes template");
+            };
+            let name = rest[start + 2..start + len].trim();
+            let Some(name) = PLACEHOLDERS.iter().find(|p| **p == name) else {
+                bail!(
+                    "unknown placeholder {{{{{}}}}} in the notes template, the known ones are {}",
+                    name,
+                    PLACEHOLDERS.join(", ")
+                );
+            };
+            parts.push(Part::Placeholder(name));
+            rest = &rest[start + len + 2..];
+        }
+        parts.push(Part::Literal(rest.to_owned()));
+        Ok(Self { parts })
+    }
+
+    /// The body of the notes of the entry, with the placeholders of
+    /// missing fields left empty
+    pub fn render(&self, bib_key: &str, entry: &hayagriva::Entry) -> String {
+        let value = |name: &str| match name {
+            "title" => entry.title().map(|t| t.to_string()),
+            "authors" => entry.authors().map(|authors| {
+                authors
+                    .iter()
+                    .map(|a| a.given_first(false))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }),
+            "year" => entry.date().map(|d| d.year.to_string()),
+            "venue" => entry
+                .parents()
+                .first()
+                .and_then(|p| p.title())
+                .map(|t| t.to_string()),
+            "key" => Some(bib_key.to_owned()),
+            "doi" => entry.doi().map(str::to_owned),
+            _ => None,
+        };
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Placeholder(name) => value(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Creates the notes file of the entry, with the metadata that `fetch`
+/// gives, unless the entry already has one, with or without the `DBLP:`
+/// prefix in its key, among the files with the given extensions
+pub fn create_notes_file<P: AsRef<Path>>(
+    dir: P,
+    extensions: &[String],
+    bib_key: &str,
+    title: &str,
+    tags: &[String],
+    template: Option<&Template>,
+    fetch: impl FnOnce() -> error::Result<hayagriva::Entry>,
+) -> error::Result<NotesFile> {
+    let key = strip_dblp_prefix(bib_key);
+    if let Some((path, meta)) = files_with_metadata(dir.as_ref(), extensions)
+        .find(|(_, meta)| strip_dblp_prefix(&meta.key) == key)
     {
-        eprintln!("file already existing: {:?}", existing.0);
-        bail!("file already existing: {:?}", existing.0);
+        eprintln!("{} already has notes, about {:?}", bib_key, meta.title);
+        return Ok(NotesFile::Existing(path));
     }
-    let title = title.replace(':', "-");
-    let p = dir.as_ref().to_owned().join(title).with_extension("md");
-    let entry = dblp::fetch_bibtex(bib_key)?;
-    let yaml_str = serde_yaml::to_string(&entry)?;
+    std::fs::create_dir_all(dir.as_ref())?;
+    let path = write_notes_file(dir, bib_key, title, tags, template, &fetch()?)?;
+    Ok(NotesFile::Created(path))
+}
 
-    let mut f = File::create(&p)?;
+/// Writes a new notes file for the given entry, without checking whether
+/// the entry already has one. The file is named after the title, numbered
+/// if there is a file with that name already, so that the notes of another
+/// paper with the same title are left alone. The body follows the front
+/// matter, if there is a template.
+pub fn write_notes_file<P: AsRef<Path>>(
+    dir: P,
+    bib_key: &str,
+    title: &str,
+    tags: &[String],
+    template: Option<&Template>,
+    entry: &hayagriva::Entry,
+) -> error::Result<PathBuf> {
+    // titles like `A/B Testing: a Survey.` make a single file name
+    let title = title.trim_end_matches('.').replace([':', '/'], "-");
+    let header = header(bib_key, tags, entry)?;
 
-    println!("---\nkey: {}\n{}---", bib_key, yaml_str);
-    writeln!(f, "---\nkey: {}\n{}---", bib_key, yaml_str)?;
+    let (p, mut f) = create_numbered(dir.as_ref(), &title)?;
+
+    writeln!(f, "{}", header)?;
+    if let Some(template) = template {
+        write!(f, "{}", template.render(bib_key, entry))?;
+    }
     Ok(p)
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Creates the file `<name>.md` in the directory or, if there is one
+/// already, the first of `<name> (2).md`, `<name> (3).md`, ... that is not
+fn create_numbered(dir: &Path, name: &str) -> error::Result<(PathBuf, File)> {
+    let mut n = 1;
+    loop {
+        let p = match n {
+            1 => dir.join(format!("{}.md", name)),
+            n => dir.join(format!("{} ({}).md", name, n)),
+        };
+        match OpenOptions::new().write(true).create_new(true).open(&p) {
+            Ok(f) => return Ok((p, f)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => n += 1,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// The front matter of the notes of the entry, with its delimiters
+fn header(bib_key: &str, tags: &[String], entry: &hayagriva::Entry) -> error::Result<String> {
+    let invalid = |err: YamlError| DblpError::InvalidEntry {
+        reason: format!("cannot write the front matter of {}: {}", bib_key, err),
+    };
+    let yaml_str = serde_yaml::to_string(entry).map_err(invalid)?;
+    let tags = match tags {
+        [] => String::new(),
+        tags => serde_yaml::to_string(&BTreeMap::from([("tags", tags)])).map_err(invalid)?,
+    };
+    Ok(format!("---\nkey: {}\n{}{}---", bib_key, tags, yaml_str))
+}
+
+/// The notes with their front matter made again from the entry, keeping
+/// their key, the keys of the front matter that are not fields of
+/// hayagriva, like `tags` and `status`, and their body as they are. None
+/// if the notes have no front matter.
+pub fn sync_header(content: &str, entry: &hayagriva::Entry) -> Result<Option<String>> {
+    let Some((old, body)) = split_header(content) else {
+        return Ok(None);
+    };
+    let meta: ShortMetadata = serde_yaml::from_str(old)?;
+    let mut header = header(&meta.key, &[], entry)?;
+    // the fields of hayagriva come after `type` in the known keys
+    let generated = NOTE_KEYS
+        .iter()
+        .skip_while(|(key, _)| *key != "type")
+        .map(|(key, _)| *key);
+    let ours: Vec<&str> = header_blocks(old)
+        .into_iter()
+        .filter(|(key, _)| *key != "key" && !generated.clone().any(|g| g == *key))
+        .map(|(_, text)| text)
+        .collect();
+    header.truncate(header.len() - "---".len());
+    for text in ours {
+        header.push_str(text);
+    }
+    Ok(Some(format!("{}---{}", header, body)))
+}
+
+/// The top level keys of the front matter, each with its lines
+fn header_blocks(header: &str) -> Vec<(&str, &str)> {
+    let mut blocks: Vec<(&str, &str)> = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+    for line in header.split_inclusive('\n') {
+        let nested = line.starts_with([' ', '\t', '-', '#']) || line.trim().is_empty();
+        if !nested {
+            if let Some((key, begin)) = start {
+                blocks.push((key, &header[begin..offset]));
+            }
+            let key = line.split(':').next().unwrap_or_default().trim();
+            start = Some((key, offset));
+        }
+        offset += line.len();
+    }
+    if let Some((key, begin)) = start {
+        blocks.push((key, &header[begin..]));
+    }
+    blocks
+}
+
+/// The keys of all the notes in the directory, without the `DBLP:` prefix
+pub fn noted_keys<P: AsRef<Path>>(dir: P, extensions: &[String]) -> HashSet<String> {
+    files_with_metadata(dir, extensions)
+        .map(|(_path, meta)| strip_dblp_prefix(&meta.key).to_owned())
+        .collect()
+}
+
+pub fn strip_dblp_prefix(key: &str) -> &str {
+    key.strip_prefix("DBLP:").unwrap_or(key)
+}
+
+/// The front matter of notes that dblp reads, the rest is left alone
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ShortMetadata {
     pub title: String,
+    /// the key of the entry in the bib file, with or without `DBLP:`
     pub key: String,
+    /// a list, or a string of comma separated tags
+    #[serde(default, deserialize_with = "lenient_tags")]
+    pub tags: Vec<String>,
+    /// the reading status, empty if missing or not a string
+    #[serde(default, deserialize_with = "lenient_status")]
+    pub status: String,
+}
+
+impl ShortMetadata {
+    /// Whether the metadata has all the tags, in any case, and the status
+    /// if one is given
+    pub fn matches(&self, tags: &[String], status: Option<&str>) -> bool {
+        tags.iter()
+            .all(|tag| self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            && status.is_none_or(|status| self.status == status)
+    }
+}
+
+fn lenient_tags<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    use serde_yaml::Value;
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Some(s.trim().to_owned()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    };
+    let tags = match Value::deserialize(deserializer)? {
+        Value::String(s) => s.split(',').map(|t| t.trim().to_owned()).collect(),
+        Value::Sequence(values) => values.iter().filter_map(scalar).collect(),
+        _ => Vec::new(),
+    };
+    Ok(tags
+        .into_iter()
+        .filter(|t: &String| !t.is_empty())
+        .collect())
+}
+
+fn lenient_status<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::String(s) => Ok(s),
+        _ => Ok(String::new()),
+    }
 }
 
 impl TryFrom<&str> for ShortMetadata {
@@ -45,38 +297,487 @@ impl TryFrom<&str> for ShortMetadata {
 
 /// Returns true if the file contains a YAML header
 pub fn get_metadata_str(content: &str) -> Option<&str> {
-    let mut chunks = content.split("---");
-    let before = chunks.next();
-    let meta = chunks.next();
-    let after = chunks.next();
+    split_header(content).map(|(meta, _)| meta)
+}
 
-    if before.is_none() || !before.unwrap().trim().is_empty() {
+/// The YAML header of the file, between lines of just `---`, and what
+/// follows its closing `---`. A `---` within a line, or a horizontal rule
+/// in the body, is not a delimiter.
+fn split_header(content: &str) -> Option<(&str, &str)> {
+    let mut lines = content.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some((start, line))
+    });
+    let (open, first) = lines.find(|(_, line)| !line.trim().is_empty())?;
+    if first.trim() != "---" {
         return None;
     }
-    after?;
+    let (close, last) = lines.find(|(_, line)| line.trim() == "---")?;
+    let end = close + last.find("---").unwrap_or_default() + "---".len();
+    Some((&content[open + first.len()..close], &content[end..]))
+}
 
-    meta
+/// The keys that the body of the notes links to, like `[[DBLP:conf/soda/Chan16]]`
+/// or `[[key|shown text]]`, without the `DBLP:` prefix and in order of first
+/// appearance. Fenced code blocks are skipped.
+pub fn links(content: &str) -> Vec<String> {
+    let body = split_header(content).map_or(content, |(_, body)| body);
+    let mut keys: Vec<String> = Vec::new();
+    let mut fence: Option<&str> = None;
+    for line in body.lines() {
+        let trimmed = line.trim_start();
+        let marker = ["```", "~~~"].into_iter().find(|m| trimmed.starts_with(*m));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            _ => {}
+        }
+        if fence.is_some() || marker.is_some() {
+            continue;
+        }
+        let mut rest = line;
+        while let Some(start) = rest.find("[[") {
+            let Some(len) = rest[start + 2..].find("]]") else {
+                break;
+            };
+            let inner = &rest[start + 2..start + 2 + len];
+            let key = strip_dblp_prefix(inner.split('|').next().unwrap_or_default().trim());
+            if !key.is_empty() && !keys.iter().any(|k| k == key) {
+                keys.push(key.to_owned());
+            }
+            rest = &rest[start + 2 + len + 2..];
+        }
+    }
+    keys
 }
 
+/// The most bytes read from a file looking for the end of its header
+const HEADER_LIMIT: u64 = 64 * 1024;
+
+/// The start of the file, up to the end of its header if it has one
+fn read_header(path: &Path) -> std::io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?.take(HEADER_LIMIT));
+    let mut text = String::new();
+    let mut opened = false;
+    loop {
+        let start = text.len();
+        if reader.read_line(&mut text)? == 0 {
+            return Ok(text);
+        }
+        match text[start..].trim() {
+            "---" if opened => return Ok(text),
+            "---" => opened = true,
+            "" => {}
+            _ if !opened => return Ok(text),
+            _ => {}
+        }
+    }
+}
+
+/// The notes files in the directory and its subdirectories that have
+/// metadata, by path. Only their headers are read, by as many threads as
+/// there are cores; the ones that cannot be read get a warning.
 pub fn files_with_metadata<P: AsRef<Path>>(
     directory: P,
+    extensions: &[String],
 ) -> impl Iterator<Item = (PathBuf, ShortMetadata)> {
-    use walkdir::WalkDir;
-    let mut buf = String::new();
-    WalkDir::new(directory)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter_map(move |p| {
-            if p.path().is_file() {
-                buf.clear();
-                let mut f = std::fs::File::open(p.path()).ok()?;
-                f.read_to_string(&mut buf).ok()?;
-                let meta = ShortMetadata::try_from(buf.as_str()).ok()?;
-                Some((p.path().to_owned(), meta))
-            } else {
+    let mut paths: Vec<PathBuf> = note_files(directory, extensions).collect();
+    paths.sort();
+    let jobs = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = paths.len().div_ceil(jobs).max(1);
+    let found: Vec<(PathBuf, ShortMetadata)> = std::thread::scope(|s| {
+        let workers: Vec<_> = paths
+            .chunks(chunk)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .filter_map(|path| match read_header(path) {
+                            Ok(header) => ShortMetadata::try_from(header.as_str())
+                                .ok()
+                                .map(|meta| (path.clone(), meta)),
+                            Err(err) => {
+                                eprintln!("warning: skipping {}: {}", path.display(), err);
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("a worker reading notes panicked"))
+            .collect()
+    });
+    found.into_iter()
+}
+
+/// Lines of the body of the notes shown in the preview of the picker
+const PREVIEW_LINES: usize = 20;
+
+/// A notes file, with its metadata unless its front matter is missing or
+/// invalid
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub path: PathBuf,
+    pub meta: Option<ShortMetadata>,
+    /// the first lines after the front matter
+    pub preview: String,
+    /// the DBLP record of the paper, or else the file itself
+    pub link: String,
+}
+
+impl Note {
+    fn new(path: PathBuf, content: &str) -> Self {
+        let meta = ShortMetadata::try_from(content).ok();
+        let body = match meta {
+            Some(_) => split_header(content).map_or(content, |(_, body)| body),
+            None => content,
+        };
+        let preview = body
+            .trim_start_matches(['\r', '\n'])
+            .lines()
+            .take(PREVIEW_LINES)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let link = match meta.as_ref().and_then(|m| m.key.strip_prefix("DBLP:")) {
+            Some(key) => format!("https://dblp.org/rec/{}", key),
+            None => path.display().to_string(),
+        };
+        Self {
+            path,
+            meta,
+            preview,
+            link,
+        }
+    }
+
+    /// Whether every word of the query is in the key, title or file name,
+    /// in any case
+    pub fn matches(&self, query: &str) -> bool {
+        let text = self.search_text().to_lowercase();
+        query
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+
+    /// The text that queries are matched against, in the picker too
+    pub fn search_text(&self) -> String {
+        let name = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        match &self.meta {
+            Some(meta) => format!("{} {} {}", meta.key, meta.title, name),
+            None => format!("(no metadata) {}", name),
+        }
+    }
+}
+
+/// The notes in the directory and its subdirectories, including the ones
+/// without metadata, by path
+pub fn notes<P: AsRef<Path>>(directory: P, extensions: &[String]) -> Vec<Note> {
+    let mut notes: Vec<Note> = note_files(directory, extensions)
+        .filter_map(|path| match std::fs::read_to_string(&path) {
+            Ok(content) => Some(Note::new(path, &content)),
+            Err(err) => {
+                eprintln!("warning: skipping {}: {}", path.display(), err);
                 None
             }
         })
+        .collect();
+    notes.sort_by(|a, b| a.path.cmp(&b.path));
+    notes
+}
+
+#[cfg(feature = "tui")]
+impl SkimItem for Note {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Owned(self.search_text())
+    }
+
+    fn display<'a>(&'a self, _context: DisplayContext<'a>) -> AnsiString<'a> {
+        match &self.meta {
+            Some(meta) => AnsiString::from(format!("{}  {}", meta.key, meta.title)),
+            None => AnsiString::from(format!("(no metadata)  {}", self.path.display())),
+        }
+    }
+
+    fn preview(&self, _context: PreviewContext) -> ItemPreview {
+        ItemPreview::Text(self.preview.clone())
+    }
+}
+
+/// The value expected for a key of the front matter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    /// A single value, rather than a list or a map
+    Scalar,
+    List,
+    Number,
+    /// A year, or a date like 2019-05-01
+    Date,
+    /// One of `STATUSES`
+    Status,
+    /// Anything, like the structured fields of hayagriva
+    Any,
+}
+
+impl std::fmt::Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            KeyType::Scalar => "a single value",
+            KeyType::List => "a list",
+            KeyType::Number => "a number",
+            KeyType::Date => "a date",
+            KeyType::Status => "a status",
+            KeyType::Any => "anything",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// The known keys of the front matter: ours, then the fields of the
+/// hayagriva entry
+pub const NOTE_KEYS: &[(&str, KeyType)] = &[
+    ("key", KeyType::Scalar),
+    ("tags", KeyType::List),
+    ("status", KeyType::Status),
+    ("added", KeyType::Date),
+    ("aliases", KeyType::List),
+    ("authors", KeyType::List),
+    ("year", KeyType::Number),
+    ("type", KeyType::Scalar),
+    ("title", KeyType::Any),
+    ("author", KeyType::Any),
+    ("date", KeyType::Date),
+    ("editor", KeyType::Any),
+    ("affiliated", KeyType::Any),
+    ("publisher", KeyType::Any),
+    ("location", KeyType::Any),
+    ("organization", KeyType::Any),
+    ("issue", KeyType::Any),
+    ("volume", KeyType::Any),
+    ("volume-total", KeyType::Any),
+    ("edition", KeyType::Any),
+    ("page-range", KeyType::Any),
+    ("page-total", KeyType::Any),
+    ("time-range", KeyType::Any),
+    ("runtime", KeyType::Any),
+    ("url", KeyType::Any),
+    ("serial-number", KeyType::Any),
+    ("language", KeyType::Scalar),
+    ("archive", KeyType::Any),
+    ("archive-location", KeyType::Any),
+    ("call-number", KeyType::Any),
+    ("note", KeyType::Any),
+    ("abstract", KeyType::Any),
+    ("genre", KeyType::Any),
+    ("parent", KeyType::Any),
+];
+
+/// The values allowed for `status`
+pub const STATUSES: &[&str] = &["unread", "reading", "read"];
+
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// An unknown key close to a known one
+    Typo {
+        key: String,
+        suggestion: String,
+    },
+    WrongType {
+        key: String,
+        expected: KeyType,
+    },
+    Invalid {
+        key: String,
+        reason: String,
+    },
+    /// The front matter is not valid YAML
+    Header(String),
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::Typo { key, suggestion } => {
+                write!(f, "unknown key `{}`, did you mean `{}`?", key, suggestion)
+            }
+            Problem::WrongType { key, expected } => {
+                write!(f, "`{}` should be {}", key, expected)
+            }
+            Problem::Invalid { key, reason } => write!(f, "`{}` {}", key, reason),
+            Problem::Header(message) => write!(f, "cannot parse the front matter: {}", message),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    pub line: usize,
+    pub problem: Problem,
+}
+
+/// The lines of the front matter, with the number of the first one
+fn header_lines(content: &str) -> Option<(usize, Vec<&str>)> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .skip_while(|(_, l)| l.trim().is_empty());
+    let (open, first) = lines.next()?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+    let mut header = Vec::new();
+    for (_, line) in lines {
+        if line.trim_end() == "---" {
+            return Some((open + 2, header));
+        }
+        header.push(line);
+    }
+    None
+}
+
+fn check_value(key: &str, expected: KeyType, value: &serde_yaml::Value) -> Option<Problem> {
+    use serde_yaml::Value;
+    let nested = matches!(value, Value::Sequence(_) | Value::Mapping(_));
+    let text = || {
+        serde_yaml::to_string(value)
+            .unwrap_or_default()
+            .trim()
+            .to_owned()
+    };
+    let invalid = |reason: String| {
+        Some(Problem::Invalid {
+            key: key.to_owned(),
+            reason,
+        })
+    };
+    let wrong_type = || {
+        Some(Problem::WrongType {
+            key: key.to_owned(),
+            expected,
+        })
+    };
+    match expected {
+        KeyType::Any => None,
+        KeyType::Scalar if nested => wrong_type(),
+        KeyType::List if !value.is_sequence() => wrong_type(),
+        KeyType::Number if !value.is_number() => invalid(format!("is {}, not a number", text())),
+        KeyType::Date => {
+            let re = regex::Regex::new(r"^\d{4}(-\d{2}(-\d{2})?)?$").unwrap();
+            match value {
+                Value::Number(_) => None,
+                Value::String(s) if re.is_match(s) => None,
+                _ => invalid(format!("is {}, not a date like 2019-05-01", text())),
+            }
+        }
+        KeyType::Status if !value.as_str().is_some_and(|s| STATUSES.contains(&s)) => {
+            invalid(format!("is {}, not one of {}", text(), STATUSES.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+/// Checks the front matter of the notes against the known keys, and the
+/// extra ones given, which can have any value. Unknown keys are fine,
+/// unless they look like typos of known ones. `None` if there is no front
+/// matter.
+pub fn validate(content: &str, extra_keys: &[String]) -> Option<Vec<Issue>> {
+    let (start, header) = header_lines(content)?;
+    let mapping: serde_yaml::Mapping = match serde_yaml::from_str(&header.join("\n")) {
+        Ok(serde_yaml::Value::Mapping(mapping)) => mapping,
+        Ok(serde_yaml::Value::Null) => serde_yaml::Mapping::new(),
+        Ok(_) => {
+            return Some(vec![Issue {
+                line: start,
+                problem: Problem::Header("not a map of keys to values".into()),
+            }])
+        }
+        Err(e) => {
+            return Some(vec![Issue {
+                line: start + e.location().map_or(0, |l| l.line().saturating_sub(1)),
+                problem: Problem::Header(e.to_string()),
+            }])
+        }
+    };
+    let line_of = |key: &str| {
+        header
+            .iter()
+            .position(|l| {
+                l.strip_prefix(key)
+                    .is_some_and(|rest| rest.trim_start().starts_with(':'))
+            })
+            .map_or(start, |i| start + i)
+    };
+    let known = NOTE_KEYS
+        .iter()
+        .map(|(k, t)| (*k, *t))
+        .chain(extra_keys.iter().map(|k| (k.as_str(), KeyType::Any)));
+
+    let mut issues = Vec::new();
+    for (key, value) in mapping.iter() {
+        let Some(key) = key.as_str() else { continue };
+        let problem = match known.clone().find(|(k, _)| *k == key) {
+            Some((_, expected)) => check_value(key, expected, value),
+            None => known
+                .clone()
+                .map(|(k, _)| (crate::similarity::edit_distance(key, k), k))
+                .filter(|(d, _)| *d <= TYPO_DISTANCE)
+                .min_by_key(|(d, _)| *d)
+                .map(|(_, suggestion)| Problem::Typo {
+                    key: key.to_owned(),
+                    suggestion: suggestion.to_owned(),
+                }),
+        };
+        if let Some(problem) = problem {
+            issues.push(Issue {
+                line: line_of(key),
+                problem,
+            });
+        }
+    }
+    issues.sort_by_key(|i| i.line);
+    Some(issues)
+}
+
+/// Renames the key defined on the given line, leaving the rest of the file
+/// untouched. `None` if the key is not defined there.
+pub fn rename_key(content: &str, line: usize, from: &str, to: &str) -> Option<String> {
+    let mut out = String::with_capacity(content.len() + to.len());
+    let mut renamed = false;
+    for (i, l) in content.split_inclusive('\n').enumerate() {
+        match l.strip_prefix(from) {
+            Some(rest) if i + 1 == line && rest.trim_start().starts_with(':') => {
+                out.push_str(to);
+                out.push_str(rest);
+                renamed = true;
+            }
+            _ => out.push_str(l),
+        }
+    }
+    renamed.then_some(out)
+}
+
+/// The notes files in the directory and its subdirectories, the ones with
+/// any of the given extensions, whatever their case
+pub fn note_files<P: AsRef<Path>>(
+    directory: P,
+    extensions: &[String],
+) -> impl Iterator<Item = PathBuf> {
+    let extensions = extensions.to_vec();
+    walkdir::WalkDir::new(directory)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(move |e| {
+            e.file_type().is_file()
+                && e.path().extension().is_some_and(|x| {
+                    extensions
+                        .iter()
+                        .any(|ext| x.eq_ignore_ascii_case(ext.as_str()))
+                })
+        })
+        .map(|e| e.into_path())
 }
 
 #[test]
@@ -113,3 +814,253 @@ fn test_has_metadata() {
     )
     .is_some());
 }
+
+#[test]
+fn test_tags_and_status() {
+    let meta = |header: &str| {
+        ShortMetadata::try_from(format!("---\nkey: k\ntitle: t\n{}---\n", header).as_str()).unwrap()
+    };
+    let listed = meta("tags:\n- streaming\n- Graphs\nstatus: reading\n");
+    assert_eq!(listed.tags, ["streaming", "Graphs"]);
+    assert_eq!(listed.status, "reading");
+    assert_eq!(
+        meta("tags: streaming, graphs,\n").tags,
+        ["streaming", "graphs"]
+    );
+    assert_eq!(meta("tags: [2024]\nstatus: 3\n").tags, ["2024"]);
+    assert_eq!(meta("status: 3\n").status, "");
+    assert!(meta("").tags.is_empty());
+
+    assert!(listed.matches(&["graphs".into()], Some("reading")));
+    assert!(listed.matches(&[], None));
+    assert!(!listed.matches(&["graphs".into(), "sketches".into()], None));
+    assert!(!listed.matches(&[], Some("read")));
+}
+
+#[test]
+fn test_links() {
+    let content = "---\nkey: a\ntitle: \"[[header]]\"\n---\n\
+        See [[DBLP:conf/soda/Chan16]] and [[ b | the b paper ]], again [[conf/soda/Chan16]].\n\
+        ```\n[[in code]]\n```\n\
+        ~~~rust\n```\n[[still code]]\n~~~\n\
+        [[]] [[unclosed\n[[c]]\n";
+    assert_eq!(links(content), ["conf/soda/Chan16", "b", "c"]);
+    assert_eq!(links("[[plain]]"), ["plain"]);
+}
+
+#[test]
+fn test_split_header() {
+    let content = "\n---\ntitle: Before --- after\n---\n\nabove\n\n---\n\nbelow\n";
+    assert_eq!(
+        split_header(content),
+        Some(("title: Before --- after\n", "\n\nabove\n\n---\n\nbelow\n"))
+    );
+    assert_eq!(split_header("--- not a header\ntitle: x\n---\n"), None);
+    assert_eq!(
+        split_header("---\r\ntitle: x\r\n---\r\n"),
+        Some(("title: x\r\n", "\r\n"))
+    );
+}
+
+#[test]
+fn test_files_with_metadata() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/notes");
+    let found: Vec<(String, String)> = files_with_metadata(&dir, &["md".to_owned()])
+        .map(|(path, meta)| {
+            let path = path.strip_prefix(&dir).unwrap().display().to_string();
+            (path, meta.title)
+        })
+        .collect();
+    // not the pdf, the text file, the note without header, nor the one
+    // that is not UTF-8
+    assert_eq!(
+        found,
+        [
+            (
+                "2016/chan.md".to_owned(),
+                "All-Pairs Shortest Paths".to_owned()
+            ),
+            ("rule.md".to_owned(), "Before --- After".to_owned()),
+        ]
+    );
+    let header = read_header(&dir.join("rule.md")).unwrap();
+    assert_eq!(header, "---\nkey: rule\ntitle: Before --- After\n---\n");
+    assert!(read_header(&dir.join("latin1.md")).is_err());
+}
+
+#[test]
+fn test_create_notes_file() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-notes-{}", std::process::id()));
+    let entry =
+        || crate::bib::hayagriva_entry("@misc{DBLP:conf/soda/Chan16, title = {A/B: Paths}}");
+    let tags = ["graphs".to_owned(), "to read".to_owned()];
+    let extensions = ["md".to_owned()];
+    let created = create_notes_file(
+        &dir,
+        &extensions,
+        "DBLP:conf/soda/Chan16",
+        "A/B: Paths.",
+        &tags,
+        None,
+        entry,
+    )
+    .unwrap();
+    let path = dir.join("A-B- Paths.md");
+    assert_eq!(created, NotesFile::Created(path.clone()));
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        content.starts_with("---\nkey: DBLP:conf/soda/Chan16\n"),
+        "{}",
+        content
+    );
+    let meta = ShortMetadata::try_from(content.as_str()).unwrap();
+    assert_eq!(meta.key, "DBLP:conf/soda/Chan16");
+    assert_eq!(meta.tags, tags);
+    // found again without the prefix, and without fetching
+    let again = create_notes_file(
+        &dir,
+        &extensions,
+        "conf/soda/Chan16",
+        "Other",
+        &[],
+        None,
+        || unreachable!(),
+    );
+    assert_eq!(again.unwrap(), NotesFile::Existing(path.clone()));
+    // another paper with the same title gets a file of its own
+    let other = create_notes_file(
+        &dir,
+        &extensions,
+        "DBLP:journals/corr/Chan16",
+        "A/B: Paths.",
+        &[],
+        None,
+        entry,
+    )
+    .unwrap();
+    assert_eq!(other, NotesFile::Created(dir.join("A-B- Paths (2).md")));
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .starts_with("---\nkey: DBLP:conf/soda/Chan16\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_note() {
+    let note = Note::new(
+        "notes/chan.md".into(),
+        "---\nkey: DBLP:conf/soda/Chan16\ntitle: All-Pairs\n---\n\n## Summary\nfast\n",
+    );
+    assert_eq!(
+        note.meta,
+        Some(ShortMetadata {
+            title: "All-Pairs".into(),
+            key: "DBLP:conf/soda/Chan16".into(),
+            tags: vec![],
+            status: String::new(),
+        })
+    );
+    assert_eq!(note.preview, "## Summary\nfast");
+    assert_eq!(note.link, "https://dblp.org/rec/conf/soda/Chan16");
+    assert!(note.matches("chan16 all-pairs"));
+    assert!(!note.matches("ideas"));
+
+    let plain = Note::new("notes/ideas.md".into(), "# Ideas\n---\nmore\n");
+    assert_eq!(plain.meta, None);
+    assert_eq!(plain.preview, "# Ideas\n---\nmore");
+    assert_eq!(plain.link, "notes/ideas.md");
+    assert_eq!(plain.search_text(), "(no metadata) ideas");
+}
+
+#[test]
+fn test_sync_header() {
+    let entry = crate::bib::hayagriva_entry(
+        "@inproceedings{DBLP:conf/soda/Chan16, title = {All-Pairs Shortest Paths}, year = {2016}}",
+    )
+    .unwrap();
+    let old = "---\nkey: DBLP:conf/soda/Chan16\ntype: misc\ntitle: All-pairs shortest paths\nurl: https://old.org\ntags:\n- graphs\nstatus: read\n---\n\n## Summary\n  kept --- as is\n";
+    let synced = sync_header(old, &entry).unwrap().unwrap();
+    let fresh = header("DBLP:conf/soda/Chan16", &[], &entry).unwrap();
+    assert_eq!(
+        synced,
+        format!(
+            "{}tags:\n- graphs\nstatus: read\n---\n\n## Summary\n  kept --- as is\n",
+            fresh.trim_end_matches("---")
+        )
+    );
+    assert!(!synced.contains("old.org"));
+    assert_eq!(sync_header(&synced, &entry).unwrap().unwrap(), synced);
+    assert_eq!(sync_header("# no header\n", &entry).unwrap(), None);
+}
+
+#[test]
+fn test_template() {
+    let entry = crate::bib::hayagriva_entry(
+        "@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan and Jane Doe}, \
+         title = {All-Pairs Shortest Paths}, booktitle = {SODA}, year = {2016}}",
+    )
+    .unwrap();
+    let template =
+        Template::parse("# {{title}}\n{{ authors }}, {{venue}} {{year}} [[{{key}}]] {{doi}}\n")
+            .unwrap();
+    assert_eq!(
+        template.render("DBLP:conf/soda/Chan16", &entry),
+        "# All-Pairs Shortest Paths\nTimothy M. Chan, Jane Doe, SODA 2016 [[DBLP:conf/soda/Chan16]] \n"
+    );
+    let plain = Template::parse("## Summary\n{ not a placeholder }\n").unwrap();
+    assert_eq!(
+        plain.render("k", &entry),
+        "## Summary\n{ not a placeholder }\n"
+    );
+    let err = Template::parse("{{abstract}}").unwrap_err().to_string();
+    assert!(err.contains("{{abstract}}"), "{}", err);
+    assert!(Template::parse("{{title").is_err());
+}
+
+#[test]
+fn test_validate() {
+    let notes = "---
+key: DBLP:conf/soda/Chan16
+satus: reading
+tag: [graphs]
+tags: shortest paths
+status: done
+year: twenty
+added: 2023-05-01
+project: thesis
+extra: anything
+title: All-Pairs Shortest Paths
+---
+
+satus: this line is in the body
+";
+    let issues = validate(notes, &["project".to_owned()]).unwrap();
+    let problems: Vec<(usize, String)> = issues
+        .iter()
+        .map(|i| (i.line, i.problem.to_string()))
+        .collect();
+    assert_eq!(
+        problems,
+        [
+            (3, "unknown key `satus`, did you mean `status`?".to_owned()),
+            (4, "unknown key `tag`, did you mean `tags`?".to_owned()),
+            (5, "`tags` should be a list".to_owned()),
+            (
+                6,
+                "`status` is done, not one of unread, reading, read".to_owned()
+            ),
+            (7, "`year` is twenty, not a number".to_owned()),
+        ]
+    );
+    assert!(validate("# no front matter\n", &[]).is_none());
+    let broken = validate("---\nkey: a\ntags: [unclosed\n---\n", &[]).unwrap();
+    assert!(matches!(broken[0].problem, Problem::Header(_)));
+
+    let fixed = rename_key(notes, 3, "satus", "status").unwrap();
+    assert_eq!(
+        fixed,
+        notes.replacen("satus: reading", "status: reading", 1)
+    );
+    assert!(fixed.contains("satus: this line is in the body"));
+    assert!(rename_key(notes, 4, "satus", "status").is_none());
+}
@@ -0,0 +1,196 @@
+/// utilities to write an index of the notes, as markdown tables between
+/// markers that keep the rest of the file as it is
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+/// The file of the index, in the notes directory
+pub const INDEX_FILE: &str = "index.md";
+
+pub const START: &str = "<!-- dblp-rs index start -->";
+pub const END: &str = "<!-- dblp-rs index end -->";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// A single table
+    None,
+    /// A table for each year, the latest first
+    Year,
+    /// A table for each tag, listing notes with many tags in each of them
+    Tag,
+}
+
+/// A line of the index
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub title: String,
+    pub year: String,
+    pub venue: String,
+    pub key: String,
+    pub status: String,
+    pub tags: Vec<String>,
+    /// the path of the notes, relative to the index
+    pub link: String,
+}
+
+/// The block of the index, markers included, with tables of the rows in
+/// the same order whatever the order they are given in
+pub fn render(rows: &[Row], group_by: GroupBy) -> String {
+    let mut rows = rows.to_vec();
+    // the latest first, and the ones without a year last
+    rows.sort_by_cached_key(|row| {
+        let year = row.year.parse::<u32>().ok();
+        (
+            year.is_none(),
+            std::cmp::Reverse(year),
+            row.title.clone(),
+            row.link.clone(),
+        )
+    });
+    // by the order of the group, then its name, then its heading
+    let mut groups: BTreeMap<(u8, String, Option<String>), Vec<&Row>> = BTreeMap::new();
+    let mut add = |order: u8, name: String, heading: Option<String>, row| {
+        groups.entry((order, name, heading)).or_default().push(row)
+    };
+    for row in &rows {
+        match group_by {
+            GroupBy::None => add(0, String::new(), None, row),
+            // the latest years first, and notes without one last
+            GroupBy::Year => match row.year.parse::<u32>() {
+                Ok(year) => add(
+                    0,
+                    format!("{:010}", u32::MAX - year),
+                    Some(row.year.clone()),
+                    row,
+                ),
+                Err(_) => add(1, String::new(), Some("Unknown year".to_owned()), row),
+            },
+            GroupBy::Tag if row.tags.is_empty() => {
+                add(1, String::new(), Some("Untagged".to_owned()), row)
+            }
+            GroupBy::Tag => {
+                for tag in &row.tags {
+                    add(0, tag.clone(), Some(tag.clone()), row)
+                }
+            }
+        }
+    }
+    let mut out = format!("{}\n", START);
+    for ((_, _, heading), rows) in &groups {
+        if let Some(heading) = heading {
+            out.push_str(&format!("\n## {}\n", heading));
+        }
+        out.push_str("\n| Title | Year | Venue | Key | Status | Tags |\n");
+        out.push_str("|---|---|---|---|---|---|\n");
+        for row in rows {
+            out.push_str(&format!(
+                "| [{}]({}) | {} | {} | {} | {} | {} |\n",
+                cell(&row.title),
+                link(&row.link),
+                cell(&row.year),
+                cell(&row.venue),
+                cell(&row.key),
+                cell(&row.status),
+                cell(&row.tags.join(", "))
+            ));
+        }
+    }
+    out.push_str(&format!("\n{}\n", END));
+    out
+}
+
+/// The text, on a single line and with its pipes escaped, not to break
+/// the table
+fn cell(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace('|', "\\|")
+}
+
+/// The path, with the characters that would end the link or break
+/// the table escaped
+fn link(path: &str) -> String {
+    path.replace(' ', "%20")
+        .replace('|', "%7C")
+        .replace('(', "%28")
+        .replace(')', "%29")
+}
+
+/// The index with the block between the markers replaced, or with the
+/// block added at the end if there are no markers
+pub fn splice(existing: &str, block: &str) -> String {
+    let start = existing.find(START);
+    let end = start.and_then(|start| existing[start..].find(END).map(|end| start + end));
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            let after = &existing[end + END.len()..];
+            let after = after.strip_prefix('\n').unwrap_or(after);
+            format!("{}{}{}", &existing[..start], block, after)
+        }
+        _ if existing.trim().is_empty() => block.to_owned(),
+        _ => format!("{}\n\n{}", existing.trim_end(), block),
+    }
+}
+
+#[cfg(test)]
+fn row(title: &str, year: &str, tags: &[&str]) -> Row {
+    Row {
+        title: title.into(),
+        year: year.into(),
+        venue: "SODA".into(),
+        key: format!("k{}", year),
+        status: "read".into(),
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        link: format!("{}/{}.md", year, title),
+    }
+}
+
+#[test]
+fn test_render() {
+    let rows = [
+        row("B | C", "2016", &["graphs"]),
+        row("A", "2019", &["graphs", "streaming"]),
+        row("Old", "", &[]),
+    ];
+    let table = render(&rows, GroupBy::None);
+    assert_eq!(
+        table,
+        format!(
+            "{}\n\n| Title | Year | Venue | Key | Status | Tags |\n|---|---|---|---|---|---|\n\
+             | [A](2019/A.md) | 2019 | SODA | k2019 | read | graphs, streaming |\n\
+             | [B \\| C](2016/B%20%7C%20C.md) | 2016 | SODA | k2016 | read | graphs |\n\
+             | [Old](/Old.md) |  | SODA | k | read |  |\n\n{}\n",
+            START, END
+        )
+    );
+    let mut reversed = rows.to_vec();
+    reversed.reverse();
+    assert_eq!(render(&reversed, GroupBy::None), table);
+
+    let by_year = render(&rows, GroupBy::Year);
+    let headings: Vec<&str> = by_year.lines().filter(|l| l.starts_with("## ")).collect();
+    assert_eq!(headings, ["## 2019", "## 2016", "## Unknown year"]);
+    let by_tag = render(&rows, GroupBy::Tag);
+    let headings: Vec<&str> = by_tag.lines().filter(|l| l.starts_with("## ")).collect();
+    assert_eq!(headings, ["## graphs", "## streaming", "## Untagged"]);
+    assert_eq!(by_tag.matches("[A](2019/A.md)").count(), 2);
+}
+
+#[test]
+fn test_splice() {
+    let block = format!("{}\nnew\n{}\n", START, END);
+    assert_eq!(splice("", &block), block);
+    assert_eq!(
+        splice("# Reading\n\nprose\n", &block),
+        format!("# Reading\n\nprose\n\n{}", block)
+    );
+    let existing = format!("# Reading\n{}\nold\n{}\nafter\n", START, END);
+    assert_eq!(
+        splice(&existing, &block),
+        format!("# Reading\n{}after\n", block)
+    );
+    assert_eq!(
+        splice(&splice(&existing, &block), &block),
+        splice(&existing, &block)
+    );
+}
@@ -0,0 +1,74 @@
+/// notices of what the requests go through, the retries and the changes
+/// of mirror, for the program using the library to show as it sees fit
+use crate::error::DblpError;
+use crate::mirrors::Failed;
+use std::{fmt, sync::OnceLock, time::Duration};
+
+/// Something worth telling about a request that is still going on
+#[derive(Debug)]
+pub enum Notice<'a> {
+    /// the request failed with the error, and is retried after the delay
+    Retry {
+        error: &'a DblpError,
+        delay: Duration,
+    },
+    /// the request went to the mirror, after another one failed
+    Failover { mirror: &'a str, failed: &'a Failed },
+}
+
+impl fmt::Display for Notice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notice::Retry { error, delay } => {
+                write!(f, "{}, retrying in {:.1}s", error, delay.as_secs_f64())
+            }
+            Notice::Failover { mirror, failed } => write!(
+                f,
+                "using {}, {} failed: {}",
+                mirror, failed.mirror, failed.error
+            ),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&Notice) + Send + Sync>;
+
+static HANDLER: OnceLock<Handler> = OnceLock::new();
+
+/// Passes the notices to `handler`, before the first request. Without one
+/// they are dropped.
+pub fn set_handler(handler: impl Fn(&Notice) + Send + Sync + 'static) {
+    let _ = HANDLER.set(Box::new(handler));
+}
+
+/// Tells the handler, if there is one
+pub fn notify(notice: Notice) {
+    if let Some(handler) = HANDLER.get() {
+        handler(&notice);
+    }
+}
+
+#[test]
+fn test_display() {
+    let error = DblpError::RateLimited { retry_after: None };
+    let retry = Notice::Retry {
+        error: &error,
+        delay: Duration::from_millis(1500),
+    };
+    assert_eq!(retry.to_string(), format!("{}, retrying in 1.5s", error));
+    let failed = Failed {
+        error,
+        mirror: "https://dblp.org".to_owned(),
+    };
+    let failover = Notice::Failover {
+        mirror: "https://dblp.uni-trier.de",
+        failed: &failed,
+    };
+    assert_eq!(
+        failover.to_string(),
+        format!(
+            "using https://dblp.uni-trier.de, https://dblp.org failed: {}",
+            failed.error
+        )
+    );
+}
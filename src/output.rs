@@ -0,0 +1,272 @@
+/// the structures printed as json by the commands, with their schemas. Each
+/// payload names its schema, whose version changes only on purpose.
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// A json output, identified by the name and version of its schema
+pub trait Output: Serialize + JsonSchema {
+    const SCHEMA: &'static str;
+}
+
+#[derive(Serialize, JsonSchema)]
+struct Envelope<'a, T> {
+    schema: &'static str,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+/// The output as json, tagged with its schema
+pub fn to_json<T: Output>(data: &T) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&Envelope {
+        schema: T::SCHEMA,
+        data,
+    })
+}
+
+/// The json schema of the output, tag included
+pub fn schema<T: Output>() -> String {
+    let mut schema =
+        serde_json::to_value(schemars::schema_for!(Envelope<T>)).expect("serializing schema");
+    schema["title"] = T::SCHEMA.into();
+    schema["properties"]["schema"] = serde_json::json!({ "const": T::SCHEMA });
+    serde_json::to_string_pretty(&schema).expect("serializing schema")
+}
+
+/// A reference to an entry of a bibtex file
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct EntryRef {
+    pub key: String,
+    pub location: Option<Location>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl Location {
+    /// The location in the source of the file of the byte at the offset
+    pub fn at(file: &Path, src: &str, offset: usize) -> Self {
+        Location {
+            file: file.to_owned(),
+            line: src[..offset].matches('\n').count() + 1,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FindingKind {
+    VenueInconsistency,
+    JournalVersion,
+    UnknownEntryType,
+    MovedKey,
+    MissingKey,
+}
+
+/// A problem found by a check, about one or more entries
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub entries: Vec<EntryRef>,
+    pub message: String,
+}
+
+#[derive(Serialize, JsonSchema, Default)]
+pub struct CheckReport {
+    pub findings: Vec<Finding>,
+}
+
+impl Output for CheckReport {
+    const SCHEMA: &'static str = "dblp-rs/check/v3";
+}
+
+/// Where the value of a setting comes from
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Provenance {
+    Flag,
+    Env,
+    Config,
+    Detected,
+    Default,
+}
+
+impl std::fmt::Display for Provenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Provenance::Flag => "flag",
+            Provenance::Env => "env",
+            Provenance::Config => "config",
+            Provenance::Detected => "detected",
+            Provenance::Default => "default",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Setting {
+    pub name: &'static str,
+    pub value: String,
+    pub source: Provenance,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct BibStatus {
+    pub path: Option<PathBuf>,
+    pub entries: usize,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct CacheStatus {
+    pub path: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MirrorStatus {
+    pub url: String,
+    pub millis: Option<u128>,
+    pub error: Option<String>,
+}
+
+/// What the failover between mirrors knows about a mirror
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct MirrorHealth {
+    pub url: String,
+    /// in a row
+    pub failures: u32,
+    /// false while skipped after too many failures
+    pub healthy: bool,
+    /// seconds until an unhealthy mirror is tried again
+    pub retry_in_s: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Backend {
+    pub command: String,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DoctorReport {
+    pub version: &'static str,
+    pub git_hash: Option<&'static str>,
+    pub os: &'static str,
+    pub settings: Vec<Setting>,
+    pub bib: BibStatus,
+    pub cache: CacheStatus,
+    /// missing when offline
+    pub mirrors: Option<Vec<MirrorStatus>>,
+    /// as left by the last commands
+    pub mirror_health: Vec<MirrorHealth>,
+    pub clipboard: Vec<Backend>,
+    pub opener: Backend,
+}
+
+impl Output for DoctorReport {
+    const SCHEMA: &'static str = "dblp-rs/doctor/v3";
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+pub struct StatsReport {
+    pub command: String,
+    pub wall_ms: u128,
+    pub requests: usize,
+    pub cache_hits: usize,
+    pub retries: usize,
+    pub bytes: u64,
+    pub phases: Vec<PhaseReport>,
+    pub mirrors: Vec<MirrorHealth>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+pub struct PhaseReport {
+    pub name: &'static str,
+    pub ms: u128,
+}
+
+impl Output for StatsReport {
+    const SCHEMA: &'static str = "dblp-rs/stats/v2";
+}
+
+/// A hit of a search of DBLP
+#[derive(Serialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct Hit {
+    /// The key of DBLP, like `conf/soda/Chan16`
+    pub key: String,
+    /// The key to cite it with, like `DBLP:conf/soda/Chan16`
+    pub cite_key: String,
+    pub title: String,
+    pub authors: Vec<String>,
+    /// Of edited volumes
+    pub editors: Vec<String>,
+    /// Empty for theses and books
+    pub venue: Vec<String>,
+    pub publisher: Option<String>,
+    pub school: Option<String>,
+    pub year: String,
+    pub url: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SearchReport {
+    pub query: String,
+    pub hits: Vec<Hit>,
+}
+
+impl Output for SearchReport {
+    const SCHEMA: &'static str = "dblp-rs/search/v2";
+}
+
+#[test]
+fn test_schemas() {
+    // a failure here means the json of a command changed: if on purpose,
+    // bump the version of its schema and update the snapshot
+    for (schema, snapshot) in [
+        (
+            schema::<CheckReport>(),
+            include_str!("../schemas/check.v3.json"),
+        ),
+        (
+            schema::<DoctorReport>(),
+            include_str!("../schemas/doctor.v3.json"),
+        ),
+        (
+            schema::<StatsReport>(),
+            include_str!("../schemas/stats.v2.json"),
+        ),
+        (
+            schema::<SearchReport>(),
+            include_str!("../schemas/search.v2.json"),
+        ),
+    ] {
+        assert_eq!(schema.trim(), snapshot.trim());
+    }
+}
+
+#[test]
+fn test_to_json() {
+    let report = CheckReport {
+        findings: vec![Finding {
+            kind: FindingKind::JournalVersion,
+            entries: vec![EntryRef {
+                key: "DBLP:conf/soda/Chan16".into(),
+                location: Some(Location {
+                    file: "refs.bib".into(),
+                    line: 3,
+                }),
+            }],
+            message: "DBLP:journals/talg/Chan18 exists".into(),
+        }],
+    };
+    let json: serde_json::Value = serde_json::from_str(&to_json(&report).unwrap()).unwrap();
+    assert_eq!(json["schema"], "dblp-rs/check/v3");
+    assert_eq!(json["findings"][0]["kind"], "journal-version");
+    assert_eq!(json["findings"][0]["entries"][0]["location"]["line"], 3);
+}
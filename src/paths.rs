@@ -0,0 +1,583 @@
+/// paths of the bibtex file and of the files derived from it
+use crate::error::{BibFileKind, DblpError, Result};
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+/// Appends the suffix to the file name, rather than replacing its extension
+pub fn with_suffix<P: AsRef<Path>>(path: P, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.as_ref().as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+pub fn backup_path<P: AsRef<Path>>(bib: P) -> PathBuf {
+    with_suffix(bib, ".bak")
+}
+
+/// Where to save the backup of the bib file made `secs` after the epoch:
+/// next to it with a `.bak` suffix or, given a directory, in there with
+/// the time in the name, like `refs-20240103-141502.bib`, so that
+/// successive backups do not overwrite each other
+pub fn backup_path_in<P: AsRef<Path>>(bib: P, dir: Option<&Path>, secs: u64) -> PathBuf {
+    let bib = bib.as_ref();
+    let Some(dir) = dir else {
+        return backup_path(bib);
+    };
+    let stem = bib.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}-{}", stem, timestamp(secs));
+    if let Some(ext) = bib.extension() {
+        name.push_str(&format!(".{}", ext.to_string_lossy()));
+    }
+    dir.join(name)
+}
+
+/// The latest backup of the bib file in the directory, among the ones
+/// named by `backup_path_in`
+pub fn latest_backup_in<P: AsRef<Path>>(bib: P, dir: &Path) -> Result<Option<PathBuf>> {
+    let bib = bib.as_ref();
+    let stem = format!("{}-", bib.file_stem().unwrap_or_default().to_string_lossy());
+    let ext = bib
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let is_backup = |name: &str| {
+        name.strip_prefix(&stem)
+            .and_then(|rest| rest.strip_suffix(&ext))
+            .is_some_and(|time| {
+                time.len() == 15
+                    && time
+                        .char_indices()
+                        .all(|(i, c)| if i == 8 { c == '-' } else { c.is_ascii_digit() })
+            })
+    };
+    let names = std::fs::read_dir(dir).map_err(|e| DblpError::BibFile {
+        kind: BibFileKind::Inaccessible(e.kind()),
+        path: dir.to_owned(),
+    })?;
+    // the names differ only by the time, which sorts like the names do
+    Ok(names
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| is_backup(name))
+        .max()
+        .map(|name| dir.join(name)))
+}
+
+/// The time, in UTC, as `20240103-141502`
+fn timestamp(secs: u64) -> String {
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // the civil date of the day, counting eras of 400 years from 0000-03-01
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Fails if the backup cannot be written in its directory, to find out
+/// before touching the bib file
+pub fn check_backup_path<P: AsRef<Path>>(backup: P) -> Result<()> {
+    let backup = backup.as_ref();
+    let error = |kind| DblpError::BibFile {
+        kind,
+        path: backup.to_owned(),
+    };
+    let dir = match backup.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match std::fs::metadata(dir) {
+        Ok(meta) if meta.permissions().readonly() => Err(error(BibFileKind::Inaccessible(
+            std::io::ErrorKind::PermissionDenied,
+        ))),
+        Ok(meta) if meta.is_dir() => Ok(()),
+        _ => Err(error(BibFileKind::MissingDirectory)),
+    }
+}
+
+/// Whether the path has a `bib` extension, in any case
+pub fn is_bib<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bib"))
+}
+
+/// The bibtex files in the directory, by name
+pub fn find_bibs<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir.as_ref())
+        .map_err(|e| DblpError::BibFile {
+            kind: BibFileKind::Inaccessible(e.kind()),
+            path: dir.as_ref().to_owned(),
+        })?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|p| is_bib(p) && p.is_file())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// The directory of the bibtex files to use, with its bibtex files: the
+/// closest of the directory and its ancestors up to the root of the
+/// workspace that has some or, if none has, the first directory of
+/// `bibinputs`, the search path of BibTeX, that has some
+pub fn locate_bibs<P: AsRef<Path>>(
+    dir: P,
+    bibinputs: Option<&OsStr>,
+) -> Result<Option<(PathBuf, Vec<PathBuf>)>> {
+    let dir = dir.as_ref();
+    let bibs = find_bibs(dir)?;
+    if !bibs.is_empty() {
+        return Ok(Some((dir.to_owned(), bibs)));
+    }
+    // the ancestors are reached through `..`, to show the paths relative
+    // to the directory
+    let mut up = if dir == Path::new(".") {
+        PathBuf::new()
+    } else {
+        dir.to_owned()
+    };
+    let real = std::fs::canonicalize(dir).unwrap_or_default();
+    for ancestor in real.ancestors() {
+        if is_workspace_root(ancestor) || ancestor.parent().is_none() {
+            break;
+        }
+        up.push("..");
+        // an ancestor that cannot be read has no file to use
+        let bibs = find_bibs(&up).unwrap_or_default();
+        if !bibs.is_empty() {
+            return Ok(Some((up, bibs)));
+        }
+    }
+    let search = bibinputs.map(std::env::split_paths).into_iter().flatten();
+    // an empty directory stands for the default path of BibTeX
+    for dir in search.filter(|dir| !dir.as_os_str().is_empty()) {
+        let bibs = find_bibs(&dir).unwrap_or_default();
+        if !bibs.is_empty() {
+            return Ok(Some((dir, bibs)));
+        }
+    }
+    Ok(None)
+}
+
+/// The file, in the directory, remembering which of its bibtex files
+/// entries are added to
+pub const TARGET_FILE: &str = ".dblp-target";
+
+/// The bibtex file that entries were last added to, as remembered in the
+/// directory, relative to it
+pub fn remembered_target<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    let text = std::fs::read_to_string(dir.as_ref().join(TARGET_FILE)).ok()?;
+    Some(text.trim_end_matches(['\r', '\n']))
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Remembers in the directory that entries are added to the bibtex file,
+/// relative to it if it is inside
+pub fn remember_target<P: AsRef<Path>>(dir: P, bib: &Path) -> std::io::Result<()> {
+    let dir = dir.as_ref();
+    let bib = bib.strip_prefix(dir).unwrap_or(bib);
+    std::fs::write(dir.join(TARGET_FILE), format!("{}\n", bib.display()))
+}
+
+/// Fails if the path cannot be a bibtex file. A path that does not exist
+/// yet is fine, since commands adding entries create it, as long as its
+/// directory exists.
+pub fn check_bib_path<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let error = |kind| DblpError::BibFile {
+        kind,
+        path: path.to_owned(),
+    };
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => Err(error(BibFileKind::Directory)),
+        Ok(_) => Ok(()),
+        Err(err)
+            if err.kind() == std::io::ErrorKind::NotFound && path.symlink_metadata().is_err() =>
+        {
+            match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                    Err(error(BibFileKind::MissingDirectory))
+                }
+                _ => Ok(()),
+            }
+        }
+        Err(err) => Err(error(BibFileKind::Inaccessible(err.kind()))),
+    }
+}
+
+/// The root of the workspace containing the directory: its closest
+/// ancestor that is a git repository or has a `.dblp.toml`
+pub fn workspace_root<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    let dir = std::fs::canonicalize(dir).ok()?;
+    dir.ancestors()
+        .find(|d| is_workspace_root(d))
+        .map(Path::to_path_buf)
+}
+
+fn is_workspace_root(dir: &Path) -> bool {
+    dir.join(".git").exists() || dir.join(crate::config::WORKSPACE_CONFIG).is_file()
+}
+
+/// The root of the workspace of `cwd`, if the bib file is outside of it.
+/// Outside of any workspace there is nothing to compare with.
+pub fn outside_workspace<P: AsRef<Path>>(bib: P, cwd: &Path) -> Option<PathBuf> {
+    let root = workspace_root(cwd)?;
+    let bib = cwd.join(bib);
+    // the file may not exist yet, its directory does
+    let dir = std::fs::canonicalize(bib.parent().unwrap_or(cwd)).ok()?;
+    (!dir.starts_with(&root)).then_some(root)
+}
+
+/// The path, with the home directory shown as `~`
+pub fn display_home<P: AsRef<Path>>(path: P) -> String {
+    let path = path.as_ref();
+    match std::env::var_os("HOME").and_then(|home| path.strip_prefix(home).ok().map(Path::to_owned))
+    {
+        Some(rest) => Path::new("~").join(rest).display().to_string(),
+        None => path.display().to_string(),
+    }
+}
+
+/// The file a symlink points to, or the path itself if it is not a symlink.
+/// Writing to the real file keeps the symlink in place, while replacing
+/// the path would turn it into a regular file.
+pub fn real_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    if !path.is_symlink() {
+        return path.to_owned();
+    }
+    std::fs::canonicalize(path).unwrap_or_else(|_| {
+        // a dangling link, pointing to a file yet to be created
+        let target = std::fs::read_link(path).unwrap_or_default();
+        path.parent().unwrap_or(Path::new("")).join(target)
+    })
+}
+
+/// Resolves a bib path that is a symlink, failing instead if asked not to
+/// follow symlinks
+pub fn resolve_bib_path<P: AsRef<Path>>(path: P, follow_symlinks: bool) -> Result<PathBuf> {
+    let path = path.as_ref();
+    if path.is_symlink() && !follow_symlinks {
+        return Err(DblpError::BibFile {
+            kind: BibFileKind::Symlink,
+            path: path.to_owned(),
+        });
+    }
+    Ok(real_path(path))
+}
+
+#[test]
+fn test_derived_paths() {
+    for (bib, backup) in [
+        ("refs.bib", "refs.bib.bak"),
+        ("my.refs.bib", "my.refs.bib.bak"),
+        ("refs.v2.bib", "refs.v2.bib.bak"),
+        ("refs", "refs.bak"),
+        ("REFS.BIB", "REFS.BIB.bak"),
+        ("my refs.bib", "my refs.bib.bak"),
+        ("références.bib", "références.bib.bak"),
+        ("paper/refs.bib", "paper/refs.bib.bak"),
+    ] {
+        assert_eq!(backup_path(bib), PathBuf::from(backup), "{}", bib);
+    }
+}
+
+#[test]
+fn test_backup_path_in() {
+    // 2024-01-03 14:15:02 UTC
+    let secs = 1704291302;
+    let dir = Path::new("/backups");
+    for (bib, backup) in [
+        ("refs.bib", "/backups/refs-20240103-141502.bib"),
+        ("paper/my.refs.bib", "/backups/my.refs-20240103-141502.bib"),
+        ("refs", "/backups/refs-20240103-141502"),
+        ("REFS.BIB", "/backups/REFS-20240103-141502.BIB"),
+    ] {
+        assert_eq!(
+            backup_path_in(bib, Some(dir), secs),
+            PathBuf::from(backup),
+            "{}",
+            bib
+        );
+    }
+    assert_eq!(
+        backup_path_in("refs.bib", None, secs),
+        PathBuf::from("refs.bib.bak")
+    );
+    assert_eq!(timestamp(0), "19700101-000000");
+    assert_eq!(timestamp(951782400 + 86399), "20000229-235959");
+}
+
+#[test]
+fn test_latest_backup_in() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-latest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(latest_backup_in("refs.bib", &dir).unwrap(), None);
+    for name in [
+        "refs-20240103-141502.bib",
+        "refs-20240105-090000.bib",
+        "refs-20231231-235959.bib",
+        "other-20250101-000000.bib",
+        "refs-20250101-000000.bib.bak",
+        "refs-latest.bib",
+    ] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+    assert_eq!(
+        latest_backup_in("paper/refs.bib", &dir).unwrap(),
+        Some(dir.join("refs-20240105-090000.bib"))
+    );
+    assert_eq!(latest_backup_in("refs", &dir).unwrap(), None);
+    assert!(latest_backup_in("refs.bib", &dir.join("missing")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_check_backup_path() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-backup-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(check_backup_path(dir.join("refs.bib.bak")).is_ok());
+    assert!(check_backup_path("refs.bib.bak").is_ok());
+    assert!(matches!(
+        check_backup_path(dir.join("missing/refs.bib")),
+        Err(DblpError::BibFile {
+            kind: BibFileKind::MissingDirectory,
+            ..
+        })
+    ));
+    let mut permissions = std::fs::metadata(&dir).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&dir, permissions.clone()).unwrap();
+    let res = check_backup_path(dir.join("refs.bib.bak"));
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(&dir, permissions).unwrap();
+    assert!(matches!(
+        res,
+        Err(DblpError::BibFile {
+            kind: BibFileKind::Inaccessible(std::io::ErrorKind::PermissionDenied),
+            ..
+        })
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_is_bib() {
+    for (path, expected) in [
+        ("refs.bib", true),
+        ("REFS.BIB", true),
+        ("refs.Bib", true),
+        ("my refs.bib", true),
+        ("références.bib", true),
+        ("refs.bib.bak", false),
+        ("refs", false),
+        ("bib", false),
+        (".bib", false),
+    ] {
+        assert_eq!(is_bib(path), expected, "{}", path);
+    }
+}
+
+#[test]
+fn test_check_bib_path() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-paths-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(matches!(
+        check_bib_path(&dir),
+        Err(DblpError::BibFile {
+            kind: BibFileKind::Directory,
+            ..
+        })
+    ));
+    assert!(check_bib_path(dir.join("new.bib")).is_ok());
+    assert!(check_bib_path("new.bib").is_ok());
+    assert!(matches!(
+        check_bib_path(dir.join("missing/new.bib")),
+        Err(DblpError::BibFile {
+            kind: BibFileKind::MissingDirectory,
+            ..
+        })
+    ));
+    std::fs::write(dir.join("REFS.BIB"), "").unwrap();
+    assert!(check_bib_path(dir.join("REFS.BIB")).is_ok());
+    assert_eq!(find_bibs(&dir).unwrap(), [dir.join("REFS.BIB")]);
+    std::fs::write(dir.join("a.bib"), "").unwrap();
+    assert_eq!(
+        find_bibs(&dir).unwrap(),
+        [dir.join("REFS.BIB"), dir.join("a.bib")]
+    );
+    assert_eq!(remembered_target(&dir), None);
+    remember_target(&dir, &dir.join("a.bib")).unwrap();
+    assert_eq!(remembered_target(&dir), Some(PathBuf::from("a.bib")));
+    std::fs::remove_file(dir.join("a.bib")).unwrap();
+    #[cfg(unix)]
+    {
+        let looping = dir.join("loop.bib");
+        std::os::unix::fs::symlink(&looping, &looping).unwrap();
+        assert!(matches!(
+            check_bib_path(&looping),
+            Err(DblpError::BibFile {
+                kind: BibFileKind::Inaccessible(_),
+                ..
+            })
+        ));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlinked_bib() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-symlink-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("shared")).unwrap();
+    std::fs::create_dir_all(dir.join("paper")).unwrap();
+    let real = dir.join("shared/master.bib");
+    let link = dir.join("paper/references.bib");
+    std::fs::write(&real, "@misc{a, title = {A}}\n").unwrap();
+    std::os::unix::fs::symlink("../shared/master.bib", &link).unwrap();
+
+    let resolved = resolve_bib_path(&link, true).unwrap();
+    assert_eq!(resolved, std::fs::canonicalize(&real).unwrap());
+    assert_eq!(
+        backup_path(&resolved),
+        std::fs::canonicalize(dir.join("shared"))
+            .unwrap()
+            .join("master.bib.bak")
+    );
+    assert!(matches!(
+        resolve_bib_path(&link, false),
+        Err(DblpError::BibFile {
+            kind: BibFileKind::Symlink,
+            ..
+        })
+    ));
+
+    // writing through the link replaces the shared file, not the link
+    crate::scratch::write_atomically(&link, |f| Ok(writeln!(f, "@misc{{b, title = {{B}}}}")?))
+        .unwrap();
+    assert!(link.is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(&real).unwrap(),
+        "@misc{b, title = {B}}\n"
+    );
+
+    let dangling = dir.join("paper/new.bib");
+    std::os::unix::fs::symlink("../shared/new.bib", &dangling).unwrap();
+    assert_eq!(real_path(&dangling), dir.join("paper/../shared/new.bib"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_outside_workspace() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-workspace-{}", std::process::id()));
+    let dir = {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(&dir).unwrap()
+    };
+    let (new, old, plain) = (
+        dir.join("new-paper"),
+        dir.join("old-paper"),
+        dir.join("plain"),
+    );
+    for d in [
+        new.join(".git"),
+        new.join("sections"),
+        old.join(".git"),
+        plain.clone(),
+    ] {
+        std::fs::create_dir_all(d).unwrap();
+    }
+    std::fs::create_dir_all(dir.join("project/sub")).unwrap();
+    std::fs::write(dir.join("project/.dblp.toml"), "").unwrap();
+
+    assert_eq!(workspace_root(new.join("sections")), Some(new.clone()));
+    assert_eq!(
+        workspace_root(dir.join("project/sub")),
+        Some(dir.join("project"))
+    );
+    for (bib, cwd, expected) in [
+        // inside
+        (new.join("refs.bib"), &new, None),
+        (PathBuf::from("refs.bib"), &new, None),
+        (PathBuf::from("../refs.bib"), &new.join("sections"), None),
+        (new.join("sections/new.bib"), &new, None),
+        (dir.join("project/refs.bib"), &dir.join("project/sub"), None),
+        // outside
+        (old.join("refs.bib"), &new, Some(new.clone())),
+        (
+            PathBuf::from("../old-paper/refs.bib"),
+            &new,
+            Some(new.clone()),
+        ),
+        (
+            plain.join("refs.bib"),
+            &dir.join("project/sub"),
+            Some(dir.join("project")),
+        ),
+        // no workspace
+        (old.join("refs.bib"), &plain, None),
+        (PathBuf::from("refs.bib"), &plain, None),
+    ] {
+        assert_eq!(
+            outside_workspace(&bib, cwd),
+            expected,
+            "{:?} from {:?}",
+            bib,
+            cwd
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_locate_bibs() {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-locate-{}", std::process::id()));
+    let (repo, sections) = (dir.join("repo"), dir.join("repo/sections"));
+    std::fs::create_dir_all(repo.join(".git")).unwrap();
+    std::fs::create_dir_all(&sections).unwrap();
+    std::fs::write(dir.join("other.bib"), "").unwrap();
+
+    // not past the root of the repository
+    assert_eq!(locate_bibs(&sections, None).unwrap(), None);
+    let bibinputs = std::env::join_paths([Path::new(""), &dir.join("none"), &dir]).unwrap();
+    assert_eq!(
+        locate_bibs(&sections, Some(&bibinputs)).unwrap(),
+        Some((dir.clone(), vec![dir.join("other.bib")]))
+    );
+
+    // the closest first
+    std::fs::write(repo.join("refs.bib"), "").unwrap();
+    let up = sections.join("..");
+    assert_eq!(
+        locate_bibs(&sections, Some(&bibinputs)).unwrap(),
+        Some((up.clone(), vec![up.join("refs.bib")]))
+    );
+    std::fs::write(sections.join("b.bib"), "").unwrap();
+    std::fs::write(sections.join("a.bib"), "").unwrap();
+    assert_eq!(
+        locate_bibs(&sections, None).unwrap(),
+        Some((
+            sections.clone(),
+            vec![sections.join("a.bib"), sections.join("b.bib")]
+        ))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,136 @@
+/// utilities to decide whether the fuzzy picker can run, and to pick from
+/// a numbered list where it cannot
+use std::io::IsTerminal;
+
+/// What the fuzzy picker needs from the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    pub term: Option<String>,
+    /// Set by the shells of Emacs, where skim renders garbage
+    pub inside_emacs: bool,
+    /// Whether the controlling terminal can be opened, skim reads keys from it
+    pub tty: bool,
+}
+
+impl Environment {
+    pub fn current() -> Self {
+        Self {
+            term: std::env::var("TERM").ok(),
+            inside_emacs: std::env::var_os("INSIDE_EMACS").is_some(),
+            tty: std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open("/dev/tty")
+                .is_ok()
+                && std::io::stderr().is_terminal(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Picker {
+    Fuzzy,
+    /// The numbered list, for the given reason
+    List(&'static str),
+}
+
+/// Which picker to use, avoiding skim where it is known not to work
+pub fn decide(env: &Environment) -> Picker {
+    match env.term.as_deref() {
+        _ if !env.tty => Picker::List("there is no terminal"),
+        None | Some("") => Picker::List("TERM is not set"),
+        Some("dumb") => Picker::List("the terminal is dumb"),
+        _ if env.inside_emacs => Picker::List("running inside Emacs"),
+        _ => Picker::Fuzzy,
+    }
+}
+
+/// An answer to the numbered list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Choice {
+    Pick(Vec<usize>),
+    /// Open the item in the browser, and ask again
+    Open(usize),
+    Abort,
+    Invalid,
+}
+
+pub fn list_prompt(multi: bool) -> &'static str {
+    if multi {
+        "numbers to select, separated by spaces, o and a number to open, q to abort"
+    } else {
+        "number to select, o and a number to open, q to abort"
+    }
+}
+
+/// Parses an answer for a list of `len` items, numbered from 1. Only with
+/// `multi` can more than one item be picked.
+pub fn parse_choice(answer: &str, len: usize, multi: bool) -> Choice {
+    let answer = answer.trim();
+    if answer == "q" {
+        return Choice::Abort;
+    }
+    let number = |s: &str| s.parse::<usize>().ok().filter(|n| (1..=len).contains(n));
+    if let Some(rest) = answer.strip_prefix('o') {
+        return number(rest.trim()).map_or(Choice::Invalid, |n| Choice::Open(n - 1));
+    }
+    let picked: Option<Vec<usize>> = answer
+        .split_whitespace()
+        .map(|s| number(s).map(|n| n - 1))
+        .collect();
+    match picked {
+        Some(picked) if picked.len() == 1 || (multi && !picked.is_empty()) => Choice::Pick(picked),
+        _ => Choice::Invalid,
+    }
+}
+
+#[test]
+fn test_decide() {
+    let env = |term: Option<&str>, inside_emacs, tty| Environment {
+        term: term.map(str::to_owned),
+        inside_emacs,
+        tty,
+    };
+    let cases = [
+        (env(Some("xterm-256color"), false, true), Picker::Fuzzy),
+        (env(Some("screen"), false, true), Picker::Fuzzy),
+        (
+            env(Some("xterm-256color"), false, false),
+            Picker::List("there is no terminal"),
+        ),
+        (env(None, false, true), Picker::List("TERM is not set")),
+        (env(Some(""), false, true), Picker::List("TERM is not set")),
+        (
+            env(Some("dumb"), false, true),
+            Picker::List("the terminal is dumb"),
+        ),
+        (
+            env(Some("dumb"), true, false),
+            Picker::List("there is no terminal"),
+        ),
+        (
+            env(Some("eterm-color"), true, true),
+            Picker::List("running inside Emacs"),
+        ),
+    ];
+    for (env, expected) in cases {
+        assert_eq!(decide(&env), expected, "{:?}", env);
+    }
+}
+
+#[test]
+fn test_parse_choice() {
+    assert_eq!(parse_choice("2\n", 3, false), Choice::Pick(vec![1]));
+    assert_eq!(parse_choice(" 3 ", 3, false), Choice::Pick(vec![2]));
+    assert_eq!(parse_choice("o1", 3, false), Choice::Open(0));
+    assert_eq!(parse_choice("o 2", 3, true), Choice::Open(1));
+    assert_eq!(parse_choice("q", 3, false), Choice::Abort);
+    assert_eq!(parse_choice("0", 3, false), Choice::Invalid);
+    assert_eq!(parse_choice("4", 3, false), Choice::Invalid);
+    assert_eq!(parse_choice("o", 3, false), Choice::Invalid);
+    assert_eq!(parse_choice("", 3, false), Choice::Invalid);
+    assert_eq!(parse_choice("1 3", 3, true), Choice::Pick(vec![0, 2]));
+    assert_eq!(parse_choice("1 3", 3, false), Choice::Invalid);
+    assert_eq!(parse_choice("1 4", 3, true), Choice::Invalid);
+    assert_eq!(parse_choice("", 3, true), Choice::Invalid);
+}
@@ -0,0 +1,198 @@
+/// progress of bulk operations, with an estimate of the remaining time
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// When not on a terminal, log a line every this many items...
+const LOG_EVERY: usize = 25;
+/// ...or after this long since the last line
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The time elapsed since some fixed instant
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock(pub Instant);
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+}
+
+/// Counts the items done by any number of workers, and reports on standard
+/// error: on a single updating line on a terminal, and with periodic log
+/// lines otherwise, so that standard output is left to the results.
+pub struct Progress<C: Clock = SystemClock> {
+    total: usize,
+    done: AtomicUsize,
+    /// the minimum time between requests, of the rate limiter
+    delay: Duration,
+    clock: C,
+    start: Duration,
+    /// milliseconds, according to the clock, of the last line logged
+    last_log: AtomicU64,
+    tty: bool,
+    out: Mutex<()>,
+}
+
+impl Progress {
+    pub fn new(total: usize, delay: Duration) -> Self {
+        use std::io::IsTerminal;
+        Self::with_clock(
+            total,
+            delay,
+            SystemClock(Instant::now()),
+            std::io::stderr().is_terminal(),
+        )
+    }
+}
+
+impl<C: Clock> Progress<C> {
+    pub fn with_clock(total: usize, delay: Duration, clock: C, tty: bool) -> Self {
+        let start = clock.now();
+        Self {
+            total,
+            done: AtomicUsize::new(0),
+            delay,
+            start,
+            last_log: AtomicU64::new(start.as_millis() as u64),
+            clock,
+            tty,
+            out: Mutex::new(()),
+        }
+    }
+
+    /// Items per second done so far
+    pub fn rate(&self) -> f64 {
+        let elapsed = (self.clock.now() - self.start).as_secs_f64();
+        let done = self.done.load(Ordering::Relaxed);
+        if elapsed > 0.0 {
+            done as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// The remaining items at the observed pace, which is never faster
+    /// than the rate limiter allows
+    pub fn eta(&self) -> Duration {
+        let done = self.done.load(Ordering::Relaxed);
+        let remaining = self.total.saturating_sub(done) as u32;
+        let elapsed = self.clock.now() - self.start;
+        let per_item = if done > 0 {
+            (elapsed / done as u32).max(self.delay)
+        } else {
+            self.delay
+        };
+        per_item * remaining
+    }
+
+    /// Marks an item as done, reporting it if it is time to
+    pub fn tick(&self, key: &str) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.tty {
+            self.print(&format!("\r\x1b[K{}", self.line(done, key)));
+            if done == self.total {
+                self.print("\n");
+            }
+            return;
+        }
+        let now = self.clock.now().as_millis() as u64;
+        let last = self.last_log.load(Ordering::Relaxed);
+        let due = done.is_multiple_of(LOG_EVERY)
+            || done == self.total
+            || now.saturating_sub(last) >= LOG_INTERVAL.as_millis() as u64;
+        if due
+            && self
+                .last_log
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.print(&format!("{}\n", self.line(done, key)));
+        }
+    }
+
+    /// Prints a message on its own line, without garbling the progress line
+    pub fn message(&self, msg: &str) {
+        if self.tty {
+            self.print(&format!("\r\x1b[K{}\n", msg));
+        } else {
+            self.print(&format!("{}\n", msg));
+        }
+    }
+
+    fn line(&self, done: usize, key: &str) -> String {
+        format!(
+            "[{}/{}] {}  {:.1} req/s (limit {:.1})  ETA {}",
+            done,
+            self.total,
+            key,
+            self.rate(),
+            1.0 / self.delay.as_secs_f64(),
+            format_duration(self.eta())
+        )
+    }
+
+    fn print(&self, s: &str) {
+        let _guard = self.out.lock();
+        let mut err = std::io::stderr();
+        let _ = write!(err, "{}", s);
+        let _ = err.flush();
+    }
+}
+
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        s if s >= 86400 => format!("{}d{:02}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// A clock that only moves when told to, in milliseconds
+#[cfg(test)]
+pub struct FakeClock(pub AtomicU64);
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[test]
+fn test_eta() {
+    let progress = Progress::with_clock(
+        10,
+        Duration::from_millis(500),
+        FakeClock(AtomicU64::new(1000)),
+        false,
+    );
+    // nothing done yet, the limiter gives the pace
+    assert_eq!(progress.eta(), Duration::from_secs(5));
+    // faster than the limiter allows, the limiter still gives the pace
+    progress.tick("a");
+    progress.tick("b");
+    progress.clock.0.store(1100, Ordering::Relaxed);
+    assert_eq!(progress.eta(), Duration::from_secs(4));
+    // slower than the limiter, the observed pace counts
+    progress.clock.0.store(5000, Ordering::Relaxed);
+    assert_eq!(progress.eta(), Duration::from_secs(16));
+    assert_eq!(progress.rate(), 0.5);
+    for _ in 0..8 {
+        progress.tick("c");
+    }
+    assert_eq!(progress.eta(), Duration::ZERO);
+    assert_eq!(format_duration(Duration::from_secs(170)), "2m50s");
+    assert_eq!(format_duration(Duration::from_secs(3700)), "1h01m");
+    assert_eq!(format_duration(Duration::from_secs(3 * 86400)), "3d00h");
+}
@@ -0,0 +1,198 @@
+/// utilities to retry the requests that DBLP turns down for the moment,
+/// because of its rate limit or of an error of the server
+use crate::config::Config;
+use crate::error::{DblpError, Result};
+use crate::notices::{notify, Notice};
+use crate::stats::STATS;
+use std::{sync::LazyLock, time::Duration};
+
+/// Retries after a rate limit, by default
+pub const RATE_LIMIT_RETRIES: u32 = 5;
+/// Retries after an error of the server, which is less likely to go away
+pub const SERVER_ERROR_RETRIES: u32 = 2;
+/// The wait before the first retry, doubling at each of the next ones
+pub const BACKOFF: Duration = Duration::from_secs(1);
+/// The longest wait, whatever DBLP asks
+pub const MAX_WAIT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub rate_limit_retries: u32,
+    pub server_error_retries: u32,
+    pub backoff: Duration,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            rate_limit_retries: RATE_LIMIT_RETRIES,
+            server_error_retries: SERVER_ERROR_RETRIES,
+            backoff: BACKOFF,
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Self {
+        let default = Self::default();
+        Self {
+            rate_limit_retries: config.retries.unwrap_or(default.rate_limit_retries),
+            server_error_retries: config.retries.map_or(default.server_error_retries, |r| {
+                r.min(default.server_error_retries)
+            }),
+            backoff: config
+                .retry_backoff_ms
+                .map_or(default.backoff, Duration::from_millis),
+        }
+    }
+}
+
+/// The policy of the config, or the default one if it cannot be read
+pub static POLICY: LazyLock<Policy> = LazyLock::new(|| {
+    crate::config::load()
+        .map(|config| Policy::from_config(&config))
+        .unwrap_or_default()
+});
+
+/// Makes the request until it succeeds, fails for good, or runs out of
+/// retries, waiting with `wait` in between. Errors other than rate limits
+/// and errors of the server fail at once.
+pub fn with_retries<T>(
+    policy: &Policy,
+    mut request: impl FnMut() -> Result<T>,
+    mut wait: impl FnMut(Duration),
+) -> Result<T> {
+    let mut retries = Retries::new(policy);
+    loop {
+        match request() {
+            Ok(res) => return Ok(res),
+            Err(err) => wait(retries.after(err)?),
+        }
+    }
+}
+
+/// The retries of a request so far, which tell how long to wait after each
+/// failure, whether the request blocks or not
+pub struct Retries<'a> {
+    policy: &'a Policy,
+    rate_limited: u32,
+    server_errors: u32,
+}
+
+impl<'a> Retries<'a> {
+    pub fn new(policy: &'a Policy) -> Self {
+        Self {
+            policy,
+            rate_limited: 0,
+            server_errors: 0,
+        }
+    }
+
+    /// How long to wait before retrying after the error, or the error if
+    /// the request is not to be retried
+    pub fn after(&mut self, err: DblpError) -> Result<Duration> {
+        let policy = self.policy;
+        let backoff = |n: u32| policy.backoff.saturating_mul(2u32.saturating_pow(n));
+        let delay = match &err {
+            DblpError::RateLimited { retry_after }
+                if self.rate_limited < policy.rate_limit_retries =>
+            {
+                self.rate_limited += 1;
+                retry_after.unwrap_or(backoff(self.rate_limited - 1))
+            }
+            err if err.http_status().is_some_and(|s| (500..600).contains(&s))
+                && self.server_errors < policy.server_error_retries =>
+            {
+                self.server_errors += 1;
+                backoff(self.server_errors - 1)
+            }
+            _ => return Err(err),
+        };
+        let delay = delay.min(MAX_WAIT);
+        notify(Notice::Retry { error: &err, delay });
+        STATS.retry();
+        Ok(delay)
+    }
+}
+
+#[cfg(test)]
+fn scripted(policy: &Policy, errors: Vec<DblpError>) -> (Result<usize>, Vec<Duration>) {
+    let mut errors = errors.into_iter();
+    let mut attempts = 0;
+    let mut waits = Vec::new();
+    let res = with_retries(
+        policy,
+        || {
+            attempts += 1;
+            errors.next().map_or(Ok(attempts), Err)
+        },
+        |d| waits.push(d),
+    );
+    (res, waits)
+}
+
+#[cfg(test)]
+fn status(code: u16) -> DblpError {
+    let text = format!("HTTP/1.1 {} Status\r\n\r\n", code);
+    DblpError::Transport(Box::new(ureq::Error::Status(
+        code,
+        text.parse::<ureq::Response>().unwrap(),
+    )))
+}
+
+#[test]
+fn test_with_retries() {
+    let policy = Policy::default();
+    let limited = |secs: Option<u64>| DblpError::RateLimited {
+        retry_after: secs.map(Duration::from_secs),
+    };
+    let secs = Duration::from_secs;
+
+    // the wait DBLP asks for, or a growing one
+    let (res, waits) = scripted(
+        &policy,
+        vec![limited(Some(30)), limited(None), limited(None)],
+    );
+    assert_eq!(res.unwrap(), 4);
+    assert_eq!(waits, [secs(30), secs(2), secs(4)]);
+
+    // until the retries run out
+    let (res, waits) = scripted(&policy, (0..10).map(|_| limited(None)).collect());
+    assert_eq!(res.unwrap_err().error_code(), "rate_limited");
+    assert_eq!(waits.len(), RATE_LIMIT_RETRIES as usize);
+    let (_, waits) = scripted(&policy, vec![limited(Some(3600))]);
+    assert_eq!(waits, [MAX_WAIT]);
+
+    // errors of the server are retried less, others not at all
+    let (res, waits) = scripted(&policy, vec![status(503), status(500), status(502)]);
+    assert_eq!(res.unwrap_err().http_status(), Some(502));
+    assert_eq!(waits, [secs(1), secs(2)]);
+    let (res, waits) = scripted(&policy, vec![status(503), status(500)]);
+    assert_eq!(res.unwrap(), 3);
+    assert_eq!(waits.len(), 2);
+    let (res, waits) = scripted(
+        &policy,
+        vec![DblpError::NotFound {
+            key: "conf/x/Y".into(),
+        }],
+    );
+    assert_eq!(res.unwrap_err().error_code(), "not_found");
+    assert!(waits.is_empty());
+    let (res, waits) = scripted(&policy, vec![status(403)]);
+    assert!(res.is_err());
+    assert!(waits.is_empty());
+}
+
+#[test]
+fn test_from_config() {
+    let config: Config = toml::from_str("retries = 1\nretry_backoff_ms = 10").unwrap();
+    assert_eq!(
+        Policy::from_config(&config),
+        Policy {
+            rate_limit_retries: 1,
+            server_error_retries: 1,
+            backoff: Duration::from_millis(10),
+        }
+    );
+    assert_eq!(Policy::from_config(&Config::default()), Policy::default());
+}
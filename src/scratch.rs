@@ -0,0 +1,327 @@
+/// scratch files that are removed when the operation using them fails
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+/// Scratch files older than this are considered left over by crashed runs
+pub const ORPHAN_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Environment variable overriding the directory of the tool's data
+pub const DATA_DIR_VAR: &str = "DBLP_RS_DATA_DIR";
+
+/// The directory where the tool keeps its own data
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os(DATA_DIR_VAR) {
+        return PathBuf::from(dir);
+    }
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("dblp-rs")
+}
+
+/// The directory of the files that can be deleted at any time, inside the
+/// data directory if that is overridden
+pub fn cache_dir() -> PathBuf {
+    if std::env::var_os(DATA_DIR_VAR).is_some() {
+        return data_dir().join("cache");
+    }
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("dblp-rs")
+}
+
+pub fn scratch_dir() -> PathBuf {
+    data_dir().join(".dblp-tmp")
+}
+
+/// A file that is deleted when dropped, unless it is persisted. Since drop
+/// also runs while unwinding, panics don't leave scratch files around.
+pub struct ScratchFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl ScratchFile {
+    pub fn create_in<P: AsRef<Path>>(dir: P, prefix: &str) -> Result<Self> {
+        Self::create_with_extension_in(dir, prefix, None)
+    }
+
+    /// Like `create_in`, ending the name with the extension, for the
+    /// programs that go by it, like editors
+    pub fn create_with_extension_in<P: AsRef<Path>>(
+        dir: P,
+        prefix: &str,
+        extension: Option<&str>,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())
+            .with_context(|| format!("creating scratch directory {:?}", dir.as_ref()))?;
+        let name = format!(
+            "{}-{}-{}{}",
+            prefix,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+            extension.map_or(String::new(), |ext| format!(".{}", ext))
+        );
+        let path = dir.as_ref().join(name);
+        let file = File::create(&path).with_context(|| format!("creating {:?}", path))?;
+        Ok(Self {
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Moves the scratch file to the target path, replacing it and keeping
+    /// its permissions. Where the scratch file is on another filesystem
+    /// than the target, it is copied over the target instead, which is not
+    /// atomic: a run killed while copying leaves the target half written.
+    pub fn persist<P: AsRef<Path>>(mut self, target: P) -> Result<()> {
+        let target = target.as_ref();
+        let file = self.file.take().context("scratch file already closed")?;
+        file.sync_all()?;
+        drop(file);
+        if let Ok(meta) = std::fs::metadata(target) {
+            std::fs::set_permissions(&self.path, meta.permissions())
+                .with_context(|| format!("setting the permissions of {:?}", self.path))?;
+        }
+        if std::fs::rename(&self.path, target).is_err() {
+            // the scratch directory may be on a different filesystem
+            std::fs::copy(&self.path, target).with_context(|| format!("writing {:?}", target))?;
+        }
+        // so that the rename itself survives a crash, where directories
+        // can be synced
+        if let Some(dir) = target.parent().and_then(|dir| File::open(dir).ok()) {
+            let _ = dir.sync_all();
+        }
+        Ok(())
+    }
+}
+
+impl Write for ScratchFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.file.as_mut() {
+            Some(f) => f.write(buf),
+            None => Err(std::io::Error::other("scratch file already closed")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(f) => f.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        drop(self.file.take());
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Replaces the content of the target with what `write` produces, leaving
+/// the target untouched if `write` fails or panics. The content is written
+/// next to the target and then renamed over it, so that even a killed run
+/// never leaves the target half written, only a scratch file that the next
+/// write removes.
+pub fn write_atomically<P, F>(target: P, write: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    write_atomically_in(scratch_dir(), crate::paths::real_path(target), write)
+}
+
+/// Like `write_atomically`, writing in `fallback` when the directory of the
+/// target is not writable, in which case the rename may become a copy, and
+/// the write is no longer atomic
+fn write_atomically_in<D, P, F>(fallback: D, target: P, write: F) -> Result<()>
+where
+    D: AsRef<Path>,
+    P: AsRef<Path>,
+    F: FnOnce(&mut dyn Write) -> Result<()>,
+{
+    let target = target.as_ref();
+    let prefix = format!(
+        ".{}.tmp",
+        target
+            .file_name()
+            .map_or("write".into(), |name| name.to_string_lossy())
+    );
+    let beside = match target.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => Path::new("."),
+    };
+    remove_left_over(beside, &prefix);
+    let mut scratch = if beside.is_dir() {
+        ScratchFile::create_in(beside, &prefix).ok()
+    } else {
+        None
+    }
+    .map_or_else(|| ScratchFile::create_in(fallback, &prefix), Ok)?;
+    write(&mut scratch)?;
+    scratch.persist(target)
+}
+
+/// Removes the scratch files with the prefix in the directory left by
+/// processes that are not running anymore, which were killed while writing
+fn remove_left_over(dir: &Path, prefix: &str) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let pid = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix)?.strip_prefix('-'))
+            .and_then(|rest| rest.split_once('-'))
+            .and_then(|(pid, _)| pid.parse().ok());
+        if pid.is_some_and(|pid| !running(pid)) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Whether a process with the PID is running
+#[cfg(unix)]
+fn running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    // signal 0 only checks the process, which may belong to another user
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process with the PID is running, which cannot be told here,
+/// so that no scratch file in use is removed
+#[cfg(not(unix))]
+fn running(_pid: u32) -> bool {
+    true
+}
+
+/// Removes scratch files older than `max_age`, returning how many were removed
+pub fn gc(max_age: Duration) -> Result<usize> {
+    let dir = scratch_dir();
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+    let now = SystemTime::now();
+    let mut removed = 0;
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let age = entry
+            .metadata()?
+            .modified()
+            .ok()
+            .and_then(|m| now.duration_since(m).ok());
+        if age.is_some_and(|age| age > max_age) {
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[test]
+fn test_write_atomically_interrupted() {
+    let root = std::env::temp_dir().join(format!("dblp-rs-test-{}", std::process::id()));
+    let (scratch, bibdir) = (root.join("scratch"), root.join("paper"));
+    std::fs::create_dir_all(&bibdir).unwrap();
+    let bib = bibdir.join("refs.bib");
+    std::fs::write(&bib, "original").unwrap();
+
+    let res = write_atomically_in(&scratch, &bib, |w| {
+        writeln!(w, "half a conv")?;
+        anyhow::bail!("interrupted")
+    });
+    assert!(res.is_err());
+    let res = std::panic::catch_unwind(|| {
+        write_atomically_in(&scratch, &bib, |w| {
+            writeln!(w, "half a conv")?;
+            panic!("interrupted")
+        })
+    });
+    assert!(res.is_err());
+
+    assert_eq!(std::fs::read_to_string(&bib).unwrap(), "original");
+    assert_eq!(std::fs::read_dir(&bibdir).unwrap().count(), 1);
+    assert!(!scratch.exists());
+
+    // written next to the target, so that it is renamed rather than copied
+    write_atomically_in(&scratch, &bib, |w| {
+        let names: Vec<_> = std::fs::read_dir(&bibdir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(
+            names.iter().any(|n| n.starts_with(".refs.bib.tmp-")),
+            "{:?}",
+            names
+        );
+        Ok(write!(w, "converted")?)
+    })
+    .unwrap();
+    assert_eq!(std::fs::read_to_string(&bib).unwrap(), "converted");
+    assert_eq!(std::fs::read_dir(&bibdir).unwrap().count(), 1);
+
+    // keeping the permissions of the target, and removing what killed
+    // processes left, but not what running ones are writing
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&bib, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let left = bibdir.join(format!(".refs.bib.tmp-{}-0", child.id()));
+        child.wait().unwrap();
+        let writing = bibdir.join(format!(".refs.bib.tmp-{}-0", std::process::id()));
+        let other = bibdir.join(".other.bib.tmp-1-0");
+        for path in [&left, &writing, &other] {
+            std::fs::write(path, "half a conv").unwrap();
+        }
+        write_atomically_in(&scratch, &bib, |w| Ok(write!(w, "again")?)).unwrap();
+        assert_eq!(std::fs::read_to_string(&bib).unwrap(), "again");
+        let mode = std::fs::metadata(&bib).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert!(!left.exists());
+        assert!(writing.exists() && other.exists());
+        std::fs::remove_file(&writing).unwrap();
+        std::fs::remove_file(&other).unwrap();
+    }
+
+    // or in the fallback, when its directory does not exist
+    let elsewhere = root.join("missing/refs.bib");
+    let res = write_atomically_in(&scratch, &elsewhere, |w| Ok(write!(w, "x")?));
+    assert!(res.is_err());
+    assert_eq!(std::fs::read_dir(&scratch).unwrap().count(), 0);
+
+    // files for other programs to open are removed all the same
+    let mut edited = ScratchFile::create_with_extension_in(&scratch, "edit", Some("bib")).unwrap();
+    write!(edited, "@misc{{a}}").unwrap();
+    let path = edited.path().to_owned();
+    assert_eq!(path.extension().unwrap(), "bib");
+    drop(edited);
+    assert!(!path.exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,216 @@
+/// utilities to decide whether two entries refer to the same paper
+use biblatex::{ChunksExt, Entry};
+use std::collections::BTreeSet;
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Minimum Jaccard similarity between the author surnames of two entries
+/// with the same title to consider them the same paper.
+pub const AUTHOR_JACCARD_THRESHOLD: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// How confident we are that the two entries are the same paper. Titles
+/// alone are not enough, since distinct papers may share a title: a high
+/// confidence requires either the same DOI, or the same title and
+/// overlapping authors.
+pub fn match_confidence(a: &Entry, b: &Entry) -> Confidence {
+    if let (Some(doi_a), Some(doi_b)) = (doi(a), doi(b)) {
+        if doi_a == doi_b {
+            return Confidence::High;
+        }
+    }
+    match (title(a), title(b)) {
+        (Some(title_a), Some(title_b)) if title_a == title_b => {}
+        _ => return Confidence::Low,
+    }
+    let (surnames_a, surnames_b) = (surnames(a), surnames(b));
+    if surnames_a.is_empty() || surnames_b.is_empty() {
+        return Confidence::Medium;
+    }
+    match jaccard(&surnames_a, &surnames_b) {
+        j if j >= AUTHOR_JACCARD_THRESHOLD => Confidence::High,
+        j if j > 0.0 => Confidence::Medium,
+        _ => Confidence::Low,
+    }
+}
+
+/// Lowercases the string and removes braces, LaTeX commands, accents and
+/// punctuation, collapsing whitespace
+pub fn normalize_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            // drop the name of the command, keeping its argument
+            while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                chars.next();
+            }
+            if chars.peek().is_some_and(|c| !c.is_alphanumeric()) {
+                chars.next();
+            }
+        } else if c.is_alphanumeric() {
+            out.extend(c.to_lowercase().nfd().filter(|c| !is_combining_mark(*c)));
+        } else if c.is_whitespace() || is_dash(c) {
+            out.push(' ');
+        }
+    }
+    out.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// The hyphen, and the Unicode dashes that stand for the `-`, `--` and
+/// `---` of TeX
+fn is_dash(c: char) -> bool {
+    matches!(
+        c,
+        '-' | '\u{2010}'..='\u{2015}' | '\u{2212}' | '\u{fe58}' | '\u{fe63}' | '\u{ff0d}'
+    )
+}
+
+pub fn normalize_doi(doi: &str) -> String {
+    let doi = doi.trim().to_lowercase();
+    [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "doi:",
+    ]
+    .iter()
+    .find_map(|prefix| doi.strip_prefix(prefix))
+    .unwrap_or(&doi)
+    .to_owned()
+}
+
+pub fn title(entry: &Entry) -> Option<String> {
+    entry
+        .title()
+        .ok()
+        .map(|t| normalize_text(&t.format_verbatim()))
+        .filter(|t| !t.is_empty())
+}
+
+pub fn doi(entry: &Entry) -> Option<String> {
+    entry.doi().ok().map(|d| normalize_doi(&d))
+}
+
+fn surnames(entry: &Entry) -> BTreeSet<String> {
+    entry
+        .author()
+        .unwrap_or_default()
+        .iter()
+        .map(|p| normalize_text(&p.name))
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+fn jaccard(a: &BTreeSet<String>, b: &BTreeSet<String>) -> f64 {
+    let intersection = a.intersection(b).count();
+    let union = a.union(b).count();
+    intersection as f64 / union as f64
+}
+
+/// Unknown names at most this far from a known one are probable typos
+pub const TYPO_DISTANCE: usize = 2;
+
+/// Levenshtein distance between the two strings, counting characters
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+fn parse_pair(a: &str, b: &str) -> (Entry, Entry) {
+    let parse = |s| {
+        biblatex::Bibliography::parse(s)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap()
+    };
+    (parse(a), parse(b))
+}
+
+#[test]
+fn test_match_confidence() {
+    let corpus = [
+        // same paper, condensed and hand-written
+        (
+            "@inproceedings{DBLP:conf/nips/VaswaniSPUJGKP17, title = {Attention is All you Need},
+              author = {Ashish Vaswani and Noam Shazeer and Niki Parmar and Jakob Uszkoreit}}",
+            "@misc{vaswani, title = {Attention Is All You {N}eed},
+              author = {Vaswani, A. and Shazeer, N. and Parmar, N.}}",
+            Confidence::High,
+        ),
+        // same DOI, different titles
+        (
+            "@article{a, title = {Foo}, doi = {10.1145/3313276.3316350}}",
+            "@article{b, title = {Bar}, doi = {https://doi.org/10.1145/3313276.3316350}}",
+            Confidence::High,
+        ),
+        // accents and braces
+        (
+            "@article{a, title = {Sch{\\\"o}ning's Algorithm}, author = {Uwe Sch{\\\"o}ning}}",
+            "@article{b, title = {Schöning's algorithm}, author = {Uwe Schöning}}",
+            Confidence::High,
+        ),
+        // same title, no author information to corroborate
+        (
+            "@book{a, title = {Deep Learning}}",
+            "@book{b, title = {Deep Learning}, author = {Ian Goodfellow}}",
+            Confidence::Medium,
+        ),
+        // same one-word title, distinct authors
+        (
+            "@article{a, title = {Deep Learning}, author = {Yann LeCun and Yoshua Bengio and Geoffrey Hinton}}",
+            "@book{b, title = {Deep Learning}, author = {Ian Goodfellow and Aaron Courville}}",
+            Confidence::Low,
+        ),
+        // distinct titles, shared authors
+        (
+            "@article{a, title = {Coresets for k-Means}, author = {Foo Bar}}",
+            "@article{b, title = {Coresets for k-Median}, author = {Foo Bar}}",
+            Confidence::Low,
+        ),
+    ];
+    for (a, b, expected) in corpus {
+        let (a, b) = parse_pair(a, b);
+        assert_eq!(match_confidence(&a, &b), expected, "{} vs {}", a.key, b.key);
+        assert_eq!(match_confidence(&b, &a), expected, "{} vs {}", b.key, a.key);
+    }
+}
+
+#[test]
+fn test_normalize_text() {
+    assert_eq!(
+        normalize_text("{Sch\\\"{o}ning}'s  Algorithm: a {SAT} Solver."),
+        "schonings algorithm a sat solver"
+    );
+    assert_eq!(normalize_text("Erdős–Rényi Graphs"), "erdos renyi graphs");
+    assert_eq!(normalize_text("Erdos--Renyi Graphs"), "erdos renyi graphs");
+    assert_eq!(normalize_text("Erd{\\H{o}}s-R\\'enyi"), "erdos renyi");
+    assert_eq!(normalize_text("Graphs—and‐Trees"), "graphs and trees");
+}
+
+#[test]
+fn test_edit_distance() {
+    assert_eq!(edit_distance("status", "status"), 0);
+    assert_eq!(edit_distance("satus", "status"), 1);
+    assert_eq!(edit_distance("tag", "tags"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+    assert_eq!(edit_distance("année", "annee"), 1);
+}
@@ -0,0 +1,186 @@
+/// utilities to sort the entries of a bibtex file, and to insert entries
+/// where they go in a sorted one
+use crate::error::DblpError;
+use crate::{bib, tex};
+use anyhow::Result;
+use biblatex::Bibliography;
+use clap::ValueEnum;
+use std::{cmp::Ordering, collections::HashMap};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// Order of first citation in the TeX sources, uncited entries last
+    Cited,
+    /// Citation key, in any case
+    Key,
+    /// Year, or the year of the date, entries without either last
+    Year,
+    /// Surname of the first author, entries without authors last
+    Author,
+}
+
+impl SortBy {
+    /// The name of the order, as given on the command line
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .expect("no variant is skipped")
+            .get_name()
+            .to_owned()
+    }
+}
+
+/// The entries of a file with the value they are sorted by, in the order
+/// of the file and sorted
+pub struct Sorting {
+    pub values: Vec<(String, Option<String>)>,
+    pub sorted: Vec<(String, Option<String>)>,
+}
+
+impl Sorting {
+    /// Sorts the entries of the source, `first` being the first citation of
+    /// each key when sorting by citation order
+    pub fn new(src: &str, by: SortBy, reverse: bool, first: &[tex::Citation]) -> Result<Self> {
+        let values = sort_values(src, by, first)?;
+        let mut sorted = values.clone();
+        sorted.sort_by(|(_, a), (_, b)| compare_values(a, b, reverse));
+        Ok(Sorting { values, sorted })
+    }
+
+    pub fn is_sorted(&self) -> bool {
+        self.values == self.sorted
+    }
+
+    /// The key of the first entry that should come earlier than it does
+    pub fn misplaced(&self) -> Option<&str> {
+        self.values
+            .iter()
+            .zip(&self.sorted)
+            .find(|(a, b)| a != b)
+            .map(|(_, (key, _))| key.as_str())
+    }
+
+    /// The source with its entries in the sorted order
+    pub fn reorder(&self, src: &str) -> Result<String> {
+        let position: HashMap<&str, usize> = self
+            .sorted
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.as_str(), i))
+            .collect();
+        Ok(bib::sort_entries(src, |key| position.get(key).copied())?)
+    }
+}
+
+/// The entries of the source with the value they are sorted by, if they
+/// have one, in file order
+pub fn sort_values(
+    src: &str,
+    by: SortBy,
+    first: &[tex::Citation],
+) -> Result<Vec<(String, Option<String>)>> {
+    let bibliography = Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+    // numbers are padded, so that they compare like numbers
+    Ok(bibliography
+        .iter()
+        .map(|entry| {
+            let value = match by {
+                SortBy::Cited => first
+                    .iter()
+                    .position(|c| c.key == entry.key)
+                    .map(|i| format!("{:>6}", i)),
+                SortBy::Key => Some(entry.key.to_lowercase()),
+                SortBy::Year => bib::year(entry).map(|year| format!("{:>6}", year)),
+                SortBy::Author => bib::first_author(entry),
+            };
+            (entry.key.clone(), value)
+        })
+        .collect())
+}
+
+/// The order of the values that entries are sorted by, the entries without
+/// one last
+pub fn compare_values(a: &Option<String>, b: &Option<String>, reverse: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    }
+}
+
+/// The source with each entry inserted after the ones that come before it
+/// or with it in the order, or None if the source does not follow it
+pub fn insert_sorted(
+    src: &str,
+    entries: &[String],
+    by: SortBy,
+    first: &[tex::Citation],
+) -> Result<Option<String>> {
+    let values = sort_values(src, by, first)?;
+    let sorted = values
+        .windows(2)
+        .all(|pair| compare_values(&pair[0].1, &pair[1].1, false).is_le());
+    if !sorted {
+        return Ok(None);
+    }
+    let mut out = src.to_owned();
+    for text in entries {
+        let value = sort_values(text, by, first)?.into_iter().next();
+        let value = value.and_then(|(_, value)| value);
+        let next = sort_values(&out, by, first)?
+            .into_iter()
+            .find(|(_, other)| compare_values(&value, other, false).is_lt());
+        out = match next {
+            Some((key, _)) => bib::insert_before(&out, &key, text)?,
+            None => bib::append(&out, std::slice::from_ref(text)),
+        };
+    }
+    Ok(Some(out))
+}
+
+#[test]
+fn test_insert_sorted() {
+    let src = "@misc{b, year = {2010}}\n\n@misc{d, year = {2020}}\n\n@misc{undated, title = {U}}\n";
+    let entries = [
+        "@misc{c, year = {2015}}".to_owned(),
+        "@misc{e, year = {2020}}".to_owned(),
+        "@misc{a, title = {A}}".to_owned(),
+    ];
+    assert_eq!(
+        insert_sorted(src, &entries, SortBy::Year, &[])
+            .unwrap()
+            .unwrap(),
+        "@misc{b, year = {2010}}\n\n@misc{c, year = {2015}}\n\n@misc{d, year = {2020}}\n\n\
+         @misc{e, year = {2020}}\n\n@misc{undated, title = {U}}\n\n@misc{a, title = {A}}\n\n"
+    );
+    assert_eq!(
+        insert_sorted(src, &entries[..1], SortBy::Key, &[]).unwrap().unwrap(),
+        "@misc{b, year = {2010}}\n\n@misc{c, year = {2015}}\n\n@misc{d, year = {2020}}\n\n@misc{undated, title = {U}}\n"
+    );
+    let unsorted = "@misc{d, year = {2020}}\n\n@misc{b, year = {2010}}\n";
+    assert_eq!(
+        insert_sorted(unsorted, &entries, SortBy::Year, &[]).unwrap(),
+        None
+    );
+    assert_eq!(
+        insert_sorted("", &entries[..1], SortBy::Key, &[])
+            .unwrap()
+            .unwrap(),
+        "@misc{c, year = {2015}}\n\n"
+    );
+}
+
+#[test]
+fn test_sorting() {
+    let src = "@misc{b, year = {2010}}\n\n@misc{undated, title = {U}}\n\n@misc{a, year = {2020}}\n";
+    let sorting = Sorting::new(src, SortBy::Year, true, &[]).unwrap();
+    assert!(!sorting.is_sorted());
+    assert_eq!(sorting.misplaced(), Some("a"));
+    assert_eq!(
+        sorting.reorder(src).unwrap(),
+        "@misc{a, year = {2020}}\n\n@misc{b, year = {2010}}\n\n@misc{undated, title = {U}}\n"
+    );
+    assert!(Sorting::new(src, SortBy::Author, false, &[])
+        .unwrap()
+        .is_sorted());
+    assert_eq!(SortBy::Cited.name(), "cited");
+}
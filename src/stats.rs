@@ -0,0 +1,151 @@
+/// counters of the work done by a command, to tune caching and rate limits
+use crate::output::{MirrorHealth, PhaseReport, StatsReport};
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// The counters of the running command, shared by all its workers
+pub static STATS: Stats = Stats::new();
+
+pub struct Stats {
+    requests: AtomicUsize,
+    cache_hits: AtomicUsize,
+    retries: AtomicUsize,
+    bytes: AtomicU64,
+    /// total time spent in each phase, in order of first appearance
+    phases: Mutex<Vec<(&'static str, Duration)>>,
+}
+
+impl Stats {
+    pub const fn new() -> Self {
+        Self {
+            requests: AtomicUsize::new(0),
+            cache_hits: AtomicUsize::new(0),
+            retries: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            phases: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Counts a completed request, with the size of its body
+    pub fn request(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Runs `f`, adding the time it takes to the phase
+    pub fn phase<T>(&self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.add_time(name, start.elapsed());
+        res
+    }
+
+    fn add_time(&self, name: &'static str, elapsed: Duration) {
+        let mut phases = self.phases.lock().unwrap_or_else(|e| e.into_inner());
+        match phases.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((name, elapsed)),
+        }
+    }
+
+    pub fn report(&self, command: &str, wall: Duration, mirrors: Vec<MirrorHealth>) -> StatsReport {
+        let phases = self.phases.lock().unwrap_or_else(|e| e.into_inner());
+        StatsReport {
+            command: command.to_owned(),
+            wall_ms: wall.as_millis(),
+            requests: self.requests.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            phases: phases
+                .iter()
+                .map(|(name, d)| PhaseReport {
+                    name,
+                    ms: d.as_millis(),
+                })
+                .collect(),
+            mirrors,
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StatsReport {
+    /// The report on a single line
+    pub fn summary(&self) -> String {
+        let mut line = format!(
+            "{}: {} ms, {} requests ({}), {} cache hits, {} retries",
+            self.command,
+            self.wall_ms,
+            self.requests,
+            crate::bib::human_size(self.bytes as usize),
+            self.cache_hits,
+            self.retries
+        );
+        for phase in self.phases.iter() {
+            line.push_str(&format!(", {} {} ms", phase.name, phase.ms));
+        }
+        for mirror in self.mirrors.iter().filter(|m| !m.healthy) {
+            line.push_str(&format!(", {} unhealthy", mirror.url));
+        }
+        line
+    }
+}
+
+#[test]
+fn test_stats() {
+    let stats = Stats::new();
+    // five fetches from parallel workers, one of them after a retry
+    std::thread::scope(|s| {
+        for _ in 0..5 {
+            s.spawn(|| stats.phase("network", || stats.request(1000)));
+        }
+    });
+    stats.retry();
+    stats.cache_hit();
+    stats.cache_hit();
+    let mirrors = vec![
+        MirrorHealth {
+            url: "https://dblp.org".into(),
+            failures: 3,
+            healthy: false,
+            retry_in_s: Some(42),
+        },
+        MirrorHealth {
+            url: "https://dblp.uni-trier.de".into(),
+            failures: 0,
+            healthy: true,
+            retry_in_s: None,
+        },
+    ];
+    let mut report = stats.report("convert", Duration::from_millis(1234), mirrors);
+    assert_eq!(report.phases.len(), 1);
+    assert_eq!(report.phases[0].name, "network");
+    report.phases[0].ms = 7;
+    assert_eq!(
+        report.summary(),
+        "convert: 1234 ms, 5 requests (5.0 KB), 2 cache hits, 1 retries, network 7 ms, https://dblp.org unhealthy"
+    );
+    assert_eq!(
+        serde_json::to_string(&report).unwrap(),
+        r#"{"command":"convert","wall_ms":1234,"requests":5,"cache_hits":2,"retries":1,"bytes":5000,"phases":[{"name":"network","ms":7}],"mirrors":[{"url":"https://dblp.org","failures":3,"healthy":false,"retry_in_s":42},{"url":"https://dblp.uni-trier.de","failures":0,"healthy":true,"retry_in_s":null}]}"#
+    );
+}
@@ -0,0 +1,257 @@
+/// replacements suggested by the checks and not applied yet, kept in the
+/// data directory to be reviewed later
+use crate::error::DblpError;
+use crate::{bib, scratch};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tui")]
+use skim::prelude::*;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The version of the format of the store, bumped on incompatible changes
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SuggestionKind {
+    /// Replace a conference paper with its journal version
+    JournalVersion,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub source_key: String,
+    pub proposed_key: String,
+    /// Why the replacement is suggested, for the user
+    pub evidence: String,
+    /// Where the proposed entry can be looked at
+    pub url: String,
+    /// seconds since the epoch
+    pub discovered: u64,
+    /// of the source entry when the suggestion was made
+    pub fingerprint: String,
+    #[serde(default)]
+    pub dismissed: bool,
+}
+
+#[cfg(feature = "tui")]
+impl SkimItem for Suggestion {
+    fn text(&self) -> Cow<'_, str> {
+        Cow::Owned(format!(
+            "{} -> {}: {}",
+            self.source_key, self.proposed_key, self.evidence
+        ))
+    }
+}
+
+/// The suggestions about the entries of a bib file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Store {
+    version: u32,
+    path: PathBuf,
+    suggestions: Vec<Suggestion>,
+}
+
+impl Store {
+    fn store_path(bib_path: &Path) -> PathBuf {
+        let name = bib::hash_hex(bib_path.to_string_lossy().as_bytes());
+        scratch::data_dir()
+            .join("suggestions")
+            .join(format!("{}.json", name))
+    }
+
+    fn empty(bib_path: &Path) -> Self {
+        Self {
+            version: VERSION,
+            path: bib_path.to_owned(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// The suggestions about the bib file, without the ones that expired
+    /// since they were made
+    pub fn load(bib_path: &Path, src: &str) -> Result<Self> {
+        let mut store = match std::fs::read_to_string(Self::store_path(bib_path)) {
+            Ok(json) => Self::from_json(&json, bib_path)?,
+            Err(_) => Self::empty(bib_path),
+        };
+        store.expire(src)?;
+        Ok(store)
+    }
+
+    fn from_json(json: &str, bib_path: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let version = serde_json::from_str::<Versioned>(json)?.version;
+        if version != VERSION {
+            bail!(
+                "the suggestions store of {:?} has version {}, this dblp reads version {}",
+                bib_path,
+                version,
+                VERSION
+            );
+        }
+        let store: Self = serde_json::from_str(json)?;
+        if store.path != bib_path {
+            return Ok(Self::empty(bib_path));
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::store_path(&self.path);
+        std::fs::create_dir_all(path.parent().expect("store path has a parent"))?;
+        scratch::write_atomically(path, |f| Ok(serde_json::to_writer_pretty(f, self)?))
+    }
+
+    /// Drops the suggestions whose source entry was removed or changed, or
+    /// whose proposed entry is already in the file, returning them
+    pub fn expire(&mut self, src: &str) -> Result<Vec<Suggestion>> {
+        let bibliography =
+            biblatex::Bibliography::parse(src).map_err(|e| DblpError::from_bib(e, src))?;
+        let fingerprints: HashMap<&str, String> = bibliography
+            .iter()
+            .map(|e| (e.key.as_str(), bib::entry_fingerprint(e)))
+            .collect();
+        let (keep, expired) = std::mem::take(&mut self.suggestions)
+            .into_iter()
+            .partition(|s| {
+                fingerprints.get(s.source_key.as_str()) == Some(&s.fingerprint)
+                    && !fingerprints.contains_key(s.proposed_key.as_str())
+            });
+        self.suggestions = keep;
+        Ok(expired)
+    }
+
+    /// Records the suggestion, unless it was dismissed. Suggesting again
+    /// keeps the date of the first discovery. Returns whether it is pending.
+    pub fn add(&mut self, suggestion: Suggestion) -> bool {
+        let existing = self.suggestions.iter_mut().find(|s| {
+            s.source_key == suggestion.source_key && s.proposed_key == suggestion.proposed_key
+        });
+        match existing {
+            Some(existing) if existing.dismissed => false,
+            Some(existing) => {
+                existing.fingerprint = suggestion.fingerprint;
+                true
+            }
+            None => {
+                self.suggestions.push(suggestion);
+                true
+            }
+        }
+    }
+
+    pub fn is_dismissed(&self, source_key: &str, proposed_key: &str) -> bool {
+        self.suggestions
+            .iter()
+            .any(|s| s.source_key == source_key && s.proposed_key == proposed_key && s.dismissed)
+    }
+
+    /// The suggestions, dismissed ones included if `all`
+    pub fn list(&self, all: bool) -> impl Iterator<Item = &Suggestion> {
+        self.suggestions.iter().filter(move |s| all || !s.dismissed)
+    }
+
+    /// Dismisses the pending suggestions about the entry, returning how many
+    pub fn dismiss(&mut self, source_key: &str) -> usize {
+        let mut dismissed = 0;
+        for s in self.suggestions.iter_mut() {
+            if s.source_key == source_key && !s.dismissed {
+                s.dismissed = true;
+                dismissed += 1;
+            }
+        }
+        dismissed
+    }
+
+    /// Forgets the suggestion, once applied
+    pub fn remove(&mut self, suggestion: &Suggestion) {
+        self.suggestions.retain(|s| {
+            s.source_key != suggestion.source_key || s.proposed_key != suggestion.proposed_key
+        });
+    }
+}
+
+#[cfg(test)]
+fn suggestion(source_key: &str, proposed_key: &str, fingerprint: &str) -> Suggestion {
+    Suggestion {
+        kind: SuggestionKind::JournalVersion,
+        source_key: source_key.to_owned(),
+        proposed_key: proposed_key.to_owned(),
+        evidence: "same title".to_owned(),
+        url: format!("https://dblp.org/rec/{}", proposed_key),
+        discovered: 10,
+        fingerprint: fingerprint.to_owned(),
+        dismissed: false,
+    }
+}
+
+#[test]
+fn test_add_and_dismiss() {
+    let mut store = Store::empty(Path::new("refs.bib"));
+    assert!(store.add(suggestion("a", "ja", "fa")));
+    assert!(store.add(suggestion("b", "jb", "fb")));
+    // found again later: still pending, first discovery kept
+    let mut again = suggestion("a", "ja", "fa2");
+    again.discovered = 20;
+    assert!(store.add(again));
+    assert_eq!(store.list(false).count(), 2);
+    assert_eq!(store.list(false).next().unwrap().discovered, 10);
+    assert_eq!(store.list(false).next().unwrap().fingerprint, "fa2");
+
+    assert_eq!(store.dismiss("a"), 1);
+    assert_eq!(store.dismiss("a"), 0);
+    assert!(store.is_dismissed("a", "ja"));
+    assert!(!store.add(suggestion("a", "ja", "fa2")));
+    assert_eq!(store.list(false).count(), 1);
+    assert_eq!(store.list(true).count(), 2);
+
+    // applied
+    store.remove(&suggestion("b", "jb", "fb"));
+    assert_eq!(store.list(false).count(), 0);
+}
+
+#[test]
+fn test_expire() {
+    let src = "@misc{same, title = {Same}}\n@misc{changed, title = {New}}\n@misc{proposed, title = {P}}\n@misc{already, title = {A}}\n";
+    let fingerprint = |key: &str| {
+        let bibliography = biblatex::Bibliography::parse(src).unwrap();
+        bib::entry_fingerprint(bibliography.get(key).unwrap())
+    };
+    let mut store = Store::empty(Path::new("refs.bib"));
+    store.add(suggestion("same", "j1", &fingerprint("same")));
+    store.add(suggestion("changed", "j2", "old fingerprint"));
+    store.add(suggestion("removed", "j3", "f"));
+    store.add(suggestion("already", "proposed", &fingerprint("already")));
+    let expired: Vec<String> = store
+        .expire(src)
+        .unwrap()
+        .into_iter()
+        .map(|s| s.source_key)
+        .collect();
+    assert_eq!(expired, ["changed", "removed", "already"]);
+    let pending: Vec<&str> = store.list(true).map(|s| s.source_key.as_str()).collect();
+    assert_eq!(pending, ["same"]);
+}
+
+#[test]
+fn test_versioning() {
+    let mut store = Store::empty(Path::new("refs.bib"));
+    store.add(suggestion("a", "ja", "fa"));
+    let json = serde_json::to_string(&store).unwrap();
+    let read = Store::from_json(&json, Path::new("refs.bib")).unwrap();
+    assert_eq!(read.suggestions, store.suggestions);
+    // a store of another file sharing the name is ignored
+    let other = Store::from_json(&json, Path::new("other.bib")).unwrap();
+    assert_eq!(other.suggestions, []);
+    let future = json.replace("\"version\":1", "\"version\":2");
+    let err = Store::from_json(&future, Path::new("refs.bib")).unwrap_err();
+    assert!(err.to_string().contains("has version 2"));
+}
@@ -0,0 +1,200 @@
+/// utilities to find the citations in the TeX sources of a document
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Citation {
+    pub key: String,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+/// Replaces comments with spaces, so that offsets into the text are
+/// preserved. A `%` escaped by a backslash does not start a comment.
+pub fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_comment = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            '\n' => {
+                in_comment = false;
+                out.push(c);
+            }
+            _ if in_comment => out.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            '%' if !escaped => {
+                in_comment = true;
+                out.push(' ');
+            }
+            _ => out.push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    out
+}
+
+/// The commands citing keys, with the keys as the first group
+static CITE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\\[a-zA-Z]*cite[a-zA-Z]*\*?\s*(?:\[[^\]]*\]\s*){0,2}\{([^}]*)\}").unwrap()
+});
+
+/// The ranges of the keys cited in the text, in the order they appear.
+/// Commands can span lines, and can have optional arguments, like
+/// `\citep[see][p.~2]{a, b}`.
+fn cited_keys(text: &str) -> Vec<Range<usize>> {
+    let text = strip_comments(text);
+    CITE.captures_iter(&text)
+        .flat_map(|caps| {
+            let keys = caps.get(1).expect("missing group");
+            let mut offset = keys.start();
+            let mut ranges = Vec::new();
+            for key in keys.as_str().split(',') {
+                let start = offset + key.len() - key.trim_start().len();
+                offset += key.len() + 1;
+                if key.trim().is_empty() {
+                    continue;
+                }
+                ranges.push(start..start + key.trim().len());
+            }
+            ranges
+        })
+        .collect()
+}
+
+/// The citations of the text, in the order they appear
+pub fn citations_in(text: &str, file: &Path) -> Vec<Citation> {
+    cited_keys(text)
+        .into_iter()
+        .map(|range| Citation {
+            key: text[range.clone()].to_owned(),
+            file: file.to_owned(),
+            line: text[..range.start].matches('\n').count() + 1,
+        })
+        .collect()
+}
+
+/// The text with the cited keys renamed, and the rest, comments included,
+/// as it was
+pub fn rename_citations(text: &str, renames: &BTreeMap<String, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for range in cited_keys(text) {
+        if let Some(new_key) = renames.get(&text[range.clone()]) {
+            out.push_str(&text[last..range.start]);
+            out.push_str(new_key);
+            last = range.end;
+        }
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// The `.tex` files in the directory and below it, in order of path
+fn tex_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "tex") && p.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// The citations of all the `.tex` files in the directory, visited in
+/// order of path
+pub fn citations<P: AsRef<Path>>(dir: P) -> Result<Vec<Citation>> {
+    let mut all = Vec::new();
+    for file in tex_files(dir.as_ref()) {
+        let text = std::fs::read_to_string(&file).with_context(|| format!("reading {:?}", file))?;
+        all.extend(citations_in(&text, &file));
+    }
+    Ok(all)
+}
+
+/// Renames the cited keys in all the `.tex` files in the directory,
+/// returning the files that changed
+pub fn rename_citations_in<P: AsRef<Path>>(
+    dir: P,
+    renames: &BTreeMap<String, String>,
+) -> Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for file in tex_files(dir.as_ref()) {
+        let text = std::fs::read_to_string(&file).with_context(|| format!("reading {:?}", file))?;
+        let renamed = rename_citations(&text, renames);
+        if renamed != text {
+            crate::scratch::write_atomically(&file, |f| Ok(write!(f, "{}", renamed)?))?;
+            changed.push(file);
+        }
+    }
+    Ok(changed)
+}
+
+/// The directory of the bib file, where the .tex files citing it are
+pub fn dir_of(bib: &Path) -> &Path {
+    match bib.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// The first citation of each key, in order of appearance
+pub fn first_citations(citations: Vec<Citation>) -> Vec<Citation> {
+    let mut seen = std::collections::HashSet::new();
+    citations
+        .into_iter()
+        .filter(|c| seen.insert(c.key.clone()))
+        .collect()
+}
+
+#[test]
+fn test_citations_in() {
+    let text = r"
+We follow \citet{DBLP:conf/soda/Chan16}, % but not \cite{commented}
+and 100\% of \citep[see][p.~2]{b,
+  DBLP:conf/soda/Chan16 , c}.
+\nocite{d}";
+    let citations: Vec<(String, usize)> = citations_in(text, Path::new("main.tex"))
+        .into_iter()
+        .map(|c| (c.key, c.line))
+        .collect();
+    let expected = [
+        ("DBLP:conf/soda/Chan16", 2),
+        ("b", 3),
+        ("DBLP:conf/soda/Chan16", 4),
+        ("c", 4),
+        ("d", 5),
+    ];
+    assert_eq!(
+        citations,
+        expected
+            .iter()
+            .map(|(k, l)| (k.to_string(), *l))
+            .collect::<Vec<_>>()
+    );
+    let first: Vec<String> = first_citations(citations_in(text, Path::new("main.tex")))
+        .into_iter()
+        .map(|c| c.key)
+        .collect();
+    assert_eq!(first, vec!["DBLP:conf/soda/Chan16", "b", "c", "d"]);
+}
+
+#[test]
+fn test_rename_citations() {
+    let renames: BTreeMap<String, String> = [("a", "new-a"), ("c", "new-c")]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+    let text = "See \\cite{a} and \\citep[p.~2]{b, c,a}.\n% \\cite{a} stays\nNot a citation: a.\n";
+    assert_eq!(
+        rename_citations(text, &renames),
+        "See \\cite{new-a} and \\citep[p.~2]{b, new-c,new-a}.\n% \\cite{a} stays\nNot a citation: a.\n"
+    );
+    assert_eq!(rename_citations(text, &BTreeMap::new()), text);
+}
@@ -0,0 +1,250 @@
+/// utilities to format every entry of a bibtex file the same way, the way
+/// DBLP formats its entries
+use crate::bib;
+use crate::error::DblpError;
+use anyhow::{bail, Result};
+use biblatex::{RawBibliography, RawEntry};
+use std::ops::Range;
+
+/// The fields that come first, in this order, before the others in
+/// alphabetical order
+const FIELD_ORDER: [&str; 10] = [
+    "author",
+    "title",
+    "booktitle",
+    "journal",
+    "volume",
+    "number",
+    "pages",
+    "year",
+    "doi",
+    "url",
+];
+
+/// Width the names of the fields are padded to, as in DBLP
+const NAME_WIDTH: usize = 12;
+
+/// Commands that are not entries, and are left as they are
+const COMMANDS: [&str; 3] = ["string", "preamble", "comment"];
+
+/// An entry that is not formatted the canonical way
+#[derive(Debug, PartialEq)]
+pub enum Untidy {
+    Changed {
+        key: String,
+        range: Range<usize>,
+        tidied: String,
+    },
+    /// the entry cannot be parsed, and is left as it is
+    Unparsable { range: Range<usize> },
+}
+
+/// The source with the untidy entries replaced by their tidy text, and the
+/// ones that cannot be parsed left as they are
+pub fn apply(src: &str, untidy: &[Untidy]) -> String {
+    let edits = untidy
+        .iter()
+        .filter_map(|untidy| match untidy {
+            Untidy::Changed { range, tidied, .. } => Some((range.clone(), tidied.clone())),
+            Untidy::Unparsable { .. } => None,
+        })
+        .collect();
+    bib::splice(src, edits)
+}
+
+/// The entries of the source that tidying changes, and the ones it cannot
+/// parse. Fails if an entry would not parse back to the same fields once
+/// tidied.
+pub fn untidy_entries(src: &str) -> Result<Vec<Untidy>> {
+    let mut untidy = Vec::new();
+    for range in blocks(src) {
+        let text = &src[range.clone()];
+        let kind = text[1..]
+            .split(['{', '('])
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if COMMANDS.contains(&kind.as_str()) {
+            continue;
+        }
+        let raw = match RawBibliography::parse(text) {
+            Ok(raw) if raw.entries.len() == 1 => raw,
+            _ => {
+                untidy.push(Untidy::Unparsable { range });
+                continue;
+            }
+        };
+        let entry = &raw.entries[0].v;
+        let tidied = format(text, entry);
+        if tidied != text {
+            check_round_trip(text, entry, &tidied)?;
+            untidy.push(Untidy::Changed {
+                key: entry.key.v.to_owned(),
+                range,
+                tidied,
+            });
+        }
+    }
+    Ok(untidy)
+}
+
+/// The parts of the source that start with an `@` at the start of a line,
+/// each up to its closing bracket or, if it is not closed, up to the next one
+fn blocks(src: &str) -> Vec<Range<usize>> {
+    let starts: Vec<usize> = src
+        .match_indices('@')
+        .map(|(i, _)| i)
+        .filter(|&i| src[..i].rsplit('\n').next().unwrap_or("").trim().is_empty())
+        .collect();
+    let mut blocks: Vec<Range<usize>> = Vec::new();
+    for (n, &start) in starts.iter().enumerate() {
+        if blocks.last().is_some_and(|last| start < last.end) {
+            continue;
+        }
+        let next = starts
+            .get(n + 1)
+            .copied()
+            .unwrap_or(src.len())
+            .max(start + 1);
+        let end = match bib::closing(&src[start..next]) {
+            Some(end) => start + end,
+            None => start + src[start..next].trim_end().len(),
+        };
+        blocks.push(start..end);
+    }
+    blocks
+}
+
+/// The position of the field in the canonical order
+fn rank(name: &str) -> (usize, &str) {
+    match FIELD_ORDER.iter().position(|f| *f == name) {
+        Some(i) => (i, ""),
+        None => (FIELD_ORDER.len(), name),
+    }
+}
+
+/// The value as written in the source, in braces if it is a quoted string
+/// or a number, with its lines after the first aligned like in DBLP
+fn tidy_value(value: &str) -> String {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .filter(|v| !v.contains('"'));
+    let value = match inner {
+        Some(inner) => format!("{{{}}}", inner),
+        None if value.chars().all(|c| c.is_ascii_digit()) => format!("{{{}}}", value),
+        None => value.to_owned(),
+    };
+    let indent = " ".repeat(2 + NAME_WIDTH + 4);
+    let mut lines = value.lines();
+    let mut out = lines.next().unwrap_or_default().to_owned();
+    for line in lines {
+        out.push('\n');
+        out.push_str(&indent);
+        out.push_str(line.trim_start());
+    }
+    out
+}
+
+/// The entry of the text, formatted the canonical way
+fn format(text: &str, entry: &RawEntry) -> String {
+    let mut fields: Vec<(String, &str)> = entry
+        .fields
+        .iter()
+        .map(|pair| {
+            (
+                pair.key.v.to_lowercase(),
+                text[pair.value.span.clone()].trim_end(),
+            )
+        })
+        .collect();
+    fields.sort_by(|(a, _), (b, _)| rank(a).cmp(&rank(b)));
+    let mut out = format!("@{}{{{},\n", entry.kind.v.to_lowercase(), entry.key.v);
+    for (name, value) in fields {
+        out.push_str(&format!(
+            "  {:<width$} = {},\n",
+            name,
+            tidy_value(value),
+            width = NAME_WIDTH
+        ));
+    }
+    out.push('}');
+    out
+}
+
+/// The type, key and fields of the entry, regardless of how the values
+/// are delimited and spaced
+fn contents(text: &str, entry: &RawEntry) -> (String, String, Vec<(String, String)>) {
+    let mut fields: Vec<(String, String)> = entry
+        .fields
+        .iter()
+        .map(|pair| {
+            let value = bib::normalize_value(&text[pair.value.span.clone()]);
+            (
+                pair.key.v.to_lowercase(),
+                value.trim_matches('"').to_owned(),
+            )
+        })
+        .collect();
+    fields.sort();
+    (entry.kind.v.to_lowercase(), entry.key.v.to_owned(), fields)
+}
+
+/// Fails unless the tidied entry parses back to the same entry
+fn check_round_trip(text: &str, entry: &RawEntry, tidied: &str) -> Result<()> {
+    let raw = RawBibliography::parse(tidied).map_err(|e| DblpError::from_bib(e, tidied))?;
+    match raw.entries.as_slice() {
+        [parsed] if contents(tidied, &parsed.v) == contents(text, entry) => Ok(()),
+        _ => bail!(
+            "tidying {} would change it, leaving the file as it is",
+            entry.key.v
+        ),
+    }
+}
+
+#[test]
+fn test_untidy_entries() {
+    let src = "% refs\n@String{acm = {ACM}}\n\n@InProceedings{b,\n\tyear=2016, Publisher = acm,\ntitle = \"A {Title}\",\n  booktitle = {Some\n     Conference},\n    author = {Jane Doe}\n}\n\n@misc{broken, title = {Open}\n\n@article{c,\n  title        = {Tidy},\n}\n";
+    let untidy = untidy_entries(src).unwrap();
+    let b = src.find("@InProceedings").unwrap();
+    let broken = src.find("@misc").unwrap();
+    assert_eq!(
+        untidy,
+        [
+            Untidy::Changed {
+                key: "b".into(),
+                range: b..src.find("\n\n@misc").unwrap(),
+                tidied: "@inproceedings{b,\n  author       = {Jane Doe},\n  title        = {A {Title}},\n  booktitle    = {Some\n                  Conference},\n  year         = {2016},\n  publisher    = acm,\n}".into(),
+            },
+            Untidy::Unparsable {
+                range: broken..broken + "@misc{broken, title = {Open}".len()
+            },
+        ]
+    );
+    let Untidy::Changed { tidied, .. } = &untidy[0] else {
+        unreachable!()
+    };
+    assert_eq!(untidy_entries(tidied).unwrap(), []);
+    let applied = apply(src, &untidy);
+    assert!(applied.starts_with("% refs\n@String{acm = {ACM}}\n\n@inproceedings{b,\n"));
+    assert!(applied.ends_with(
+        "\n}\n\n@misc{broken, title = {Open}\n\n@article{c,\n  title        = {Tidy},\n}\n"
+    ));
+}
+
+#[test]
+fn test_tidy_dblp() {
+    let src = include_str!("../tests/fixtures/Chan16.bib");
+    let untidy = untidy_entries(src).unwrap();
+    let Untidy::Changed { tidied, .. } = &untidy[0] else {
+        panic!("{:?}", untidy)
+    };
+    // only editor and publisher move after the fields that come first
+    assert!(
+        tidied.contains("  pages        = {514--523},\n  year         = {2016},\n  doi          = {10.1137/1.9781611974331.ch38},\n"),
+        "{}",
+        tidied
+    );
+    assert!(tidied.contains("  booktitle    = {Proceedings of the Twenty-Seventh Annual {ACM-SIAM} Symposium on Discrete\n                  Algorithms,"));
+}
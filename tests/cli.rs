@@ -0,0 +1,3252 @@
+//! end to end tests, running the binary in a sandbox directory against a
+//! local server standing in for DBLP
+use assert_cmd::Command;
+use common::{fixture_path, MockDblp};
+use dblp_rs::error::exit;
+use std::{net::TcpListener, path::PathBuf};
+
+mod common;
+
+/// A temporary directory to run the binary in, with its own data directory
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("dblp-rs-cli-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn fixture(&self, fixture: &str, name: &str) -> PathBuf {
+        let path = self.dir.join(name);
+        std::fs::copy(fixture_path(fixture), &path).unwrap();
+        path
+    }
+
+    /// Writes the config file the binary reads
+    fn config(&self, toml: &str) {
+        let path = self.dir.join("config/dblp-rs/config.toml");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, toml).unwrap();
+    }
+
+    fn read(&self, name: &str) -> String {
+        std::fs::read_to_string(self.dir.join(name)).unwrap()
+    }
+
+    /// The binary, pointed at the server if any, and at a closed port
+    /// otherwise, so that tests never reach the real DBLP, and at a config
+    /// directory of its own
+    fn cmd(&self, server: Option<&MockDblp>) -> Command {
+        Command::from_std(self.process(server))
+    }
+
+    /// Like `cmd`, as a process that can be spawned and killed
+    fn process(&self, server: Option<&MockDblp>) -> std::process::Command {
+        let mut cmd = std::process::Command::new(env!("CARGO_BIN_EXE_dblp"));
+        cmd.current_dir(&self.dir)
+            .env_remove("BIBINPUTS")
+            .env("DBLP_RS_DATA_DIR", self.dir.join("data"))
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env(
+                "DBLP_RS_API_BASE",
+                server.map_or("http://127.0.0.1:9", |s| s.base.as_str()),
+            )
+            .env(
+                "DBLP_RS_DOI_BASE",
+                server.map_or("http://127.0.0.1:9", |s| s.base.as_str()),
+            )
+            .env(
+                "DBLP_RS_ARXIV_BASE",
+                server.map_or("http://127.0.0.1:9", |s| s.base.as_str()),
+            );
+        cmd
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_convert() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched.clone())]);
+    let sandbox = Sandbox::new("convert");
+    let original = "% my references\n@string{soda = \"SODA\"}\n\n@inproceedings{DBLP:conf/soda/Chan16,\n  title = {Old},\n  keywords = {mine},\n  abstract = {In short.},\n  year = {2016}\n}\n\n@misc{local, title = {Local}, howpublished = soda}\n\n@misc{DBLP:conf/soda/Missing16, title = {Gone}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["--verbose", "convert", "standard"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let converted = sandbox.read("refs.bib");
+    assert!(converted.contains("All-Pairs Shortest Paths"));
+    assert!(converted.contains("keywords = {mine}"));
+    // the fields DBLP lacks are kept, the ones it has are its own
+    assert!(
+        converted.contains("abstract = {In short.}"),
+        "{}",
+        converted
+    );
+    assert!(!converted.contains("{Old}"), "{}", converted);
+    // everything else is kept as written
+    assert!(
+        converted.starts_with("% my references\n@string{soda = \"SODA\"}\n\n@inproceedings"),
+        "{}",
+        converted
+    );
+    assert!(converted.contains("\n@misc{local, title = {Local}, howpublished = soda}\n"));
+    assert!(converted.ends_with("\n\n@misc{DBLP:conf/soda/Missing16, title = {Gone}}\n"));
+    assert!(sandbox.read("refs.bib.bak").starts_with(original));
+    // the missing record is reported, and progress is logged as plain lines
+    let err = stderr(&output);
+    assert!(err.contains("Missing16 (not_found)"), "{}", err);
+    assert!(err.contains("[3/3]"), "{}", err);
+    assert_eq!(server.requests().len(), 2);
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["convert", "standard", "--no-merge"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let converted = sandbox.read("refs.bib");
+    assert!(converted.contains("keywords = {mine}"), "{}", converted);
+    assert!(!converted.contains("abstract"), "{}", converted);
+}
+
+/// A run killed while converting leaves the bib file as it was, and the next
+/// one removes what a run killed while writing leaves
+#[cfg(unix)]
+#[test]
+fn test_convert_killed() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let route = "/rec/conf/soda/Chan16.bib?param=1";
+    let slow = MockDblp::start_slow(
+        vec![(route, fetched.clone())],
+        std::time::Duration::from_secs(10),
+    );
+    let sandbox = Sandbox::new("convert-killed");
+    let original = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {Old}\n}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let left = || -> Vec<String> {
+        std::fs::read_dir(&sandbox.dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(".refs.bib.tmp-") || name.ends_with(".lock"))
+            .collect()
+    };
+
+    let mut child = sandbox
+        .process(Some(&slow))
+        .args(["--no-cache", "convert", "standard"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let start = std::time::Instant::now();
+    while slow.requests().is_empty() {
+        assert!(start.elapsed().as_secs() < 10, "convert never fetched");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(sandbox.read("refs.bib"), original);
+    assert!(left().is_empty(), "{:?}", left());
+
+    // as if it had been killed while writing the converted file
+    let scratch = format!(".refs.bib.tmp-{}-0", child.id());
+    std::fs::write(sandbox.dir.join(&scratch), "@inproceedings{DBLP:conf/").unwrap();
+    let server = MockDblp::start(vec![(route, fetched)]);
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["--no-cache", "convert", "standard"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("All-Pairs Shortest Paths"));
+    assert!(left().is_empty(), "{:?}", left());
+}
+
+#[test]
+fn test_convert_backup() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("convert-backup");
+    let original = "@inproceedings{DBLP:conf/soda/Chan16, title = {Old}}\n";
+    let backups = sandbox.dir.join("backups");
+    std::fs::create_dir_all(&backups).unwrap();
+    let convert = |args: &[&str]| {
+        std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+        sandbox
+            .cmd(Some(&server))
+            .args(["--no-cache", "convert", "standard"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = convert(&["--no-backup"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!sandbox.dir.join("refs.bib.bak").exists());
+
+    let output = convert(&["--backup-dir", "backups"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!sandbox.dir.join("refs.bib.bak").exists());
+    let names: Vec<String> = std::fs::read_dir(&backups)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names.len(), 1, "{:?}", names);
+    assert!(
+        names[0].starts_with("refs-") && names[0].ends_with(".bib"),
+        "{:?}",
+        names
+    );
+    assert!(std::fs::read_to_string(backups.join(&names[0]))
+        .unwrap()
+        .starts_with(original));
+
+    // the original is left alone if the backup cannot be made
+    let output = convert(&["--backup-dir", "missing"]);
+    assert_eq!(output.status.code(), Some(exit::BIB_FILE.into()));
+    assert!(
+        stderr(&output).contains("its directory does not exist"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+}
+
+#[test]
+fn test_undo() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("undo");
+    let original =
+        "@inproceedings{DBLP:conf/soda/Chan16, title = {Old}}\n\n@misc{local, title = {Local}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let undo = |args: &[&str], stdin: &str| {
+        sandbox
+            .cmd(None)
+            .arg("undo")
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .unwrap()
+    };
+
+    let output = undo(&["--yes"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("there is no backup of ./refs.bib"),
+        "{}",
+        stderr(&output)
+    );
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["convert", "standard"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let converted = sandbox.read("refs.bib");
+    assert_ne!(converted, original);
+
+    // declining leaves the converted file
+    let output = undo(&[], "n\n");
+    assert_eq!(output.status.code(), Some(exit::ABORTED.into()));
+    let err = stderr(&output);
+    assert!(
+        err.contains("restoring ./refs.bib.bak adds 0 entries, removes 0 and changes 1\n  ~ DBLP:conf/soda/Chan16\n"),
+        "{}",
+        err
+    );
+    assert_eq!(sandbox.read("refs.bib"), converted);
+    let output = undo(&[], "y\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    // a backup much older than the file is refused
+    std::fs::write(sandbox.dir.join("refs.bib"), "@misc{edited, title = {E}}\n").unwrap();
+    let now = std::time::SystemTime::now();
+    for (name, modified) in [
+        ("refs.bib", now),
+        ("refs.bib.bak", now - std::time::Duration::from_secs(3600)),
+    ] {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(sandbox.dir.join(name))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+    let output = undo(&["--yes"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("1h00m older than ./refs.bib"),
+        "{}",
+        stderr(&output)
+    );
+    sandbox.config("undo_window_s = 7200");
+    let output = undo(&["--yes"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(sandbox.read("refs.bib"), original);
+}
+
+#[test]
+fn test_update() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched.clone()),
+        // a record DBLP moved to another key
+        ("/rec/journals/corr/Chan15.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("update");
+    let other = "@misc{DBLP:conf/soda/Other16,   title = {Other}}";
+    let original = format!(
+        "@inproceedings{{DBLP:conf/soda/Chan16,\n  title = {{Old}},\n  keywords = {{mine}},\n  pages = {{514--523}}\n}}\n\n{}\n",
+        other
+    );
+    std::fs::write(sandbox.dir.join("refs.bib"), &original).unwrap();
+    let update = |key: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["--no-cache", "update", key])
+            .output()
+            .unwrap()
+    };
+
+    let output = update("conf/soda/Chan16");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        out.contains("~ title: Old → All-Pairs Shortest Paths"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("+ doi = 10.1137/1.9781611974331.ch38\n"),
+        "{}",
+        out
+    );
+    assert!(!out.contains("pages"), "{}", out);
+    let updated = sandbox.read("refs.bib");
+    assert!(updated.contains("keywords = {mine}"), "{}", updated);
+    assert!(
+        updated.ends_with(&format!("}}\n\n{}\n", other)),
+        "{}",
+        updated
+    );
+    let output = update("DBLP:conf/soda/Chan16");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("DBLP:conf/soda/Chan16 is up to date"));
+    assert_eq!(sandbox.read("refs.bib"), updated);
+
+    // keys resolve as everywhere else
+    sandbox.config("[aliases]\napsp = \"DBLP:conf/soda/Chan16\"\n");
+    for key in ["Chan16", "apsp"] {
+        let output = update(key);
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert!(stderr(&output).contains("DBLP:conf/soda/Chan16 is up to date"));
+    }
+
+    let output = update("conf/soda/Missing16");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("no entry of the bib file matches conf/soda/Missing16"),
+        "{}",
+        stderr(&output)
+    );
+
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{DBLP:journals/corr/Chan15, title = {A Preprint}}\n",
+    )
+    .unwrap();
+    let output = update("DBLP:journals/corr/Chan15");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output)
+            .contains("DBLP replaced DBLP:journals/corr/Chan15 with DBLP:conf/soda/Chan16"),
+        "{}",
+        stderr(&output)
+    );
+    let updated = sandbox.read("refs.bib");
+    assert!(
+        updated.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"),
+        "{}",
+        updated
+    );
+
+    // an entry DBLP does not serve becomes what the policy says
+    let original = format!("@misc{{local, title = {{L}}}}\n\n{}\n", other);
+    for (policy, code, expected) in [
+        ("keep-original", 0, original.clone()),
+        (
+            "serialize",
+            0,
+            "@misc{local, title = {L}}\n\n@misc{DBLP:conf/soda/Other16,\ntitle = {Other},\n}\n"
+                .to_owned(),
+        ),
+        ("skip", 0, "@misc{local, title = {L}}\n\n".to_owned()),
+        ("fail", 1, original.clone()),
+    ] {
+        std::fs::write(sandbox.dir.join("refs.bib"), &original).unwrap();
+        let output = sandbox
+            .cmd(Some(&server))
+            .args([
+                "--no-cache",
+                "update",
+                "Other16",
+                "--on-fetch-error",
+                policy,
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(code), "{}", stderr(&output));
+        assert!(
+            stderr(&output).contains("1 entries could not be fetched, ")
+                && stderr(&output).contains("  DBLP:conf/soda/Other16: HTTP 404 from "),
+            "{}",
+            stderr(&output)
+        );
+        assert_eq!(sandbox.read("refs.bib"), expected, "{}", policy);
+    }
+}
+
+#[test]
+fn test_remove() {
+    let sandbox = Sandbox::new("remove");
+    let original = "% my references\n@misc{a, title = {First Paper}}\n\n@misc{b, title = {Second Paper}}\n\n@misc{c, title = {Third Paper}, author = {Jane Doe}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    std::fs::write(sandbox.dir.join("main.tex"), "As in~\\cite{c}.\n").unwrap();
+    let remove = |args: &[&str], stdin: &str| {
+        sandbox
+            .cmd(None)
+            .arg("remove")
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .unwrap()
+    };
+
+    let output = remove(&["nothing", "like", "it"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    // the query leaves the second and third, of which both are picked
+    let output = remove(&["paper", "d"], "1 2\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let err = stderr(&output);
+    assert!(err.contains("  1. b Second Paper"), "{}", err);
+    assert!(!err.contains("First Paper"), "{}", err);
+    assert!(err.contains("removed b, c"), "{}", err);
+    assert!(
+        err.contains("warning: c is still cited at ./main.tex:1"),
+        "{}",
+        err
+    );
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        "% my references\n@misc{a, title = {First Paper}}\n"
+    );
+    assert_eq!(sandbox.read("refs.bib.bak"), original);
+
+    // a key is removed without picking
+    let output = remove(&["a"], "");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("removed a"), "{}", stderr(&output));
+    assert_eq!(sandbox.read("refs.bib"), "% my references\n");
+}
+
+#[test]
+fn test_list() {
+    let sandbox = Sandbox::new("list");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {First Paper}}\n\n@inproceedings{b, title = {Second Paper}, booktitle = {SODA}, year = 2016}\n",
+    )
+    .unwrap();
+
+    let output = sandbox
+        .cmd(None)
+        .args(["list", "--print-key"])
+        .write_stdin("2\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("  2. b Second Paper"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "b\n");
+
+    std::fs::write(sandbox.dir.join("refs.bib"), "% nothing yet\n").unwrap();
+    let output = sandbox.cmd(None).arg("list").output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("there are no entries in ./refs.bib"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+/// Replaces xdg-open with a script that writes the url it is given to
+/// `opened` in the sandbox
+#[cfg(all(unix, not(target_os = "macos")))]
+fn fake_opener(sandbox: &Sandbox) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let bin = sandbox.dir.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let script = bin.join("xdg-open");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$1\" > {}\n",
+            sandbox.dir.join("opened").display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{}:{}", bin.display(), std::env::var("PATH").unwrap())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_open() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    let sandbox = Sandbox::new("open");
+    let path = fake_opener(&sandbox);
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {First Paper}, url = {https://example.org/a}, doi = {10.1/a}}\n",
+    )
+    .unwrap();
+    let opened = || {
+        // the browser is not waited for
+        for _ in 0..50 {
+            if let Ok(url) = std::fs::read_to_string(sandbox.dir.join("opened")) {
+                let _ = std::fs::remove_file(sandbox.dir.join("opened"));
+                return url;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        panic!("nothing was opened");
+    };
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .env("PATH", &path)
+        .args(["open", "chan"])
+        .write_stdin("2\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(opened(), format!("{}/rec/conf/soda/Other16\n", server.base));
+
+    // the doi of the entry of the bib file, rather than its url
+    let output = sandbox
+        .cmd(None)
+        .env("PATH", &path)
+        .arg("open")
+        .write_stdin("1\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(opened(), "https://doi.org/10.1/a\n");
+}
+
+/// Without a clipboard, or with the output going to a program, clip prints
+/// the entry instead
+#[cfg(unix)]
+#[test]
+fn test_clip_without_clipboard() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("clip-fallback");
+    let empty = sandbox.dir.join("empty");
+    std::fs::create_dir_all(&empty).unwrap();
+    for (args, warning) in [
+        (&["clip", "--first", "chan"][..], false),
+        (&["--no-clipboard", "clip", "--first", "chan"], false),
+        (&["--clipboard", "command", "clip", "--first", "chan"], true),
+    ] {
+        let output = sandbox
+            .cmd(Some(&server))
+            .env("PATH", &empty)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            stderr(&output).contains("printing the entry instead"),
+            warning,
+            "{:?}: {}",
+            args,
+            stderr(&output)
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"),
+            "{}",
+            stdout
+        );
+    }
+}
+
+#[test]
+fn test_clip_what() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=0", fetched),
+    ]);
+    let sandbox = Sandbox::new("clip-what");
+    let clip = |args: &[&str], stdin: &str| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .arg("clip")
+            .args(args)
+            .arg("chan")
+            .write_stdin(stdin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    assert_eq!(
+        clip(&["--what", "cite"], "1 2\n"),
+        "\\cite{DBLP:conf/soda/Chan16,DBLP:conf/soda/Other16}\n"
+    );
+    assert_eq!(
+        clip(&["--what", "autocite"], "2\n"),
+        "\\autocite{DBLP:conf/soda/Other16}\n"
+    );
+    assert_eq!(
+        clip(&["--what", "key", "--first"], ""),
+        "DBLP:conf/soda/Chan16\n"
+    );
+    assert_eq!(
+        clip(&["--what", "text", "--first"], ""),
+        "Timothy M. Chan. All-Pairs Shortest Paths. SODA 2016.\n"
+    );
+    let condensed = clip(&["--format", "condensed", "--first"], "");
+    assert!(condensed.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"));
+    assert!(server
+        .requests()
+        .iter()
+        .any(|r| r.ends_with("/rec/conf/soda/Chan16.bib?param=0")));
+}
+
+#[test]
+fn test_dedup() {
+    let sandbox = Sandbox::new("dedup");
+    let original = "@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan}, title = {All-Pairs Shortest Paths}, year = {2016}}\n\n@misc{other, title = {Another Paper}, year = {2016}}\n\n@misc{chan16, author = {Chan, Timothy}, title = {All-pairs shortest paths.}, year = {2016}}\n\n@misc{namesake, author = {Jane Doe}, title = {All-pairs shortest paths}, year = {2016}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    std::fs::write(sandbox.dir.join("main.tex"), "\\cite{chan16, other}\n").unwrap();
+
+    let output = sandbox
+        .cmd(None)
+        .args(["dedup", "--report"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Chan16 (./refs.bib:1), chan16 (./refs.bib:5)\n"
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    let dedup = |answer: &str| {
+        sandbox
+            .cmd(None)
+            .arg("dedup")
+            .write_stdin(answer)
+            .output()
+            .unwrap()
+    };
+    // the last choice keeps them all
+    let output = dedup("3\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("3. not duplicates, keep all"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    let output = dedup("1\n");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        "@inproceedings{DBLP:conf/soda/Chan16, author = {Timothy M. Chan}, title = {All-Pairs Shortest Paths}, year = {2016}}\n\n@misc{other, title = {Another Paper}, year = {2016}}\n\n@misc{namesake, author = {Jane Doe}, title = {All-pairs shortest paths}, year = {2016}}\n"
+    );
+    assert_eq!(
+        sandbox.read("main.tex"),
+        "\\cite{DBLP:conf/soda/Chan16, other}\n"
+    );
+    let output = sandbox
+        .cmd(None)
+        .args(["dedup", "--report"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_sort() {
+    let sandbox = Sandbox::new("sort");
+    let original = "% my references\n\n@misc{b, author = {Zoe Adams}, date = {2019-05}}\n\n@string{acm = {ACM}}\n\n% read again\n@misc{A,\n    author = {Jane Doe},\n  year = 2021}\n\n@misc{c, title = {No Year}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let sort = |args: &[&str]| sandbox.cmd(None).arg("sort").args(args).output().unwrap();
+
+    let output = sort(&["--by", "key", "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("./refs.bib is not sorted by key, A should come earlier"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sort(&["--by", "year", "--check"]).status.success());
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    // entries without a year stay last, even in reverse
+    let output = sort(&["--by", "year", "--reverse"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        "% my references\n\n@string{acm = {ACM}}\n\n% read again\n@misc{A,\n    author = {Jane Doe},\n  year = 2021}\n\n@misc{b, author = {Zoe Adams}, date = {2019-05}}\n\n@misc{c, title = {No Year}}\n"
+    );
+    assert_eq!(sandbox.read("refs.bib.bak"), original);
+
+    let output = sort(&["--by", "author", "--dry-run"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "b  adams zoe\nA  doe jane\nc  -\n"
+    );
+}
+
+/// Only writing the bib file waits for the lock, checking and showing the
+/// order do not
+#[cfg(unix)]
+#[test]
+fn test_sort_locked() {
+    let sandbox = Sandbox::new("sort-locked");
+    sandbox.config("lock_timeout_s = 0\n");
+    let original = "@misc{b, title = {B}}\n\n@misc{a, title = {A}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let lock = sandbox.dir.join("refs.bib.lock");
+    std::fs::write(&lock, std::process::id().to_string()).unwrap();
+    let held = std::fs::File::open(&lock).unwrap();
+    held.lock().unwrap();
+    let sort = |args: &[&str]| sandbox.cmd(None).arg("sort").args(args).output().unwrap();
+
+    let output = sort(&["--by", "key", "--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let output = sort(&["--by", "key", "--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("is not sorted by key"),
+        "{}",
+        stderr(&output)
+    );
+    let output = sort(&["--by", "key"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("is locked by another dblp process"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+}
+
+#[test]
+fn test_tidy() {
+    let sandbox = Sandbox::new("tidy");
+    let original = "@Misc{a, Year = 2020,\n title = \"First\"}\n\n@misc{broken, title = {Open}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let tidy = |args: &[&str]| sandbox.cmd(None).arg("tidy").args(args).output().unwrap();
+
+    let output = tidy(&["--check"]);
+    assert_eq!(output.status.code(), Some(1));
+    let err = stderr(&output);
+    assert!(err.contains("a (./refs.bib:1) is not tidy"), "{}", err);
+    assert!(
+        err.contains("warning: cannot parse the entry at ./refs.bib:4, leaving it as it is"),
+        "{}",
+        err
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    let output = tidy(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        "@misc{a,\n  title        = {First},\n  year         = {2020},\n}\n\n@misc{broken, title = {Open}\n"
+    );
+    assert_eq!(sandbox.read("refs.bib.bak"), original);
+    let output = tidy(&["--check"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_convert_dry_run() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("convert-dry-run");
+    let original = "@inproceedings{DBLP:conf/soda/Chan16,\n  title = {Old},\n  year = {2016}\n}\n\n@misc{local, title = {Local}}\n\n@misc{DBLP:conf/soda/Missing16, title = {Gone}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let convert = |args: &[&str]| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["convert", "standard"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = convert(&["--dry-run"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(sandbox.read("refs.bib"), original);
+    assert!(!sandbox.dir.join("refs.bib.bak").exists());
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        out.contains(
+            "--- DBLP:conf/soda/Chan16 (./refs.bib:1)\n+++ DBLP:conf/soda/Chan16 (standard)\n"
+        ),
+        "{}",
+        out
+    );
+    assert!(out.contains("\n-  title = {Old},\n"), "{}", out);
+    assert!(out.contains("\n+  pages        = {514--523},\n"), "{}", out);
+    assert!(!out.contains("Missing16"), "{}", out);
+    let err = stderr(&output);
+    assert!(
+        err.contains("1 entries unchanged, 0 reformatted, 1 changed, 1 fetch failures"),
+        "{}",
+        err
+    );
+    // each entry is only reported with --verbose
+    assert!(!err.contains("Error in fetching"), "{}", err);
+    assert!(
+        err.contains(
+            "1 refreshed from DBLP, 1 kept as local text, 1 not from DBLP, 1 failed: DBLP:conf/soda/Missing16"
+        ),
+        "{}",
+        err
+    );
+
+    // once converted, nothing would change
+    assert!(convert(&[]).status.success());
+    let output = convert(&["--dry-run"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(output.stdout.is_empty());
+    assert!(stderr(&output).contains("2 entries unchanged, 0 reformatted, 0 changed"));
+}
+
+#[test]
+fn test_convert_only() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let other = fetched.replace("Chan16", "Other16");
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+        ("/rec/conf/soda/Other16.bib?param=1", other),
+    ]);
+    let sandbox = Sandbox::new("convert-only");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@inproceedings{DBLP:conf/soda/Chan16, title = {Old}}\n\n@inproceedings{DBLP:conf/soda/Other16, title = {Old}}\n",
+    )
+    .unwrap();
+    let convert = |args: &[&str]| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["--no-cache", "convert", "standard"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stderr(&output)
+    };
+
+    let err = convert(&["--only", "*/Chan1?", "--only", "nothing"]);
+    assert!(
+        err.contains("1 of 2 DBLP entries match the filter"),
+        "{}",
+        err
+    );
+    assert_eq!(server.requests(), ["/rec/conf/soda/Chan16.bib?param=1"]);
+    let converted = sandbox.read("refs.bib");
+    assert!(
+        converted.contains("All-Pairs Shortest Paths"),
+        "{}",
+        converted
+    );
+    assert!(converted.contains("title = {Old}"), "{}", converted);
+
+    let err = convert(&["--skip", "soda"]);
+    assert!(
+        err.contains("0 of 2 DBLP entries match the filter"),
+        "{}",
+        err
+    );
+    assert!(err.contains("0 refreshed from DBLP"), "{}", err);
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn test_convert_crossref() {
+    let proceedings = "@proceedings{DBLP:conf/soda/2016,\n  editor = {Robert Krauthgamer},\n  title = {SODA 2016},\n  year = {2016},\n}";
+    let paper = |key: &str| {
+        format!(
+            "@inproceedings{{DBLP:conf/soda/{},\n  title = {{{} Paper}},\n  crossref = {{DBLP:conf/soda/2016}},\n}}\n\n{}\n",
+            key, key, proceedings
+        )
+    };
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=2", paper("Chan16")),
+        ("/rec/conf/soda/Other16.bib?param=2", paper("Other16")),
+    ]);
+    let sandbox = Sandbox::new("convert-crossref");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@inproceedings{DBLP:conf/soda/Chan16, title = {Old}}\n\n@inproceedings{DBLP:conf/soda/Other16, title = {Old}}\n",
+    )
+    .unwrap();
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["convert", "crossref"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    // the proceedings are written once, after the papers referring to them
+    let converted = sandbox.read("refs.bib");
+    assert_eq!(
+        converted.matches("@proceedings").count(),
+        1,
+        "{}",
+        converted
+    );
+    let at = |needle: &str| converted.find(needle).unwrap();
+    assert!(at("Chan16 Paper") < at("Other16 Paper"));
+    assert!(at("Other16 Paper") < at("@proceedings{DBLP:conf/soda/2016,"));
+}
+
+#[test]
+fn test_convert_on_fetch_error() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+        ("/rec/conf/soda/Broken16.bib?param=1", "HTTP 500".to_owned()),
+    ]);
+    let sandbox = Sandbox::new("on-fetch-error");
+    // not to wait before retrying the errors of the server
+    sandbox.config("retry_backoff_ms = 0");
+    let broken = "@misc{DBLP:conf/soda/Broken16,   title={Broken},\n    year = 2016 }";
+    let original = format!(
+        "@inproceedings{{DBLP:conf/soda/Chan16, title = {{Old}}}}\n\n{}\n\n@misc{{local, title = {{Local}}}}\n",
+        broken
+    );
+    let convert = |policy: &str| {
+        std::fs::write(sandbox.dir.join("refs.bib"), &original).unwrap();
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["convert", "standard", "--on-fetch-error", policy])
+            .output()
+            .unwrap();
+        let err = stderr(&output);
+        assert!(
+            err.contains("1 entries could not be fetched"),
+            "{}: {}",
+            policy,
+            err
+        );
+        assert!(
+            err.contains(&format!(
+                "  DBLP:conf/soda/Broken16: HTTP 500 from {} (transport)",
+                server.base
+            )),
+            "{}: {}",
+            policy,
+            err
+        );
+        (output.status.code(), sandbox.read("refs.bib"))
+    };
+
+    let (code, converted) = convert("keep-original");
+    assert_eq!(code, Some(0));
+    assert!(
+        converted.contains(&format!("\n\n{}\n\n@misc{{local,", broken)),
+        "{}",
+        converted
+    );
+    let (code, converted) = convert("serialize");
+    assert_eq!(code, Some(0));
+    assert!(
+        converted.contains(
+            "\n\n@misc{DBLP:conf/soda/Broken16,\ntitle = {Broken},\nyear = {2016},\n}\n\n"
+        ),
+        "{}",
+        converted
+    );
+    let (code, converted) = convert("skip");
+    assert_eq!(code, Some(0));
+    assert!(!converted.contains("Broken16"), "{}", converted);
+    assert!(converted.contains("All-Pairs Shortest Paths"));
+    assert!(converted.contains("@misc{local,"));
+    let (code, converted) = convert("fail");
+    assert_eq!(code, Some(1));
+    assert_eq!(converted, original);
+}
+
+#[test]
+fn test_add_atomically() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+        ("/rec/conf/soda/Other16.bib?param=1", "HTTP 500".to_owned()),
+    ]);
+    let sandbox = Sandbox::new("add-atomically");
+    sandbox.config("retry_backoff_ms = 0");
+    let original = "@misc{local, title = {Local}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+
+    // the second entry fails after the first is fetched
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "chan"])
+        .write_stdin("1 2\n")
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.status.code(),
+        Some(exit::NETWORK.into()),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+    let leftovers: Vec<_> = std::fs::read_dir(&sandbox.dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.contains(".tmp"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[test]
+fn test_strip_fields() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib"))
+        .unwrap()
+        .replace(
+            "}\n",
+            "  timestamp    = {Fri, 02 Nov 2018 09:38:56 +0100},\n  biburl       = {https://dblp.org/rec/conf/soda/Chan16.bib},\n  bibsource    = {dblp computer science bibliography,\n                  https://dblp.org}\n}\n",
+        );
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("strip-fields");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--first", "chan", "--strip-fields"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let added = sandbox.read("refs.bib");
+    assert!(
+        added.contains("  doi          = {10.1137/1.9781611974331.ch38},\n}"),
+        "{}",
+        added
+    );
+    for field in ["timestamp", "biburl", "bibsource", "dblp.org"] {
+        assert!(!added.contains(field), "{}", added);
+    }
+
+    // the fields of the config, and the given ones
+    sandbox.config("strip_fields = [\"timestamp\", \"editor\"]");
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["--no-cache", "convert", "standard", "--strip-fields=pages"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let converted = sandbox.read("refs.bib");
+    assert!(converted.contains("biburl"), "{}", converted);
+    for field in ["timestamp", "editor", "pages"] {
+        assert!(!converted.contains(field), "{}", converted);
+    }
+}
+
+fn search_response() -> String {
+    let hit = |key: &str, title: &str| {
+        format!(
+            r#"{{"info": {{"key": "{}", "authors": {{"author": {{"@pid": "1", "text": "Timothy M. Chan"}}}},
+            "title": "{}", "venue": "SODA", "year": "2016", "url": "{{base}}/rec/{}"}}}}"#,
+            key, title, key
+        )
+    };
+    format!(
+        r#"{{"result": {{"hits": {{"@total": "2", "hit": [{}, {}]}}}}}}"#,
+        hit("conf/soda/Chan16", "All-Pairs Shortest Paths."),
+        hit("conf/soda/Other16", "Another Paper.")
+    )
+}
+
+/// The bib file is not modified while another process has it locked, and
+/// locks are released when the process is interrupted
+#[cfg(unix)]
+#[test]
+fn test_add_locked() {
+    use std::os::unix::{fs::PermissionsExt, process::ExitStatusExt};
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add-locked");
+    sandbox.config("lock_timeout_s = 0\n");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let lock = sandbox.dir.join("refs.bib.lock");
+    let add_key = || {
+        sandbox
+            .cmd(Some(&server))
+            .args(["add-key", "conf/soda/Chan16"])
+            .output()
+            .unwrap()
+    };
+
+    // held by a running process, here the test itself
+    std::fs::write(&lock, std::process::id().to_string()).unwrap();
+    let held = std::fs::File::open(&lock).unwrap();
+    held.lock().unwrap();
+    let output = add_key();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains(&format!(
+            "./refs.bib is locked by another dblp process ({})",
+            std::process::id()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), "");
+    drop(held);
+
+    // left by a process that is gone
+    let mut child = std::process::Command::new("true").spawn().unwrap();
+    std::fs::write(&lock, child.id().to_string()).unwrap();
+    child.wait().unwrap();
+    let output = add_key();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("which is not running"));
+    assert!(!lock.exists());
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("All-Pairs Shortest Paths"));
+
+    // interrupted in the editor, as with Ctrl-C
+    let editor = sandbox.dir.join("editor");
+    std::fs::write(&editor, "#!/bin/sh\nkill -INT $PPID\nsleep 1\n").unwrap();
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--edit", "--first", "chan"])
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.signal(), Some(2), "{}", stderr(&output));
+    assert!(!lock.exists());
+    assert_eq!(sandbox.read("refs.bib"), "");
+
+    // not locked while in the editor, and the file changed meanwhile is
+    // left as it is
+    let other = "@misc{other, title = {Other}}\n";
+    std::fs::write(
+        &editor,
+        format!(
+            "#!/bin/sh\ntest -e {} && exit 1\nprintf '{}' >> {}\n",
+            lock.display(),
+            other.replace('\n', "\\n"),
+            sandbox.dir.join("refs.bib").display()
+        ),
+    )
+    .unwrap();
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--edit", "--first", "chan"])
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("./refs.bib changed meanwhile, leaving it as it is"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!lock.exists());
+    assert_eq!(sandbox.read("refs.bib"), other);
+}
+
+/// Whatever the file ends with, the entry is appended after one blank line
+#[test]
+fn test_add_newlines() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![(
+        "/rec/conf/soda/Chan16.bib?param=1",
+        format!("\n{}\n\n\n", fetched.trim()),
+    )]);
+    let sandbox = Sandbox::new("add-newlines");
+    for (src, before) in [
+        ("", ""),
+        ("@misc{a, title = {A}}", "@misc{a, title = {A}}\n\n"),
+        ("@misc{a, title = {A}}\n\n\n\n", "@misc{a, title = {A}}\n\n"),
+    ] {
+        std::fs::write(sandbox.dir.join("refs.bib"), src).unwrap();
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["add-key", "conf/soda/Chan16"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(
+            sandbox.read("refs.bib"),
+            format!("{}{}\n\n", before, fetched.trim()),
+            "{:?}",
+            src
+        );
+    }
+}
+
+#[test]
+fn test_add_sorted() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add-sorted");
+    let add = |args: &[&str], src: &str| {
+        std::fs::write(sandbox.dir.join("refs.bib"), src).unwrap();
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["add", "--first"])
+            .args(args)
+            .arg("chan")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        (sandbox.read("refs.bib"), stderr(&output))
+    };
+    let sorted = "@misc{Abel, title = {A}}\n\n% last\n@misc{zhang, title = {Z}}\n";
+
+    // the query that follows is not taken as the order
+    let (bib, _) = add(&["--sorted"], sorted);
+    assert!(
+        bib.starts_with("@misc{Abel, title = {A}}\n\n@inproceedings{DBLP:conf/soda/Chan16,"),
+        "{}",
+        bib
+    );
+    assert!(
+        bib.ends_with("}\n\n% last\n@misc{zhang, title = {Z}}\n"),
+        "{}",
+        bib
+    );
+
+    // from the config too, and not when the file is not sorted
+    sandbox.config("insert_sorted = \"key\"\n");
+    let (bib, _) = add(&[], sorted);
+    assert!(bib.starts_with("@misc{Abel, title = {A}}\n\n@inproceedings{"));
+    let unsorted = "@misc{zhang, title = {Z}}\n\n@misc{Abel, title = {A}}\n";
+    let (bib, err) = add(&[], unsorted);
+    assert!(bib.starts_with(unsorted), "{}", bib);
+    assert!(
+        err.contains("warning: ./refs.bib is not sorted by key, appending the entries"),
+        "{}",
+        err
+    );
+    // by year, the entries without one come last
+    let (bib, _) = add(&["--sorted=year"], sorted);
+    assert!(bib.starts_with("@inproceedings{"), "{}", bib);
+}
+
+/// Entries edited into something else than an entry are opened again, with
+/// the error, and the ones left empty are not added
+#[cfg(unix)]
+#[test]
+fn test_add_edit() {
+    use std::os::unix::fs::PermissionsExt;
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add-edit");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    // an editor saving an unclosed entry the first time, and then an entry
+    // with another key, keeping what it was shown each time
+    let editor = sandbox.dir.join("editor");
+    let seen = sandbox.dir.join("seen");
+    std::fs::write(
+        &editor,
+        format!(
+            "#!/bin/sh\ncat \"$1\" >> {seen}\necho --- >> {seen}\n\
+             if [ $(grep -c -- --- {seen}) = 1 ]; then echo '@misc{{x, title = {{Open}}' > \"$1\"\n\
+             else echo '@misc{{chan16apsp, title = {{All-Pairs Shortest Paths}}}}' > \"$1\"; fi\n",
+            seen = seen.display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let add = || {
+        sandbox
+            .cmd(Some(&server))
+            .args(["add", "--edit", "--first", "--print-key", "chan"])
+            .env_remove("VISUAL")
+            .env("EDITOR", &editor)
+            .output()
+            .unwrap()
+    };
+
+    let output = add();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "chan16apsp\n");
+    let seen = sandbox.read("seen");
+    let shown: Vec<&str> = seen.split("---\n").collect();
+    assert!(
+        shown[0].starts_with(
+            "% dblp: empty the file not to add the entry\n@inproceedings{DBLP:conf/soda/Chan16,"
+        ),
+        "{}",
+        seen
+    );
+    assert!(shown[1].starts_with("% dblp: error: "), "{}", seen);
+    assert!(shown[1].contains("\n@misc{x, title = {Open}\n"), "{}", seen);
+    assert_eq!(shown.len(), 3, "{}", seen);
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        "@misc{chan16apsp, title = {All-Pairs Shortest Paths}}\n\n"
+    );
+
+    // left empty, nothing is added nor printed
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    std::fs::write(&editor, "#!/bin/sh\n: > \"$1\"\n").unwrap();
+    let output = add();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+    assert!(
+        stderr(&output).contains("not adding DBLP:conf/soda/Chan16, left empty"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), "");
+}
+
+/// An entry added by hand with the same DOI is kept by default, or replaced
+#[test]
+fn test_add_duplicate() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add-duplicate");
+    let mine = "@misc{mine,\n  title = {APSP},\n  doi = {https://doi.org/10.1137/1.9781611974331.CH38}\n}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), mine).unwrap();
+    std::fs::write(sandbox.dir.join("paper.tex"), "see \\cite{mine}\n").unwrap();
+    let add = |stdin: &str| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["add", "--first", "chan"])
+            .write_stdin(stdin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    for stdin in ["", "\n", "s\n"] {
+        assert_eq!(add(stdin), "mine\n");
+        assert_eq!(sandbox.read("refs.bib"), mine);
+    }
+    assert_eq!(add("r\n"), "DBLP:conf/soda/Chan16\n");
+    let replaced = sandbox.read("refs.bib");
+    assert!(
+        replaced.starts_with("% formerly mine\n@inproceedings{DBLP:conf/soda/Chan16,"),
+        "{}",
+        replaced
+    );
+    assert!(!replaced.contains("APSP"), "{}", replaced);
+    assert_eq!(
+        sandbox.read("paper.tex"),
+        "see \\cite{DBLP:conf/soda/Chan16}\n"
+    );
+    assert_eq!(sandbox.read("refs.bib.bak"), mine);
+}
+
+#[test]
+fn test_add_first() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add-first");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--first", "--print-key", "chan"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let err = stderr(&output);
+    assert!(
+        err.contains("picked All-Pairs Shortest Paths. SODA 2016"),
+        "{}",
+        err
+    );
+    assert!(!err.contains("not using the fuzzy picker"), "{}", err);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Chan16\n"
+    );
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["clip", "--first", "--type", "phd", "chan"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(exit::NO_RESULTS.into()));
+    assert!(
+        stderr(&output).contains("no hits for \"chan\""),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_info() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched.clone()),
+    ]);
+    let sandbox = Sandbox::new("info");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let info = |args: &[&str], stdin: &str| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .arg("info")
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let out = info(&["chan"], "1\n");
+    assert!(
+        out.starts_with("Timothy M. Chan\nAll-Pairs Shortest Paths"),
+        "{}",
+        out
+    );
+    assert!(out.contains("\npages: 514--523\n"), "{}", out);
+    assert!(
+        out.ends_with("\nkey:   DBLP:conf/soda/Chan16, not in ./refs.bib\n"),
+        "{}",
+        out
+    );
+
+    std::fs::write(sandbox.dir.join("refs.bib"), &fetched).unwrap();
+    let out = info(&["DBLP:conf/soda/Chan16"], "");
+    assert!(
+        out.ends_with("\nkey:   DBLP:conf/soda/Chan16, in ./refs.bib\n"),
+        "{}",
+        out
+    );
+    assert_eq!(info(&["--bibtex", "conf/soda/Chan16"], ""), fetched);
+}
+
+#[test]
+fn test_note() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("note");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{local, title = {A Local Paper}}\n",
+    )
+    .unwrap();
+    let note = |args: &[&str], stdin: &str| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["note", "--no-edit"])
+            .args(args)
+            .write_stdin(stdin)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        (
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr(&output),
+        )
+    };
+
+    let (out, err) = note(&["chan"], "1\n");
+    assert_eq!(out, "notes/All-Pairs Shortest Paths.md\n");
+    assert!(
+        err.contains("created notes for DBLP:conf/soda/Chan16"),
+        "{}",
+        err
+    );
+    let notes = sandbox.read("notes/All-Pairs Shortest Paths.md");
+    assert!(
+        notes.starts_with("---\nkey: DBLP:conf/soda/Chan16\n"),
+        "{}",
+        notes
+    );
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("@inproceedings{DBLP:conf/soda/Chan16,"));
+
+    // the notes are left as they are
+    std::fs::write(
+        sandbox.dir.join("notes/All-Pairs Shortest Paths.md"),
+        notes.clone() + "mine\n",
+    )
+    .unwrap();
+    let (again, err) = note(&["chan"], "1\n");
+    assert_eq!(again, out);
+    assert!(
+        err.contains("DBLP:conf/soda/Chan16 already has notes"),
+        "{}",
+        err
+    );
+    assert!(sandbox
+        .read("notes/All-Pairs Shortest Paths.md")
+        .ends_with("mine\n"));
+
+    let (out, _) = note(&["--local", "--notes-dir", "papers", "local"], "1\n");
+    assert_eq!(out, "papers/A Local Paper.md\n");
+    assert!(sandbox
+        .read("papers/A Local Paper.md")
+        .starts_with("---\nkey: local\n"));
+}
+
+#[test]
+fn test_note_template() {
+    let sandbox = Sandbox::new("note-template");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {First Paper}, author = {Jane Doe}, year = {2020}}\n\
+         @misc{b, title = {Second Paper}}\n\
+         @misc{c, title = {Third Paper}}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        sandbox.dir.join("template.md"),
+        "# {{title}}\n{{authors}} ({{year}})\n\n## Summary\n",
+    )
+    .unwrap();
+    let note = |args: &[&str]| {
+        sandbox
+            .cmd(None)
+            .args(["note", "--no-edit", "--local"])
+            .args(args)
+            .write_stdin("1\n")
+            .output()
+            .unwrap()
+    };
+
+    let output = note(&["--template", "template.md", "first"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let notes = sandbox.read("notes/First Paper.md");
+    assert!(notes.starts_with("---\nkey: a\n"), "{}", notes);
+    assert!(
+        notes.ends_with("---\n# First Paper\nJane Doe (2020)\n\n## Summary\n"),
+        "{}",
+        notes
+    );
+
+    sandbox.config("notes_template = \"missing.md\"");
+    let output = note(&["second"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("warning: the notes template"));
+    assert!(sandbox.read("notes/Second Paper.md").ends_with("---\n"));
+
+    std::fs::write(
+        sandbox.dir.join("config/dblp-rs/missing.md"),
+        "{{title}} {{abstract}}\n",
+    )
+    .unwrap();
+    let output = note(&["third"]);
+    assert_eq!(output.status.code(), Some(1));
+    let err = stderr(&output);
+    assert!(err.contains("unknown placeholder {{abstract}}"), "{}", err);
+    assert!(!sandbox.dir.join("notes/Third Paper.md").exists());
+}
+
+#[test]
+fn test_notes_find() {
+    let sandbox = Sandbox::new("notes-find");
+    std::fs::create_dir_all(sandbox.dir.join("notes/2016")).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/2016/chan.md"),
+        "---\nkey: DBLP:conf/soda/Chan16\ntitle: All-Pairs Shortest Paths\n---\n",
+    )
+    .unwrap();
+    std::fs::write(sandbox.dir.join("notes/ideas.md"), "# Ideas\n").unwrap();
+    let find = |args: &[&str]| {
+        sandbox
+            .cmd(None)
+            .args(["notes", "find", "--path-only"])
+            .args(args)
+            .write_stdin("1\n")
+            .output()
+            .unwrap()
+    };
+
+    let output = find(&["shortest", "paths"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "notes/2016/chan.md\n"
+    );
+
+    let output = find(&[]);
+    let err = stderr(&output);
+    assert!(
+        err.contains("1. DBLP:conf/soda/Chan16 All-Pairs Shortest Paths chan"),
+        "{}",
+        err
+    );
+    assert!(err.contains("2. (no metadata) ideas"), "{}", err);
+
+    let output = find(&["ideas"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "notes/ideas.md\n");
+
+    let output = find(&["nothing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("no notes in notes match \"nothing\""));
+}
+
+#[test]
+fn test_notes_sync() {
+    let sandbox = Sandbox::new("notes-sync");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {First Paper}}\n",
+    )
+    .unwrap();
+    let note = |args: &[&str]| {
+        let output = sandbox
+            .cmd(None)
+            .args(args)
+            .write_stdin("1\n")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    note(&["note", "--no-edit", "--local", "first"]);
+    let path = sandbox.dir.join("notes/First Paper.md");
+    let created = sandbox.read("notes/First Paper.md");
+    let header = created.strip_suffix("---\n").unwrap();
+    let with_body = format!("{}status: read\n---\n\n## Summary\nkept\n", header);
+    std::fs::write(&path, &with_body).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/gone.md"),
+        "---\nkey: gone\ntitle: Gone\n---\n",
+    )
+    .unwrap();
+
+    let out = note(&["notes", "sync"]);
+    assert_eq!(
+        out,
+        "current  notes/First Paper.md\nmissing  notes/gone.md (gone is not in ./refs.bib)\n"
+    );
+
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {The First Paper}}\n",
+    )
+    .unwrap();
+    let out = note(&["notes", "sync", "--dry-run"]);
+    assert!(
+        out.starts_with("would update  notes/First Paper.md\n"),
+        "{}",
+        out
+    );
+    assert_eq!(sandbox.read("notes/First Paper.md"), with_body);
+
+    note(&["notes", "sync"]);
+    let synced = sandbox.read("notes/First Paper.md");
+    assert!(synced.contains("title: The First Paper\n"), "{}", synced);
+    assert!(synced.contains("status: read\n---\n"), "{}", synced);
+    assert!(synced.ends_with("---\n\n## Summary\nkept\n"), "{}", synced);
+}
+
+#[test]
+fn test_notes_status() {
+    let sandbox = Sandbox::new("notes-status");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{DBLP:conf/soda/Chan16, title = {A}}\n@misc{unread, title = {B}}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("notes")).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/chan.md"),
+        "---\nkey: conf/soda/Chan16\ntitle: A\n---\n",
+    )
+    .unwrap();
+    let status = |args: &[&str]| {
+        sandbox
+            .cmd(None)
+            .args(["notes", "status"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = status(&[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "orphaned notes, whose key is not in ./refs.bib (0):\nentries without notes (1):\n  unread\n"
+    );
+
+    std::fs::write(
+        sandbox.dir.join("notes/renamed.md"),
+        "---\nkey: DBLP:conf/soda/Old16\ntitle: C\n---\n",
+    )
+    .unwrap();
+    let output = status(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("(1):\n  notes/renamed.md  DBLP:conf/soda/Old16\n"));
+    assert!(stderr(&output).contains("found 1 orphaned notes"));
+
+    let output = status(&["--orphans-only"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "notes/renamed.md\n"
+    );
+    let output = status(&["--missing-only"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "unread\n");
+}
+
+#[test]
+fn test_notes_fixtures() {
+    let sandbox = Sandbox::new("notes-fixtures");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let dir = fixture_path("notes");
+    let orphans = || {
+        let output = sandbox
+            .cmd(None)
+            .args(["notes", "status", "--orphans-only", "--notes-dir"])
+            .arg(&dir)
+            .output()
+            .unwrap();
+        let out = String::from_utf8_lossy(&output.stdout).into_owned();
+        let mut names: Vec<String> = out
+            .lines()
+            .map(|l| l.strip_prefix(dir.to_str().unwrap()).unwrap().to_owned())
+            .collect();
+        names.sort();
+        (names, stderr(&output))
+    };
+
+    let (names, err) = orphans();
+    assert_eq!(names, ["/2016/chan.md", "/rule.md"]);
+    assert!(err.contains("warning: skipping"), "{}", err);
+    assert!(err.contains("latin1.md"), "{}", err);
+
+    sandbox.config("notes_extensions = [\"md\", \"txt\"]");
+    let (names, _) = orphans();
+    assert_eq!(names, ["/2016/chan.md", "/rule.md", "/todo.txt"]);
+}
+
+#[test]
+fn test_notes_tags() {
+    let sandbox = Sandbox::new("notes-tags");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{a, title = {First Paper}}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("notes")).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/b.md"),
+        "---\nkey: b\ntitle: Second Paper\ntags: streaming, sketches\nstatus: read\n---\n",
+    )
+    .unwrap();
+    std::fs::write(sandbox.dir.join("notes/c.md"), "# no metadata\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = sandbox
+            .cmd(None)
+            .args(args)
+            .write_stdin("1\n")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    run(&[
+        "note",
+        "--no-edit",
+        "--local",
+        "--tag",
+        "streaming",
+        "--tag",
+        "to read",
+        "first",
+    ]);
+    assert!(sandbox
+        .read("notes/First Paper.md")
+        .starts_with("---\nkey: a\ntags:\n- streaming\n- to read\n"));
+
+    assert_eq!(
+        run(&["notes", "list"]),
+        "notes/First Paper.md  a  -  streaming,to read\nnotes/b.md  b  read  streaming,sketches\nnotes/c.md  (no metadata)\n"
+    );
+    assert_eq!(
+        run(&["notes", "list", "--tag", "Streaming", "--status", "read"]),
+        "notes/b.md  b  read  streaming,sketches\n"
+    );
+    assert_eq!(
+        run(&["notes", "find", "--path-only", "--tag", "to read"]),
+        "notes/First Paper.md\n"
+    );
+    let output = sandbox
+        .cmd(None)
+        .args(["notes", "list", "--status", "done"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_notes_create_missing() {
+    let sandbox = Sandbox::new("notes-create-missing");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@inproceedings{conf, title = {Paths}}\n\n@article{corr, title = {Paths}}\n\n@misc{other, title = {Other}}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("notes")).unwrap();
+    std::fs::write(sandbox.dir.join("keys.txt"), "  conf \nmissing\n\ncorr\n").unwrap();
+    let output = sandbox
+        .cmd(None)
+        .args(["notes", "create-missing", "--keys-from", "keys.txt"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("Error creating notes for missing:"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(
+        stderr(&output).contains("2 created, 0 skipped, 1 failed"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(sandbox
+        .read("notes/Paths.md")
+        .starts_with("---\nkey: conf\n"));
+    assert!(sandbox
+        .read("notes/Paths (2).md")
+        .starts_with("---\nkey: corr\n"));
+    assert!(!sandbox.dir.join("notes/Other.md").exists());
+}
+
+#[test]
+fn test_notes_index() {
+    let sandbox = Sandbox::new("notes-index");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@inproceedings{DBLP:conf/soda/Chan16, title = {All-Pairs}, booktitle = {SODA}, year = {2016}}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("notes/2016")).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/2016/All Pairs.md"),
+        "---\nkey: conf/soda/Chan16\ntitle: All-Pairs\ntags: [graphs]\nstatus: read\n---\n",
+    )
+    .unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/later.md"),
+        "---\nkey: later\ntitle: Later\n---\n",
+    )
+    .unwrap();
+    let index = |args: &[&str]| {
+        let output = sandbox
+            .cmd(None)
+            .args(["notes", "index"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        stderr(&output)
+    };
+
+    std::fs::write(sandbox.dir.join("notes/index.md"), "# Reading\n\nMine.\n").unwrap();
+    let err = index(&[]);
+    assert!(err.contains("indexed 2 notes in notes/index.md"), "{}", err);
+    let written = sandbox.read("notes/index.md");
+    assert_eq!(
+        written,
+        "# Reading\n\nMine.\n\n<!-- dblp-rs index start -->\n\n\
+         | Title | Year | Venue | Key | Status | Tags |\n|---|---|---|---|---|---|\n\
+         | [All-Pairs](2016/All%20Pairs.md) | 2016 | SODA | conf/soda/Chan16 | read | graphs |\n\
+         | [Later](later.md) |  |  | later |  |  |\n\n<!-- dblp-rs index end -->\n"
+    );
+    assert!(index(&[]).contains("notes/index.md is up to date"));
+
+    std::fs::write(
+        sandbox.dir.join("notes/index.md"),
+        written.replace("Mine.", "Still mine.") + "After.\n",
+    )
+    .unwrap();
+    index(&["--group-by", "year"]);
+    let grouped = sandbox.read("notes/index.md");
+    assert!(
+        grouped
+            .starts_with("# Reading\n\nStill mine.\n\n<!-- dblp-rs index start -->\n\n## 2016\n"),
+        "{}",
+        grouped
+    );
+    assert!(grouped.contains("\n## Unknown year\n"), "{}", grouped);
+    assert!(
+        grouped.ends_with("<!-- dblp-rs index end -->\nAfter.\n"),
+        "{}",
+        grouped
+    );
+}
+
+#[test]
+fn test_notes_links() {
+    let sandbox = Sandbox::new("notes-links");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{DBLP:conf/soda/Foo20, title = {Foo in the Bib}}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("notes")).unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/chan.md"),
+        "---\nkey: DBLP:conf/soda/Chan16\ntitle: All-Pairs\n---\nBuilds on [[DBLP:conf/soda/Foo20]].\n```\n[[ignored]]\n```\n",
+    )
+    .unwrap();
+    std::fs::write(
+        sandbox.dir.join("notes/bar.md"),
+        "---\nkey: bar\ntitle: Bar \"quoted\"\n---\nSee [[conf/soda/Chan16|Chan]] and [[unknown]].\n",
+    )
+    .unwrap();
+    let links = |args: &[&str]| {
+        let output = sandbox
+            .cmd(None)
+            .args(["notes", "links"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    assert_eq!(
+        links(&["conf/soda/Chan16"]),
+        "conf/soda/Chan16  All-Pairs\nlinks to (1):\n  conf/soda/Foo20  Foo in the Bib\nlinked from (1):\n  bar  Bar \"quoted\"\n"
+    );
+    assert_eq!(
+        links(&["--graph"]),
+        "digraph notes {\n  \"bar\" [label=\"Bar \\\"quoted\\\"\"];\n  \"conf/soda/Chan16\" [label=\"All-Pairs\"];\n  \"conf/soda/Foo20\" [label=\"Foo in the Bib\"];\n  \"unknown\" [label=\"unknown\"];\n  \"bar\" -> \"conf/soda/Chan16\";\n  \"bar\" -> \"unknown\";\n  \"conf/soda/Chan16\" -> \"conf/soda/Foo20\";\n}\n"
+    );
+    let graph = links(&["--graph", "DBLP:conf/soda/Foo20"]);
+    assert!(
+        graph.contains("  \"conf/soda/Chan16\" -> \"conf/soda/Foo20\";\n"),
+        "{}",
+        graph
+    );
+    assert!(!graph.contains("unknown"), "{}", graph);
+
+    let output = sandbox.cmd(None).args(["notes", "links"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_note_editor() {
+    use std::os::unix::fs::PermissionsExt;
+    let sandbox = Sandbox::new("note-editor");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{local, title = {A Local Paper}}\n",
+    )
+    .unwrap();
+    // an editor recording what it opens, and failing
+    let editor = sandbox.dir.join("editor");
+    std::fs::write(
+        &editor,
+        format!(
+            "#!/bin/sh\necho \"$1\" > {}\nexit 3\n",
+            sandbox.dir.join("edited").display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&editor, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let output = sandbox
+        .cmd(None)
+        .args(["note", "--local", "local"])
+        .env_remove("VISUAL")
+        .env("EDITOR", &editor)
+        .write_stdin("1\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(sandbox.read("edited"), "notes/A Local Paper.md\n");
+    let err = stderr(&output);
+    assert!(err.contains("warning: the editor"), "{}", err);
+    assert!(err.contains("exit status: 3"), "{}", err);
+}
+
+#[test]
+fn test_add_key() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+        ("/rec/conf/soda/Broken16.bib?param=1", "HTTP 500".to_owned()),
+    ]);
+    let sandbox = Sandbox::new("add-key");
+    // not to wait before retrying the errors of the server
+    sandbox.config("retry_backoff_ms = 0");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let add_key = |key: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["add-key", "--print-key", key])
+            .output()
+            .unwrap()
+    };
+
+    let output = add_key("https://dblp.org/rec/conf/soda/Chan16.html");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Chan16\n"
+    );
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("All-Pairs Shortest Paths"));
+
+    // already there, so not fetched again
+    let requests = server.requests().len();
+    let output = add_key("DBLP:conf/soda/Chan16");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.requests().len(), requests);
+
+    // nor when it is there under another key
+    let requests = server.requests().len();
+    let renamed = sandbox.read("refs.bib").replace(
+        "{DBLP:conf/soda/Chan16,",
+        "{chan16,\n  biburl = {https://dblp.org/rec/conf/soda/Chan16.bib},",
+    );
+    std::fs::write(sandbox.dir.join("refs.bib"), renamed).unwrap();
+    let output = add_key("conf/soda/Chan16");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "chan16\n");
+    assert!(
+        stderr(&output).contains("DBLP:conf/soda/Chan16 already present as chan16"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(server.requests().len(), requests);
+
+    let output = add_key("conf/soda/Missing16");
+    assert_eq!(output.status.code(), Some(exit::NOT_FOUND.into()));
+    let err = stderr(&output);
+    assert!(
+        err.contains("no DBLP record with key conf/soda/Missing16"),
+        "{}",
+        err
+    );
+
+    let output = add_key("conf/soda/Broken16");
+    assert_eq!(output.status.code(), Some(exit::NETWORK.into()));
+    let err = stderr(&output);
+    assert!(!err.contains("no DBLP record"), "{}", err);
+    assert!(err.contains("500"), "{}", err);
+    assert_eq!(err.matches("retrying in").count(), 2, "{}", err);
+}
+
+#[test]
+fn test_doi() {
+    let crossref = " @article{Doe_2020, title={A Paper Not on DBLP}, volume={12}, \
+        DOI={10.1000/XYZ123}, journal={Some Journal}, author={Doe, Jane}, year={2020}}";
+    let server = MockDblp::start(vec![
+        ("/10.1000/XYZ123", crossref.to_owned()),
+        ("/10.1000/plain", "J. Doe, A Paper, 2020.".to_owned()),
+    ]);
+    let sandbox = Sandbox::new("doi");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let doi = |doi: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["doi", "--print-key", doi])
+            .output()
+            .unwrap()
+    };
+
+    let output = doi("https://doi.org/10.1000/XYZ123");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "doi:10.1000/xyz123\n"
+    );
+    let bib = sandbox.read("refs.bib");
+    assert!(bib.starts_with("@article{doi:10.1000/xyz123,"), "{}", bib);
+    assert!(bib.contains("A Paper Not on DBLP"), "{}", bib);
+
+    let requests = server.requests().len();
+    assert!(doi("10.1000/xyz123").status.success());
+    assert_eq!(server.requests().len(), requests);
+
+    let output = doi("10.1000/missing");
+    assert_eq!(output.status.code(), Some(exit::NOT_FOUND.into()));
+    assert!(
+        stderr(&output).contains("doi.org knows no DOI 10.1000/missing"),
+        "{}",
+        stderr(&output)
+    );
+    let output = doi("10.1000/plain");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("not bibtex"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), bib);
+}
+
+fn arxiv_feed(title: &str) -> String {
+    format!(
+        r#"<feed xmlns="http://www.w3.org/2005/Atom"><entry>
+        <id>http://arxiv.org/abs/2301.01234v2</id>
+        <published>2023-01-03T17:00:00Z</published>
+        <title>{}</title>
+        <author><name>Jane Doe</name></author>
+        </entry></feed>"#,
+        title
+    )
+}
+
+#[test]
+fn test_arxiv() {
+    let server = MockDblp::start(vec![
+        ("/api/query", arxiv_feed("Sketching Shortest Paths")),
+        ("/search/publ/api", search_response()),
+    ]);
+    let sandbox = Sandbox::new("arxiv");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["arxiv", "--print-key", "arXiv:2301.01234v2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "arXiv:2301.01234\n"
+    );
+    let bib = sandbox.read("refs.bib");
+    assert!(bib.contains("eprint        = {2301.01234}"), "{}", bib);
+    assert!(server
+        .requests()
+        .contains(&"/api/query?id_list=2301.01234v2".to_owned()));
+
+    // DBLP has the paper, so its key is suggested instead
+    let server = MockDblp::start(vec![
+        ("/api/query", arxiv_feed("All-Pairs\n  Shortest Paths")),
+        ("/search/publ/api", search_response()),
+    ]);
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let arxiv = |force: bool| {
+        let mut cmd = sandbox.cmd(Some(&server));
+        cmd.arg("arxiv");
+        if force {
+            cmd.arg("--force");
+        }
+        cmd.arg("2301.01234").output().unwrap()
+    };
+    let output = arxiv(false);
+    assert_eq!(output.status.code(), Some(1));
+    let err = stderr(&output);
+    assert!(
+        err.contains("DBLP has this paper as DBLP:conf/soda/Chan16"),
+        "{}",
+        err
+    );
+    assert_eq!(sandbox.read("refs.bib"), "");
+    assert!(arxiv(true).status.success());
+    assert!(sandbox.read("refs.bib").contains("@misc{arXiv:2301.01234,"));
+}
+
+#[test]
+fn test_search() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    // in a directory without any bib file
+    let sandbox = Sandbox::new("search");
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["search", "chan"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", out);
+    assert!(
+        lines[0].starts_with("DBLP:conf/soda/Chan16   2016  SODA  All-Pairs Shortest Paths.  "),
+        "{}",
+        out
+    );
+    assert!(!out.contains('\x1b'), "{}", out);
+    assert!(lines[1].ends_with("/rec/conf/soda/Other16"), "{}", out);
+
+    let search_json = |args: &[&str]| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["search", "--json"])
+            .args(args)
+            .output()
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (output.status.code(), json)
+    };
+    let (code, json) = search_json(&["chan"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(json["schema"], "dblp-rs/search/v2");
+    assert_eq!(json["hits"][0]["cite_key"], "DBLP:conf/soda/Chan16");
+    assert_eq!(json["hits"][0]["authors"][0], "Timothy M. Chan");
+    assert_eq!(json["hits"][1]["venue"][0], "SODA");
+
+    // no hits gives an empty list, and fails
+    let (code, json) = search_json(&["--type", "phd", "chan"]);
+    assert_eq!(code, Some(exit::NO_RESULTS.into()));
+    assert_eq!(json["hits"], serde_json::json!([]));
+}
+
+#[test]
+fn test_no_hits() {
+    let server = MockDblp::start(vec![(
+        "/search/publ/api",
+        r#"{"result": {"hits": {"@total": "0", "@sent": "0", "@first": "0"}}}"#.to_owned(),
+    )]);
+    let sandbox = Sandbox::new("no-hits");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    for subcommand in ["add", "clip", "note"] {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args([subcommand, "nosuchpaper"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(exit::NO_RESULTS.into()));
+        let err = stderr(&output);
+        assert!(err.contains("no hits for \"nosuchpaper\""), "{}", err);
+        assert!(!err.contains("json"), "{}", err);
+    }
+}
+
+#[test]
+fn test_cache() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    let sandbox = Sandbox::new("cache");
+    let search = |args: &[&str]| {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(args)
+            .args(["search", "chan"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        server.requests().len()
+    };
+    assert_eq!(search(&[]), 1);
+    // the same search is answered from the cache, unless told otherwise
+    assert_eq!(search(&[]), 1);
+    assert_eq!(search(&["--no-cache"]), 2);
+
+    let output = sandbox.cmd(None).args(["cache", "clear"]).output().unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stderr(&output).contains("removed 1 cached responses"));
+    assert_eq!(search(&[]), 3);
+
+    // or after the hits expire
+    sandbox.config("search_cache_ttl_s = 0");
+    assert_eq!(search(&[]), 4);
+}
+
+#[test]
+fn test_timeout() {
+    // accepts connections, and never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let sandbox = Sandbox::new("timeout");
+    let start = std::time::Instant::now();
+    let output = sandbox
+        .cmd(None)
+        .env("DBLP_RS_API_BASE", &base)
+        .args(["--timeout", "1", "search", "chan"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("timed out"), "{}", stderr(&output));
+    assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    drop(listener);
+}
+
+#[test]
+fn test_dblp_url() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    let proxy = MockDblp::start(vec![(
+        "/search/publ/api",
+        "<html>please log in</html>".to_owned(),
+    )]);
+    let sandbox = Sandbox::new("dblp-url");
+    let search = |url: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["--no-cache", "--dblp-url", url, "search", "chan"])
+            .output()
+            .unwrap()
+    };
+
+    // the custom endpoint is tried first, and skipped if it does not
+    // answer like DBLP
+    let output = search(&format!("{}/", proxy.base));
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(proxy.requests().len(), 1);
+    assert_eq!(server.requests().len(), 1);
+    let err = stderr(&output);
+    assert!(
+        err.contains(&format!("using {}, {} failed", server.base, proxy.base)),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains("did not answer with the JSON of DBLP, but with `<html>please log in</html>`"),
+        "{}",
+        err
+    );
+
+    // or from the environment
+    let output = sandbox
+        .cmd(Some(&server))
+        .env("DBLP_BASE_URL", &server.base)
+        .args(["--no-cache", "search", "chan"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.requests().len(), 2);
+
+    let output = search("dblp.example.org");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("is not the base url of a DBLP mirror"));
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[test]
+fn test_add_several() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let other = fetched
+        .replace("Chan16", "Other16")
+        .replace("All-Pairs Shortest Paths", "Another Paper");
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+        ("/rec/conf/soda/Other16.bib?param=1", other),
+    ]);
+    let sandbox = Sandbox::new("add-several");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--print-key", "chan"])
+        .write_stdin("2 1\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Other16, DBLP:conf/soda/Chan16\n"
+    );
+    let bib = sandbox.read("refs.bib");
+    assert!(bib.contains("Another Paper"), "{}", bib);
+    assert!(bib.contains("All-Pairs Shortest Paths"), "{}", bib);
+
+    // the ones already there are not fetched again
+    let requests = server.requests().len();
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["--no-cache", "add", "--print-key", "chan"])
+        .write_stdin("1 2\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(server.requests().len(), requests + 1);
+    assert_eq!(sandbox.read("refs.bib"), bib);
+}
+
+#[test]
+fn test_add_from_list() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("add");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+
+    // without a terminal the hits are listed, and read from standard input
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--print-key", "chan", "shortest"])
+        .write_stdin("3\n1\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let err = stderr(&output);
+    assert!(err.contains("not using the fuzzy picker"), "{}", err);
+    assert!(err.contains("  2. Another Paper."), "{}", err);
+    assert!(err.contains("not a number between 1 and 2"), "{}", err);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Chan16\n"
+    );
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("All-Pairs Shortest Paths"));
+
+    // aborting, or closing the input, fetches nothing
+    for input in ["q\n", ""] {
+        let requests = server.requests().len();
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["--no-cache", "clip", "chan"])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(exit::ABORTED.into()));
+        assert!(stderr(&output).contains("Aborting"), "{}", stderr(&output));
+        assert_eq!(server.requests().len(), requests + 1);
+    }
+}
+
+#[test]
+fn test_add_creates_bib() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("create");
+    let add = |bib: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["--bibtex", bib, "add", "chan", "shortest"])
+            .write_stdin("1\n")
+            .output()
+            .unwrap()
+    };
+
+    let output = add("plain.bib");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("created plain.bib"),
+        "{}",
+        stderr(&output)
+    );
+    let plain = sandbox.read("plain.bib");
+    assert!(
+        plain.starts_with("@inproceedings{DBLP:conf/soda/Chan16"),
+        "{}",
+        plain
+    );
+    assert!(plain.ends_with("}\n\n"), "{:?}", plain);
+
+    sandbox.config("new_bib_template = \"% !BIB TS-program = biber\\n% encoding: utf-8\"\n");
+    let output = add("templated.bib");
+    assert!(output.status.success(), "{}", stderr(&output));
+    let templated = sandbox.read("templated.bib");
+    assert!(
+        templated.starts_with("% !BIB TS-program = biber\n% encoding: utf-8\n\n@inproceedings{"),
+        "{}",
+        templated
+    );
+    // an existing file is appended to, without the template
+    let output = add("plain.bib");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("created"), "{}", stderr(&output));
+    assert_eq!(sandbox.read("plain.bib"), plain);
+
+    let requests = server.requests().len();
+    let output = add("missing/new.bib");
+    assert_eq!(output.status.code(), Some(exit::BIB_FILE.into()));
+    assert!(
+        stderr(&output).contains("its directory does not exist"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!sandbox.dir.join("missing").exists());
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[test]
+fn test_add_key_pattern() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", search_response()),
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("key-pattern");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{soda16-Chan, title = {Other}}\n",
+    )
+    .unwrap();
+    sandbox.config("key_pattern = \"{venue}{yy}-{author1}\"\n");
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--print-key", "chan"])
+        .write_stdin("1\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "soda16-Chana\n");
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("\n@inproceedings{soda16-Chana,\n  author       = {Timothy M. Chan},"));
+
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add", "--key-pattern", "{author1", "chan"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("unclosed {"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_add_exact_key() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("exact-key");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{Taken, title = {Other}}\n",
+    )
+    .unwrap();
+    let add_key = |key: &str| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["add-key", "--print-key", "--key", key, "conf/soda/Chan16"])
+            .output()
+            .unwrap()
+    };
+
+    for (key, err) in [
+        ("taken", "taken is already the key of another entry"),
+        ("chan 2016", "cannot contain ' '"),
+    ] {
+        let output = add_key(key);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(stderr(&output).contains(err), "{}", stderr(&output));
+    }
+    assert_eq!(sandbox.read("refs.bib"), "@misc{Taken, title = {Other}}\n");
+
+    let output = add_key("chan2016");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "chan2016\n");
+    let bib = sandbox.read("refs.bib");
+    assert!(bib.contains("@inproceedings{chan2016,\n"), "{}", bib);
+    assert!(!bib.contains("DBLP:conf/soda/Chan16"), "{}", bib);
+}
+
+#[test]
+fn test_several_bibs() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("several");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    let strings = "@misc{Taken, title = {Other}}\n";
+    std::fs::write(sandbox.dir.join("strings.bib"), strings).unwrap();
+
+    // nothing is picked without asking
+    let output = sandbox
+        .cmd(None)
+        .args(["doctor", "--offline"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("several bibtex files"));
+
+    // the picked file is remembered, and its keys are free in the others
+    let output = sandbox
+        .cmd(Some(&server))
+        .args([
+            "add-key",
+            "--print-key",
+            "--key",
+            "taken",
+            "conf/soda/Chan16",
+        ])
+        .write_stdin("1\n")
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr(&output).contains("taken is already the key of another entry"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read(".dblp-target"), "refs.bib\n");
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["add-key", "--print-key", "conf/soda/Chan16"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox.read("refs.bib").contains("DBLP:conf/soda/Chan16"));
+
+    // entries of the other files are cited without fetching them
+    let output = sandbox
+        .cmd(None)
+        .args([
+            "--into",
+            "strings.bib",
+            "add-key",
+            "--print-key",
+            "conf/soda/Chan16",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "DBLP:conf/soda/Chan16\n"
+    );
+    assert!(
+        stderr(&output).contains("already present in"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("strings.bib"), strings);
+
+    // only the given files are candidates
+    let output = sandbox
+        .cmd(None)
+        .args(["--bibtex", "strings.bib", "doctor", "--offline"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+}
+
+#[test]
+fn test_bib_in_parent() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![("/rec/conf/soda/Chan16.bib?param=1", fetched)]);
+    let sandbox = Sandbox::new("parent");
+    let sections = sandbox.dir.join("paper/sections");
+    std::fs::create_dir_all(sandbox.dir.join("paper/.git")).unwrap();
+    std::fs::create_dir_all(&sections).unwrap();
+    std::fs::create_dir_all(sandbox.dir.join("shared")).unwrap();
+    std::fs::write(sandbox.dir.join("shared/all.bib"), "").unwrap();
+    let run = |args: &[&str]| {
+        sandbox
+            .cmd(Some(&server))
+            .current_dir(&sections)
+            .env(
+                "BIBINPUTS",
+                format!(":{}", sandbox.dir.join("shared").display()),
+            )
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // without one in the repository, the one of BIBINPUTS
+    let output = run(&["--trust-config", "add-key", "conf/soda/Chan16"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox
+        .read("shared/all.bib")
+        .contains("DBLP:conf/soda/Chan16"));
+
+    // the closest one, up to the root of the repository
+    std::fs::write(sandbox.dir.join("paper/refs.bib"), "").unwrap();
+    let output = run(&["add-key", "conf/soda/Chan16"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox
+        .read("paper/refs.bib")
+        .contains("DBLP:conf/soda/Chan16"));
+
+    // several at the same level are listed
+    std::fs::write(sandbox.dir.join("paper/more.bib"), "").unwrap();
+    let output = run(&["doctor", "--offline"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("several bibtex files in \"..\": ../more.bib, ../refs.bib"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_bib_outside_repository() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    let sandbox = Sandbox::new("outside");
+    let (new, old) = (sandbox.dir.join("new-paper"), sandbox.dir.join("old-paper"));
+    std::fs::create_dir_all(new.join(".git")).unwrap();
+    std::fs::create_dir_all(&old).unwrap();
+    let original = "@misc{local, title = {Local}}\n";
+    std::fs::write(old.join("refs.bib"), original).unwrap();
+    let run = |args: &[&str]| {
+        sandbox
+            .cmd(Some(&server))
+            .current_dir(&new)
+            .args(["--bibtex", "../old-paper/refs.bib"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // without a terminal to ask, nothing is written or fetched
+    for args in [&["convert", "standard"][..], &["add", "chan"]] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        let err = stderr(&output);
+        assert!(err.contains("refusing to modify"), "{}", err);
+        assert!(err.contains("--trust-config"), "{}", err);
+    }
+    assert_eq!(sandbox.read("old-paper/refs.bib"), original);
+    assert!(!old.join("refs.bib.bak").exists());
+    assert!(server.requests().is_empty());
+
+    let output = run(&["--trust-config", "convert", "standard"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    std::fs::write(new.join(".dblp.toml"), "allow_outside = true\n").unwrap();
+    let output = run(&["convert", "standard"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(old.join("refs.bib.bak").exists());
+}
+
+#[test]
+fn test_check_entry_types() {
+    let sandbox = Sandbox::new("entry-types");
+    sandbox.fixture("types.bib", "refs.bib");
+    let check = || {
+        sandbox
+            .cmd(None)
+            .args(["check", "--entry-types"])
+            .output()
+            .unwrap()
+    };
+    let output = check();
+    assert_eq!(output.status.code(), Some(1));
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        out.contains("typo (./refs.bib:32): unknown entry type @onlne, did you mean @online?"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("alien (./refs.bib:37): unknown entry type @BlogPost, map it"),
+        "{}",
+        out
+    );
+    assert_eq!(out.lines().count(), 2, "{}", out);
+    assert!(
+        stderr(&output).contains("inproceedings 2"),
+        "{}",
+        stderr(&output)
+    );
+
+    sandbox.config("type_aliases = { blogpost = \"online\" }\n");
+    let output = check();
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(!out.contains("alien"), "{}", out);
+    assert!(stderr(&output).contains("online 3"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_check_keys() {
+    let fetched = std::fs::read_to_string(fixture_path("Chan16.bib")).unwrap();
+    let server = MockDblp::start(vec![
+        ("/rec/conf/soda/Chan16.bib?param=1", fetched.clone()),
+        ("/rec/journals/corr/Chan15.bib?param=1", fetched.clone()),
+        (
+            "/rec/conf/soda/Other16.bib?param=1",
+            fetched.replace("Chan16", "Other16"),
+        ),
+        ("/rec/journals/corr/Other15.bib?param=1", fetched),
+    ]);
+    let sandbox = Sandbox::new("check-keys");
+    let original = "@misc{DBLP:conf/soda/Other16, title = {A}}\n\n@misc{DBLP:journals/corr/Chan15, title = {B}}\n\n@misc{DBLP:conf/soda/Gone16, title = {C}}\n\n@misc{local, title = {D}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), original).unwrap();
+    let tex = "\\cite{DBLP:journals/corr/Chan15,local}\n";
+    std::fs::write(sandbox.dir.join("main.tex"), tex).unwrap();
+    let check = |args: &[&str]| {
+        sandbox
+            .cmd(Some(&server))
+            .args(["check", "--keys"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = check(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(
+        out,
+        "DBLP:journals/corr/Chan15 (./refs.bib:3): DBLP moved the record to DBLP:conf/soda/Chan16\n\
+         DBLP:conf/soda/Gone16 (./refs.bib:5): DBLP no longer has the record\n"
+    );
+    assert!(
+        stderr(&output).contains("keys: 1 ok, 1 moved, 1 missing, 0 could not be checked"),
+        "{}",
+        stderr(&output)
+    );
+    assert_eq!(sandbox.read("refs.bib"), original);
+
+    let output = check(&["--fix", "--rename-citations"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        sandbox.read("refs.bib"),
+        original.replace("DBLP:journals/corr/Chan15", "DBLP:conf/soda/Chan16")
+    );
+    assert_eq!(
+        sandbox.read("main.tex"),
+        "\\cite{DBLP:conf/soda/Chan16,local}\n"
+    );
+
+    // not renamed to a key already taken
+    let taken = "@misc{DBLP:conf/soda/Chan16, title = {A}}\n\n@misc{DBLP:journals/corr/Other15, title = {B}}\n";
+    std::fs::write(sandbox.dir.join("refs.bib"), taken).unwrap();
+    let output = check(&["--fix"]);
+    let out = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        out.contains("moved the record to DBLP:conf/soda/Chan16, which is in the file too"),
+        "{}",
+        out
+    );
+    assert_eq!(sandbox.read("refs.bib"), taken);
+}
+
+#[test]
+fn test_books_and_theses() {
+    let fixture = |name| std::fs::read_to_string(fixture_path(name)).unwrap();
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", fixture("books-search.json")),
+        ("/rec/books/aw/Knuth97.bib?param=1", fixture("Knuth97.bib")),
+        ("/rec/phd/us/Doe19.bib?param=1", fixture("Doe19.bib")),
+    ]);
+    let sandbox = Sandbox::new("books");
+    std::fs::write(sandbox.dir.join("refs.bib"), "").unwrap();
+    for (hit_type, key) in [
+        ("book", "DBLP:books/aw/Knuth97"),
+        ("phd", "DBLP:phd/us/Doe19"),
+    ] {
+        let output = sandbox
+            .cmd(Some(&server))
+            .args(["add", "--print-key", "--type", hit_type, "shortest"])
+            .write_stdin("1\n")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        // only the hit of the type is listed
+        assert!(!stderr(&output).contains("  2. "), "{}", stderr(&output));
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{}\n", key)
+        );
+    }
+
+    // the entries go through add and convert untouched, isbn included
+    let expected = |bib: &str| {
+        for name in ["Knuth97.bib", "Doe19.bib"] {
+            assert!(bib.contains(fixture(name).trim_end()), "{}: {}", name, bib);
+        }
+    };
+    expected(&sandbox.read("refs.bib"));
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["convert", "standard"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("could not be fetched"));
+    expected(&sandbox.read("refs.bib"));
+}
+
+const JOURNAL_VERSION: &str = "@article{DBLP:journals/talg/Chan12,
+  author       = {Timothy M. Chan},
+  title        = {All-Pairs Shortest Paths for Unweighted Undirected Graphs in o(mn) Time},
+  journal      = {{ACM} Trans. Algorithms},
+  volume       = {8},
+  year         = {2012},
+}
+";
+
+fn journal_server() -> MockDblp {
+    let hit = r#"{"result": {"hits": {"@total": "1", "hit": [{"info": {"key": "journals/talg/Chan12",
+        "authors": {"author": {"@pid": "1", "text": "Timothy M. Chan"}},
+        "title": "All-Pairs Shortest Paths for Unweighted Undirected Graphs in o(mn) Time.",
+        "venue": "ACM Trans. Algorithms", "year": "2012", "url": "{base}/rec/journals/talg/Chan12"}}]}}}"#;
+    MockDblp::start(vec![
+        ("/search/publ/api", hit.to_owned()),
+        (
+            "/rec/journals/talg/Chan12.bib?param=1",
+            JOURNAL_VERSION.to_owned(),
+        ),
+    ])
+}
+
+#[test]
+fn test_suggestions() {
+    let server = journal_server();
+    let sandbox = Sandbox::new("suggestions");
+    sandbox.fixture("Chan16.bib", "refs.bib");
+    let list = |all: bool| {
+        let mut cmd = sandbox.cmd(Some(&server));
+        cmd.args(["suggestions", "list"]);
+        if all {
+            cmd.arg("--all");
+        }
+        String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+    };
+
+    // found by the check, and kept for later
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["check", "--journal-versions"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    let listed = list(false);
+    assert!(
+        listed.starts_with("DBLP:conf/soda/Chan16 -> DBLP:journals/talg/Chan12 (found "),
+        "{}",
+        listed
+    );
+    assert!(listed.contains("same title and authors"), "{}", listed);
+
+    // dismissed ones are not reported again, keys can be shortened
+    sandbox
+        .cmd(Some(&server))
+        .args(["suggestions", "dismiss", "Chan16"])
+        .assert()
+        .success();
+    assert_eq!(list(false), "");
+    assert!(list(true).contains(", dismissed)"));
+    sandbox
+        .cmd(Some(&server))
+        .args(["check", "--journal-versions"])
+        .assert()
+        .success();
+    sandbox
+        .cmd(Some(&server))
+        .args(["suggestions", "apply", "DBLP:conf/soda/Chan16"])
+        .assert()
+        .failure();
+
+    // changing the entry expires the suggestion, so it is found again
+    let src = sandbox.read("refs.bib").replace("514--523", "514--524");
+    std::fs::write(sandbox.dir.join("refs.bib"), src).unwrap();
+    assert_eq!(list(true), "");
+    sandbox
+        .cmd(Some(&server))
+        .args(["check", "--journal-versions"])
+        .assert()
+        .failure();
+    sandbox.config("[aliases]\napsp = \"DBLP:conf/soda/Chan16\"\n");
+    std::fs::write(
+        sandbox.dir.join("main.tex"),
+        "\\cite{DBLP:conf/soda/Chan16}\n",
+    )
+    .unwrap();
+    sandbox
+        .cmd(Some(&server))
+        .args(["suggestions", "apply", "apsp"])
+        .assert()
+        .success();
+    let applied = sandbox.read("refs.bib");
+    assert!(
+        applied.contains("@article{DBLP:journals/talg/Chan12,"),
+        "{}",
+        applied
+    );
+    assert!(!applied.contains("DBLP:conf/soda/Chan16"));
+    assert_eq!(
+        sandbox.read("main.tex"),
+        "\\cite{DBLP:journals/talg/Chan12}\n"
+    );
+    assert_eq!(list(true), "");
+}
+
+#[test]
+fn test_journal_versions_failed_search() {
+    let sandbox = Sandbox::new("journal-failed");
+    sandbox.fixture("Chan16.bib", "refs.bib");
+    let down = MockDblp::start(vec![]);
+    let output = sandbox
+        .cmd(Some(&down))
+        .args(["check", "--journal-versions"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("cannot check DBLP:conf/soda/Chan16: "),
+        "{}",
+        stderr(&output)
+    );
+
+    // the failure is not remembered, so the entry is searched again
+    let server = journal_server();
+    std::fs::write(
+        sandbox.dir.join("main.tex"),
+        "\\cite{DBLP:conf/soda/Chan16}\n",
+    )
+    .unwrap();
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["check", "--journal-versions", "--fix"])
+        .write_stdin("r\n")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(sandbox
+        .read("refs.bib")
+        .contains("@article{DBLP:journals/talg/Chan12,"));
+    assert_eq!(
+        sandbox.read("main.tex"),
+        "\\cite{DBLP:journals/talg/Chan12}\n"
+    );
+    assert!(
+        stderr(&output).contains("renamed the citations in ./main.tex"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_check_exit_codes() {
+    let sandbox = Sandbox::new("check");
+    sandbox.fixture("inconsistent.bib", "refs.bib");
+    let output = sandbox.cmd(None).arg("check").output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("found 1 problems"));
+
+    let output = sandbox
+        .cmd(None)
+        .args(["check", "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["schema"], "dblp-rs/check/v3");
+    assert_eq!(json["findings"][0]["entries"].as_array().unwrap().len(), 2);
+
+    sandbox
+        .cmd(None)
+        .args(["check", "--fix"])
+        .assert()
+        .success();
+    sandbox.cmd(None).arg("check").assert().success();
+    assert!(sandbox.read("refs.bib.bak").contains("51st Annual"));
+}
+
+#[test]
+fn test_missing_bib_file() {
+    let sandbox = Sandbox::new("missing");
+    // not to look in the directories above it
+    std::fs::create_dir(sandbox.dir.join(".git")).unwrap();
+    let output = sandbox.cmd(None).arg("check").output().unwrap();
+    assert_eq!(output.status.code(), Some(exit::BIB_FILE.into()));
+    assert!(stderr(&output).contains("missing bibtex file"));
+    assert!(stderr(&output).contains("hint: pass the bibtex file with --bibtex"));
+}
+
+#[test]
+fn test_config() {
+    let server = MockDblp::start(vec![("/search/publ/api", search_response())]);
+    let sandbox = Sandbox::new("config");
+    sandbox.config("max_hits = 5\nclipboard = \"osc52\"\n");
+    let sub = sandbox.dir.join("sub");
+    std::fs::create_dir_all(&sub).unwrap();
+    let project = sandbox.dir.join(".dblp.toml");
+    std::fs::write(&project, "max_hits = 7\nnotes_dir = \"my-notes\"\n").unwrap();
+
+    // the project overrides the user, and flags override both
+    let output = sandbox
+        .cmd(None)
+        .current_dir(&sub)
+        .args(["--dblp-url", "https://example.org", "config"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let user = sandbox.dir.join("config/dblp-rs/config.toml");
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "clipboard = \"osc52\"  # {}\n\
+             dblp_url = \"https://example.org\"  # flag\n\
+             format = \"standard\"  # default\n\
+             max_hits = 7  # {}\n\
+             notes_dir = \"{}\"  # {}\n",
+            user.display(),
+            project.display(),
+            sandbox.dir.join("my-notes").display(),
+            project.display()
+        )
+    );
+    let output = sandbox
+        .cmd(Some(&server))
+        .current_dir(&sub)
+        .args(["search", "chan"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        server.requests()[0].contains("&h=7&"),
+        "{:?}",
+        server.requests()
+    );
+    let output = sandbox
+        .cmd(Some(&server))
+        .args(["search", "--max-hits", "3", "chan"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        server.requests()[1].contains("&h=3&"),
+        "{:?}",
+        server.requests()
+    );
+
+    // the errors name the file, and show the setting
+    std::fs::write(&project, "max_hits = \"many\"\n").unwrap();
+    let output = sandbox.cmd(None).arg("config").output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let err = stderr(&output);
+    assert!(err.contains(&format!("{:?}", project)), "{}", err);
+    assert!(err.contains("max_hits = \"many\""), "{}", err);
+}
+
+#[test]
+fn test_completions() {
+    let sandbox = Sandbox::new("completions");
+    let output = sandbox
+        .cmd(None)
+        .args(["completions", "bash"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    let script = String::from_utf8_lossy(&output.stdout);
+    assert!(script.contains("complete -F _dblp_keys"), "{}", script);
+    assert!(script.contains("dblp __complete-keys -- "), "{}", script);
+
+    let complete = |prefix: &str| {
+        let output = sandbox
+            .cmd(None)
+            .args(["__complete-keys", "--", prefix])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        // nothing that would show in the prompt
+        assert_eq!(stderr(&output), "");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    std::fs::create_dir(sandbox.dir.join(".git")).unwrap();
+    assert_eq!(complete(""), "");
+    std::fs::write(
+        sandbox.dir.join("refs.bib"),
+        "@misc{DBLP:conf/soda/Chan16, title = {A}}\n@misc{local, title = {B}}\n",
+    )
+    .unwrap();
+    std::fs::write(
+        sandbox.dir.join("more.bib"),
+        "@misc{DBLP:journals/talg/Chan18, title = {C}}\n",
+    )
+    .unwrap();
+    assert_eq!(
+        complete("DBLP:"),
+        "DBLP:journals/talg/Chan18\nDBLP:conf/soda/Chan16\n"
+    );
+    assert_eq!(complete("lo"), "local\n");
+    // what the key can be shortened to, and the aliases
+    assert_eq!(complete("Chan"), "Chan18\nChan16\n");
+    sandbox.config("[aliases]\napsp = \"DBLP:conf/soda/Chan16\"\n");
+    assert_eq!(complete("ap"), "apsp\n");
+    std::fs::write(sandbox.dir.join("refs.bib"), "@misc{broken, title = {").unwrap();
+    assert_eq!(complete("DBLP:"), "DBLP:journals/talg/Chan18\n");
+}
+
+#[test]
+fn test_doctor_json() {
+    let sandbox = Sandbox::new("doctor");
+    sandbox.fixture("inconsistent.bib", "refs.bib");
+    let output = sandbox
+        .cmd(None)
+        .args(["doctor", "--json", "--offline"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["schema"], "dblp-rs/doctor/v3");
+    assert!(json["mirror_health"].is_array());
+    assert_eq!(json["bib"]["entries"], 2);
+    assert!(json["mirrors"].is_null());
+    assert!(json["cache"]["path"]
+        .as_str()
+        .unwrap()
+        .starts_with(sandbox.dir.to_str().unwrap()));
+}
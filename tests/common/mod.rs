@@ -0,0 +1,83 @@
+/// helpers shared by the tests, like a local server standing in for DBLP
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Serves the given bodies at the given paths, with or without the query,
+/// and 404 for anything else. `{base}` in the bodies is replaced by the
+/// url of the server, and a body like `HTTP 500` is served as that status.
+pub struct MockDblp {
+    pub base: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockDblp {
+    pub fn start(routes: Vec<(&'static str, String)>) -> Self {
+        Self::start_slow(routes, Duration::ZERO)
+    }
+
+    /// Like `start`, waiting for the delay before each response, for the
+    /// tests of runs that are interrupted meanwhile
+    pub fn start_slow(routes: Vec<(&'static str, String)>, delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let routes: Vec<(&str, String)> = routes
+            .into_iter()
+            .map(|(path, body)| (path, body.replace("{base}", &base)))
+            .collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request_line
+                    .split_whitespace()
+                    .nth(1)
+                    .unwrap_or("")
+                    .to_owned();
+                let (status, body) = match routes
+                    .iter()
+                    .find(|(p, _)| *p == path || path.split('?').next() == Some(*p))
+                {
+                    Some((_, body)) => match body.strip_prefix("HTTP ") {
+                        Some(status) => (status, ""),
+                        None => ("200 OK", body.as_str()),
+                    },
+                    None => ("404 Not Found", ""),
+                };
+                log.lock().unwrap().push(path);
+                std::thread::sleep(delay);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+            }
+        });
+        Self { base, requests }
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// The path of a file in `tests/fixtures`, like `Chan16.bib`
+pub fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
@@ -0,0 +1,193 @@
+//! tests of the library, against a local server serving recorded responses
+//! of DBLP
+use common::{fixture_path, MockDblp};
+use dblp_rs::{bib, dblp, notes, DblpError, DblpHitInfo, DblpResponse, Format};
+use std::{path::PathBuf, sync::LazyLock};
+
+mod common;
+
+/// The server every test queries, set up before the first request along
+/// with the directories the library writes its cache and reads its config
+/// in, which are the ones of the test
+static SERVER: LazyLock<MockDblp> = LazyLock::new(|| {
+    let server = MockDblp::start(vec![
+        ("/search/publ/api", read_fixture("books-search.json")),
+        ("/rec/conf/soda/Chan16.bib", read_fixture("Chan16.bib")),
+    ]);
+    let dir = scratch_dir("env");
+    std::env::set_var(dblp::API_BASE_VAR, &server.base);
+    std::env::set_var("DBLP_RS_DATA_DIR", dir.join("data"));
+    std::env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+    server
+});
+
+fn read_fixture(name: &str) -> String {
+    std::fs::read_to_string(fixture_path(name)).unwrap()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dblp-rs-lib-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_query() {
+    LazyLock::force(&SERVER);
+    let hits = dblp::query("shortest paths", Format::Condensed, 2).unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].key, "conf/soda/Chan16");
+    assert_eq!(hits[0].get_key(), "DBLP:conf/soda/Chan16");
+    assert_eq!(hits[0].people(), ["Timothy M. Chan"]);
+    assert!(
+        SERVER
+            .requests()
+            .iter()
+            .any(|r| r.contains("&format=json&h=2&")),
+        "{:?}",
+        SERVER.requests()
+    );
+
+    // hits and responses go through JSON and back as they are
+    let json = serde_json::to_string(&hits[0]).unwrap();
+    let back: DblpHitInfo = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.title, hits[0].title);
+    assert_eq!(back.people(), hits[0].people());
+    let resp = DblpResponse::parse(&read_fixture("books-search.json")).unwrap();
+    let again = DblpResponse::parse(&serde_json::to_string(&resp).unwrap()).unwrap();
+    let keys = |resp: &DblpResponse| resp.matches().map(|hit| hit.key).collect::<Vec<_>>();
+    assert_eq!(keys(&again), keys(&resp));
+    assert_eq!(
+        serde_json::to_string(&Format::Crossref).unwrap(),
+        "\"crossref\""
+    );
+}
+
+#[test]
+fn test_fetch_and_append() {
+    LazyLock::force(&SERVER);
+    let key = "DBLP:conf/soda/Chan16";
+    let record = dblp::fetch_record(key, Format::Condensed).unwrap();
+    assert!(record.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"));
+
+    let src = "@misc{local, title = {Local}}\n";
+    assert!(!bib::is_present(src, key));
+    let updated = bib::append(src, &[record]);
+    assert!(updated.starts_with(src));
+    assert!(bib::is_present(&updated, key));
+    assert_eq!(
+        bib::presence(&updated, key),
+        bib::Presence::PresentAs(key.to_owned())
+    );
+
+    match dblp::fetch_record("DBLP:conf/soda/Missing16", Format::Condensed) {
+        Err(DblpError::NotFound { key }) => assert_eq!(key, "DBLP:conf/soda/Missing16"),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_notes() {
+    LazyLock::force(&SERVER);
+    let dir = scratch_dir("notes");
+    let key = "DBLP:conf/soda/Chan16";
+    let fetch = || dblp::fetch_bibtex(key);
+    let extensions = ["md".to_owned()];
+    let created =
+        notes::create_notes_file(&dir, &extensions, key, "Chan16", &[], None, fetch).unwrap();
+    let notes::NotesFile::Created(path) = created else {
+        panic!("{:?}", created);
+    };
+    assert_eq!(
+        notes::create_notes_file(&dir, &extensions, key, "Chan16", &[], None, fetch).unwrap(),
+        notes::NotesFile::Existing(path.clone())
+    );
+
+    let found: Vec<_> = notes::files_with_metadata(&dir, &extensions).collect();
+    assert_eq!(found.len(), 1);
+    let (found_path, meta) = &found[0];
+    assert_eq!(found_path, &path);
+    assert_eq!(meta.key, key);
+    let json = serde_json::to_string(meta).unwrap();
+    let back: notes::ShortMetadata = serde_json::from_str(&json).unwrap();
+    assert_eq!(&back, meta);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_async() {
+    use dblp_rs::{fetcher::AsyncFetcher, Query};
+    LazyLock::force(&SERVER);
+    let fetcher = AsyncFetcher::new();
+    // a query and a format of their own, not to be served from the cache
+    let query = Query {
+        max_hits: 2,
+        format: Format::Crossref,
+        ..Query::new("all pairs")
+    };
+    let hits = query.execute_async(&fetcher).await.unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].key, "conf/soda/Chan16");
+    assert!(
+        SERVER
+            .requests()
+            .iter()
+            .any(|r| r.ends_with("&format=json&h=2&f=0&param=2")),
+        "{:?}",
+        SERVER.requests()
+    );
+
+    let key = "DBLP:conf/soda/Chan16";
+    let record = dblp::fetch_record_async(&fetcher, key, Format::Crossref)
+        .await
+        .unwrap();
+    assert!(record.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"));
+    assert!(SERVER
+        .requests()
+        .contains(&"/rec/conf/soda/Chan16.bib?param=2".to_owned()));
+    match dblp::fetch_record_async(&fetcher, "DBLP:conf/soda/Missing16", Format::Crossref).await {
+        Err(DblpError::NotFound { key }) => assert_eq!(key, "DBLP:conf/soda/Missing16"),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_convert_async() {
+    use dblp_rs::{convert, fetcher::AsyncFetcher, keys::KeyFilter, limiter::Limiter};
+    use std::time::{Duration, Instant};
+    LazyLock::force(&SERVER);
+    let src = "@misc{DBLP:conf/soda/Chan16, title = {Old}, note = {mine}}\n\n@misc{DBLP:conf/soda/Missing16, title = {Gone}}\n\n@misc{local, title = {Local}}\n";
+    let options = convert::Options {
+        to: Format::Standard,
+        on_fetch_error: convert::OnFetchError::KeepOriginal,
+        jobs: 2,
+        filter: KeyFilter::default(),
+        no_merge: false,
+        strip: Vec::new(),
+        verbose: false,
+    };
+    let limiter = Limiter::new(Duration::from_millis(200));
+    let start = Instant::now();
+    let conversion = convert::convert_entries_async(&AsyncFetcher::new(), &limiter, src, &options)
+        .await
+        .unwrap();
+    // the second request waited for its slot, even with two tasks
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!((conversion.dblp, conversion.fetched), (2, 2));
+    assert_eq!(conversion.not_dblp, 1);
+    assert_eq!(conversion.failures.len(), 1);
+    assert_eq!(conversion.failures[0].key, "DBLP:conf/soda/Missing16");
+    assert_eq!(conversion.failures[0].code(), "not_found");
+    let converted = convert::render_converted(src, &conversion.entries);
+    assert!(
+        converted.starts_with("@inproceedings{DBLP:conf/soda/Chan16,"),
+        "{}",
+        converted
+    );
+    assert!(converted.contains("mine"), "{}", converted);
+    assert!(converted.contains("@misc{DBLP:conf/soda/Missing16, title = {Gone}}"));
+    assert!(converted.ends_with("@misc{local, title = {Local}}\n"));
+}